@@ -8,6 +8,8 @@ const COLLISION_MASK_STRUCTURE := 1 << (20 - 1)
 const MAIN_MENU := "res://start_menu/main.tscn"
 const VEHICLE_DIRECTORY := "user://vehicles"
 const VEHICLE_EXTENSION := "owv"
+const REPLAY_DIRECTORY := "user://replays"
+const REPLAY_EXTENSION := "owr"
 const NET_COMPRESSION := NetworkedMultiplayerENet.COMPRESS_RANGE_CODER
 const SETTINGS_FILE := "user://settings.cfg"
 const MAINFRAME_ID := 76
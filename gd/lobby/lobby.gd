@@ -2,16 +2,23 @@ extends Node
 
 
 class Entry:
-	var ip: PoolIntArray
+	# The address as "ip:port", as used by the lobby protocol.
+	var addr: String
+	var ip: String
 	var port: int
 	var name: String
+	var map: String
+	var max_players: int
+	var description: String
+	# The protocol version the server registered with.
+	var version: int
+	var players: int
+	var phase: int
+	# The elapsed match time in seconds.
+	var elapsed: int
 
 	func get_ip() -> String:
-		match len(ip):
-			4: return "%d.%d.%d.%d" % Array(ip)
-			8: return "%x:%x:%x:%x:%x:%x:%x:%x" % Array(ip)
-			_: assert(false, "Invalid IP")
-		return ""
+		return ip
 
 
 class ServerInfo:
@@ -26,18 +33,79 @@ enum {
 	MSG_TYPE_PING = 3,
 	MSG_TYPE_INFO = 4,
 	MSG_TYPE_PUNCH_HOLE = 5,
+	MSG_TYPE_HELLO = 6,
+	MSG_TYPE_ERROR = 7,
+	MSG_TYPE_LIST_SERVERS = 8,
+	MSG_TYPE_WHO_AM_I = 10,
+	MSG_TYPE_PUNCH_REQUEST = 11,
+	MSG_TYPE_PUNCH = 12,
+	MSG_TYPE_PUNCH_ACK = 13,
+	MSG_TYPE_ADVERTISE = 14,
+	MSG_TYPE_ENQUEUE = 15,
+	MSG_TYPE_DEQUEUE = 16,
+	MSG_TYPE_MATCHED = 17,
+	MSG_TYPE_UPLOAD_VEHICLE = 18,
+	MSG_TYPE_SEARCH_VEHICLES = 19,
+	MSG_TYPE_DOWNLOAD_VEHICLE = 20,
+	MSG_TYPE_VERIFY = 21,
+}
+
+enum {
+	PUNCH_RECEIVED = 0,
+	PUNCH_CONNECTED = 1,
+	PUNCH_FAILED = 2,
+}
+
+enum {
+	LOBBY_OK = 0,
+	LOBBY_ERR_UNSUPPORTED_VERSION = 1,
+	LOBBY_ERR_UNKNOWN_MESSAGE = 2,
+	LOBBY_ERR_UNAUTHORIZED = 3,
+	LOBBY_ERR_TOO_MANY_REGISTRATIONS = 4,
+	LOBBY_ERR_REQUEST_TOO_SMALL = 5,
+	LOBBY_ERR_DENIED = 6,
+	LOBBY_ERR_REJECTED = 7,
+	LOBBY_ERR_INVALID_VEHICLE = 8,
+	LOBBY_ERR_NOT_FOUND = 9,
+	LOBBY_ERR_STORAGE_FULL = 10,
+}
+
+enum {
+	MATCH_PHASE_WARMUP = 0,
+	MATCH_PHASE_IN_PROGRESS = 1,
+	MATCH_PHASE_ENDING = 2,
 }
 
+# The lobby limits the size of responses based on the size of the request, so pad requests
+# that may get a large response.
+const REQUEST_PADDING := 1200
+const VEHICLE_CHUNK_SIZE := 1024
+# The lobby forgets verified addresses after 10 minutes, verify again well before that.
+const VERIFY_INTERVAL_MSEC := 5 * 60 * 1000
+
 signal server_list(entries)
 signal server_info(info)
+signal public_address(address, port)
+signal queued(waiting)
+signal matched(address, port)
+signal vehicle_shared(id)
+signal vehicle_share_failed(code)
+signal vehicles_found(entries, more)
+signal vehicle_downloaded(id, data)
 
 var lobby_address := "107.189.30.116"
 var lobby_port := 39984
 var lobby_peer := PacketPeerUDP.new()
 
 var registered := false
+var registration_token := 0
 var got_server_list := true # TODO name isn't quite correct
 var got_server_info := true
+# The filters of the server list being received, the entries received so far by address and
+# the last entry of the last page, which is the cursor of the next page.
+var _list_filter := {}
+var _list_entries := {}
+var _list_cursor: Entry = null
 
 var disable_lobby := false
 var disable_upnp := false
@@ -47,15 +115,36 @@ var server_name := ""
 var server_port := 39983
 var server_max_players := 32
 var server_description := ""
+# Players per team and region tag for matchmaking, 0 and empty to not advertise.
+var server_team_size := 0
+var server_region := ""
 var server_scene: Node
 var server_ping_timer := Timer.new()
 
 var client_connected := false
+# The session of the last punch the lobby asked us to do, 0 if none.
+var punch_session := 0
 var player_vehicle_valid := false
 var player_vehicle_invalid_reason = null
 var player_name := "" setget set_player_name
+var matchmaking := false
+var got_vehicle_list := true
+
+var _upload_data := PoolByteArray()
+var _upload_tags := ""
+# How much of the upload the lobby acknowledged, -1 if not uploading.
+var _upload_received := -1
+var _download_id := 0
+var _download_data := PoolByteArray()
+var _download_total := -1
 
 var _retry_timer: SceneTreeTimer = null
+# The last cookie the lobby sent and until when our address is verified, see `verify`.
+var _cookie := 0
+var _verified_until := 0
+
+# Encodes and decodes the packets of the lobby protocol.
+var _protocol = OwnWar_LobbyProtocol.new()
 
 
 func _ready() -> void:
@@ -68,97 +157,114 @@ func _ready() -> void:
 
 func _process(_delta: float) -> void:
 	for _i in lobby_peer.get_available_packet_count():
-		var spb := StreamPeerBuffer.new()
-		spb.data_array = lobby_peer.get_packet()
-		var msg_type := spb.get_u8()
-		match msg_type:
-			MSG_TYPE_LIST:
-				var entries := []
-				while spb.get_available_bytes() > 0:
-					var entry := Entry.new()
-					var is_ipv6 := spb.get_u8()
-					if is_ipv6:
-						var a := spb.get_u16()
-						var b := spb.get_u16()
-						var c := spb.get_u16()
-						var d := spb.get_u16()
-						var e := spb.get_u16()
-						var f := spb.get_u16()
-						var g := spb.get_u16()
-						var h := spb.get_u16()
-						entry.ip = PoolIntArray([a, b, c, d, e, f, g, h])
-					else:
-						var a := spb.get_u8()
-						var b := spb.get_u8()
-						var c := spb.get_u8()
-						var d := spb.get_u8()
-						entry.ip = PoolIntArray([a, b, c, d])
-					entry.port = spb.get_u16()
-					var l := spb.get_u8()
-					entry.name = spb.get_data(l)[1].get_string_from_utf8()
-					entries.push_back(entry)
-				emit_signal("server_list", entries)
-				got_server_list = true
+		var rsp: Dictionary = _protocol.decode_response(lobby_peer.get_packet())
+		if "error" in rsp:
+			print("Ignoring lobby packet: %s" % rsp.error)
+			continue
+		match rsp.type:
+			MSG_TYPE_LIST_SERVERS:
+				_receive_list_page(rsp)
 			MSG_TYPE_REGISTER_SERVER:
-				var status := spb.get_u8()
-				match status:
-					OK:
-						if not server_scene.is_connected("tree_exiting", self, "remove_server"):
-							var e := server_scene.connect("tree_exiting", self, "remove_server")
-							assert(e == OK)
-						registered = true
-						print("Registered server")
-						server_ping_timer.start()
-					_:
-						print("Failed to register server: %s" % Global.ERROR_TO_STRING[status])
-						assert(false, "Failed to register server")
+				_receive_registration(rsp)
 			MSG_TYPE_INFO:
-				if spb.get_available_bytes() == 0:
-					print("Failed to get info") # Can happen due to unfortunate timing
-				else:
-					var info := ServerInfo.new()
-					info.max_players = spb.get_u8()
-					var l := spb.get_u8()
-					info.map = spb.get_data(l)[1].get_string_from_utf8()
-					l = spb.get_u16()
-					info.description = spb.get_data(l)[1].get_string_from_utf8()
-					emit_signal("server_info", info)
-					got_server_info = true
+				var info := ServerInfo.new()
+				info.max_players = rsp.max_players
+				info.map = rsp.map
+				info.description = rsp.description
+				emit_signal("server_info", info)
+				got_server_info = true
 			MSG_TYPE_PUNCH_HOLE:
-				var port := spb.get_u16()
-				if port != server_port:
+				if rsp.port != server_port:
 					print("Received punch request for unbound port")
 					assert(false, "Received punch request for unbound port")
-				var is_ipv6 := spb.get_u8()
-				var addr: String
-				if is_ipv6:
-					var a := spb.get_u16()
-					var b := spb.get_u16()
-					var c := spb.get_u16()
-					var d := spb.get_u16()
-					var e := spb.get_u16()
-					var f := spb.get_u16()
-					var g := spb.get_u16()
-					var h := spb.get_u16()
-					addr = "%x:%x:%x:%x:%x:%x:%x:%x" % [a, b, c, d, e, f, g, h]
-				else:
-					var a := spb.get_u8()
-					var b := spb.get_u8()
-					var c := spb.get_u8()
-					var d := spb.get_u8()
-					addr = "%d.%d.%d.%d" % [a, b, c, d]
-				port = spb.get_u16()
+				var addr := _split_address(rsp.client)
 				var ppu := PacketPeerUDP.new()
-				var e := ppu.set_dest_address(addr, port)
+				var e := ppu.set_dest_address(addr[0], addr[1])
 				assert(e == OK)
 				print(("I got a request to punch a hole for %s:%d but I can't use"
-					+ " the ENet socket yet :(") % [addr, port])
+					+ " the ENet socket yet :(") % addr)
+			MSG_TYPE_WHO_AM_I:
+				var addr := _split_address(rsp.addr)
+				emit_signal("public_address", addr[0], addr[1])
+			MSG_TYPE_PUNCH:
+				var session: int = rsp.session
+				var addr := _split_address(rsp.peer)
+				# Acknowledge every copy, the lobby keeps resending until it gets one.
+				_punch_ack(session, PUNCH_RECEIVED)
+				if session != punch_session:
+					punch_session = session
+					if server_scene == null:
+						print("Punching to %s:%d" % addr)
+					elif rsp.port != server_port:
+						print("Received punch for unbound port")
+						_punch_ack(session, PUNCH_FAILED)
+					else:
+						# The client reports whether it could connect.
+						print(("I got a punch for %s:%d but I can't use the ENet socket"
+							+ " yet :(") % addr)
+			MSG_TYPE_ENQUEUE:
+				emit_signal("queued", rsp.waiting)
+			MSG_TYPE_MATCHED:
+				var addr := _split_address(rsp.server)
+				if matchmaking:
+					matchmaking = false
+					punch_session = rsp.session
+					emit_signal("matched", addr[0], addr[1])
+			MSG_TYPE_UPLOAD_VEHICLE:
+				if _upload_received < 0:
+					pass
+				elif "id" in rsp:
+					_upload_received = -1
+					emit_signal("vehicle_shared", rsp.id)
+				elif rsp.received > _upload_received:
+					_upload_received = rsp.received
+					_send_upload_chunk()
+			MSG_TYPE_SEARCH_VEHICLES:
+				# Each entry has the keys id, size, name and tags.
+				emit_signal("vehicles_found", rsp.vehicles, rsp.more)
+				got_vehicle_list = true
+			MSG_TYPE_DOWNLOAD_VEHICLE:
+				if rsp.id == _download_id and rsp.offset == len(_download_data):
+					_download_total = rsp.total
+					_download_data.append_array(rsp.data)
+					if len(_download_data) >= rsp.total:
+						_download_total = -1
+						emit_signal("vehicle_downloaded", rsp.id, _download_data)
+					else:
+						_send_download_request()
+			MSG_TYPE_VERIFY:
+				if rsp.cookie == _cookie:
+					_verified_until = OS.get_ticks_msec() + VERIFY_INTERVAL_MSEC
+				else:
+					_cookie = rsp.cookie
+					_send_verify()
+			MSG_TYPE_HELLO:
+				print("Lobby supports protocol versions %d to %d" % [rsp.min_version, rsp.max_version])
+			MSG_TYPE_ERROR:
+				var request_type: int = rsp.request
+				var code: int = rsp.code
+				if request_type == MSG_TYPE_UPLOAD_VEHICLE and _upload_received >= 0:
+					_upload_received = -1
+					emit_signal("vehicle_share_failed", code)
+				elif request_type == MSG_TYPE_DOWNLOAD_VEHICLE and code == LOBBY_ERR_NOT_FOUND:
+					_download_total = -1
+					print("Vehicle %x doesn't exist" % _download_id)
+				match code:
+					LOBBY_ERR_UNSUPPORTED_VERSION:
+						print("Lobby rejected protocol version %d, supported versions are %d to %d" \
+							% [_protocol.protocol_version(), rsp.min_version, rsp.max_version])
+					_:
+						print("Lobby rejected message type %d with error %d" % [request_type, code])
 			MSG_TYPE_REMOVE_SERVER, MSG_TYPE_PING:
 				assert(false, "Recieved response for no-response packet")
 			_:
 				assert(false, "Invalid message type")
 
 
+func hello() -> void:
+	_send(_protocol.create_hello())
+
+
 func register_server(scene: Node) -> void:
 	if not disable_upnp:
 		var upnp := UPNP.new()
@@ -192,114 +298,313 @@ func register_server(scene: Node) -> void:
 	assert(len(server_description) < 65536, "Description too long")
 
 	server_scene = scene
-	var spb := StreamPeerBuffer.new()
-	spb.put_u8(MSG_TYPE_REGISTER_SERVER)
-	spb.put_u16(server_port)
-	spb.put_u8(len(server_name))
-	var e := spb.put_data(server_name.to_utf8())
-	assert(e == OK)
-	spb.put_u8(len(server_scene.filename))
-	e = spb.put_data(server_scene.filename.to_utf8())
-	assert(e == OK)
-	spb.put_u8(server_max_players)
-	spb.put_u16(len(server_description))
-	e = spb.put_data(server_description.to_utf8())
-	assert(e == OK)
+	registration_token = 0
 
 	while not registered:
+		if server_scene == null:
+			# The lobby rejected the server, trying again won't help.
+			return
 		print("Attempting to register server")
-		e = lobby_peer.put_packet(spb.data_array)
-		assert(e == OK)
+		_send(_create_register_packet())
 		yield(get_tree().create_timer(1.0), "timeout")
+	_advertise()
 
 	# Reregister periodically in case the lobby stopped (and thus lost the entry)
 	while true:
 		yield(get_tree().create_timer(100), "timeout")
 		if server_scene == null:
 			break
-		e = lobby_peer.put_packet(spb.data_array)
-		assert(e == OK)
+		_send(_create_register_packet())
+		_advertise()
 
 
 func remove_server() -> void:
-	var spb := StreamPeerBuffer.new()
-	spb.put_u8(MSG_TYPE_REGISTER_SERVER)
-	spb.put_u16(server_port)
+	_send(_protocol.create_remove_server(server_port, registration_token))
 	registered = false
+	registration_token = 0
 	server_scene = null
 	server_ping_timer.stop()
 
 
 func ping() -> void:
 	assert(registered)
-	var spb := StreamPeerBuffer.new()
-	spb.put_u8(MSG_TYPE_PING)
-	spb.put_u16(server_port)
-	var e := lobby_peer.put_packet(spb.data_array)
-	assert(e == OK)
+	_send(_protocol.create_ping(server_port, registration_token, _get_match_status()))
 
 
-func get_server_list(filter := "") -> void:
-	var pkt := PoolByteArray()
-	pkt.push_back(MSG_TYPE_LIST)
-	pkt.append_array(filter.to_utf8())
+# Get all servers page by page. Empty strings and 0 match anything. Emits "server_list" with
+# the entries once the last page is received.
+func get_server_list(name_filter := "", map := "", free_slots := 0) -> void:
+	_list_filter = { name = name_filter, map = map, free_slots = free_slots }
+	_list_entries = {}
+	_list_cursor = null
 
 	got_server_list = false
 	while not got_server_list:
-		var e := lobby_peer.put_packet(pkt)
-		assert(e == OK)
+		_send_list_servers()
 		yield(get_tree().create_timer(1.0), "timeout")
 
 
 func get_server_info(entry: Entry) -> void:
-	var spb := StreamPeerBuffer.new()
-	spb.put_u8(MSG_TYPE_INFO)
-	match len(entry.ip):
-		4:
-			spb.put_u8(0)
-			for n in entry.ip:
-				spb.put_u8(n)
-		8:
-			spb.put_u8(1)
-			for n in entry.ip:
-				spb.put_u16(n)
-		_:
-			assert(false, "Invalid IP address")
-	spb.put_u16(entry.port)
-
+	var pkt := _padded(_protocol.create_server_info(entry.addr))
 	got_server_info = false
 	while not got_server_info:
-		var e := lobby_peer.put_packet(spb.data_array)
-		assert(e == OK)
+		_send(pkt)
 		yield(get_tree().create_timer(1), "timeout")
 
 
+# Prove to the lobby that we receive packets at our address. Until then the lobby only sends
+# us a few times as much data as we send it, which isn't enough for punches and matchmaking.
+func verify() -> void:
+	while not is_verified():
+		_send_verify()
+		yield(get_tree().create_timer(1.0), "timeout")
+
+
+func is_verified() -> bool:
+	return OS.get_ticks_msec() < _verified_until
+
+
+func who_am_i() -> void:
+	_send(_protocol.create_who_am_i())
+
+
 func punch_hole(entry: Entry) -> void:
-	var spb := StreamPeerBuffer.new()
-	spb.put_u8(MSG_TYPE_PUNCH_HOLE)
-	match len(entry.ip):
-		4:
-			spb.put_u8(0)
-			for n in entry.ip:
-				spb.put_u8(n)
-		8:
-			spb.put_u8(1)
-			for n in entry.ip:
-				spb.put_u16(n)
-		_:
-			assert(false, "Invalid IP address")
-	spb.put_u16(entry.port)
+	var pkt: PoolByteArray = _protocol.create_punch_request(entry.addr)
 
 	client_connected = false
+	punch_session = 0
+	if not is_verified():
+		yield(verify(), "completed")
 	# If the connection fails after 5 tries, the server is likely unreachable anyways
 	for _i in 5:
-		var e := lobby_peer.put_packet(spb.data_array)
-		assert(e == OK)
+		if punch_session == 0:
+			_send(pkt)
 		yield(get_tree().create_timer(1.0), "timeout")
 		if client_connected:
 			break
+	if punch_session != 0:
+		_punch_ack(punch_session, PUNCH_CONNECTED if client_connected else PUNCH_FAILED)
+		punch_session = 0
+
+
+# Wait in the lobby's queue until it assigns a server. Empty values and a team size of 0 match
+# anything. The lobby starts a punch between us and the server, so connect to the address of
+# the "matched" signal and report the result with `report_match`.
+func find_match(map := "", team_size := 0, region := "") -> void:
+	var pkt: PoolByteArray = _protocol.create_enqueue(map, team_size, region)
+
+	client_connected = false
+	punch_session = 0
+	matchmaking = true
+	# The lobby forgets us if we stop resending
+	while matchmaking:
+		if not is_verified():
+			yield(verify(), "completed")
+		_send(pkt)
+		yield(get_tree().create_timer(5.0), "timeout")
+
+
+func cancel_match() -> void:
+	if not matchmaking:
+		return
+	matchmaking = false
+	_send(_protocol.create_dequeue())
+
+
+# Tell the lobby whether we could connect to the matched server.
+func report_match() -> void:
+	if punch_session != 0:
+		_punch_ack(punch_session, PUNCH_CONNECTED if client_connected else PUNCH_FAILED)
+		punch_session = 0
+
+
+# Upload the contents of an .owv file to the lobby. Emits "vehicle_shared" with the ID or
+# "vehicle_share_failed" with an error code.
+func share_vehicle(data: PoolByteArray, tags := "") -> void:
+	_upload_data = data
+	_upload_tags = tags
+	_upload_received = 0
+	var last := -1
+	var tries := 0
+	# The next chunk is sent as soon as the previous one is acknowledged, this only resends
+	# lost chunks.
+	while _upload_received >= 0:
+		if _upload_received == last:
+			tries += 1
+			if tries >= 5:
+				_upload_received = -1
+				emit_signal("vehicle_share_failed", -1)
+				break
+		else:
+			tries = 0
+		last = _upload_received
+		_send_upload_chunk()
+		yield(get_tree().create_timer(1.0), "timeout")
+
+
+func search_vehicles(name_filter := "", tags := "", cursor := 0) -> void:
+	var pkt := _padded(_protocol.create_search_vehicles(cursor, 0, name_filter, tags))
+	got_vehicle_list = false
+	while not got_vehicle_list:
+		_send(pkt)
+		yield(get_tree().create_timer(1.0), "timeout")
+
+
+# Download a shared vehicle. Emits "vehicle_downloaded" with the contents of the .owv file.
+func download_vehicle(id: int) -> void:
+	_download_id = id
+	_download_data = PoolByteArray()
+	_download_total = 0
+	var last := -1
+	var tries := 0
+	while _download_total >= 0:
+		if len(_download_data) == last:
+			tries += 1
+			if tries >= 5:
+				print("Failed to download vehicle %x" % id)
+				_download_total = -1
+				break
+		else:
+			tries = 0
+		last = len(_download_data)
+		_send_download_request()
+		yield(get_tree().create_timer(1.0), "timeout")
 
 
 func set_player_name(value: String) -> void:
 	player_name = value
 	OwnWar_Settings.dirty = true
+
+
+func _receive_list_page(rsp: Dictionary) -> void:
+	if got_server_list:
+		return
+	var last: Entry = null
+	for d in rsp.servers:
+		last = _get_entry(d)
+		_list_entries[last.addr] = last
+	if rsp.more and last != null:
+		_list_cursor = last
+		_send_list_servers()
+	else:
+		got_server_list = true
+		emit_signal("server_list", _list_entries.values())
+
+
+func _receive_registration(rsp: Dictionary) -> void:
+	if "token" in rsp:
+		registration_token = rsp.token
+		if not server_scene.is_connected("tree_exiting", self, "remove_server"):
+			var e := server_scene.connect("tree_exiting", self, "remove_server")
+			assert(e == OK)
+		registered = true
+		print("Registered server")
+		server_ping_timer.start()
+		return
+	match rsp.code:
+		LOBBY_ERR_DENIED:
+			print("Failed to register server: this host may not register servers")
+			server_scene = null
+		LOBBY_ERR_REJECTED:
+			print("Failed to register server: the name or description was rejected")
+			server_scene = null
+		_:
+			print("Failed to register server: %d" % rsp.code)
+			assert(false, "Failed to register server")
+
+
+func _create_register_packet() -> PoolByteArray:
+	return _protocol.create_register_server(server_port, registration_token, server_name,
+		server_scene.filename, server_max_players, server_description, _get_match_status())
+
+
+func _send_list_servers() -> void:
+	var cursor := "" if _list_cursor == null else _list_cursor.addr
+	# Servers of other versions can't be joined anyways.
+	var filter := { version = _protocol.protocol_version() }
+	if _list_filter.map != "":
+		filter.map = _list_filter.map
+	if _list_filter.name != "":
+		filter.name = _list_filter.name
+	if _list_filter.free_slots > 0:
+		filter.free_slots = _list_filter.free_slots
+	# Let the lobby decide how many entries fit in a page.
+	_send(_padded(_protocol.create_list_servers(cursor, 0, filter)))
+
+
+func _advertise() -> void:
+	if server_team_size == 0 and server_region == "":
+		return
+	_send(_protocol.create_advertise(server_port, registration_token, server_team_size,
+		server_region))
+
+
+func _send_upload_chunk() -> void:
+	var chunk := _upload_data.subarray(_upload_received,
+		min(_upload_received + VEHICLE_CHUNK_SIZE, len(_upload_data)) - 1)
+	_send(_protocol.create_upload_vehicle(len(_upload_data), _upload_received, _upload_tags,
+		chunk))
+
+
+func _send_download_request() -> void:
+	# Chunks are larger than the request, so it must be padded.
+	_send(_padded(_protocol.create_download_vehicle(_download_id, len(_download_data))))
+
+
+func _send_verify() -> void:
+	_send(_protocol.create_verify(_cookie))
+
+
+func _punch_ack(session: int, result: int) -> void:
+	_send(_protocol.create_punch_ack(session, result))
+
+
+func _send(pkt: PoolByteArray) -> void:
+	# The protocol returns an empty packet and logs an error if encoding failed.
+	if len(pkt) == 0:
+		return
+	var e := lobby_peer.put_packet(pkt)
+	assert(e == OK)
+
+
+func _padded(pkt: PoolByteArray) -> PoolByteArray:
+	if len(pkt) > 0 and len(pkt) < REQUEST_PADDING:
+		pkt.resize(REQUEST_PADDING)
+	return pkt
+
+
+# Split an "ip:port" address as used by the lobby protocol into the IP and the port. IPv6
+# addresses are enclosed in brackets.
+func _split_address(addr: String) -> Array:
+	var i := addr.find_last(":")
+	return [addr.substr(0, i).trim_prefix("[").trim_suffix("]"), int(addr.substr(i + 1))]
+
+
+func _get_entry(d: Dictionary) -> Entry:
+	var entry := Entry.new()
+	entry.addr = d.addr
+	var addr := _split_address(d.addr)
+	entry.ip = addr[0]
+	entry.port = addr[1]
+	entry.name = d.name
+	entry.map = d.map
+	entry.max_players = d.max_players
+	entry.description = d.description
+	entry.version = d.version
+	entry.players = d.status.players
+	entry.phase = d.status.phase
+	entry.elapsed = d.status.elapsed
+	return entry
+
+
+# The status is reported by the map with `get_match_status`, which returns a dictionary with
+# the keys `players`, `phase` and `elapsed`.
+func _get_match_status() -> Dictionary:
+	var status := {}
+	if server_scene != null and server_scene.has_method("get_match_status"):
+		status = server_scene.get_match_status()
+	return {
+		players = status.get("players", 0),
+		phase = status.get("phase", MATCH_PHASE_WARMUP),
+		elapsed = status.get("elapsed", 0),
+	}
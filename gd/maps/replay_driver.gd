@@ -0,0 +1,90 @@
+extends Node
+# Plays back a replay recorded by a server. Add it to the map the replay was recorded on.
+
+
+# The kinds of records returned by OwnWar_ReplayPlayer
+const KEYFRAME := 0
+const PERMANENT := 1
+const TEMPORARY := 2
+const DESTROY := 3
+
+export var path := ""
+export var speed := 1.0
+export var paused := false
+
+var player = OwnWar_ReplayPlayer.new()
+var vehicles := []
+
+
+func _ready() -> void:
+	if not player.load(path):
+		set_physics_process(false)
+
+
+func _process(delta: float) -> void:
+	for v in vehicles:
+		if v != null:
+			v.visual_step(delta)
+
+
+func _physics_process(delta: float) -> void:
+	var records := []
+	if not paused:
+		records = player.advance(delta * speed)
+	# Vehicles are shown at the time of the replay, so they follow the speed and pausing.
+	var time: float = player.get_time()
+	for v in vehicles:
+		if v != null:
+			v.set_playback_time(time)
+	apply_records(records)
+	for v in vehicles:
+		if v != null:
+			v.step(delta)
+
+
+# Jump to the given time in seconds. Vehicles are recreated from the last keyframe before it.
+func seek(time: float) -> void:
+	for i in len(vehicles):
+		if vehicles[i] != null:
+			vehicles[i].destroy()
+			vehicles[i] = null
+	apply_records(player.seek(time))
+
+
+func get_time() -> float:
+	return player.get_time()
+
+
+func get_duration() -> float:
+	return player.get_duration()
+
+
+# Feed records to the vehicles as if they were sent by the server.
+func apply_records(records: Array) -> void:
+	for r in records:
+		var id: int = r[1]
+		while len(vehicles) <= id:
+			vehicles.append(null)
+		var v = vehicles[id]
+		match r[0]:
+			KEYFRAME:
+				if v != null:
+					v.destroy()
+				vehicles[id] = null
+				v = OwnWar_Vehicle.new()
+				if v.deserialize(r[2], id, OwnWar.ENEMY_COLOR, false, false) == OK:
+					v.set_interpolation(
+						OwnWar_Settings.playout_delay, OwnWar_Settings.max_extrapolation)
+					v.set_playback_time(player.get_time())
+					vehicles[id] = v
+					v.spawn(self, false)
+			PERMANENT:
+				if v != null and v.process_permanent_packet(r[2]):
+					vehicles[id] = null
+			TEMPORARY:
+				if v != null:
+					v.process_temporary_packet(r[2])
+			DESTROY:
+				if v != null:
+					v.destroy()
+					vehicles[id] = null
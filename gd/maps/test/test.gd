@@ -100,7 +100,7 @@ func _process(delta: float) -> void:
 func _physics_process(delta: float) -> void:
 	for v in vehicles:
 		if v != null:
-			v.apply_input(0, Vector3())
+			v.apply_input(0, Vector3(), 0)
 	for i in len(vehicles):
 		var v = vehicles[i]
 		if v != null:
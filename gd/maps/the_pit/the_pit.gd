@@ -4,6 +4,11 @@ extends Node
 const TRAILER_MODE := false
 const TRAILER_FREE_CAM := false
 
+# The minimum amount of physics frames between two resyncs of a vehicle caused by desync
+# reports of a client. Vehicles send a checksum every 64 packets (`CHECKSUM_INTERVAL`), so a
+# client can't notice a desync more often anyway.
+const DESYNC_REPORT_INTERVAL := 64
+
 const AI_VEHICLES := [
 	"res://default_user_dir/vehicles/skunk.owv",
 	"res://default_user_dir/vehicles/tank.owv",
@@ -27,17 +32,32 @@ onready var hud = get_node("HUD")
 var vehicles := []
 var vehicle_data := []
 var vehicle_is_local := []
-var inputs := []
 var free_vehicle_slots := []
 var ai := []
+# Incremented whenever a vehicle is spawned in a slot, so packets meant for an earlier
+# vehicle are ignored.
+var vehicle_epochs := []
+
+# Connections carrying vehicle packets as [epoch, OwnWar_VehicleConnection]. The server has
+# one per client and vehicle, keyed by peer ID and vehicle ID, clients have one per vehicle.
+var connections := {}
+
+# When a desync report of a client was last accepted as physics frame, keyed by peer ID and
+# vehicle ID.
+var desync_reports := {}
+
+# Counter to reduce the amount of packets send
+var packet_counter = 0
 
-# Temporary vehicle data to be applied
-var pending_temporary_data := []
-# Permanent vehicle data to be applied
-var pending_permanent_data := []
+# When the first of the current players joined in milliseconds, -1 if there are no players.
+var match_start := -1
 
-# Counter to reduce the amount of temporary packets send
-var temp_packet_counter = 0
+# Records the match on the server if enabled in the settings.
+var replay = null
+var replay_path := ""
+var replay_time := 0.0
+# The vehicles as last recorded, to notice when one is spawned or removed.
+var replay_vehicles := []
 
 
 func _ready() -> void:
@@ -57,6 +77,8 @@ func _ready() -> void:
 		e = get_tree().connect("network_peer_disconnected", self, "remove_client")
 		assert(e == OK)
 		OwnWar_Lobby.register_server(self)
+		if OwnWar_Settings.record_replays:
+			start_recording()
 
 		if TRAILER_FREE_CAM:
 			var cam = FreeCamera.new()
@@ -73,6 +95,7 @@ func _ready() -> void:
 			cam.far = 2000
 		elif not headless:
 			clients[1] = null
+			update_match_start()
 			spawn_player_vehicle()
 
 		# Spawn some AI to keep the map busy even when there are no players.
@@ -94,6 +117,9 @@ func _ready() -> void:
 
 
 func _exit_tree() -> void:
+	if replay != null:
+		print("Saving replay to ", replay_path)
+		replay.finish()
 	get_tree().network_peer = null
 	get_tree().multiplayer_poll = true
 
@@ -116,29 +142,29 @@ func _physics_process(delta: float) -> void:
 		if hud.player_vehicle_id >= 0:
 			var v = vehicles[hud.player_vehicle_id]
 			if v != null:
-				rpc_unreliable("sync_client_input", v.get_controller_bitmap(), v.aim_at)
+				# The input is applied immediately and corrected when the state of the server
+				# arrives.
+				var index = v.apply_input(0, Vector3(), 0)
+				rpc_unreliable("sync_client_input", index, v.get_controller_bitmap(), v.aim_at)
 
 	# Receive client inputs _or_ receive server data
 	get_tree().multiplayer.poll()
 
 	# Apply client inputs
 	if server_mode:
-		# Apply client inputs. If this is the client, any inputs from remote vehicles
-		# will be overridden anyways, so no worries.
-		for i in len(vehicles):
-			var v = vehicles[i]
+		# Apply one of the buffered inputs of every client per step, so the state sent back
+		# matches the inputs the client predicted.
+		for v in vehicles:
 			if v != null:
-				v.apply_input(inputs[i][0], inputs[i][1])
+				v.apply_queued_input()
 
 	# Process physics & server inputs
 	if !server_mode:
 		# Process temporary data (physics, inputs)
-		for pkt in pending_temporary_data:
-			if pkt[0] < len(vehicles):
-				var v = vehicles[pkt[0]]
-				if v != null:
-					v.process_temporary_packet(pkt[1])
-		pending_temporary_data.clear()
+		for id in connections:
+			var data = connections[id][1].receive_temporary()
+			if data != null and vehicles[id] != null:
+				vehicles[id].process_temporary_packet(data)
 
 	# Apply inputs
 	for a in ai:
@@ -149,28 +175,51 @@ func _physics_process(delta: float) -> void:
 
 	# Send packets including physics, inputs & damage events
 	if server_mode:
-		# Create packets with state to be applied to the vehicles & send it to the clients.
+		if replay != null:
+			replay_time += delta
+			record_keyframes()
+		# Create packets with state to be applied to the vehicles & queue it for the clients.
 		for i in len(vehicles):
 			var v = vehicles[i]
 			if v != null:
 				var pt = v.create_packet()
-				rpc("sync_permanent_vehicle_data", i, pt[0]);
-				# Send a packet every 3 frames (20 packets/sec)
-				temp_packet_counter += 1
-				if temp_packet_counter >= 3:
-					rpc_unreliable("sync_temporary_vehicle_data", i, pt[1]);
-					temp_packet_counter = 0
+				if replay != null:
+					replay.record_permanent(replay_time, i, pt[0])
+					replay.record_temporary(replay_time, i, pt[1])
+				for peer in connections:
+					var c = connections[peer].get(i)
+					if c != null:
+						if not c[1].send_permanent(pt[0]):
+							# The client fell too far behind to be kept in sync with this
+							# connection, start over with the current state.
+							print("Client ", peer, " can't keep up with vehicle ", i)
+							resync(peer, i)
+							c = connections[peer][i]
+							var e: bool = c[1].send_permanent(pt[0])
+							assert(e)
+						c[1].send_temporary(pt[1])
+		# Send a packet every 3 frames (20 packets/sec)
+		packet_counter += 1
+		if packet_counter >= 3:
+			packet_counter = 0
+			for peer in connections:
+				for i in connections[peer]:
+					var c = connections[peer][i]
+					rpc_unreliable_id(peer, "sync_vehicle_packet", i, c[0], c[1].create_packet())
 
 	# Process damage events
 	if !server_mode:
 		# Process permanent data (damage)
-		for pkt in pending_permanent_data:
-			if pkt[0] < len(vehicles):
-				var v = vehicles[pkt[0]]
-				if v != null:
-					if v.process_permanent_packet(pkt[1]):
-						vehicles[pkt[0]] = null
-		pending_permanent_data.clear()
+		for id in connections:
+			var data = connections[id][1].receive_permanent()
+			while data != null:
+				var v = vehicles[id]
+				if v != null and v.process_permanent_packet(data):
+					vehicles[id] = null
+				data = connections[id][1].receive_permanent()
+			var vehicle = vehicles[id]
+			if vehicle != null and vehicle.take_desync():
+				rpc_id(1, "report_desync", id, connections[id][0])
 	else:
 		# Apply damage
 		for i in len(vehicles):
@@ -187,6 +236,45 @@ func _physics_process(delta: float) -> void:
 			v.step(delta)
 
 
+func start_recording() -> void:
+	var dir := Directory.new()
+	if not dir.dir_exists(OwnWar.REPLAY_DIRECTORY):
+		var e := dir.make_dir_recursive(OwnWar.REPLAY_DIRECTORY)
+		assert(e == OK)
+	var d := OS.get_datetime()
+	var file_name := "%04d-%02d-%02d_%02d-%02d-%02d.%s" % [
+		d["year"], d["month"], d["day"], d["hour"], d["minute"], d["second"],
+		OwnWar.REPLAY_EXTENSION,
+	]
+	replay_path = OwnWar.REPLAY_DIRECTORY.plus_file(file_name)
+	replay = OwnWar_ReplayRecorder.new()
+	if not replay.start(replay_path):
+		replay = null
+		return
+	replay.set_keyframe_interval(OwnWar_Settings.replay_keyframe_interval)
+	print("Recording replay to ", replay_path)
+
+
+# Record the state of vehicles that were spawned or removed since the last step, and of all
+# vehicles if a keyframe is due.
+func record_keyframes() -> void:
+	var due: bool = replay.is_keyframe_due(replay_time)
+	if due:
+		# Keep the file up to date in case the server stops unexpectedly.
+		replay.flush()
+	replay_vehicles.resize(len(vehicles))
+	for i in len(vehicles):
+		var v = vehicles[i]
+		if replay_vehicles[i] != v:
+			if replay_vehicles[i] != null:
+				replay.record_destroy(replay_time, i)
+			replay_vehicles[i] = v
+			if v != null:
+				replay.record_keyframe(replay_time, i, v.serialize())
+		elif due and v != null:
+			replay.record_keyframe(replay_time, i, v.serialize())
+
+
 # Load vehicle data from a file
 func load_vehicle_data(path):
 	var file := File.new()
@@ -240,6 +328,9 @@ func spawn_ai(path):
 func new_client(id: int) -> void:
 	print("New client ", id)
 	clients[id] = null
+	connections[id] = {}
+	desync_reports[id] = {}
+	update_match_start()
 
 
 master func request_sync_vehicles() -> void:
@@ -250,7 +341,8 @@ master func request_sync_vehicles() -> void:
 		var v = vehicles[i]
 		if v != null:
 			print("Syncing vehicle ", i)
-			rpc_id(id, "sync_vehicle", i, v.serialize(), vehicle_data[i])
+			rpc_id(id, "sync_vehicle", i, v.serialize(), vehicle_data[i], vehicle_epochs[i])
+			connect_vehicle(id, i)
 	OwnWar_NetInfo.enable_broadcast(id)
 
 
@@ -263,21 +355,48 @@ func remove_client(id) -> void:
 			rpc("free_vehicle_slot", i)
 	var e := clients.erase(id)
 	assert(e)
+	e = connections.erase(id)
+	assert(e)
+	e = desync_reports.erase(id)
+	assert(e)
+	update_match_start()
+
+
+# The pit has no rounds. The match is in progress as long as there are players and starts over
+# once everyone left.
+func update_match_start() -> void:
+	if clients.empty():
+		match_start = -1
+	elif match_start < 0:
+		match_start = OS.get_ticks_msec()
+
+
+# The status of the match reported to the lobby.
+func get_match_status() -> Dictionary:
+	if match_start < 0:
+		return { players = 0, phase = OwnWar_Lobby.MATCH_PHASE_WARMUP, elapsed = 0 }
+	return {
+		players = len(clients),
+		phase = OwnWar_Lobby.MATCH_PHASE_IN_PROGRESS,
+		elapsed = (OS.get_ticks_msec() - match_start) / 1000,
+	}
 
 
 # Sync a full vehicle's state, including destroyed blocks, on the client side
 #
 # `serialized` is the current vehicle's state
 # `data` is the "fresh" vehicle state, i.e. file data.
-puppet func sync_vehicle(id, serialized, data) -> void:
+puppet func sync_vehicle(id, serialized, data, epoch) -> void:
 	print("Synced vehicle ", id)
 	allocate_vehicle_slot(id)
+	connect_vehicle(1, id, epoch)
 	var v = OwnWar_Vehicle.new()
 	var e = v.deserialize(serialized, id, OwnWar.ENEMY_COLOR, false, false)
 	assert(e == OK)
 	vehicles[id] = v
 	vehicle_data[id] = data
 	vehicle_is_local[id] = false
+	apply_network_settings(v)
 	v.spawn(self, false)
 
 
@@ -309,11 +428,14 @@ master func request_vehicle(data: PoolByteArray, color = OwnWar.ENEMY_COLOR):
 		vehicle_is_local[vehicle_id] = id == 1
 		clients[id] = vehicle_id
 		vehicle.spawn(self, true)
+		var epoch = vehicle_epochs[vehicle_id]
 		if id != 1:
-			rpc_id(id, "accepted_vehicle", vehicle_id, transform)
-			rpc_id(-id, "sync_vehicle", vehicle_id, vehicle.serialize(), data)
+			rpc_id(id, "accepted_vehicle", vehicle_id, transform, epoch)
+			rpc_id(-id, "sync_vehicle", vehicle_id, vehicle.serialize(), data, epoch)
 		else:
-			rpc("sync_vehicle", vehicle_id, vehicle.serialize(), data)
+			rpc("sync_vehicle", vehicle_id, vehicle.serialize(), data, epoch)
+		for peer in connections:
+			connect_vehicle(peer, vehicle_id)
 
 		clients[id] = vehicle_id
 
@@ -327,9 +449,10 @@ puppet func rejected_vehicle(reason):
 
 
 # Callback executed when a client's vehicle is accepted.
-puppet func accepted_vehicle(id, transform: Transform) -> void:
+puppet func accepted_vehicle(id, transform: Transform, epoch) -> void:
 	print("Vehicle accepted, controlling ", id)
 	allocate_vehicle_slot(id)
+	connect_vehicle(1, id, epoch)
 	var v := OwnWar_Vehicle.new()
 	var team = id
 	var e = v.load_from_data(
@@ -346,12 +469,19 @@ puppet func accepted_vehicle(id, transform: Transform) -> void:
 	vehicle_data[id] = player_vehicle_data
 	vehicle_is_local[id] = true
 	hud.player_vehicle_id = id
+	apply_network_settings(v)
 	v.spawn(self, true)
 
 
 # Respawn a vehicle by recreating it from file data
-puppetsync func respawn_vehicle(id, transform):
+puppetsync func respawn_vehicle(id, transform, epoch):
 	print("Respawning ", id)
+	vehicle_epochs[id] = epoch
+	if server_mode:
+		for peer in connections:
+			connect_vehicle(peer, id)
+	else:
+		connect_vehicle(1, id, epoch)
 	var v = OwnWar_Vehicle.new()
 	var team = id
 	var e = v.load_from_data(
@@ -365,17 +495,94 @@ puppetsync func respawn_vehicle(id, transform):
 	)
 	assert(e == null)
 	vehicles[id] = v
+	apply_network_settings(v)
 	v.spawn(self, true)
 
 
-# Receive temporary from the server for a specific vehicle
-puppet func sync_temporary_vehicle_data(id, data):
-	pending_temporary_data.push_back([id, data])
+# Configure how mispredictions of the vehicle controlled by this client are corrected and
+# how the states of other vehicles are played back.
+func apply_network_settings(v) -> void:
+	v.set_correction_thresholds(
+		OwnWar_Settings.correction_min_position_error,
+		OwnWar_Settings.correction_max_position_error,
+		OwnWar_Settings.correction_min_rotation_error,
+		OwnWar_Settings.correction_max_rotation_error,
+		OwnWar_Settings.correction_blend
+	)
+	v.set_interpolation(OwnWar_Settings.playout_delay, OwnWar_Settings.max_extrapolation)
+
+
+# Start a new connection for a vehicle that was just synced. On the server `peer` is the
+# client the vehicle was synced to, on clients it is the server and `epoch` is the one sent
+# by the server.
+func connect_vehicle(peer, id, epoch = null) -> void:
+	if server_mode:
+		connections[peer][id] = [vehicle_epochs[id], OwnWar_VehicleConnection.new()]
+	else:
+		vehicle_epochs[id] = epoch
+		connections[id] = [epoch, OwnWar_VehicleConnection.new()]
+
+
+# Receive a packet from the server for a specific vehicle & acknowledge it
+puppet func sync_vehicle_packet(id, epoch, packet):
+	var c = connections.get(id)
+	if c != null and c[0] == epoch and c[1].receive(packet):
+		rpc_unreliable_id(1, "ack_vehicle_packet", id, epoch, c[1].create_packet())
+
+
+# Resynchronize a vehicle of which the damage state doesn't match that of the server. This
+# uses a new connection as the permanent data still in flight is for the old state.
+master func report_desync(id, epoch):
+	var peer = get_tree().get_rpc_sender_id()
+	var c = connections.get(peer, {}).get(id)
+	# Ignore reports for connections that were already replaced.
+	if c == null or c[0] != epoch or vehicles[id] == null:
+		return
+	# Serializing a vehicle is expensive, so don't let a client ask for it every frame.
+	var frame := Engine.get_physics_frames()
+	var reports: Dictionary = desync_reports[peer]
+	if frame - reports.get(id, -DESYNC_REPORT_INTERVAL) < DESYNC_REPORT_INTERVAL:
+		return
+	reports[id] = frame
+	print("Client ", peer, " desynced on vehicle ", id)
+	resync(peer, id)
+
+
+# Replace the connection of a client for a vehicle and send it the current state of the
+# vehicle. Packets of the old connection are ignored as they carry a different epoch.
+func resync(peer, id) -> void:
+	vehicle_epochs[id] += 1
+	connect_vehicle(peer, id)
+	rpc_id(peer, "resync_vehicle", id, vehicles[id].serialize(), vehicle_epochs[id])
+
+
+# Replace a vehicle with the state of the server after a desync.
+puppet func resync_vehicle(id, serialized, epoch) -> void:
+	print("Resyncing vehicle ", id)
+	var old = vehicles[id]
+	if old != null:
+		old.destroy()
+	connect_vehicle(1, id, epoch)
+	var is_player = hud.player_vehicle_id == id
+	var v = OwnWar_Vehicle.new()
+	var e = v.deserialize(
+		serialized,
+		id,
+		OwnWar.ALLY_COLOR if is_player else OwnWar.ENEMY_COLOR,
+		false,
+		is_player
+	)
+	assert(e == OK)
+	vehicles[id] = v
+	apply_network_settings(v)
+	v.spawn(self, false)
 
 
-# Receive permanent from the server for a specific vehicle
-puppet func sync_permanent_vehicle_data(id, data):
-	pending_permanent_data.push_back([id, data])
+# Receive the acknowledgements of a client for a specific vehicle
+master func ack_vehicle_packet(id, epoch, packet):
+	var c = connections.get(get_tree().get_rpc_sender_id(), {}).get(id)
+	if c != null and c[0] == epoch:
+		c[1].receive(packet)
 
 
 # Return a valid slot in the vehicle list.
@@ -385,8 +592,9 @@ func reserve_vehicle_slot():
 		vehicles.push_back(null)
 		vehicle_data.push_back(null)
 		vehicle_is_local.push_back(null)
-		inputs.push_back([0, Vector3()])
+		vehicle_epochs.push_back(0)
 		return len(vehicles) - 1
+	vehicle_epochs[e] += 1
 	return e
 
 
@@ -396,7 +604,7 @@ func allocate_vehicle_slot(id):
 		vehicles.resize(id + 1)
 		vehicle_data.resize(id + 1)
 		vehicle_is_local.resize(id + 1)
-		inputs.push_back([0, Vector3()])
+		vehicle_epochs.resize(id + 1)
 	assert(vehicles[id] == null, "Vehicle slot already in use")
 
 
@@ -409,16 +617,21 @@ puppetsync func free_vehicle_slot(id):
 	vehicle_data[id] = null
 	vehicle_is_local[id] = null
 	free_vehicle_slots.push_back(id)
+	if server_mode:
+		for peer in connections:
+			connections[peer].erase(id)
+	else:
+		connections.erase(id)
 
 
 # Sync client input with the server
-master func sync_client_input(bitmap, aim_at):
+master func sync_client_input(index, bitmap, aim_at):
 	var id = get_tree().get_rpc_sender_id()
 	id = clients[id]
 	if id != null:
 		var v = vehicles[id]
 		if v != null:
-			inputs[id] = [bitmap, aim_at]
+			v.queue_input(bitmap, aim_at, index)
 
 # Request the server to destroy the player's vehicle
 func request_destroy_player_vehicle():
@@ -454,7 +667,7 @@ func start_respawn(id):
 	vehicles[id] = null
 	print("Will respawn ", id)
 	yield(get_tree().create_timer(1.5), "timeout")
-	rpc("respawn_vehicle", id, get_next_spawn_point())
+	rpc("respawn_vehicle", id, get_next_spawn_point(), vehicle_epochs[id] + 1)
 
 
 # Get the next spawn point
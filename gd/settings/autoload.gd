@@ -23,6 +23,21 @@ var mouse_scroll_sensitivity := 1.0 setget set_mouse_scroll_sensitivity
 
 var selected_vehicle_path := "" setget set_selected_vehicle_path
 
+# How mispredictions of the vehicle controlled by this client are corrected. Errors are in
+# meters and radians.
+var correction_min_position_error := 0.01
+var correction_max_position_error := 2.0
+var correction_min_rotation_error := 0.01
+var correction_max_rotation_error := 0.5
+var correction_blend := 0.1
+# How many seconds the states of other vehicles are shown late and may be extrapolated.
+var playout_delay := 0.1
+var max_extrapolation := 0.25
+# Whether servers record matches to the replay directory and how many seconds there are
+# between keyframes, which limits how much has to be replayed when seeking.
+var record_replays := false
+var replay_keyframe_interval := 10.0
+
 var dirty := false
 
 
@@ -195,6 +210,17 @@ func save_settings() -> void:
 	cf.set_value("mouse", "move_sensitivity", mouse_move_sensitivity)
 	cf.set_value("mouse", "scroll_sensitivity", mouse_scroll_sensitivity)
 
+	cf.set_value("network", "min_position_error", correction_min_position_error)
+	cf.set_value("network", "max_position_error", correction_max_position_error)
+	cf.set_value("network", "min_rotation_error", correction_min_rotation_error)
+	cf.set_value("network", "max_rotation_error", correction_max_rotation_error)
+	cf.set_value("network", "correction_blend", correction_blend)
+	cf.set_value("network", "playout_delay", playout_delay)
+	cf.set_value("network", "max_extrapolation", max_extrapolation)
+
+	cf.set_value("replay", "record", record_replays)
+	cf.set_value("replay", "keyframe_interval", replay_keyframe_interval)
+
 	var e := cf.save(get_settings_file())
 	if e != OK:
 		print("Failed to save custom settings: %s" % Global.ERROR_TO_STRING[e])
@@ -275,6 +301,19 @@ func load_settings() -> void:
 				mouse_move_sensitivity = cf.get_value("mouse", "move_sensitivity", 1.0)
 				mouse_scroll_sensitivity = cf.get_value("mouse", "scroll_sensitivity", 1.0)
 
+			if cf.has_section("network"):
+				correction_min_position_error = cf.get_value("network", "min_position_error", 0.01)
+				correction_max_position_error = cf.get_value("network", "max_position_error", 2.0)
+				correction_min_rotation_error = cf.get_value("network", "min_rotation_error", 0.01)
+				correction_max_rotation_error = cf.get_value("network", "max_rotation_error", 0.5)
+				correction_blend = cf.get_value("network", "correction_blend", 0.1)
+				playout_delay = cf.get_value("network", "playout_delay", 0.1)
+				max_extrapolation = cf.get_value("network", "max_extrapolation", 0.25)
+
+			if cf.has_section("replay"):
+				record_replays = cf.get_value("replay", "record", false)
+				replay_keyframe_interval = cf.get_value("replay", "keyframe_interval", 10.0)
+
 			var root := get_tree().root
 			root.msaa = ProjectSettings.get_setting("rendering/quality/filters/msaa")
 
@@ -11,6 +11,8 @@ export var _filter := NodePath()
 export var _list := NodePath()
 export var _map := NodePath()
 export var _max_players := NodePath()
+export var _players := NodePath()
+export var _match := NodePath()
 export var _description := NodePath()
 export var _status := NodePath()
 
@@ -23,6 +25,8 @@ onready var filter: LineEdit = get_node(_filter)
 onready var list: Control = get_node(_list)
 onready var map: Label = get_node(_map)
 onready var max_players: Label = get_node(_max_players)
+onready var players: Label = get_node(_players)
+onready var match_status: Label = get_node(_match)
 onready var description: Label = get_node(_description)
 onready var status: Status = get_node(_status)
 
@@ -30,20 +34,17 @@ onready var status: Status = get_node(_status)
 func _ready() -> void:
 	var e := OwnWar_Lobby.connect("server_list", self, "generate_list")
 	assert(e == OK)
-	e = OwnWar_Lobby.connect("server_info", self, "set_info")
+	e = filter.connect("text_entered", self, "refresh")
 	assert(e == OK)
 
 	while true:
-		if OwnWar_Lobby.got_server_list:
-			OwnWar_Lobby.get_server_list()
-			status.set_status(Status.STATUS_NONE, "Getting server list...", loading_icon, true)
+		refresh()
 		_timer = get_tree().create_timer(10.0)
 		yield(_timer, "timeout")
 
 
 func _exit_tree() -> void:
 	OwnWar_Lobby.disconnect("server_list", self, "generate_list")
-	OwnWar_Lobby.disconnect("server_info", self, "set_info")
 	for sig in _timer.get_signal_connection_list("timeout"):
 		_timer.disconnect(sig["signal"], sig["target"], sig["method"])
 
@@ -70,6 +71,13 @@ func launch() -> void:
 	status.set_status(Status.STATUS_NONE, "Connecting...", connecting_icon, true)
 
 
+# Get the servers whose name contains the text of the filter.
+func refresh(_text := "") -> void:
+	if OwnWar_Lobby.got_server_list:
+		OwnWar_Lobby.get_server_list(filter.text)
+		status.set_status(Status.STATUS_NONE, "Getting server list...", loading_icon, true)
+
+
 func sort_list(a, b) -> bool:
 	return a.name < b.name
 
@@ -79,25 +87,28 @@ func generate_list(entries: Array) -> void:
 	entries.sort_custom(self, "sort_list")
 	for entry in entries:
 		var btn := Button.new()
-		btn.text = entry.name
+		btn.text = "%s (%d/%d)" % [entry.name, entry.players, entry.max_players]
 		btn.group = _list_button_group
 		btn.toggle_mode = true
-		var e := btn.connect("pressed", OwnWar_Lobby, "get_server_info", [entry])
+		var e := btn.connect("pressed", self, "set", ["selected_entry", entry])
 		assert(e == OK)
-		e = btn.connect("pressed", self, "set", ["selected_entry", entry])
+		e = btn.connect("pressed", self, "set_info", [entry])
 		assert(e == OK)
-		e = btn.connect("pressed", status, "set_status", [
-			Status.STATUS_NONE,
-			"Getting server info...",
-			loading_icon,
-			true,
-		])
 		list.add_child(btn)
 	status.set_status(Status.STATUS_OK, "Received server list", null)
 
 
-func set_info(info: OwnWar_Lobby.ServerInfo) -> void:
-	map.text = info.map.get_file().get_basename()
-	description.text = info.description
-	max_players.text = str(info.max_players)
-	status.set_status(Status.STATUS_OK, "Received server info", null)
+func set_info(entry: OwnWar_Lobby.Entry) -> void:
+	map.text = entry.map.get_file().get_basename()
+	description.text = entry.description
+	max_players.text = str(entry.max_players)
+	players.text = str(entry.players)
+	match entry.phase:
+		OwnWar_Lobby.MATCH_PHASE_WARMUP:
+			match_status.text = "Warmup"
+		OwnWar_Lobby.MATCH_PHASE_IN_PROGRESS:
+			match_status.text = "In progress (%d:%02d)" % [entry.elapsed / 60, entry.elapsed % 60]
+		OwnWar_Lobby.MATCH_PHASE_ENDING:
+			match_status.text = "Ending"
+		_:
+			match_status.text = ""
@@ -0,0 +1,77 @@
+//! Checksums to detect when the state of a vehicle differs between the server and a client.
+//!
+//! Damage is applied deterministically on both sides, so the state should never differ.
+//! Bugs happen though, and comparing a checksum now and then is much cheaper than sending
+//! the full state.
+
+/// A 64 bit FNV-1a hash. It is fast and good enough to detect accidental differences, but
+/// it is not meant to withstand deliberate collisions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checksum(u64);
+
+const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+impl Checksum {
+	pub const fn new() -> Self {
+		Self(OFFSET_BASIS)
+	}
+
+	pub fn write(&mut self, data: &[u8]) {
+		for &b in data {
+			self.0 ^= u64::from(b);
+			self.0 = self.0.wrapping_mul(PRIME);
+		}
+	}
+
+	pub fn write_u16(&mut self, n: u16) {
+		self.write(&n.to_le_bytes());
+	}
+
+	pub fn write_u32(&mut self, n: u32) {
+		self.write(&n.to_le_bytes());
+	}
+
+	pub const fn get(&self) -> u64 {
+		self.0
+	}
+}
+
+impl Default for Checksum {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn checksum(data: &[u8]) -> u64 {
+		let mut c = Checksum::new();
+		c.write(data);
+		c.get()
+	}
+
+	#[test]
+	fn fnv1a() {
+		assert_eq!(checksum(b""), 0xcbf2_9ce4_8422_2325);
+		assert_eq!(checksum(b"a"), 0xaf63_dc4c_8601_ec8c);
+		assert_eq!(checksum(b"foobar"), 0x8594_4171_f739_67e8);
+	}
+
+	#[test]
+	fn order() {
+		let (mut a, mut b) = (Checksum::new(), Checksum::new());
+		a.write_u16(1);
+		a.write_u32(2);
+		b.write_u32(2);
+		b.write_u16(1);
+		assert_ne!(a, b);
+		// Writing in parts is the same as writing everything at once.
+		let mut c = Checksum::new();
+		c.write(&[1, 0]);
+		c.write(&[2, 0, 0, 0]);
+		assert_eq!(a, c);
+	}
+}
@@ -0,0 +1,643 @@
+//! A connection carrying vehicle data over an unreliable transport like UDP.
+//!
+//! Every packet has a sequence number and acknowledges the packets received from the other
+//! side with the newest sequence number and a bitfield of the packets before it. On top of
+//! that a [`Connection`] has two channels:
+//!
+//! * The permanent channel delivers every message exactly once and in order. A message is
+//!   resent until a packet carrying it is acknowledged.
+//! * The temporary channel only delivers the newest message. Messages arriving after a newer
+//!   one are dropped and lost messages are never resent.
+//!
+//! A packet looks like this, with all integers in little endian:
+//!
+//! ```text
+//! [sequence u16][ack u16][ack bits u32][message count u8]
+//! ([message ID u16][length u16][data]) for every permanent message
+//! ([baseline u8][length u16][delta]) if there is a temporary message
+//! ```
+//!
+//! Bit `n` of the ack bits is set if packet `ack - n` was received, so an ack without any
+//! bits set means nothing was received yet.
+//!
+//! Temporary messages are sent as the difference to the newest temporary message the other
+//! side acknowledged, which was sent `baseline` packets earlier. Successive messages like
+//! the state of a vehicle mostly differ in a few bytes, so this makes them a lot smaller. A
+//! baseline of 0 means the message is sent as a whole. The delta is the message XORed with
+//! the baseline, with runs of zeroes compressed: a byte `n` below 128 is followed by `n + 1`
+//! literal bytes and any other byte stands for `n - 127` zeroes.
+
+use crate::newer;
+use crate::packet::{put_bytes16, Decoder, ProcessPacketError};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// The maximum size of a packet.
+pub const MAX_PACKET_SIZE: usize = 1200;
+
+/// The maximum size of a single message, so a permanent message always fits in a packet.
+pub const MAX_MESSAGE_SIZE: usize = 1024;
+
+/// The maximum amount of permanent messages that haven't been acknowledged yet. This is
+/// also the window in which the receiver accepts out of order messages.
+pub const MAX_QUEUED: usize = 1024;
+
+const HEADER_SIZE: usize = 2 + 2 + 4 + 1;
+
+/// The amount of sent packets to remember. Older packets can't be acknowledged anymore as
+/// they don't fit in the ack bits, so their messages are resent when they time out.
+const MAX_SENT: usize = 64;
+
+/// The round trip time assumed before any packet is acknowledged.
+const INITIAL_RTT: Duration = Duration::from_millis(100);
+
+/// The minimum time to wait before resending a permanent message.
+const MIN_RESEND_DELAY: Duration = Duration::from_millis(30);
+
+/// The maximum amount of packets between a temporary message and its baseline.
+const MAX_BASELINE_AGE: u16 = 32;
+
+/// The amount of packets the receiver keeps temporary messages for, to decode later ones.
+const MAX_BASELINES: u16 = 2 * MAX_BASELINE_AGE;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SendError {
+	/// The message is larger than [`MAX_MESSAGE_SIZE`].
+	TooLarge,
+	/// [`MAX_QUEUED`] permanent messages are waiting for an acknowledgement.
+	QueueFull,
+}
+
+impl fmt::Display for SendError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::TooLarge => "message too large".fmt(f),
+			Self::QueueFull => "too many unacknowledged messages".fmt(f),
+		}
+	}
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReceiveError {
+	Decode(ProcessPacketError),
+	/// A temporary message decodes to more than [`MAX_MESSAGE_SIZE`] bytes.
+	TooLarge,
+}
+
+impl fmt::Display for ReceiveError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Decode(e) => e.fmt(f),
+			Self::TooLarge => "message too large".fmt(f),
+		}
+	}
+}
+
+impl From<ProcessPacketError> for ReceiveError {
+	fn from(e: ProcessPacketError) -> Self {
+		Self::Decode(e)
+	}
+}
+
+/// A permanent message that hasn't been acknowledged yet.
+struct Outgoing {
+	id: u16,
+	data: Box<[u8]>,
+	last_sent: Option<Instant>,
+}
+
+/// A packet that hasn't been acknowledged yet.
+struct Sent {
+	sequence: u16,
+	time: Instant,
+	/// The IDs of the permanent messages in the packet.
+	messages: Vec<u16>,
+}
+
+pub struct Connection {
+	/// The sequence number of the next packet.
+	sequence: u16,
+	sent: VecDeque<Sent>,
+	/// The newest sequence number received.
+	remote_sequence: u16,
+	/// Bit `n` is set if packet `remote_sequence - n` was received.
+	received: u32,
+	/// The ID of the next permanent message to send.
+	next_outgoing: u16,
+	outgoing: VecDeque<Outgoing>,
+	/// The ID of the next permanent message to deliver.
+	next_incoming: u16,
+	incoming: HashMap<u16, Box<[u8]>>,
+	/// The temporary message to send with the next packet.
+	temporary: Option<Box<[u8]>>,
+	/// Recently sent temporary messages by the sequence number of their packet.
+	sent_temporary: VecDeque<(u16, Box<[u8]>)>,
+	/// The newest temporary message the other side acknowledged.
+	baseline: Option<(u16, Box<[u8]>)>,
+	/// The newest temporary message received and the sequence number of its packet.
+	incoming_temporary: Option<Box<[u8]>>,
+	temporary_sequence: Option<u16>,
+	/// Recently received temporary messages, which may be the baseline of later ones.
+	received_temporary: VecDeque<(u16, Box<[u8]>)>,
+	rtt: Duration,
+}
+
+impl Connection {
+	pub fn new() -> Self {
+		Self {
+			sequence: 0,
+			sent: VecDeque::new(),
+			remote_sequence: 0,
+			received: 0,
+			next_outgoing: 0,
+			outgoing: VecDeque::new(),
+			next_incoming: 0,
+			incoming: HashMap::new(),
+			temporary: None,
+			sent_temporary: VecDeque::new(),
+			baseline: None,
+			incoming_temporary: None,
+			temporary_sequence: None,
+			received_temporary: VecDeque::new(),
+			rtt: INITIAL_RTT,
+		}
+	}
+
+	/// Queue a message that must arrive in order.
+	pub fn send_permanent(&mut self, data: &[u8]) -> Result<(), SendError> {
+		if data.len() > MAX_MESSAGE_SIZE {
+			return Err(SendError::TooLarge);
+		}
+		if self.outgoing.len() >= MAX_QUEUED {
+			return Err(SendError::QueueFull);
+		}
+		self.outgoing.push_back(Outgoing {
+			id: self.next_outgoing,
+			data: data.into(),
+			last_sent: None,
+		});
+		self.next_outgoing = self.next_outgoing.wrapping_add(1);
+		Ok(())
+	}
+
+	/// Set the message to send with the next packet, replacing one that hasn't been sent yet.
+	pub fn send_temporary(&mut self, data: &[u8]) -> Result<(), SendError> {
+		if data.len() > MAX_MESSAGE_SIZE {
+			return Err(SendError::TooLarge);
+		}
+		self.temporary = Some(data.into());
+		Ok(())
+	}
+
+	/// Create the next packet. It includes the pending temporary message and the permanent
+	/// messages that weren't sent yet or timed out, as far as they fit.
+	///
+	/// A packet should be sent regularly even if there is no data, as it acknowledges the
+	/// packets of the other side.
+	pub fn create_packet(&mut self, now: Instant) -> Vec<u8> {
+		let mut packet = Vec::with_capacity(MAX_PACKET_SIZE);
+		packet.extend(&self.sequence.to_le_bytes());
+		packet.extend(&self.remote_sequence.to_le_bytes());
+		packet.extend(&self.received.to_le_bytes());
+		packet.push(0);
+
+		let sequence = self.sequence;
+		let temporary = self.temporary.as_ref().map(|data| match &self.baseline {
+			Some((s, b)) if sequence.wrapping_sub(*s) <= MAX_BASELINE_AGE => {
+				(sequence.wrapping_sub(*s) as u8, encode_delta(data, b))
+			}
+			_ => (0, encode_delta(data, &[])),
+		});
+
+		// Leave room for the temporary message, unless not even one permanent message would
+		// fit then.
+		let reserved = temporary
+			.as_ref()
+			.map_or(0, |(_, delta)| 1 + 2 + delta.len());
+		let resend_delay = self.resend_delay();
+		let mut messages = Vec::new();
+		for msg in self.outgoing.iter_mut() {
+			match msg.last_sent {
+				Some(t) if now < t + resend_delay => continue,
+				_ => (),
+			}
+			let size = 2 + 2 + msg.data.len();
+			let full = packet.len() + size + reserved > MAX_PACKET_SIZE;
+			if messages.len() == usize::from(u8::MAX) || (full && !messages.is_empty()) {
+				break;
+			}
+			packet.extend(&msg.id.to_le_bytes());
+			put_bytes16(&mut packet, &msg.data);
+			msg.last_sent = Some(now);
+			messages.push(msg.id);
+		}
+		packet[HEADER_SIZE - 1] = messages.len() as u8;
+
+		match temporary {
+			Some((baseline, delta)) if packet.len() + reserved <= MAX_PACKET_SIZE => {
+				packet.push(baseline);
+				put_bytes16(&mut packet, &delta);
+				let data = self.temporary.take().unwrap();
+				self.sent_temporary.push_back((sequence, data));
+				if self.sent_temporary.len() > usize::from(MAX_BASELINE_AGE) {
+					self.sent_temporary.pop_front();
+				}
+			}
+			_ => (),
+		}
+
+		self.sent.push_back(Sent {
+			sequence: self.sequence,
+			time: now,
+			messages,
+		});
+		if self.sent.len() > MAX_SENT {
+			self.sent.pop_front();
+		}
+		self.sequence = self.sequence.wrapping_add(1);
+		packet
+	}
+
+	/// Process a packet of the other side. Invalid packets are rejected as a whole.
+	pub fn receive(&mut self, packet: &[u8], now: Instant) -> Result<(), ReceiveError> {
+		let mut r = Decoder::new(packet);
+		let (sequence, ack, ack_bits) = (r.u16()?, r.u16()?, r.u32()?);
+		let mut messages = Vec::new();
+		for _ in 0..r.u8()? {
+			messages.push((r.u16()?, r.bytes16()?));
+		}
+		let temporary = if r.is_empty() {
+			None
+		} else {
+			Some((r.u8()?, r.bytes16()?))
+		};
+		r.finish()?;
+		// If the baseline is unknown the message can't be decoded, but as a newer message
+		// will arrive soon it can simply be dropped.
+		let temporary = match temporary {
+			Some((0, delta)) => Some(decode_delta(delta, &[])?),
+			Some((baseline, delta)) => {
+				let baseline = sequence.wrapping_sub(baseline.into());
+				match self.received_temporary.iter().find(|(s, _)| *s == baseline) {
+					Some((_, b)) => Some(decode_delta(delta, b)?),
+					None => None,
+				}
+			}
+			None => None,
+		};
+
+		self.acknowledge(ack, ack_bits, now);
+
+		let offset = self.remote_sequence.wrapping_sub(sequence);
+		if self.received == 0 || newer(sequence, self.remote_sequence) {
+			let shift = u32::from(sequence.wrapping_sub(self.remote_sequence));
+			self.received = self.received.checked_shl(shift).unwrap_or(0) | 1;
+			self.remote_sequence = sequence;
+		} else if offset < 32 {
+			self.received |= 1 << offset;
+		}
+
+		for (id, data) in messages {
+			if usize::from(id.wrapping_sub(self.next_incoming)) < MAX_QUEUED {
+				self.incoming.entry(id).or_insert_with(|| data.into());
+			}
+		}
+
+		if let Some(data) = temporary {
+			// Late messages are still kept as the other side may use them as a baseline once
+			// they are acknowledged.
+			if !self.received_temporary.iter().any(|(s, _)| *s == sequence) {
+				self.received_temporary.push_back((sequence, data.clone()));
+			}
+			let remote_sequence = self.remote_sequence;
+			self.received_temporary
+				.retain(|(s, _)| remote_sequence.wrapping_sub(*s) < MAX_BASELINES);
+
+			let old = matches!(self.temporary_sequence, Some(s) if !newer(sequence, s));
+			if !old {
+				self.temporary_sequence = Some(sequence);
+				self.incoming_temporary = Some(data);
+			}
+		}
+		Ok(())
+	}
+
+	/// Forget the packets that were acknowledged and the messages they carried, and use the
+	/// newest temporary message as the baseline.
+	fn acknowledge(&mut self, ack: u16, ack_bits: u32, now: Instant) {
+		let is_acked = |sequence: u16| {
+			let offset = ack.wrapping_sub(sequence);
+			offset < 32 && ack_bits & (1 << offset) != 0
+		};
+		let mut acked = Vec::new();
+		for sent in self.sent.iter().filter(|s| is_acked(s.sequence)) {
+			// Older packets were acknowledged late if they were acknowledged only now.
+			if sent.sequence == ack {
+				let sample = now.saturating_duration_since(sent.time);
+				self.rtt = (self.rtt * 7 + sample) / 8;
+			}
+			acked.extend(&sent.messages);
+		}
+		self.sent.retain(|s| !is_acked(s.sequence));
+		self.outgoing.retain(|m| !acked.contains(&m.id));
+
+		let newest = self.sent_temporary.iter().rev().find(|(s, _)| is_acked(*s));
+		if let Some((s, data)) = newest {
+			if !matches!(self.baseline, Some((b, _)) if !newer(*s, b)) {
+				self.baseline = Some((*s, data.clone()));
+			}
+		}
+	}
+
+	/// The next permanent message, if it arrived.
+	pub fn receive_permanent(&mut self) -> Option<Box<[u8]>> {
+		let data = self.incoming.remove(&self.next_incoming)?;
+		self.next_incoming = self.next_incoming.wrapping_add(1);
+		Some(data)
+	}
+
+	/// The newest temporary message, if one arrived since the last call.
+	pub fn receive_temporary(&mut self) -> Option<Box<[u8]>> {
+		self.incoming_temporary.take()
+	}
+
+	/// The amount of permanent messages that haven't been acknowledged yet.
+	pub fn pending(&self) -> usize {
+		self.outgoing.len()
+	}
+
+	/// The smoothed round trip time.
+	pub fn rtt(&self) -> Duration {
+		self.rtt
+	}
+
+	fn resend_delay(&self) -> Duration {
+		(self.rtt * 3 / 2).max(MIN_RESEND_DELAY)
+	}
+}
+
+impl Default for Connection {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// XOR `data` with `baseline` and compress the runs of zeroes.
+fn encode_delta(data: &[u8], baseline: &[u8]) -> Vec<u8> {
+	let xor = data
+		.iter()
+		.enumerate()
+		.map(|(i, b)| b ^ baseline.get(i).unwrap_or(&0))
+		.collect::<Vec<_>>();
+	let mut delta = Vec::with_capacity(xor.len() + 1);
+	let mut i = 0;
+	while i < xor.len() {
+		// Single zeroes between other bytes are cheaper as literals.
+		let zeroes = xor[i..].iter().take(128).take_while(|b| **b == 0).count();
+		if zeroes >= 2 || (zeroes == 1 && i + 1 == xor.len()) {
+			delta.push(127 + zeroes as u8);
+			i += zeroes;
+			continue;
+		}
+		let start = i;
+		while i < xor.len() && i - start < 128 && xor[i..].get(..2) != Some(&[0, 0]) {
+			i += 1;
+		}
+		delta.push((i - start - 1) as u8);
+		delta.extend(&xor[start..i]);
+	}
+	delta
+}
+
+/// The reverse of [`encode_delta`].
+fn decode_delta(delta: &[u8], baseline: &[u8]) -> Result<Box<[u8]>, ReceiveError> {
+	let mut r = Decoder::new(delta);
+	let mut data = Vec::new();
+	while !r.is_empty() {
+		match r.u8()? {
+			n if n < 128 => data.extend(r.bytes(usize::from(n) + 1)?),
+			n => data.resize(data.len() + usize::from(n) - 127, 0),
+		}
+		if data.len() > MAX_MESSAGE_SIZE {
+			return Err(ReceiveError::TooLarge);
+		}
+	}
+	for (d, b) in data.iter_mut().zip(baseline) {
+		*d ^= b;
+	}
+	Ok(data.into())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::convert::TryFrom;
+
+	/// A one way link that loses, duplicates and reorders packets.
+	struct Link {
+		rng: u64,
+		/// The chances of a packet being lost or duplicated, in percent.
+		loss: u64,
+		duplicate: u64,
+		latency: Duration,
+		/// The maximum random delay added to the latency. Packets are reordered if this is
+		/// larger than the time between packets.
+		jitter: Duration,
+		in_flight: Vec<(Instant, Vec<u8>)>,
+	}
+
+	impl Link {
+		fn new(seed: u64) -> Self {
+			Self {
+				rng: seed,
+				loss: 20,
+				duplicate: 10,
+				latency: Duration::from_millis(30),
+				jitter: Duration::from_millis(80),
+				in_flight: Vec::new(),
+			}
+		}
+
+		/// xorshift64
+		fn random(&mut self, n: u64) -> u64 {
+			self.rng ^= self.rng << 13;
+			self.rng ^= self.rng >> 7;
+			self.rng ^= self.rng << 17;
+			self.rng % n
+		}
+
+		fn send(&mut self, packet: Vec<u8>, now: Instant) {
+			if self.random(100) < self.loss {
+				return;
+			}
+			let copies = if self.random(100) < self.duplicate {
+				2
+			} else {
+				1
+			};
+			for _ in 0..copies {
+				let jitter = self.jitter * self.random(1000) as u32 / 1000;
+				let arrival = now + self.latency + jitter;
+				self.in_flight.push((arrival, packet.clone()));
+			}
+		}
+
+		/// Take the packets that arrived, in the order they arrived.
+		fn deliver(&mut self, now: Instant) -> Vec<Vec<u8>> {
+			self.in_flight.sort_by_key(|(t, _)| *t);
+			let n = self.in_flight.iter().take_while(|(t, _)| *t <= now).count();
+			self.in_flight.drain(..n).map(|(_, p)| p).collect()
+		}
+	}
+
+	#[test]
+	fn lossy_link() {
+		let start = Instant::now();
+		let tick = Duration::from_millis(16);
+		let (mut a, mut b) = (Connection::new(), Connection::new());
+		let (mut ab, mut ba) = (Link::new(0x1234_5678), Link::new(0x9abc_def0));
+		let (mut permanent, mut temporary) = (Vec::new(), Vec::new());
+
+		for i in 0..2000u32 {
+			let now = start + tick * i;
+			if i < 1000 {
+				a.send_permanent(&i.to_le_bytes()).unwrap();
+				a.send_temporary(&i.to_le_bytes()).unwrap();
+			}
+			if i % 3 == 0 {
+				ab.send(a.create_packet(now), now);
+				ba.send(b.create_packet(now), now);
+			}
+			for p in ab.deliver(now) {
+				b.receive(&p, now).unwrap();
+			}
+			for p in ba.deliver(now) {
+				a.receive(&p, now).unwrap();
+			}
+			while let Some(data) = b.receive_permanent() {
+				permanent.push(u32::from_le_bytes(<[u8; 4]>::try_from(&*data).unwrap()));
+			}
+			if let Some(data) = b.receive_temporary() {
+				temporary.push(u32::from_le_bytes(<[u8; 4]>::try_from(&*data).unwrap()));
+			}
+		}
+
+		assert_eq!(permanent, (0..1000).collect::<Vec<_>>());
+		assert_eq!(a.pending(), 0);
+		// Temporary messages are only delivered if nothing newer arrived before.
+		assert!(temporary.windows(2).all(|w| w[0] < w[1]));
+		assert_eq!(temporary.last(), Some(&999));
+		assert!(temporary.len() < 1000 / 3);
+		assert!(temporary.len() > 1000 / 3 / 2);
+		assert!(a.rtt() > Duration::from_millis(60) && a.rtt() < Duration::from_millis(250));
+	}
+
+	#[test]
+	fn reorder_and_acknowledge() {
+		let now = Instant::now();
+		let (mut a, mut b) = (Connection::new(), Connection::new());
+		a.send_permanent(&[1]).unwrap();
+		a.send_temporary(&[1]).unwrap();
+		let first = a.create_packet(now);
+		assert_eq!(
+			first,
+			[0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 0, 1, 0, 2, 0, 0, 1]
+		);
+		a.send_permanent(&[2]).unwrap();
+		a.send_temporary(&[2]).unwrap();
+		let second = a.create_packet(now);
+
+		b.receive(&second, now).unwrap();
+		assert_eq!(b.receive_permanent(), None);
+		b.receive(&first, now).unwrap();
+		assert_eq!(b.receive_permanent().as_deref(), Some(&[1][..]));
+		assert_eq!(b.receive_permanent().as_deref(), Some(&[2][..]));
+		// The late first packet doesn't overwrite the newer temporary message.
+		assert_eq!(b.receive_temporary().as_deref(), Some(&[2][..]));
+		b.receive(&first, now).unwrap();
+		assert_eq!(b.receive_permanent(), None);
+		assert_eq!(b.receive_temporary(), None);
+
+		// Nothing is resent before the timeout and everything is forgotten once acknowledged.
+		assert_eq!(a.create_packet(now).len(), HEADER_SIZE);
+		assert_eq!(a.pending(), 2);
+		a.receive(&b.create_packet(now), now).unwrap();
+		assert_eq!(a.pending(), 0);
+		let later = now + Duration::from_secs(1);
+		assert_eq!(a.create_packet(later).len(), HEADER_SIZE);
+	}
+
+	#[test]
+	fn limits() {
+		let now = Instant::now();
+		let mut c = Connection::new();
+		let big = [0; MAX_MESSAGE_SIZE + 1];
+		assert_eq!(c.send_permanent(&big), Err(SendError::TooLarge));
+		assert_eq!(c.send_temporary(&big), Err(SendError::TooLarge));
+		for _ in 0..MAX_QUEUED {
+			c.send_permanent(&big[..MAX_MESSAGE_SIZE]).unwrap();
+		}
+		assert_eq!(c.send_permanent(&[]), Err(SendError::QueueFull));
+
+		// A full message doesn't leave room for a temporary message, which is sent later.
+		c.send_temporary(&[0x55; MAX_MESSAGE_SIZE]).unwrap();
+		let packet = c.create_packet(now);
+		assert_eq!(packet.len(), HEADER_SIZE + 4 + MAX_MESSAGE_SIZE);
+		assert!(c.temporary.is_some());
+
+		let mut other = Connection::new();
+		assert_eq!(
+			other.receive(&packet[..packet.len() - 1], now),
+			Err(ReceiveError::Decode(ProcessPacketError::Truncated))
+		);
+		assert_eq!(other.receive_permanent(), None);
+		let mut trailing = c.create_packet(now);
+		trailing.extend(&[0, 0, 0, 0]);
+		assert_eq!(
+			other.receive(&trailing, now),
+			Err(ReceiveError::Decode(ProcessPacketError::TrailingData))
+		);
+		let mut large = c.create_packet(now);
+		large.extend(&[0, 9, 0]);
+		large.extend(&[255; 9]);
+		assert_eq!(other.receive(&large, now), Err(ReceiveError::TooLarge));
+	}
+
+	#[test]
+	fn delta() {
+		let now = Instant::now();
+		let (mut a, mut b) = (Connection::new(), Connection::new());
+		let mut state = [7; 100];
+		a.send_temporary(&state).unwrap();
+		let full = a.create_packet(now);
+		assert_eq!(full.len(), HEADER_SIZE + 1 + 2 + 1 + 100);
+		b.receive(&full, now).unwrap();
+		assert_eq!(b.receive_temporary().as_deref(), Some(&state[..]));
+
+		// Without an acknowledgement there is no baseline yet.
+		state[50] = 8;
+		a.send_temporary(&state).unwrap();
+		let lost = a.create_packet(now);
+		assert_eq!(lost.len(), full.len());
+
+		a.receive(&b.create_packet(now), now).unwrap();
+		state[60] = 9;
+		a.send_temporary(&state).unwrap();
+		let delta = a.create_packet(now);
+		// Sent two packets after the baseline, as 50 zeroes, 1 byte, 9 zeroes, 1 byte and 39
+		// zeroes.
+		assert_eq!(
+			delta[HEADER_SIZE..],
+			[2, 7, 0, 127 + 50, 0, 15, 127 + 9, 0, 14, 127 + 39]
+		);
+		b.receive(&delta, now).unwrap();
+		assert_eq!(b.receive_temporary().as_deref(), Some(&state[..]));
+
+		// Messages may be shorter or longer than their baseline.
+		for data in [&[][..], &[0, 7, 0, 0, 1], &[0; 300]] {
+			let delta = encode_delta(data, &state);
+			assert_eq!(&*decode_delta(&delta, &state).unwrap(), data);
+		}
+	}
+}
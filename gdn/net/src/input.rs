@@ -0,0 +1,104 @@
+//! Buffering of the inputs of a client on the server.
+//!
+//! Clients send one input per step over an unreliable channel, so inputs arrive in bursts,
+//! out of order or not at all. The server applies exactly one input per step in the order
+//! of their indices, which is the order the client predicted them in. If no input arrived in
+//! time the previous input stays in effect.
+
+use crate::newer;
+use std::collections::VecDeque;
+
+/// The maximum amount of inputs to buffer. If a client gets further ahead than this, e.g.
+/// after a lag spike, the oldest inputs are dropped so its inputs don't lag behind forever.
+pub const MAX_QUEUED_INPUTS: usize = 8;
+
+/// The inputs received from a client that haven't been applied yet.
+pub struct InputQueue<I> {
+	/// Sorted by index, oldest first.
+	queue: VecDeque<(u16, I)>,
+	/// The index of the last input taken, older inputs are dropped.
+	last: Option<u16>,
+}
+
+impl<I> InputQueue<I> {
+	pub fn new() -> Self {
+		Self {
+			queue: VecDeque::new(),
+			last: None,
+		}
+	}
+
+	/// Add an input the client sent along with the given index. Inputs that were already
+	/// taken or received are ignored.
+	pub fn push(&mut self, index: u16, input: I) {
+		if self.last.is_some_and(|last| !newer(index, last)) {
+			return;
+		}
+		let at = self.queue.iter().rposition(|(i, _)| !newer(*i, index));
+		let at = match at {
+			Some(at) if self.queue[at].0 == index => return,
+			Some(at) => at + 1,
+			None => 0,
+		};
+		self.queue.insert(at, (index, input));
+		if self.queue.len() > MAX_QUEUED_INPUTS {
+			self.queue.pop_front();
+		}
+	}
+
+	/// Take the oldest input to apply this step, if any.
+	pub fn pop(&mut self) -> Option<(u16, I)> {
+		let (index, input) = self.queue.pop_front()?;
+		self.last = Some(index);
+		Some((index, input))
+	}
+}
+
+impl<I> Default for InputQueue<I> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn one_per_step_in_order() {
+		let mut q = InputQueue::new();
+		assert_eq!(q.pop(), None);
+		for i in [2, 0, 1, 1] {
+			q.push(i, i);
+		}
+		assert_eq!(q.pop(), Some((0, 0)));
+		// Older than the input that was taken already
+		q.push(0, 0);
+		q.push(3, 3);
+		assert_eq!(q.pop(), Some((1, 1)));
+		assert_eq!(q.pop(), Some((2, 2)));
+		assert_eq!(q.pop(), Some((3, 3)));
+		assert_eq!(q.pop(), None);
+
+		// Indices wrap around
+		let mut q = InputQueue::new();
+		for i in [0, u16::MAX, 1] {
+			q.push(i, i);
+		}
+		assert_eq!(q.pop(), Some((u16::MAX, u16::MAX)));
+		assert_eq!(q.pop(), Some((0, 0)));
+		assert_eq!(q.pop(), Some((1, 1)));
+	}
+
+	#[test]
+	fn drop_oldest() {
+		let mut q = InputQueue::new();
+		for i in 0..20 {
+			q.push(i, ());
+		}
+		let first = 20 - MAX_QUEUED_INPUTS as u16;
+		assert_eq!(q.pop(), Some((first, ())));
+		q.push(0, ());
+		assert_eq!(q.pop(), Some((first + 1, ())));
+	}
+}
@@ -0,0 +1,29 @@
+//! Networking of vehicles between game servers and clients.
+//!
+//! Nothing in here depends on Godot, so it can be tested on its own.
+
+mod checksum;
+mod connection;
+mod input;
+mod math;
+mod packet;
+mod prediction;
+mod replay;
+mod snapshot;
+mod state;
+mod vehicle;
+
+pub use checksum::*;
+pub use connection::*;
+pub use input::*;
+pub use packet::*;
+pub use prediction::*;
+pub use replay::*;
+pub use snapshot::*;
+pub use state::*;
+pub use vehicle::*;
+
+/// Whether sequence number `a` is newer than `b`, accounting for wrap around.
+pub(crate) fn newer(a: u16, b: u16) -> bool {
+	(a.wrapping_sub(b) as i16) > 0
+}
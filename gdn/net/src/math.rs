@@ -0,0 +1,57 @@
+//! Vector and quaternion math on the arrays used by [`crate::BodyState`].
+
+/// The rotation that does nothing as `[x, y, z, w]`.
+pub(crate) const IDENTITY: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+pub(crate) fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+	[a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub(crate) fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+	[a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub(crate) fn length(a: [f32; 3]) -> f32 {
+	a.iter().map(|c| c * c).sum::<f32>().sqrt()
+}
+
+pub(crate) fn mul(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+	let [ax, ay, az, aw] = a;
+	let [bx, by, bz, bw] = b;
+	[
+		aw * bx + ax * bw + ay * bz - az * by,
+		aw * by - ax * bz + ay * bw + az * bx,
+		aw * bz + ax * by - ay * bx + az * bw,
+		aw * bw - ax * bx - ay * by - az * bz,
+	]
+}
+
+pub(crate) fn conjugate([x, y, z, w]: [f32; 4]) -> [f32; 4] {
+	[-x, -y, -z, w]
+}
+
+pub(crate) fn normalize(q: [f32; 4]) -> [f32; 4] {
+	let len = q.iter().map(|c| c * c).sum::<f32>().sqrt();
+	if len > 0.0 {
+		q.map(|c| c / len)
+	} else {
+		IDENTITY
+	}
+}
+
+/// The angle in radians a unit quaternion rotates by.
+pub(crate) fn angle(q: [f32; 4]) -> f32 {
+	2.0 * q[3].abs().min(1.0).acos()
+}
+
+/// Interpolate between two unit quaternions. This is not as accurate as slerp but small
+/// rotations are all that is needed here.
+pub(crate) fn nlerp(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+	let dot = a.iter().zip(&b).map(|(a, b)| a * b).sum::<f32>();
+	let b = if dot < 0.0 { b.map(|c| -c) } else { b };
+	let mut q = [0.0; 4];
+	for (q, (a, b)) in q.iter_mut().zip(a.iter().zip(&b)) {
+		*q = a + (b - a) * t;
+	}
+	normalize(q)
+}
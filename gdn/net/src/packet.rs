@@ -0,0 +1,551 @@
+//! Decoding of the vehicle data in packets.
+//!
+//! All of it comes from another peer, so none of it is trusted: every read is bounds
+//! checked and every value that could make the simulation misbehave is rejected with a
+//! [`ProcessPacketError`].
+//!
+//! Which bodies a message describes follows from the vehicle the receiver already has, so
+//! the decoders are given the shape of its body tree: the amount of children of every body,
+//! depth first with every body before its children. A vehicle whose main body has two
+//! children without children of their own has the shape `[2, 0, 0]`.
+
+use crate::state::{BodyState, BODY_STATE_SIZE};
+use core::convert::TryFrom;
+use core::fmt;
+
+/// Enum returned if the data in a packet is malformed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessPacketError {
+	/// The packet ends before all data is read.
+	Truncated,
+	/// The packet has data after the last field.
+	TrailingData,
+	/// The type of a damage event isn't known.
+	UnknownDamageType(u8),
+	/// A flag is neither 0 nor 1.
+	InvalidFlag(u8),
+	/// A value is out of the range it may have.
+	OutOfRange(&'static str),
+}
+
+impl fmt::Display for ProcessPacketError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Truncated => "truncated packet".fmt(f),
+			Self::TrailingData => "trailing data".fmt(f),
+			Self::UnknownDamageType(t) => write!(f, "unknown damage type {}", t),
+			Self::InvalidFlag(v) => write!(f, "invalid flag {}", v),
+			Self::OutOfRange(what) => write!(f, "{} is out of range", what),
+		}
+	}
+}
+
+/// Reads the fields of a packet.
+#[derive(Clone)]
+pub struct Decoder<'a> {
+	data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+	pub fn new(data: &'a [u8]) -> Self {
+		Self { data }
+	}
+
+	pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], ProcessPacketError> {
+		if self.data.len() < n {
+			return Err(ProcessPacketError::Truncated);
+		}
+		let (b, rest) = self.data.split_at(n);
+		self.data = rest;
+		Ok(b)
+	}
+
+	pub fn u8(&mut self) -> Result<u8, ProcessPacketError> {
+		self.bytes(1).map(|b| b[0])
+	}
+
+	pub fn u16(&mut self) -> Result<u16, ProcessPacketError> {
+		self.bytes(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+	}
+
+	pub fn u32(&mut self) -> Result<u32, ProcessPacketError> {
+		let mut buf = [0; 4];
+		buf.copy_from_slice(self.bytes(4)?);
+		Ok(u32::from_le_bytes(buf))
+	}
+
+	pub fn u64(&mut self) -> Result<u64, ProcessPacketError> {
+		let mut buf = [0; 8];
+		buf.copy_from_slice(self.bytes(8)?);
+		Ok(u64::from_le_bytes(buf))
+	}
+
+	/// Bytes prefixed with a `u16` length.
+	pub fn bytes16(&mut self) -> Result<&'a [u8], ProcessPacketError> {
+		let len = self.u16()?;
+		self.bytes(len.into())
+	}
+
+	/// A byte that is either 0 or 1.
+	pub fn flag(&mut self) -> Result<bool, ProcessPacketError> {
+		match self.u8()? {
+			0 => Ok(false),
+			1 => Ok(true),
+			v => Err(ProcessPacketError::InvalidFlag(v)),
+		}
+	}
+
+	/// Three `f32`s, which must be finite.
+	pub fn vector3(&mut self) -> Result<[f32; 3], ProcessPacketError> {
+		let mut v = [0.0; 3];
+		for c in v.iter_mut() {
+			*c = f32::from_bits(self.u32()?);
+			if !c.is_finite() {
+				return Err(ProcessPacketError::OutOfRange("vector"));
+			}
+		}
+		Ok(v)
+	}
+
+	pub fn body_state(&mut self) -> Result<BodyState, ProcessPacketError> {
+		// The quantized state can't hold any invalid values.
+		BodyState::decode(&mut self.bytes(BODY_STATE_SIZE)?)
+			.map_err(|_| ProcessPacketError::Truncated)
+	}
+
+	/// Whether all data has been read.
+	pub fn is_empty(&self) -> bool {
+		self.data.is_empty()
+	}
+
+	/// Check that all data has been read.
+	pub fn finish(self) -> Result<(), ProcessPacketError> {
+		if self.data.is_empty() {
+			Ok(())
+		} else {
+			Err(ProcessPacketError::TrailingData)
+		}
+	}
+}
+
+/// Append bytes prefixed with a `u16` length, the reverse of [`Decoder::bytes16`].
+///
+/// # Panics
+///
+/// If there are more than `u16::MAX` bytes.
+pub(crate) fn put_bytes16(out: &mut Vec<u8>, data: &[u8]) {
+	let len = u16::try_from(data.len()).expect("data too large");
+	out.extend(&len.to_le_bytes());
+	out.extend(data);
+}
+
+/// A damage event as sent in permanent messages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Damage {
+	Ray {
+		damage: u32,
+		origin: [f32; 3],
+		direction: [f32; 3],
+	},
+	Explosion {
+		damage: u32,
+		origin: [f32; 3],
+		radius: i8,
+	},
+}
+
+impl Damage {
+	pub fn encode(&self, out: &mut Vec<u8>) {
+		let put_vector3 = |out: &mut Vec<u8>, v: &[f32; 3]| {
+			v.iter().for_each(|c| out.extend(&c.to_le_bytes()));
+		};
+		match self {
+			Self::Ray {
+				damage,
+				origin,
+				direction,
+			} => {
+				out.push(0);
+				out.extend(&damage.to_le_bytes());
+				put_vector3(out, origin);
+				put_vector3(out, direction);
+			}
+			Self::Explosion {
+				damage,
+				origin,
+				radius,
+			} => {
+				out.push(1);
+				out.extend(&damage.to_le_bytes());
+				put_vector3(out, origin);
+				out.extend(&radius.to_le_bytes());
+			}
+		}
+	}
+
+	pub fn decode(d: &mut Decoder) -> Result<Self, ProcessPacketError> {
+		match d.u8()? {
+			0 => {
+				let damage = d.u32()?;
+				let origin = d.vector3()?;
+				let direction = d.vector3()?;
+				if direction == [0.0; 3] {
+					// The ray would never move.
+					return Err(ProcessPacketError::OutOfRange("ray direction"));
+				}
+				Ok(Self::Ray {
+					damage,
+					origin,
+					direction,
+				})
+			}
+			1 => {
+				let damage = d.u32()?;
+				let origin = d.vector3()?;
+				let radius = d.u8()? as i8;
+				if radius < 0 {
+					return Err(ProcessPacketError::OutOfRange("explosion radius"));
+				}
+				Ok(Self::Explosion {
+					damage,
+					origin,
+					radius,
+				})
+			}
+			ty => Err(ProcessPacketError::UnknownDamageType(ty)),
+		}
+	}
+}
+
+/// The temporary message of a vehicle, which the server sends every step:
+///
+/// ```text
+/// [packet index u16][input index u16][controller bitmap u16][aim at [f32; 3]]
+/// ([alive flag][state if alive]) for every body, skipping the children of dead bodies
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemporaryMessage {
+	pub index: u16,
+	/// The index of the last input the server applied.
+	pub input_index: u16,
+	pub bitmap: u16,
+	pub aim_at: [f32; 3],
+	/// The state of every body in the order of the shape, `None` for destroyed bodies.
+	pub states: Vec<Option<BodyState>>,
+}
+
+impl TemporaryMessage {
+	/// Decode a message for a vehicle with the given shape.
+	///
+	/// # Panics
+	///
+	/// If a count in the shape exceeds the amount of bodies that follow it.
+	pub fn decode(data: &[u8], shape: &[usize]) -> Result<Self, ProcessPacketError> {
+		let mut d = Decoder::new(data);
+		let (index, input_index, bitmap) = (d.u16()?, d.u16()?, d.u16()?);
+		let aim_at = d.vector3()?;
+		let mut states = Vec::with_capacity(shape.len());
+		while states.len() < shape.len() {
+			let at = states.len();
+			if d.flag()? {
+				states.push(Some(d.body_state()?));
+			} else {
+				// The children aren't sent either.
+				states.resize(at + subtree_len(shape, at), None);
+			}
+		}
+		d.finish()?;
+		Ok(Self {
+			index,
+			input_index,
+			bitmap,
+			aim_at,
+			states,
+		})
+	}
+}
+
+/// The amount of bodies in the tree starting at `shape[at]`, including that body.
+fn subtree_len(shape: &[usize], at: usize) -> usize {
+	let (mut end, mut pending) = (at, 1);
+	while pending > 0 {
+		pending += shape[end];
+		pending -= 1;
+		end += 1;
+	}
+	end - at
+}
+
+/// The permanent message of a vehicle, which the server sends every step:
+///
+/// ```text
+/// ([event count u16][damage]...) for every body that isn't destroyed
+/// [checksum flag][checksum u64 if set]
+/// ```
+///
+/// Destroyed bodies and their children are skipped entirely. Both sides know which bodies
+/// are destroyed as damage is applied deterministically.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PermanentMessage {
+	/// The damage events of every body that isn't destroyed, depth first.
+	pub damage: Vec<Vec<Damage>>,
+	/// The checksum of the vehicle before the damage is applied, see [`Checksum`].
+	///
+	/// [`Checksum`]: crate::Checksum
+	pub checksum: Option<u64>,
+}
+
+impl PermanentMessage {
+	/// Decode a message for a vehicle with the given amount of bodies that aren't destroyed.
+	pub fn decode(data: &[u8], bodies: usize) -> Result<Self, ProcessPacketError> {
+		let mut d = Decoder::new(data);
+		let mut damage = Vec::with_capacity(bodies);
+		for _ in 0..bodies {
+			// Every event takes several bytes, so the count can't make this allocate much.
+			let events = (0..d.u16()?)
+				.map(|_| Damage::decode(&mut d))
+				.collect::<Result<_, _>>()?;
+			damage.push(events);
+		}
+		let checksum = if d.flag()? { Some(d.u64()?) } else { None };
+		d.finish()?;
+		Ok(Self { damage, checksum })
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::Connection;
+	use std::time::Instant;
+
+	/// xorshift64
+	struct Rng(u64);
+
+	impl Rng {
+		fn next(&mut self) -> u64 {
+			self.0 ^= self.0 << 13;
+			self.0 ^= self.0 >> 7;
+			self.0 ^= self.0 << 17;
+			self.0
+		}
+
+		fn below(&mut self, n: usize) -> usize {
+			(self.next() % n as u64) as usize
+		}
+	}
+
+	/// A random shape with up to `max` bodies.
+	fn shape(rng: &mut Rng, max: usize) -> Vec<usize> {
+		let mut shape = vec![0];
+		// Attach every body to a random earlier one, then list them depth first.
+		let mut parents = vec![None];
+		for i in 1..rng.below(max) + 1 {
+			parents.push(Some(rng.below(i)));
+		}
+		fn walk(parents: &[Option<usize>], body: usize, shape: &mut Vec<usize>) {
+			let children = (0..parents.len()).filter(|&c| parents[c] == Some(body));
+			let at = shape.len() - 1;
+			for c in children {
+				shape[at] += 1;
+				shape.push(0);
+				walk(parents, c, shape);
+			}
+		}
+		walk(&parents, 0, &mut shape);
+		shape
+	}
+
+	fn temporary(rng: &mut Rng, shape: &[usize]) -> (Vec<u8>, TemporaryMessage) {
+		let mut msg = TemporaryMessage {
+			index: rng.next() as u16,
+			input_index: rng.next() as u16,
+			bitmap: rng.next() as u16,
+			aim_at: [1.0, 2.0, 3.0],
+			states: Vec::new(),
+		};
+		let mut data = Vec::new();
+		data.extend(&msg.index.to_le_bytes());
+		data.extend(&msg.input_index.to_le_bytes());
+		data.extend(&msg.bitmap.to_le_bytes());
+		msg.aim_at
+			.iter()
+			.for_each(|c| data.extend(&c.to_le_bytes()));
+		while msg.states.len() < shape.len() {
+			if rng.below(4) == 0 {
+				data.push(0);
+				let n = subtree_len(shape, msg.states.len());
+				msg.states.resize(msg.states.len() + n, None);
+			} else {
+				data.push(1);
+				let state = BodyState {
+					rotation: [0.0, 0.0, 0.0, 1.0],
+					position: [rng.below(100) as f32, 0.0, 0.0],
+					linear_velocity: [0.0; 3],
+					angular_velocity: [0.0; 3],
+				};
+				let start = data.len();
+				state.encode(&mut data).unwrap();
+				// The state is quantized.
+				let state = BodyState::decode(&mut &data[start..]).unwrap();
+				msg.states.push(Some(state));
+			}
+		}
+		(data, msg)
+	}
+
+	fn permanent(rng: &mut Rng, bodies: usize) -> (Vec<u8>, PermanentMessage) {
+		let mut msg = PermanentMessage {
+			damage: Vec::new(),
+			checksum: None,
+		};
+		let mut data = Vec::new();
+		for _ in 0..bodies {
+			let events = rng.below(3);
+			data.extend(&(events as u16).to_le_bytes());
+			let events = (0..events).map(|i| {
+				if i % 2 == 0 {
+					Damage::Ray {
+						damage: rng.next() as u32,
+						origin: [1.0, 2.0, 3.0],
+						direction: [0.0, 0.0, 1.0],
+					}
+				} else {
+					Damage::Explosion {
+						damage: rng.next() as u32,
+						origin: [1.0, 2.0, 3.0],
+						radius: rng.below(128) as i8,
+					}
+				}
+			});
+			let events = events.collect::<Vec<_>>();
+			events.iter().for_each(|e| e.encode(&mut data));
+			msg.damage.push(events);
+		}
+		if rng.below(2) == 0 {
+			msg.checksum = Some(rng.next());
+			data.push(1);
+			data.extend(&msg.checksum.unwrap().to_le_bytes());
+		} else {
+			data.push(0);
+		}
+		(data, msg)
+	}
+
+	#[test]
+	fn round_trip() {
+		let mut rng = Rng(0x0123_4567_89ab_cdef);
+		for _ in 0..100 {
+			let shape = shape(&mut rng, 6);
+			let (data, msg) = temporary(&mut rng, &shape);
+			assert_eq!(TemporaryMessage::decode(&data, &shape), Ok(msg));
+			let (data, msg) = permanent(&mut rng, shape.len());
+			assert_eq!(PermanentMessage::decode(&data, shape.len()), Ok(msg));
+		}
+	}
+
+	#[test]
+	fn shapes() {
+		assert_eq!(subtree_len(&[2, 1, 0, 0], 0), 4);
+		assert_eq!(subtree_len(&[2, 1, 0, 0], 1), 2);
+		assert_eq!(subtree_len(&[2, 1, 0, 0], 3), 1);
+		// Only the main body is alive, the second child of it is skipped with its child.
+		let mut data = vec![0; 6 + 12];
+		data.push(1);
+		BodyState {
+			rotation: [0.0, 0.0, 0.0, 1.0],
+			position: [0.0; 3],
+			linear_velocity: [0.0; 3],
+			angular_velocity: [0.0; 3],
+		}
+		.encode(&mut data)
+		.unwrap();
+		data.extend(&[0, 0]);
+		let msg = TemporaryMessage::decode(&data, &[2, 0, 1, 0]).unwrap();
+		let alive = msg.states.iter().map(Option::is_some).collect::<Vec<_>>();
+		assert_eq!(alive, [true, false, false, false]);
+		assert_eq!(
+			TemporaryMessage::decode(&data[..data.len() - 1], &[2, 0, 1, 0]),
+			Err(ProcessPacketError::Truncated)
+		);
+	}
+
+	#[test]
+	fn errors() {
+		let decode = |data: &[u8]| Damage::decode(&mut Decoder::new(data));
+		assert_eq!(decode(&[]), Err(ProcessPacketError::Truncated));
+		assert_eq!(decode(&[2]), Err(ProcessPacketError::UnknownDamageType(2)));
+		let mut ray = vec![0, 1, 0, 0, 0];
+		ray.extend(&f32::NAN.to_le_bytes());
+		ray.extend(&[0; 20]);
+		assert_eq!(decode(&ray), Err(ProcessPacketError::OutOfRange("vector")));
+		assert_eq!(
+			decode(&[0; 29]),
+			Err(ProcessPacketError::OutOfRange("ray direction"))
+		);
+		let mut explosion = vec![1; 17];
+		explosion[5..17].copy_from_slice(&[0; 12]);
+		explosion.push(0xff);
+		assert_eq!(
+			decode(&explosion),
+			Err(ProcessPacketError::OutOfRange("explosion radius"))
+		);
+		assert_eq!(
+			Decoder::new(&[2]).flag(),
+			Err(ProcessPacketError::InvalidFlag(2))
+		);
+		assert_eq!(
+			Decoder::new(&[0]).finish(),
+			Err(ProcessPacketError::TrailingData)
+		);
+	}
+
+	/// Throw random and mutated data at the decoders. None of it may panic.
+	#[test]
+	fn fuzz() {
+		let mut rng = Rng(0xdead_beef_cafe_f00d);
+		let (mut sender, mut receiver) = (Connection::new(), Connection::new());
+		let now = Instant::now();
+		for i in 0..20_000 {
+			let shape = shape(&mut rng, 6);
+			let mut data = match i % 4 {
+				0 => (0..rng.below(200)).map(|_| rng.next() as u8).collect(),
+				1 => {
+					// A packet of a connection carrying vehicle data.
+					let _ = sender.send_permanent(&permanent(&mut rng, shape.len()).0);
+					let _ = sender.send_temporary(&temporary(&mut rng, &shape).0);
+					sender.create_packet(now)
+				}
+				2 => permanent(&mut rng, shape.len()).0,
+				_ => temporary(&mut rng, &shape).0,
+			};
+			// Flip, insert, remove or truncate some bytes.
+			for _ in 0..rng.below(4) {
+				if data.is_empty() {
+					break;
+				}
+				let at = rng.below(data.len());
+				match rng.below(4) {
+					0 => data[at] ^= 1 << rng.below(8),
+					1 => data.insert(at, rng.next() as u8),
+					2 => drop(data.remove(at)),
+					_ => data.truncate(at),
+				}
+			}
+			let decode = |data: &[u8]| {
+				let _ = TemporaryMessage::decode(data, &shape);
+				let _ = PermanentMessage::decode(data, shape.len());
+			};
+			decode(&data);
+			if receiver.receive(&data, now).is_ok() {
+				while let Some(m) = receiver.receive_permanent() {
+					decode(&m);
+				}
+				if let Some(m) = receiver.receive_temporary() {
+					decode(&m);
+				}
+				let _ = sender.receive(&receiver.create_packet(now), now);
+			}
+		}
+	}
+}
@@ -0,0 +1,355 @@
+//! Client side prediction of the vehicle controlled by the player.
+//!
+//! The server only sends the state of a vehicle a few times per second and the state is a
+//! round trip old when it arrives, so simply applying it makes the vehicle lag behind the
+//! input of the player and snap back regularly. Instead the client applies its input
+//! immediately and records the state of all bodies under the index of the input. The server sends
+//! the index of the last input it applied with every state, which is compared with the
+//! state recorded for that input. Any difference is the error of the prediction, which is
+//! corrected gradually over the next steps so it doesn't cause visible jumps.
+//!
+//! Since the physics run in the engine, inputs can't be replayed to redo the prediction.
+//! The correction is applied to the states recorded after the input instead, so the same
+//! error isn't corrected twice.
+
+use crate::math::*;
+use crate::{newer, BodyState};
+use std::collections::VecDeque;
+
+/// The maximum amount of inputs to remember. At 60 steps per second this is a bit more than
+/// 4 seconds, which is far more than any playable round trip time.
+const MAX_HISTORY: usize = 256;
+
+/// When the state of a body is considered wrong and how quickly it is corrected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Thresholds {
+	/// Position errors in meters below this are ignored if the rotation error is too.
+	pub min_position_error: f32,
+	/// Position errors in meters above this are corrected immediately.
+	pub max_position_error: f32,
+	/// Rotation errors in radians below this are ignored if the position error is too.
+	pub min_rotation_error: f32,
+	/// Rotation errors in radians above this are corrected immediately.
+	pub max_rotation_error: f32,
+	/// The fraction of the remaining error corrected every step.
+	pub blend: f32,
+}
+
+impl Default for Thresholds {
+	fn default() -> Self {
+		Self {
+			min_position_error: 0.01,
+			max_position_error: 2.0,
+			min_rotation_error: 0.01,
+			max_rotation_error: 0.5,
+			blend: 0.1,
+		}
+	}
+}
+
+/// A change to apply to the state of a body.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Correction {
+	pub position: [f32; 3],
+	/// A rotation applied after the current rotation of the body as `[x, y, z, w]`.
+	pub rotation: [f32; 4],
+	pub linear_velocity: [f32; 3],
+	pub angular_velocity: [f32; 3],
+}
+
+impl Correction {
+	pub const NONE: Self = Self {
+		position: [0.0; 3],
+		rotation: IDENTITY,
+		linear_velocity: [0.0; 3],
+		angular_velocity: [0.0; 3],
+	};
+
+	pub fn is_none(&self) -> bool {
+		*self == Self::NONE
+	}
+
+	pub fn apply(&self, state: &BodyState) -> BodyState {
+		BodyState {
+			rotation: normalize(mul(self.rotation, state.rotation)),
+			position: add(state.position, self.position),
+			linear_velocity: add(state.linear_velocity, self.linear_velocity),
+			angular_velocity: add(state.angular_velocity, self.angular_velocity),
+		}
+	}
+
+	/// Combine with a correction applied after this one.
+	fn then(&self, other: &Self) -> Self {
+		Self {
+			position: add(self.position, other.position),
+			rotation: normalize(mul(other.rotation, self.rotation)),
+			linear_velocity: add(self.linear_velocity, other.linear_velocity),
+			angular_velocity: add(self.angular_velocity, other.angular_velocity),
+		}
+	}
+}
+
+/// The error of a body that still has to be corrected.
+#[derive(Clone, Copy, Debug)]
+struct Pending {
+	correction: Correction,
+	/// Whether the error is too large to be blended.
+	snap: bool,
+}
+
+/// The state of the bodies when an input was applied.
+struct Entry {
+	index: u16,
+	states: Vec<Option<BodyState>>,
+}
+
+/// Keeps track of the inputs applied to a vehicle and corrects its bodies when the state of
+/// the server arrives.
+///
+/// The bodies are identified by their index, which must be the same for the states given
+/// to [`Predictor::record`] and [`Predictor::reconcile`]. `None` is used for destroyed
+/// bodies.
+pub struct Predictor {
+	pub thresholds: Thresholds,
+	history: VecDeque<Entry>,
+	next_index: u16,
+	pending: Vec<Option<Pending>>,
+}
+
+impl Predictor {
+	pub fn new(thresholds: Thresholds) -> Self {
+		Self {
+			thresholds,
+			history: VecDeque::new(),
+			next_index: 0,
+			pending: Vec::new(),
+		}
+	}
+
+	/// Record the current state of the bodies before an input is applied. Returns the index
+	/// to send to the server along with the input.
+	pub fn record(&mut self, states: &[Option<BodyState>]) -> u16 {
+		let index = self.next_index;
+		self.next_index = self.next_index.wrapping_add(1);
+		// The bodies will end up where they would be without the errors that are still
+		// being corrected, so record that.
+		let states = states
+			.iter()
+			.enumerate()
+			.map(|(i, s)| {
+				s.map(|s| match self.pending.get(i) {
+					Some(Some(p)) => p.correction.apply(&s),
+					_ => s,
+				})
+			})
+			.collect();
+		if self.history.len() >= MAX_HISTORY {
+			self.history.pop_front();
+		}
+		self.history.push_back(Entry { index, states });
+		index
+	}
+
+	/// Compare the state of the server after applying the input with the given index with
+	/// the state that was predicted. Inputs up to and including the index are forgotten.
+	///
+	/// Nothing happens if the input isn't remembered, e.g. because the state of a newer
+	/// input arrived earlier, or if the index wasn't recorded yet.
+	pub fn reconcile(&mut self, index: u16, server: &[Option<BodyState>]) {
+		match self.history.back() {
+			Some(e) if !newer(index, e.index) => (),
+			_ => return,
+		}
+		while let Some(e) = self.history.front() {
+			if newer(e.index, index) {
+				return;
+			} else if e.index == index {
+				break;
+			}
+			self.history.pop_front();
+		}
+		let predicted = match self.history.pop_front() {
+			Some(e) => e.states,
+			None => return,
+		};
+
+		let t = self.thresholds;
+		for (i, (s, p)) in server.iter().zip(predicted).enumerate() {
+			let (s, p) = match (s, p) {
+				(Some(s), Some(p)) => (s, p),
+				_ => continue,
+			};
+			let mut rotation = mul(s.rotation, conjugate(p.rotation));
+			if rotation[3] < 0.0 {
+				// Take the shortest way around.
+				rotation = rotation.map(|c| -c);
+			}
+			let error = Correction {
+				position: sub(s.position, p.position),
+				rotation: normalize(rotation),
+				linear_velocity: sub(s.linear_velocity, p.linear_velocity),
+				angular_velocity: sub(s.angular_velocity, p.angular_velocity),
+			};
+			let (position, rotation) = (length(error.position), angle(error.rotation));
+			if position < t.min_position_error && rotation < t.min_rotation_error {
+				continue;
+			}
+			let snap = position > t.max_position_error || rotation > t.max_rotation_error;
+
+			if self.pending.len() <= i {
+				self.pending.resize(i + 1, None);
+			}
+			let pending = &mut self.pending[i];
+			*pending = Some(match pending {
+				Some(p) => Pending {
+					correction: p.correction.then(&error),
+					snap: p.snap || snap,
+				},
+				None => Pending {
+					correction: error,
+					snap,
+				},
+			});
+
+			// Later predictions were made from the same wrong state.
+			for e in self.history.iter_mut() {
+				if let Some(Some(s)) = e.states.get_mut(i) {
+					*s = error.apply(s);
+				}
+			}
+		}
+	}
+
+	/// Take the corrections to apply to the bodies this step. Velocities are corrected
+	/// immediately, positions and rotations are blended unless the error is too large.
+	pub fn step(&mut self) -> Vec<Correction> {
+		let blend = self.thresholds.blend.clamp(0.0, 1.0);
+		let (min_position, min_rotation) = (
+			self.thresholds.min_position_error,
+			self.thresholds.min_rotation_error,
+		);
+		self.pending
+			.iter_mut()
+			.map(|pending| {
+				let p = match pending {
+					Some(p) => p,
+					None => return Correction::NONE,
+				};
+				let c = &mut p.correction;
+				let position = length(c.position);
+				let rotation = angle(c.rotation);
+				if p.snap || (position < min_position && rotation < min_rotation) {
+					// Either the error is too large to hide or so small that blending is
+					// pointless.
+					let c = *c;
+					*pending = None;
+					return c;
+				}
+				let step = Correction {
+					position: c.position.map(|x| x * blend),
+					rotation: nlerp(IDENTITY, c.rotation, blend),
+					linear_velocity: c.linear_velocity,
+					angular_velocity: c.angular_velocity,
+				};
+				c.position = sub(c.position, step.position);
+				c.rotation = normalize(mul(c.rotation, conjugate(step.rotation)));
+				c.linear_velocity = [0.0; 3];
+				c.angular_velocity = [0.0; 3];
+				step
+			})
+			.collect()
+	}
+}
+
+impl Default for Predictor {
+	fn default() -> Self {
+		Self::new(Thresholds::default())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn state(x: f32) -> Option<BodyState> {
+		Some(BodyState {
+			rotation: IDENTITY,
+			position: [x, 0.0, 0.0],
+			linear_velocity: [0.0; 3],
+			angular_velocity: [0.0; 3],
+		})
+	}
+
+	/// Step the predictor, apply the correction and record the next input.
+	fn step(p: &mut Predictor, body: &mut Option<BodyState>) -> u16 {
+		if let (Some(c), Some(b)) = (p.step().first(), body.as_mut()) {
+			*b = c.apply(b);
+		}
+		p.record(&[*body])
+	}
+
+	#[test]
+	fn blend() {
+		let mut p = Predictor::default();
+		let mut body = state(0.0);
+		let first = step(&mut p, &mut body);
+		for _ in 0..10 {
+			step(&mut p, &mut body);
+		}
+		// The server put the body half a meter further than predicted.
+		p.reconcile(first, &[state(0.5)]);
+		step(&mut p, &mut body);
+		let x = body.unwrap().position[0];
+		assert!((x - 0.05).abs() < 1e-5, "{}", x);
+
+		// Later states of the server include the same error, which should not be corrected
+		// again.
+		for i in 0..100 {
+			let index = step(&mut p, &mut body);
+			if i == 5 {
+				p.reconcile(index, &[state(0.5)]);
+			}
+		}
+		let x = body.unwrap().position[0];
+		assert!((x - 0.5).abs() < 0.01, "{}", x);
+		assert!(p.history.iter().all(|e| e.index != first));
+	}
+
+	#[test]
+	fn snap() {
+		let mut p = Predictor::default();
+		let mut body = state(0.0);
+		let index = step(&mut p, &mut body);
+		p.reconcile(index, &[state(10.0)]);
+		step(&mut p, &mut body);
+		assert_eq!(body.unwrap().position[0], 10.0);
+
+		let rotation = [0.0, 0.5f32.sqrt(), 0.0, 0.5f32.sqrt()];
+		let index = step(&mut p, &mut body);
+		let mut server = body;
+		server.as_mut().unwrap().rotation = rotation;
+		p.reconcile(index, &[server]);
+		step(&mut p, &mut body);
+		let r = body.unwrap().rotation;
+		assert!(angle(mul(r, conjugate(rotation))) < 1e-3, "{:?}", r);
+	}
+
+	#[test]
+	fn ignore_small_errors() {
+		let mut p = Predictor::default();
+		let mut body = state(0.0);
+		let index = step(&mut p, &mut body);
+		p.reconcile(index, &[state(0.001)]);
+		assert!(p.step().iter().all(Correction::is_none));
+		assert_eq!(body.unwrap().position[0], 0.0);
+
+		// Old or unknown indices do nothing.
+		let next = step(&mut p, &mut body);
+		p.reconcile(index, &[state(1.0)]);
+		p.reconcile(next.wrapping_add(10), &[state(1.0)]);
+		assert!(p.step().iter().all(Correction::is_none));
+		// Inputs that are still to be reconciled aren't forgotten either.
+		p.reconcile(next, &[state(1.0)]);
+		assert!(!p.step().iter().all(Correction::is_none));
+	}
+}
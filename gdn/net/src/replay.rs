@@ -0,0 +1,437 @@
+//! Recording and playback of matches.
+//!
+//! Everything that happens to a vehicle is either in its serialized state or in the packets
+//! created for it, so a replay is simply a list of those with the time they were created.
+//! Vehicles appear with a keyframe holding their serialized state and the packets are fed
+//! to them as if they came from the server.
+//!
+//! Keyframes are also recorded periodically for every vehicle. Seeking starts from the
+//! last keyframe of each vehicle, so only the packets after it have to be processed.
+
+use crate::packet::{Decoder, ProcessPacketError};
+use core::convert::TryFrom;
+use core::fmt;
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+/// The first bytes of every replay.
+const MAGIC: u32 = 0x5052_574f;
+
+/// The revision of the format. Increase it whenever the format changes.
+const REVISION: u16 = 0;
+
+/// The default amount of seconds between keyframes.
+const DEFAULT_KEYFRAME_INTERVAL: f64 = 10.0;
+
+/// Enum returned if a replay can't be read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplayError {
+	/// The data isn't a replay.
+	BadMagic,
+	/// The replay is of a revision that isn't supported.
+	UnknownRevision(u16),
+	/// The type of a record isn't known.
+	UnknownRecord(u8),
+	/// A record is older than the one before it.
+	OutOfOrder,
+	/// A field couldn't be read.
+	Decode(ProcessPacketError),
+}
+
+impl From<ProcessPacketError> for ReplayError {
+	fn from(e: ProcessPacketError) -> Self {
+		Self::Decode(e)
+	}
+}
+
+impl fmt::Display for ReplayError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::BadMagic => "bad magic".fmt(f),
+			Self::UnknownRevision(r) => write!(f, "unknown revision {}", r),
+			Self::UnknownRecord(k) => write!(f, "unknown record type {}", k),
+			Self::OutOfOrder => "records are out of order".fmt(f),
+			Self::Decode(e) => e.fmt(f),
+		}
+	}
+}
+
+/// What a record contains.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordKind {
+	/// The serialized state of a vehicle. The vehicle is created if it doesn't exist yet.
+	Keyframe = 0,
+	/// Permanent data created by a vehicle.
+	Permanent = 1,
+	/// Temporary data created by a vehicle.
+	Temporary = 2,
+	/// The vehicle was removed.
+	Destroy = 3,
+}
+
+impl RecordKind {
+	fn from_u8(n: u8) -> Result<Self, ReplayError> {
+		Ok(match n {
+			0 => Self::Keyframe,
+			1 => Self::Permanent,
+			2 => Self::Temporary,
+			3 => Self::Destroy,
+			n => return Err(ReplayError::UnknownRecord(n)),
+		})
+	}
+}
+
+/// A single event in a replay.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+	/// The time in seconds since the start of the recording.
+	pub time: f64,
+	pub vehicle: u16,
+	pub kind: RecordKind,
+	pub data: Vec<u8>,
+}
+
+/// Writes records to a replay.
+pub struct Recorder<W: io::Write> {
+	out: W,
+	/// The amount of seconds between keyframes.
+	pub keyframe_interval: f64,
+	last_keyframe: Option<f64>,
+	last_time: f64,
+}
+
+impl<W: io::Write> Recorder<W> {
+	/// Start a new replay by writing the header.
+	pub fn new(mut out: W) -> io::Result<Self> {
+		out.write_all(&MAGIC.to_le_bytes())?;
+		out.write_all(&REVISION.to_le_bytes())?;
+		Ok(Self {
+			out,
+			keyframe_interval: DEFAULT_KEYFRAME_INTERVAL,
+			last_keyframe: None,
+			last_time: 0.0,
+		})
+	}
+
+	/// Append a record. Times before that of the previous record are clamped to it.
+	pub fn record(
+		&mut self,
+		time: f64,
+		vehicle: u16,
+		kind: RecordKind,
+		data: &[u8],
+	) -> io::Result<()> {
+		let len = u32::try_from(data.len())
+			.map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "data is too large"))?;
+		self.last_time = time.max(self.last_time);
+		self.out.write_all(&self.last_time.to_le_bytes())?;
+		self.out.write_all(&[kind as u8])?;
+		self.out.write_all(&vehicle.to_le_bytes())?;
+		self.out.write_all(&len.to_le_bytes())?;
+		self.out.write_all(data)
+	}
+
+	/// Whether keyframes of all vehicles should be recorded at the given time. If so, the
+	/// next keyframes are due `keyframe_interval` seconds later.
+	pub fn keyframe_due(&mut self, time: f64) -> bool {
+		match self.last_keyframe {
+			Some(t) if time - t < self.keyframe_interval => false,
+			_ => {
+				self.last_keyframe = Some(time);
+				true
+			}
+		}
+	}
+
+	/// Flush the writer, so everything recorded so far ends up in the file.
+	pub fn flush(&mut self) -> io::Result<()> {
+		self.out.flush()
+	}
+
+	pub fn get_ref(&self) -> &W {
+		&self.out
+	}
+
+	pub fn into_inner(self) -> W {
+		self.out
+	}
+}
+
+/// Plays back a replay.
+pub struct Player {
+	records: Vec<Record>,
+	/// The index of the next record to play.
+	next: usize,
+	time: f64,
+	/// The vehicles that currently exist.
+	alive: HashSet<u16>,
+}
+
+impl Player {
+	/// Read a replay.
+	pub fn new(data: &[u8]) -> Result<Self, ReplayError> {
+		let mut d = Decoder::new(data);
+		if d.u32().ok() != Some(MAGIC) {
+			return Err(ReplayError::BadMagic);
+		}
+		match d.u16()? {
+			REVISION => {}
+			r => return Err(ReplayError::UnknownRevision(r)),
+		}
+
+		let mut records = Vec::<Record>::new();
+		while !d.is_empty() {
+			let time = f64::from_bits(d.u64()?);
+			if !time.is_finite() || matches!(records.last(), Some(r) if r.time > time) {
+				return Err(ReplayError::OutOfOrder);
+			}
+			let kind = RecordKind::from_u8(d.u8()?)?;
+			let vehicle = d.u16()?;
+			let len = d.u32()?;
+			let data = d.bytes(len as usize)?.to_vec();
+			records.push(Record {
+				time,
+				vehicle,
+				kind,
+				data,
+			});
+		}
+
+		Ok(Self {
+			records,
+			next: 0,
+			time: 0.0,
+			alive: HashSet::new(),
+		})
+	}
+
+	/// The current time of the playback in seconds.
+	pub fn time(&self) -> f64 {
+		self.time
+	}
+
+	/// The time of the last record in seconds.
+	pub fn duration(&self) -> f64 {
+		self.records.last().map_or(0.0, |r| r.time)
+	}
+
+	/// Advance the playback by `delta` seconds. Returns the records to apply in order.
+	///
+	/// Keyframes of vehicles that already exist are skipped, as their state follows from
+	/// the packets.
+	pub fn advance(&mut self, delta: f64) -> Vec<&Record> {
+		self.time += delta.max(0.0);
+		let end = self.end(self.time);
+		let mut out = Vec::new();
+		for r in &self.records[self.next..end] {
+			match r.kind {
+				RecordKind::Keyframe => {
+					if self.alive.insert(r.vehicle) {
+						out.push(r);
+					}
+				}
+				RecordKind::Destroy => {
+					if self.alive.remove(&r.vehicle) {
+						out.push(r);
+					}
+				}
+				RecordKind::Permanent | RecordKind::Temporary => {
+					if self.alive.contains(&r.vehicle) {
+						out.push(r);
+					}
+				}
+			}
+		}
+		self.next = end;
+		out
+	}
+
+	/// Jump to the given time. All existing vehicles should be removed before applying the
+	/// returned records.
+	///
+	/// The records are the last keyframe of each vehicle that exists at that time, all
+	/// permanent data after it and the last temporary data.
+	pub fn seek(&mut self, time: f64) -> Vec<&Record> {
+		self.time = time.max(0.0);
+		let end = self.end(self.time);
+
+		// Find the last keyframe & temporary data of every vehicle.
+		let mut keyframes = HashMap::new();
+		let mut temporary = HashMap::new();
+		for (i, r) in self.records[..end].iter().enumerate() {
+			match r.kind {
+				RecordKind::Keyframe => {
+					keyframes.insert(r.vehicle, i);
+				}
+				RecordKind::Destroy => {
+					keyframes.remove(&r.vehicle);
+				}
+				RecordKind::Temporary => {
+					temporary.insert(r.vehicle, i);
+				}
+				RecordKind::Permanent => {}
+			}
+		}
+
+		let start = keyframes.values().copied().min().unwrap_or(end);
+		let out = self.records[start..end]
+			.iter()
+			.enumerate()
+			.map(|(i, r)| (start + i, r))
+			.filter(|&(i, r)| {
+				let keyframe = match keyframes.get(&r.vehicle) {
+					Some(&k) if k <= i => k,
+					_ => return false,
+				};
+				match r.kind {
+					RecordKind::Keyframe => i == keyframe,
+					RecordKind::Permanent => true,
+					RecordKind::Temporary => temporary.get(&r.vehicle) == Some(&i),
+					RecordKind::Destroy => false,
+				}
+			})
+			.map(|(_, r)| r)
+			.collect();
+
+		self.alive = keyframes.keys().copied().collect();
+		self.next = end;
+		out
+	}
+
+	/// The index of the first record after the given time.
+	fn end(&self, time: f64) -> usize {
+		self.records.partition_point(|r| r.time <= time)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Record two vehicles, one of which is destroyed halfway, with keyframes every second.
+	fn replay() -> Vec<u8> {
+		let mut rec = Recorder::new(Vec::new()).unwrap();
+		rec.keyframe_interval = 1.0;
+		for step in 0..40u8 {
+			let time = f64::from(step) / 10.0;
+			let keyframe = rec.keyframe_due(time);
+			for v in 0..2 {
+				if v == 1 && step == 20 {
+					rec.record(time, v, RecordKind::Destroy, &[]).unwrap();
+				}
+				if v == 1 && step >= 20 {
+					continue;
+				}
+				if keyframe {
+					rec.record(time, v, RecordKind::Keyframe, &[step]).unwrap();
+				}
+				rec.record(time, v, RecordKind::Permanent, &[step]).unwrap();
+				rec.record(time, v, RecordKind::Temporary, &[step]).unwrap();
+			}
+		}
+		rec.into_inner()
+	}
+
+	fn summary(records: &[&Record]) -> Vec<(u16, RecordKind, u8)> {
+		records
+			.iter()
+			.map(|r| (r.vehicle, r.kind, r.data.first().copied().unwrap_or(0)))
+			.collect()
+	}
+
+	#[test]
+	fn advance() {
+		let mut p = Player::new(&replay()).unwrap();
+		assert_eq!(p.duration(), 3.9);
+		let r = summary(&p.advance(0.0));
+		assert_eq!(
+			r,
+			[
+				(0, RecordKind::Keyframe, 0),
+				(0, RecordKind::Permanent, 0),
+				(0, RecordKind::Temporary, 0),
+				(1, RecordKind::Keyframe, 0),
+				(1, RecordKind::Permanent, 0),
+				(1, RecordKind::Temporary, 0),
+			]
+		);
+		// Keyframes of existing vehicles are skipped.
+		let r = summary(&p.advance(1.05));
+		assert_eq!(r.len(), 40);
+		assert!(r.iter().all(|r| r.1 != RecordKind::Keyframe));
+		let r = summary(&p.advance(1.0));
+		assert!(r.contains(&(1, RecordKind::Destroy, 0)));
+		assert!(!r.iter().any(|&(v, _, s)| v == 1 && s >= 20));
+		assert_eq!(p.advance(10.0).len(), 38);
+		assert!(p.advance(1.0).is_empty());
+	}
+
+	#[test]
+	fn seek() {
+		let mut p = Player::new(&replay()).unwrap();
+		let r = summary(&p.seek(1.55));
+		assert_eq!(r[0], (0, RecordKind::Keyframe, 10));
+		assert_eq!(r[1], (0, RecordKind::Permanent, 10));
+		let permanent = |v| {
+			r.iter()
+				.filter(|&&(w, k, _)| w == v && k == RecordKind::Permanent)
+				.map(|r| r.2)
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(permanent(0), (10..=15).collect::<Vec<_>>());
+		assert_eq!(permanent(1), (10..=15).collect::<Vec<_>>());
+		let temporary = r
+			.iter()
+			.filter(|r| r.1 == RecordKind::Temporary)
+			.collect::<Vec<_>>();
+		assert_eq!(
+			temporary,
+			[
+				&(0, RecordKind::Temporary, 15),
+				&(1, RecordKind::Temporary, 15)
+			]
+		);
+
+		// Destroyed vehicles don't come back, also when seeking backwards.
+		let r = summary(&p.seek(3.0));
+		assert!(r.iter().all(|r| r.0 == 0));
+		assert_eq!(r[0], (0, RecordKind::Keyframe, 30));
+		let r = summary(&p.seek(0.0));
+		assert_eq!(r.len(), 6);
+
+		// Playback continues after the time seeked to.
+		p.seek(2.95);
+		let r = summary(&p.advance(0.1));
+		assert_eq!(
+			r,
+			[
+				(0, RecordKind::Permanent, 30),
+				(0, RecordKind::Temporary, 30)
+			]
+		);
+	}
+
+	#[test]
+	fn errors() {
+		let data = replay();
+		assert_eq!(Player::new(&[]).err(), Some(ReplayError::BadMagic));
+		let mut bad = data.clone();
+		bad[4] = 9;
+		assert_eq!(
+			Player::new(&bad).err(),
+			Some(ReplayError::UnknownRevision(9))
+		);
+		let mut bad = data.clone();
+		bad[6 + 8] = 9;
+		assert_eq!(Player::new(&bad).err(), Some(ReplayError::UnknownRecord(9)));
+		assert_eq!(
+			Player::new(&data[..data.len() - 1]).err(),
+			Some(ReplayError::Decode(ProcessPacketError::Truncated))
+		);
+		let mut rec = Recorder::new(Vec::new()).unwrap();
+		rec.record(1.0, 0, RecordKind::Destroy, &[]).unwrap();
+		rec.record(0.5, 0, RecordKind::Destroy, &[]).unwrap();
+		let p = Player::new(rec.get_ref()).unwrap();
+		assert_eq!(p.records[1].time, 1.0);
+	}
+}
@@ -0,0 +1,272 @@
+//! Smooth playback of the states of vehicles controlled by other players.
+//!
+//! States arrive at irregular intervals due to jitter and some never arrive at all, so
+//! applying them as they arrive makes vehicles stutter. Instead every state is stamped with
+//! the time the server created it and stored in a [`SnapshotBuffer`]. A [`Timeline`] maps
+//! the local clock to the time of the server minus a playout delay, so there usually are
+//! states on both sides of the time being shown. The position in between is found with
+//! Hermite interpolation, which uses the velocities of both states so the path is smooth.
+//! If no newer state arrived in time, the last state is extrapolated for a short while.
+
+use crate::math::*;
+use crate::BodyState;
+use std::collections::VecDeque;
+
+/// The maximum amount of states to keep per body. At 20 packets per second this is more
+/// than a second, which is far more than any reasonable playout delay.
+const MAX_SNAPSHOTS: usize = 32;
+
+/// How much of the difference between the measured and the current clock offset is
+/// applied per received state. A low value filters out jitter.
+const OFFSET_SMOOTHING: f64 = 0.05;
+
+/// If the measured clock offset differs more than this many seconds, the timeline is reset
+/// instead of adjusted gradually, e.g. when the connection stalled for a while.
+const MAX_OFFSET_DRIFT: f64 = 1.0;
+
+/// Maps the local clock to the clock of the server.
+#[derive(Clone, Debug)]
+pub struct Timeline {
+	/// How far in the past of the server the states are shown, in seconds.
+	pub delay: f64,
+	/// The duration of a tick of the server in seconds.
+	tick_duration: f64,
+	/// The newest tick received, without wrapping around.
+	tick: Option<i64>,
+	/// The time of the server minus the local time.
+	offset: Option<f64>,
+}
+
+impl Timeline {
+	pub fn new(tick_duration: f64, delay: f64) -> Self {
+		Self {
+			delay,
+			tick_duration,
+			tick: None,
+			offset: None,
+		}
+	}
+
+	/// Register a state created by the server at the given tick, which arrived at local time
+	/// `now`. Returns the time of the server at that tick.
+	pub fn receive(&mut self, tick: u16, now: f64) -> f64 {
+		let tick = match self.tick {
+			Some(t) => t + i64::from(tick.wrapping_sub(t as u16) as i16),
+			None => i64::from(tick),
+		};
+		self.tick = Some(self.tick.map_or(tick, |t| t.max(tick)));
+		let time = tick as f64 * self.tick_duration;
+
+		let offset = time - now;
+		self.offset = Some(match self.offset {
+			Some(o) if (offset - o).abs() <= MAX_OFFSET_DRIFT => {
+				o + (offset - o) * OFFSET_SMOOTHING
+			}
+			_ => offset,
+		});
+		time
+	}
+
+	/// The time of the server to show at local time `now`, or `None` if nothing was received
+	/// yet.
+	pub fn playback_time(&self, now: f64) -> Option<f64> {
+		self.offset.map(|o| now + o - self.delay)
+	}
+}
+
+/// The states of a single body received from the server, ordered by time.
+#[derive(Clone, Debug, Default)]
+pub struct SnapshotBuffer {
+	snapshots: VecDeque<(f64, BodyState)>,
+}
+
+impl SnapshotBuffer {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Add a state the server had at the given time. States that arrive late are inserted
+	/// in order, duplicates are ignored.
+	pub fn push(&mut self, time: f64, state: BodyState) {
+		let i = self.snapshots.partition_point(|(t, _)| *t < time);
+		if matches!(self.snapshots.get(i), Some((t, _)) if *t == time) {
+			return;
+		}
+		self.snapshots.insert(i, (time, state));
+		if self.snapshots.len() > MAX_SNAPSHOTS {
+			self.snapshots.pop_front();
+		}
+	}
+
+	/// The state of the body at the given time of the server. States newer than the last
+	/// one are extrapolated for at most `max_extrapolation` seconds, after which the body
+	/// stops. Returns `None` if no states have been received.
+	pub fn sample(&self, time: f64, max_extrapolation: f64) -> Option<BodyState> {
+		let i = self.snapshots.partition_point(|(t, _)| *t <= time);
+		match (
+			i.checked_sub(1).map(|i| &self.snapshots[i]),
+			self.snapshots.get(i),
+		) {
+			(Some((t0, a)), Some((t1, b))) => Some(hermite(
+				a,
+				b,
+				(t1 - t0) as f32,
+				((time - t0) / (t1 - t0)) as f32,
+			)),
+			(Some((t, a)), None) if time - t <= max_extrapolation => {
+				Some(extrapolate(a, (time - t) as f32))
+			}
+			(Some((_, a)), None) => Some(BodyState {
+				linear_velocity: [0.0; 3],
+				angular_velocity: [0.0; 3],
+				..extrapolate(a, max_extrapolation as f32)
+			}),
+			// Too far in the past, which happens right after the first state arrived.
+			(None, Some((_, b))) => Some(*b),
+			(None, None) => None,
+		}
+	}
+
+	/// Remove all states, e.g. because the body was teleported.
+	pub fn clear(&mut self) {
+		self.snapshots.clear();
+	}
+}
+
+/// Interpolate between two states `dt` seconds apart. `s` is between 0 and 1.
+fn hermite(a: &BodyState, b: &BodyState, dt: f32, s: f32) -> BodyState {
+	let (s2, s3) = (s * s, s * s * s);
+	// The basis functions and their derivatives.
+	let (h00, h10, h01, h11) = (
+		2.0 * s3 - 3.0 * s2 + 1.0,
+		s3 - 2.0 * s2 + s,
+		-2.0 * s3 + 3.0 * s2,
+		s3 - s2,
+	);
+	let (d00, d10, d01, d11) = (
+		6.0 * s2 - 6.0 * s,
+		3.0 * s2 - 4.0 * s + 1.0,
+		-6.0 * s2 + 6.0 * s,
+		3.0 * s2 - 2.0 * s,
+	);
+	let (mut position, mut linear_velocity, mut angular_velocity) = ([0.0; 3], [0.0; 3], [0.0; 3]);
+	for i in 0..3 {
+		let (p0, v0) = (a.position[i], a.linear_velocity[i]);
+		let (p1, v1) = (b.position[i], b.linear_velocity[i]);
+		position[i] = h00 * p0 + h10 * dt * v0 + h01 * p1 + h11 * dt * v1;
+		linear_velocity[i] = (d00 * p0 + d10 * dt * v0 + d01 * p1 + d11 * dt * v1) / dt;
+		angular_velocity[i] =
+			a.angular_velocity[i] + (b.angular_velocity[i] - a.angular_velocity[i]) * s;
+	}
+	BodyState {
+		rotation: nlerp(a.rotation, b.rotation, s),
+		position,
+		linear_velocity,
+		angular_velocity,
+	}
+}
+
+/// Move a state `dt` seconds forward assuming the velocities stay the same.
+fn extrapolate(state: &BodyState, dt: f32) -> BodyState {
+	let mut position = state.position;
+	for (p, v) in position.iter_mut().zip(&state.linear_velocity) {
+		*p += v * dt;
+	}
+	// Rotate around the axis of the angular velocity.
+	let w = state.angular_velocity;
+	let half = length(w) * dt / 2.0;
+	let rotation = if half > 0.0 {
+		let axis = w.map(|c| c / length(w) * half.sin());
+		normalize(mul([axis[0], axis[1], axis[2], half.cos()], state.rotation))
+	} else {
+		state.rotation
+	};
+	BodyState {
+		rotation,
+		position,
+		..*state
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn state(x: f32, vx: f32) -> BodyState {
+		BodyState {
+			rotation: IDENTITY,
+			position: [x, 0.0, 0.0],
+			linear_velocity: [vx, 0.0, 0.0],
+			angular_velocity: [0.0; 3],
+		}
+	}
+
+	fn assert_near(a: f32, b: f32) {
+		assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+	}
+
+	#[test]
+	fn interpolate() {
+		let mut buf = SnapshotBuffer::new();
+		assert_eq!(buf.sample(0.0, 0.0), None);
+		// A body accelerating at 2 m/s², i.e. x = t², arriving out of order.
+		for &t in &[0.0, 0.2, 0.1, 0.3, 0.2] {
+			buf.push(t, state((t * t) as f32, (2.0 * t) as f32));
+		}
+		for i in 0..=30 {
+			let t = f64::from(i) / 100.0;
+			let s = buf.sample(t, 0.0).unwrap();
+			// Hermite interpolation is exact for quadratic motion.
+			assert_near(s.position[0], (t * t) as f32);
+			assert_near(s.linear_velocity[0], (2.0 * t) as f32);
+		}
+		// Before the first state.
+		assert_eq!(buf.sample(-1.0, 0.0).unwrap().position[0], 0.0);
+	}
+
+	#[test]
+	fn extrapolate() {
+		let mut buf = SnapshotBuffer::new();
+		let mut s = state(1.0, 2.0);
+		s.angular_velocity = [0.0, std::f32::consts::PI, 0.0];
+		buf.push(1.0, s);
+		let e = buf.sample(1.25, 0.5).unwrap();
+		assert_near(e.position[0], 1.5);
+		// A quarter turn around the Y axis.
+		let (sin, cos) = (std::f32::consts::PI / 8.0).sin_cos();
+		assert!(angle(mul(e.rotation, conjugate([0.0, sin, 0.0, cos]))) < 1e-3);
+		// The body stops after the maximum extrapolation time.
+		let e = buf.sample(10.0, 0.5).unwrap();
+		assert_near(e.position[0], 2.0);
+		assert_eq!(e.linear_velocity, [0.0; 3]);
+	}
+
+	#[test]
+	fn timeline() {
+		let mut tl = Timeline::new(1.0 / 60.0, 0.1);
+		assert_eq!(tl.playback_time(0.0), None);
+		// Ticks wrap around and arrive with up to 50 ms of jitter, 3 ticks at a time.
+		let mut rng = 0x1234_5678u32;
+		let mut last = f64::MIN;
+		for i in 0..2000u32 {
+			let tick = 65000u32.wrapping_add(i * 3);
+			rng ^= rng << 13;
+			rng ^= rng >> 17;
+			rng ^= rng << 5;
+			let jitter = f64::from(rng % 50) / 1000.0;
+			let now = f64::from(i * 3) / 60.0 + 0.03 + jitter;
+			let time = tl.receive(tick as u16, now);
+			assert!((time - f64::from(65000 + i * 3) / 60.0).abs() < 1e-9);
+
+			let t = tl.playback_time(now).unwrap();
+			if i > 100 {
+				// The time shown is that of the server minus the delay and the average jitter.
+				let server = now - 0.03 + 65000.0 / 60.0;
+				let behind = server - tl.delay - t;
+				assert!((0.0..0.05).contains(&behind), "{}", behind);
+				assert!(t >= last - 0.01);
+			}
+			last = t;
+		}
+	}
+}
@@ -0,0 +1,201 @@
+//! Quantization of the physics state of a body.
+//!
+//! The rotation is sent as the three smallest components of the quaternion with 10 bits
+//! each, as the largest one follows from the others. Positions and velocities are sent as
+//! fixed point numbers and clamped to a range that is plenty for any map. This takes 25
+//! bytes instead of the 48 bytes of four `Vector3`s.
+
+use std::f32::consts::SQRT_2;
+use std::io;
+
+/// The units per meter of positions, which are 24 bit signed integers. Positions are
+/// limited to 8 km from the origin with a resolution of 1 mm.
+const POSITION_SCALE: f32 = 1024.0;
+const POSITION_BITS: u32 = 24;
+
+/// The units per m/s of linear velocities, which are 16 bit signed integers. Velocities are
+/// limited to 512 m/s.
+const LINEAR_VELOCITY_SCALE: f32 = 64.0;
+
+/// The units per rad/s of angular velocities, which are 16 bit signed integers. Velocities
+/// are limited to about 5 rotations per second.
+const ANGULAR_VELOCITY_SCALE: f32 = 1024.0;
+
+/// The bits per component of a rotation.
+const ROTATION_BITS: u32 = 10;
+const ROTATION_MAX: u32 = (1 << ROTATION_BITS) - 1;
+
+/// The size of an encoded [`BodyState`].
+pub const BODY_STATE_SIZE: usize = 4 + 3 * 3 + 2 * 3 + 2 * 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BodyState {
+	/// A unit quaternion as `[x, y, z, w]`.
+	pub rotation: [f32; 4],
+	pub position: [f32; 3],
+	pub linear_velocity: [f32; 3],
+	pub angular_velocity: [f32; 3],
+}
+
+impl BodyState {
+	pub fn encode(&self, out: &mut impl io::Write) -> io::Result<()> {
+		out.write_all(&encode_rotation(self.rotation).to_le_bytes())?;
+		for &p in self.position.iter() {
+			out.write_all(&fixed(p, POSITION_SCALE, POSITION_BITS).to_le_bytes()[..3])?;
+		}
+		for &v in self.linear_velocity.iter() {
+			out.write_all(&(fixed(v, LINEAR_VELOCITY_SCALE, 16) as i16).to_le_bytes())?;
+		}
+		for &v in self.angular_velocity.iter() {
+			out.write_all(&(fixed(v, ANGULAR_VELOCITY_SCALE, 16) as i16).to_le_bytes())?;
+		}
+		Ok(())
+	}
+
+	pub fn decode(in_: &mut impl io::Read) -> io::Result<Self> {
+		let mut buf = [0; BODY_STATE_SIZE];
+		in_.read_exact(&mut buf)?;
+		let rotation = decode_rotation(u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]));
+		let mut position = [0.0; 3];
+		for (p, b) in position.iter_mut().zip(buf[4..13].chunks(3)) {
+			// Shift the sign bit into place.
+			let n = i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8;
+			*p = n as f32 / POSITION_SCALE;
+		}
+		let mut velocities = buf[13..]
+			.chunks(2)
+			.map(|b| i16::from_le_bytes([b[0], b[1]]));
+		let mut linear_velocity = [0.0; 3];
+		for v in linear_velocity.iter_mut() {
+			*v = f32::from(velocities.next().unwrap()) / LINEAR_VELOCITY_SCALE;
+		}
+		let mut angular_velocity = [0.0; 3];
+		for v in angular_velocity.iter_mut() {
+			*v = f32::from(velocities.next().unwrap()) / ANGULAR_VELOCITY_SCALE;
+		}
+		Ok(Self {
+			rotation,
+			position,
+			linear_velocity,
+			angular_velocity,
+		})
+	}
+}
+
+/// Convert to a signed fixed point number of the given amount of bits, clamping values that
+/// are out of range. NaN becomes 0.
+fn fixed(value: f32, scale: f32, bits: u32) -> i32 {
+	let max = ((1 << (bits - 1)) - 1) as f32;
+	let value = (value * scale).round();
+	if value.is_nan() {
+		0
+	} else {
+		value.clamp(-max, max) as i32
+	}
+}
+
+/// Pack the index of the largest component in the upper 2 bits and the other three
+/// components in 10 bits each. The other components are at most `1 / sqrt(2)` as the
+/// quaternion is normalized.
+fn encode_rotation(q: [f32; 4]) -> u32 {
+	let largest = (0..4)
+		.max_by(|a, b| {
+			q[*a]
+				.abs()
+				.partial_cmp(&q[*b].abs())
+				.unwrap_or(core::cmp::Ordering::Equal)
+		})
+		.unwrap();
+	// q and -q are the same rotation, so the largest component is made positive and left
+	// out.
+	let sign = if q[largest] < 0.0 { -1.0 } else { 1.0 };
+	let mut bits = largest as u32;
+	for (_, c) in q.iter().enumerate().filter(|(i, _)| *i != largest) {
+		let c = (c * sign * SQRT_2).clamp(-1.0, 1.0);
+		let n = ((c + 1.0) / 2.0 * ROTATION_MAX as f32).round() as u32;
+		bits = bits << ROTATION_BITS | n;
+	}
+	bits
+}
+
+fn decode_rotation(bits: u32) -> [f32; 4] {
+	let largest = (bits >> (3 * ROTATION_BITS)) as usize;
+	let mut q = [0.0; 4];
+	let mut shift = 3 * ROTATION_BITS;
+	for (i, c) in q.iter_mut().enumerate() {
+		if i != largest {
+			shift -= ROTATION_BITS;
+			let n = (bits >> shift) & ROTATION_MAX;
+			*c = (n as f32 / ROTATION_MAX as f32 * 2.0 - 1.0) / SQRT_2;
+		}
+	}
+	q[largest] = (1.0 - q.iter().map(|c| c * c).sum::<f32>()).max(0.0).sqrt();
+	let len = q.iter().map(|c| c * c).sum::<f32>().sqrt();
+	for c in q.iter_mut() {
+		*c /= len;
+	}
+	q
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn round_trip(state: &BodyState) -> BodyState {
+		let mut buf = Vec::new();
+		state.encode(&mut buf).unwrap();
+		assert_eq!(buf.len(), BODY_STATE_SIZE);
+		BodyState::decode(&mut &buf[..]).unwrap()
+	}
+
+	fn assert_near(a: &[f32], b: &[f32], max: f32) {
+		for (x, y) in a.iter().zip(b) {
+			assert!((x - y).abs() <= max, "{:?} != {:?}", a, b);
+		}
+	}
+
+	#[test]
+	fn precision() {
+		let half = 0.5f32.sqrt();
+		for &rotation in &[
+			[0.0, 0.0, 0.0, 1.0],
+			[0.0, -1.0, 0.0, 0.0],
+			[half, 0.0, 0.0, -half],
+			[0.1, -0.7, 0.5, 0.4989],
+		] {
+			let state = BodyState {
+				rotation,
+				position: [-1234.5678, 0.0012, 8000.0],
+				linear_velocity: [-12.34, 0.01, 300.0],
+				angular_velocity: [3.0, -0.001, -25.0],
+			};
+			let decoded = round_trip(&state);
+			// The decoded rotation may be negated, which is the same rotation.
+			let dot = decoded
+				.rotation
+				.iter()
+				.zip(&rotation)
+				.map(|(a, b)| a * b)
+				.sum::<f32>();
+			let len = rotation.iter().map(|c| c * c).sum::<f32>().sqrt();
+			assert!(dot.abs() / len > 0.99999, "{:?}", decoded.rotation);
+			assert_near(&decoded.position, &state.position, 0.5 / POSITION_SCALE);
+			assert_near(&decoded.linear_velocity, &state.linear_velocity, 0.01);
+			assert_near(&decoded.angular_velocity, &state.angular_velocity, 0.001);
+		}
+	}
+
+	#[test]
+	fn clamp() {
+		let state = BodyState {
+			rotation: [0.0, 0.0, 0.0, 1.0],
+			position: [1e9, -1e9, f32::NAN],
+			linear_velocity: [1000.0, -1000.0, 0.0],
+			angular_velocity: [f32::INFINITY, 0.0, 0.0],
+		};
+		let decoded = round_trip(&state);
+		assert_near(&decoded.position, &[8192.0, -8192.0, 0.0], 0.001);
+		assert_near(&decoded.linear_velocity, &[512.0, -512.0, 0.0], 0.02);
+		assert_near(&decoded.angular_velocity, &[32.0, 0.0, 0.0], 0.001);
+	}
+}
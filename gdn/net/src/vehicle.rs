@@ -0,0 +1,723 @@
+//! Decoding & checking of serialized vehicles.
+//!
+//! Clients receive every vehicle in this format when they join or when it spawns, so it is
+//! checked as thoroughly as packets are: a vehicle that decodes without errors can be created
+//! without panicking. The blocks are known only to the game, which describes them with a
+//! [`BlockInfo`].
+//!
+//! A vehicle consists of:
+//!
+//! * The revision of the format, [`VEHICLE_REVISION`].
+//! * The index of the last processed packet as `u16`.
+//! * The team.
+//! * The amount of colors as `u8`, followed by each color as three bytes.
+//! * The main body.
+//!
+//! A body consists of:
+//!
+//! * The offset and end of the AABB, three bytes each.
+//! * A bit for every cell of the AABB, set if the cell is occupied.
+//! * The block ID as `u16`, the rotation and the color of every occupied cell.
+//! * A bit for every occupied cell, set if it is damaged, followed by the health as `u16` of
+//!   every damaged cell.
+//! * The amount of multiblocks as `u16`, a bit for every multiblock, set if it is damaged,
+//!   followed by the health as `u32` of every damaged multiblock.
+//! * The amount of children as `u8`, followed by the children.
+//! * If any block is left, the position, the rotation, the linear velocity & the angular
+//!   velocity as three `f32`s each. Only X, Y & Z of the rotation are included as W is
+//!   positive.
+//!
+//! Bits are packed in bytes, starting with the lowest bit.
+
+use crate::packet::{Decoder, ProcessPacketError};
+use crate::state::BodyState;
+use core::convert::TryFrom;
+use core::fmt;
+use core::num::{NonZeroU16, NonZeroU32};
+use std::collections::HashMap;
+use std::io;
+
+/// The revision of the format. Increase it whenever the format changes.
+pub const VEHICLE_REVISION: u8 = 1;
+
+/// The maximum amount of bodies in a vehicle, which is the maximum amount of layers.
+pub const MAX_BODIES: usize = u8::MAX as usize;
+
+/// The highest valid rotation of a block.
+const MAX_ROTATION: u8 = 23;
+
+/// The bit of the health of a cell that is set if it points to a multiblock.
+const MULTI_BLOCK: u16 = 0x8000;
+
+/// What the game knows about a block.
+#[derive(Clone, Copy, Debug)]
+pub struct BlockKind {
+	/// The health of the block if it isn't damaged.
+	pub health: NonZeroU32,
+	/// Whether the block stores its health in a multiblock.
+	pub multi_block: bool,
+}
+
+/// Looks up the blocks of the game.
+pub trait BlockInfo {
+	/// Get the block with the given ID, or `None` if it isn't registered.
+	fn get(&self, id: NonZeroU16) -> Option<BlockKind>;
+
+	/// The offsets from the cell of a multiblock to the other cells it occupies when it has
+	/// the given rotation, which is at most 23.
+	fn multi_block_cells(&self, id: NonZeroU16, rotation: u8) -> Vec<[i16; 3]>;
+}
+
+/// Enum returned when a serialized vehicle is malformed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeserializeError {
+	/// A field couldn't be read.
+	Decode(ProcessPacketError),
+	/// The data is of a revision of the format that isn't supported.
+	UnknownRevision(u8),
+	/// The AABB of a body doesn't fit in the vehicle.
+	InvalidAABB,
+	/// A block ID isn't registered.
+	UnknownBlock(u16),
+	/// The health of a block is too high or doesn't match the type of block.
+	InvalidHealth(u16),
+	/// A rotation is out of range.
+	InvalidRotation(u8),
+	/// A color index is past the end of the palette.
+	InvalidColor(u8),
+	/// A multiblock is missing, used by multiple blocks or has too much health.
+	InvalidMultiBlock(u16),
+	/// There are more bodies than there can be layers.
+	TooManyBodies,
+}
+
+impl From<ProcessPacketError> for DeserializeError {
+	fn from(e: ProcessPacketError) -> Self {
+		Self::Decode(e)
+	}
+}
+
+impl fmt::Display for DeserializeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Decode(e) => e.fmt(f),
+			Self::UnknownRevision(r) => write!(f, "unknown revision {}", r),
+			Self::InvalidAABB => "AABB is out of bounds".fmt(f),
+			Self::UnknownBlock(id) => write!(f, "unknown block ID {}", id),
+			Self::InvalidHealth(hp) => write!(f, "invalid block health {:#x}", hp),
+			Self::InvalidRotation(r) => write!(f, "invalid rotation {}", r),
+			Self::InvalidColor(c) => write!(f, "invalid color index {}", c),
+			Self::InvalidMultiBlock(i) => write!(f, "invalid multiblock {}", i),
+			Self::TooManyBodies => "too many bodies".fmt(f),
+		}
+	}
+}
+
+/// A vehicle that has been decoded and checked.
+#[derive(Debug, PartialEq)]
+pub struct DecodedVehicle {
+	pub last_processed_packet_index: u16,
+	pub team: u8,
+	pub colors: Vec<[u8; 3]>,
+	pub main_body: DecodedBody,
+}
+
+/// A body that has been decoded and checked.
+///
+/// The cells are in the order they are serialized in, which is that of the voxel grids of
+/// the game.
+#[derive(Debug, PartialEq)]
+pub struct DecodedBody {
+	pub offset: [u8; 3],
+	pub end: [u8; 3],
+	/// The block ID of every cell.
+	pub ids: Box<[Option<NonZeroU16>]>,
+	/// The health of every cell. The upper bit (`0x8000`) is set if it points to a
+	/// multiblock.
+	pub health: Box<[Option<NonZeroU16>]>,
+	/// The rotation of every cell, which is at most 23.
+	pub rotations: Box<[u8]>,
+	/// The color of every cell, which is in the palette if there is a block.
+	pub colors: Box<[u8]>,
+	/// The health of every multiblock.
+	pub multi_blocks: Vec<Option<NonZeroU32>>,
+	pub children: Vec<DecodedBody>,
+	/// The state if the body isn't destroyed.
+	pub state: Option<BodyState>,
+}
+
+impl DecodedVehicle {
+	pub fn decode(data: &[u8], blocks: &impl BlockInfo) -> Result<Self, DeserializeError> {
+		let mut in_ = Decoder::new(data);
+
+		match in_.u8()? {
+			VEHICLE_REVISION => {}
+			rev => return Err(DeserializeError::UnknownRevision(rev)),
+		}
+		let last_processed_packet_index = in_.u16()?;
+		let team = in_.u8()?;
+		let count = in_.u8()?;
+		let colors = in_
+			.bytes(usize::from(count) * 3)?
+			.chunks_exact(3)
+			.map(|c| [c[0], c[1], c[2]])
+			.collect::<Vec<_>>();
+		let main_body = DecodedBody::decode(&mut in_, blocks, colors.len(), &mut 0)?;
+		in_.finish()?;
+
+		Ok(Self {
+			last_processed_packet_index,
+			team,
+			colors,
+			main_body,
+		})
+	}
+}
+
+impl DecodedBody {
+	/// Decode a body and its children.
+	///
+	/// `palette` is the amount of colors of the vehicle and `bodies` the amount of bodies
+	/// decoded so far.
+	fn decode(
+		in_: &mut Decoder,
+		blocks: &impl BlockInfo,
+		palette: usize,
+		bodies: &mut usize,
+	) -> Result<Self, DeserializeError> {
+		*bodies += 1;
+		if *bodies > MAX_BODIES {
+			return Err(DeserializeError::TooManyBodies);
+		}
+
+		// Get AABB
+		let offset = [in_.u8()?, in_.u8()?, in_.u8()?];
+		let end = [in_.u8()?, in_.u8()?, in_.u8()?];
+		if offset
+			.iter()
+			.zip(&end)
+			.any(|(&o, &e)| u16::from(o) + u16::from(e) > u16::from(u8::MAX))
+		{
+			return Err(DeserializeError::InvalidAABB);
+		}
+		let count = end.iter().map(|&e| usize::from(e) + 1).product::<usize>();
+
+		// Get the occupied cells. This is read before anything is allocated so a large AABB
+		// can't be used to allocate a lot of memory with little data.
+		let occupied = read_bits(in_, count)?
+			.enumerate()
+			.filter_map(|(i, o)| o.then_some(i))
+			.collect::<Vec<_>>();
+
+		// Get the block ids, rotations & colors
+		let mut ids = vec![None; count].into_boxed_slice();
+		let mut health = vec![None; count].into_boxed_slice();
+		let mut rotations = vec![0; count].into_boxed_slice();
+		let mut colors = vec![0; count].into_boxed_slice();
+		for &i in occupied.iter() {
+			let id = in_.u16()?;
+			if let Some(id) = NonZeroU16::new(id) {
+				blocks
+					.get(id)
+					.ok_or(DeserializeError::UnknownBlock(id.get()))?;
+				ids[i] = Some(id);
+			}
+			rotations[i] = match in_.u8()? {
+				r if r <= MAX_ROTATION => r,
+				r => return Err(DeserializeError::InvalidRotation(r)),
+			};
+			colors[i] = in_.u8()?;
+			if ids[i].is_some() && usize::from(colors[i]) >= palette {
+				return Err(DeserializeError::InvalidColor(colors[i]));
+			}
+		}
+
+		// Get the health of damaged blocks
+		for (&i, damaged) in occupied.iter().zip(read_bits(in_, occupied.len())?) {
+			health[i] = if damaged {
+				NonZeroU16::new(in_.u16()?)
+			} else {
+				ids[i].and_then(|id| full_health(blocks, id))
+			};
+		}
+
+		// Get the health of damaged multiblocks
+		let count = in_.u16()?;
+		let cells = ids.iter().copied().zip(health.iter().copied());
+		let full = full_multi_block_health(blocks, cells, count.into());
+		let mut multi_blocks = Vec::with_capacity(count.into());
+		for (damaged, full) in read_bits(in_, count.into())?.zip(full.iter()) {
+			multi_blocks.push(if damaged {
+				NonZeroU32::new(in_.u32()?)
+			} else {
+				*full
+			});
+		}
+
+		// Check the health of all blocks
+		let mut bases = vec![None; multi_blocks.len()];
+		let mut alive = false;
+		for (i, (id, hp)) in ids.iter().zip(health.iter()).enumerate() {
+			let hp = hp.map_or(0, NonZeroU16::get);
+			let block = id.and_then(|id| blocks.get(id));
+			let multi_block = if hp & MULTI_BLOCK != 0 {
+				let index = hp & !MULTI_BLOCK;
+				if usize::from(index) >= multi_blocks.len() {
+					return Err(DeserializeError::InvalidMultiBlock(index));
+				}
+				Some(index)
+			} else {
+				None
+			};
+			match (block, multi_block) {
+				_ if hp == 0 => {}
+				// The other spots of a multiblock, which are checked below.
+				(None, Some(_)) => {}
+				(Some(b), Some(index)) if b.multi_block => {
+					let mb_hp = multi_blocks[usize::from(index)];
+					if mb_hp.is_none_or(|mb_hp| mb_hp > b.health)
+						|| bases[usize::from(index)].replace(i).is_some()
+					{
+						return Err(DeserializeError::InvalidMultiBlock(index));
+					}
+					alive = true;
+				}
+				(Some(b), None) if !b.multi_block && u32::from(hp) <= b.health.get() => {
+					alive = true;
+				}
+				_ => return Err(DeserializeError::InvalidHealth(hp)),
+			}
+		}
+
+		// Every multiblock that isn't destroyed must belong to a block.
+		if let Some(i) = bases
+			.iter()
+			.zip(&multi_blocks)
+			.position(|(base, hp)| base.is_none() && hp.is_some())
+		{
+			return Err(DeserializeError::InvalidMultiBlock(i as u16));
+		}
+
+		// The other spots of a multiblock that isn't destroyed must be part of it, else
+		// damage anywhere could be routed to it. Spots of destroyed multiblocks are left
+		// behind by the game, but can't be damaged anymore.
+		let mut spots = HashMap::new();
+		for (index, base) in bases.iter().enumerate() {
+			if let Some(base) = *base {
+				let cells = blocks.multi_block_cells(ids[base].unwrap(), rotations[base]);
+				for delta in cells {
+					if let Some(i) = offset_index(end, base, delta) {
+						spots.insert(i, index);
+					}
+				}
+			}
+		}
+		for (i, (id, hp)) in ids.iter().zip(health.iter()).enumerate() {
+			if let (None, Some(hp)) = (id, hp) {
+				let index = usize::from(hp.get() & !MULTI_BLOCK);
+				if multi_blocks[index].is_some() && spots.get(&i) != Some(&index) {
+					return Err(DeserializeError::InvalidMultiBlock(index as u16));
+				}
+			}
+		}
+
+		// Get the children
+		let count = in_.u8()?;
+		let mut children = Vec::with_capacity(count.into());
+		for _ in 0..count {
+			children.push(Self::decode(in_, blocks, palette, bodies)?);
+		}
+
+		// If alive, get position & velocity
+		let state = if alive {
+			let position = in_.vector3()?;
+			let [x, y, z] = in_.vector3()?;
+			let square_length = x * x + y * y + z * z;
+			if square_length > 1.0 + 1e-3 {
+				return Err(ProcessPacketError::OutOfRange("rotation").into());
+			}
+			let w = (1.0 - square_length).max(0.0).sqrt();
+			Some(BodyState {
+				rotation: [x, y, z, w],
+				position,
+				linear_velocity: in_.vector3()?,
+				angular_velocity: in_.vector3()?,
+			})
+		} else {
+			None
+		};
+
+		Ok(Self {
+			offset,
+			end,
+			ids,
+			health,
+			rotations,
+			colors,
+			multi_blocks,
+			children,
+			state,
+		})
+	}
+}
+
+/// The health of a block if it isn't damaged. Multiblocks don't store their health in the
+/// cell, so this is `None` for them.
+pub fn full_health(blocks: &impl BlockInfo, id: NonZeroU16) -> Option<NonZeroU16> {
+	blocks
+		.get(id)
+		.filter(|b| !b.multi_block)
+		.and_then(|b| NonZeroU16::try_from(b.health).ok())
+}
+
+/// The health of each multiblock if it isn't damaged, which is that of the block using it.
+///
+/// `cells` are the block ID & health of every cell.
+pub fn full_multi_block_health(
+	blocks: &impl BlockInfo,
+	cells: impl Iterator<Item = (Option<NonZeroU16>, Option<NonZeroU16>)>,
+	count: usize,
+) -> Vec<Option<NonZeroU32>> {
+	let mut health = vec![None; count];
+	for (id, hp) in cells {
+		if let (Some(b), Some(hp)) = (id.and_then(|id| blocks.get(id)), hp) {
+			if b.multi_block && hp.get() & MULTI_BLOCK != 0 {
+				if let Some(h) = health.get_mut(usize::from(hp.get() & !MULTI_BLOCK)) {
+					*h = Some(b.health);
+				}
+			}
+		}
+	}
+	health
+}
+
+/// The index of the cell at `delta` from the cell at index `from` in a grid with the given
+/// end, or `None` if it is out of range. Cells are ordered by X, then Y, then Z.
+fn offset_index(end: [u8; 3], from: usize, delta: [i16; 3]) -> Option<usize> {
+	let size = end.map(|e| usize::from(e) + 1);
+	let position = [
+		from / (size[1] * size[2]),
+		from / size[2] % size[1],
+		from % size[2],
+	];
+	let mut index = 0;
+	for ((p, d), s) in position.iter().zip(&delta).zip(&size) {
+		let p = usize::try_from(*p as isize + isize::from(*d)).ok()?;
+		if p >= *s {
+			return None;
+		}
+		index = index * s + p;
+	}
+	Some(index)
+}
+
+/// Write a bit for every item, packed in bytes starting with the lowest bit.
+pub fn write_bits(out: &mut impl io::Write, bits: impl Iterator<Item = bool>) -> io::Result<()> {
+	let (mut byte, mut n) = (0, 0);
+	for b in bits {
+		byte |= u8::from(b) << n;
+		n += 1;
+		if n == 8 {
+			out.write_all(&[byte])?;
+			byte = 0;
+			n = 0;
+		}
+	}
+	if n > 0 {
+		out.write_all(&[byte])?;
+	}
+	Ok(())
+}
+
+/// Read `count` bits written by `write_bits`.
+fn read_bits<'a>(
+	in_: &mut Decoder<'a>,
+	count: usize,
+) -> Result<impl Iterator<Item = bool> + 'a, ProcessPacketError> {
+	let bytes = in_.bytes(count.div_ceil(8))?;
+	Ok((0..count).map(move |i| bytes[i / 8] & (1 << (i % 8)) != 0))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	const BLOCK: NonZeroU16 = NonZeroU16::new(1).unwrap();
+	const MULTI: NonZeroU16 = NonZeroU16::new(2).unwrap();
+
+	/// A regular block with 100 health and a multiblock with 1000 health, which also
+	/// occupies the next cell along X.
+	struct Blocks;
+
+	impl BlockInfo for Blocks {
+		fn get(&self, id: NonZeroU16) -> Option<BlockKind> {
+			let (health, multi_block) = match id.get() {
+				1 => (100, false),
+				2 => (1000, true),
+				_ => return None,
+			};
+			Some(BlockKind {
+				health: NonZeroU32::new(health).unwrap(),
+				multi_block,
+			})
+		}
+
+		fn multi_block_cells(&self, _: NonZeroU16, _: u8) -> Vec<[i16; 3]> {
+			vec![[1, 0, 0]]
+		}
+	}
+
+	fn encode(v: &DecodedVehicle) -> Vec<u8> {
+		let mut out = vec![VEHICLE_REVISION];
+		out.extend(&v.last_processed_packet_index.to_le_bytes());
+		out.push(v.team);
+		out.push(v.colors.len() as u8);
+		v.colors.iter().for_each(|c| out.extend(c));
+		encode_body(&v.main_body, &mut out);
+		out
+	}
+
+	/// The same as the game does.
+	fn encode_body(b: &DecodedBody, out: &mut Vec<u8>) {
+		out.extend(&b.offset);
+		out.extend(&b.end);
+		let cells = || b.ids.iter().copied().zip(b.health.iter().copied());
+		let occupied = cells()
+			.enumerate()
+			.filter(|(_, (id, hp))| id.is_some() || hp.is_some())
+			.map(|(i, _)| i)
+			.collect::<Vec<_>>();
+		write_bits(out, cells().map(|(id, hp)| id.is_some() || hp.is_some())).unwrap();
+		for &i in occupied.iter() {
+			out.extend(&b.ids[i].map_or(0, NonZeroU16::get).to_le_bytes());
+			out.extend(&[b.rotations[i], b.colors[i]]);
+		}
+		let damaged = |i: usize| b.health[i] != b.ids[i].and_then(|id| full_health(&Blocks, id));
+		write_bits(out, occupied.iter().map(|&i| damaged(i))).unwrap();
+		for &i in occupied.iter().filter(|&&i| damaged(i)) {
+			out.extend(&b.health[i].map_or(0, NonZeroU16::get).to_le_bytes());
+		}
+		let full = full_multi_block_health(&Blocks, cells(), b.multi_blocks.len());
+		out.extend(&(b.multi_blocks.len() as u16).to_le_bytes());
+		let health = || {
+			b.multi_blocks
+				.iter()
+				.zip(&full)
+				.filter(|(hp, full)| hp != full)
+		};
+		write_bits(out, b.multi_blocks.iter().zip(&full).map(|(hp, f)| hp != f)).unwrap();
+		for (hp, _) in health() {
+			out.extend(&hp.map_or(0, NonZeroU32::get).to_le_bytes());
+		}
+		out.push(b.children.len() as u8);
+		b.children.iter().for_each(|c| encode_body(c, out));
+		if let Some(s) = &b.state {
+			let [x, y, z, _] = s.rotation;
+			for v in [s.position, [x, y, z], s.linear_velocity, s.angular_velocity] {
+				v.iter().for_each(|c| out.extend(&c.to_le_bytes()));
+			}
+		}
+	}
+
+	fn hp(n: u16) -> Option<NonZeroU16> {
+		NonZeroU16::new(n)
+	}
+
+	fn state() -> Option<BodyState> {
+		Some(BodyState {
+			rotation: [0.0, 0.0, 1.0, 0.0],
+			position: [1.0, 2.0, 3.0],
+			linear_velocity: [4.0, 5.0, 6.0],
+			angular_velocity: [7.0, 8.0, 9.0],
+		})
+	}
+
+	/// A body with only empty cells, which is destroyed.
+	fn empty() -> DecodedBody {
+		DecodedBody {
+			offset: [0; 3],
+			end: [0; 3],
+			ids: Box::new([None]),
+			health: Box::new([None]),
+			rotations: Box::new([0]),
+			colors: Box::new([0]),
+			multi_blocks: Vec::new(),
+			children: Vec::new(),
+			state: None,
+		}
+	}
+
+	/// A main body with a damaged block, an undamaged block and a multiblock spanning two
+	/// cells. Its child has a destroyed block.
+	fn vehicle() -> DecodedVehicle {
+		let mut child = empty();
+		child.ids[0] = Some(BLOCK);
+		DecodedVehicle {
+			last_processed_packet_index: 1234,
+			team: 3,
+			colors: vec![[255, 0, 0], [0, 255, 0]],
+			main_body: DecodedBody {
+				offset: [10, 20, 30],
+				end: [4, 0, 0],
+				ids: Box::new([Some(BLOCK), Some(BLOCK), Some(MULTI), None, None]),
+				health: Box::new([hp(50), hp(100), hp(0x8000), hp(0x8000), None]),
+				rotations: Box::new([0, 23, 5, 0, 0]),
+				colors: Box::new([1, 0, 1, 0, 0]),
+				multi_blocks: vec![NonZeroU32::new(1000)],
+				children: vec![child],
+				state: state(),
+			},
+		}
+	}
+
+	fn decode(v: &DecodedVehicle) -> Result<DecodedVehicle, DeserializeError> {
+		DecodedVehicle::decode(&encode(v), &Blocks)
+	}
+
+	#[test]
+	fn accept() {
+		let v = vehicle();
+		assert_eq!(decode(&v), Ok(vehicle()));
+
+		// A damaged multiblock
+		let mut v = vehicle();
+		v.main_body.multi_blocks[0] = NonZeroU32::new(1);
+		assert_eq!(decode(&v), Ok(v));
+
+		// A destroyed multiblock whose cells are left
+		let mut v = vehicle();
+		v.main_body.ids[2] = None;
+		v.main_body.health[2] = None;
+		v.main_body.rotations[2] = 0;
+		v.main_body.colors[2] = 0;
+		v.main_body.multi_blocks[0] = None;
+		assert_eq!(decode(&v), Ok(v));
+
+		// Colors of empty spots of multiblocks aren't used
+		let mut v = vehicle();
+		v.main_body.colors[3] = 200;
+		assert_eq!(decode(&v), Ok(v));
+
+		// An AABB that touches the edge
+		let mut v = vehicle();
+		v.main_body.offset = [251, 0, 0];
+		assert_eq!(decode(&v), Ok(v));
+
+		// As many bodies as there may be
+		let mut v = vehicle();
+		v.main_body.children.clear();
+		let mut body = &mut v.main_body;
+		for _ in 1..MAX_BODIES {
+			body.children.push(empty());
+			body = &mut body.children[0];
+		}
+		assert_eq!(decode(&v), Ok(v));
+	}
+
+	#[test]
+	fn offset_index() {
+		// The cell at (1, 1, 1) of a 2x3x4 grid
+		let end = [1, 2, 3];
+		let from = (3 + 1) * 4 + 1;
+		assert_eq!(super::offset_index(end, from, [0, 0, 0]), Some(from));
+		assert_eq!(super::offset_index(end, from, [0, 0, 1]), Some(from + 1));
+		assert_eq!(super::offset_index(end, from, [0, 1, 0]), Some(from + 4));
+		assert_eq!(super::offset_index(end, from, [-1, 0, 0]), Some(from - 12));
+		assert_eq!(super::offset_index(end, from, [0, 0, 3]), None);
+		assert_eq!(super::offset_index(end, from, [-2, 0, 0]), None);
+	}
+
+	#[test]
+	fn reject() {
+		let mut data = encode(&vehicle());
+		data.push(0);
+		let e = DeserializeError::Decode(ProcessPacketError::TrailingData);
+		assert_eq!(DecodedVehicle::decode(&data, &Blocks), Err(e));
+		data.truncate(data.len() - 2);
+		let e = DeserializeError::Decode(ProcessPacketError::Truncated);
+		assert_eq!(DecodedVehicle::decode(&data, &Blocks), Err(e));
+
+		let mut data = encode(&vehicle());
+		data[0] = VEHICLE_REVISION + 1;
+		let e = DeserializeError::UnknownRevision(VEHICLE_REVISION + 1);
+		assert_eq!(DecodedVehicle::decode(&data, &Blocks), Err(e));
+
+		let check = |f: fn(&mut DecodedVehicle), e| {
+			let mut v = vehicle();
+			f(&mut v);
+			assert_eq!(decode(&v), Err(e));
+		};
+		check(
+			|v| v.main_body.state.as_mut().unwrap().rotation = [1.0, 1.0, 0.0, 0.0],
+			DeserializeError::Decode(ProcessPacketError::OutOfRange("rotation")),
+		);
+		check(
+			|v| v.main_body.offset = [252, 0, 0],
+			DeserializeError::InvalidAABB,
+		);
+		check(
+			|v| v.main_body.ids[1] = NonZeroU16::new(3),
+			DeserializeError::UnknownBlock(3),
+		);
+		check(
+			|v| v.main_body.health[0] = hp(101),
+			DeserializeError::InvalidHealth(101),
+		);
+		// A multiblock that doesn't point to its health
+		check(
+			|v| v.main_body.health[2] = hp(100),
+			DeserializeError::InvalidHealth(100),
+		);
+		// A regular block that points to a multiblock
+		check(
+			|v| v.main_body.health[1] = hp(0x8000),
+			DeserializeError::InvalidHealth(0x8000),
+		);
+		check(
+			|v| v.main_body.rotations[0] = 24,
+			DeserializeError::InvalidRotation(24),
+		);
+		check(
+			|v| v.main_body.colors[0] = 2,
+			DeserializeError::InvalidColor(2),
+		);
+		// Past the end
+		check(
+			|v| v.main_body.health[3] = hp(0x8001),
+			DeserializeError::InvalidMultiBlock(1),
+		);
+		// Too much health
+		check(
+			|v| v.main_body.multi_blocks[0] = NonZeroU32::new(1001),
+			DeserializeError::InvalidMultiBlock(0),
+		);
+		// Used twice
+		check(
+			|v| {
+				v.main_body.ids[3] = Some(MULTI);
+				v.main_body.colors[3] = 0;
+			},
+			DeserializeError::InvalidMultiBlock(0),
+		);
+		// A spot that isn't part of the multiblock
+		check(
+			|v| v.main_body.health[4] = hp(0x8000),
+			DeserializeError::InvalidMultiBlock(0),
+		);
+		// Not used by any block
+		check(
+			|v| v.main_body.multi_blocks.push(NonZeroU32::new(1)),
+			DeserializeError::InvalidMultiBlock(1),
+		);
+		check(
+			|v| {
+				let mut body = &mut v.main_body;
+				for _ in 0..MAX_BODIES {
+					body.children.push(empty());
+					body = body.children.last_mut().unwrap();
+				}
+			},
+			DeserializeError::TooManyBodies,
+		);
+	}
+}
@@ -560,6 +560,30 @@ impl Block {
 	}
 }
 
+/// The registered blocks, as needed to decode vehicles.
+pub struct Registered;
+
+impl own_war_net::BlockInfo for Registered {
+	fn get(&self, id: NonZeroU16) -> Option<own_war_net::BlockKind> {
+		Block::get(id).map(|b| own_war_net::BlockKind {
+			health: b.health,
+			multi_block: b.is_multi_block(),
+		})
+	}
+
+	fn multi_block_cells(&self, id: NonZeroU16, rotation: u8) -> Vec<[i16; 3]> {
+		let rotation = Rotation::new(rotation).expect("Invalid rotation");
+		Block::get(id).map_or(Vec::new(), |b| {
+			// The same cells as `Body::add_block` uses.
+			b.extra_mount_points
+				.iter()
+				.map(|m| rotation * voxel::Delta::from(m.position))
+				.map(|d| [d.x, d.y, d.z])
+				.collect()
+		})
+	}
+}
+
 /// Generic helper methods
 impl Block {
 	pub fn get(id: NonZeroU16) -> Option<&'static Block> {
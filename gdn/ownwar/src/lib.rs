@@ -14,6 +14,7 @@
 mod block;
 mod constants;
 mod editor;
+mod lobby;
 mod rotation;
 mod types;
 mod util;
@@ -25,6 +26,7 @@ fn init(handle: InitHandle) {
 	vehicle::init(handle);
 	block::init(handle);
 	editor::init(handle);
+	lobby::init(handle);
 }
 
 godot_init!(init);
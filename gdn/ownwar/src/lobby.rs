@@ -0,0 +1,427 @@
+//! Bindings to the lobby protocol so GDScript doesn't need to encode packets by hand.
+
+use gdnative::prelude::*;
+use own_war_lobby_protocol::*;
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+
+/// Helper class intended for GDScript usage
+///
+/// Addresses are passed as `"ip:port"` strings. Packets that fail to encode are returned as
+/// empty arrays.
+#[derive(NativeClass)]
+#[inherit(Reference)]
+pub struct LobbyProtocol;
+
+#[methods]
+impl LobbyProtocol {
+	fn new(_: &Reference) -> Self {
+		Self
+	}
+
+	#[export]
+	fn protocol_version(&self, _: TRef<Reference>) -> u8 {
+		PROTOCOL_VERSION
+	}
+
+	#[export]
+	fn create_hello(&self, _: TRef<Reference>) -> TypedArray<u8> {
+		encode(Request::Hello)
+	}
+
+	#[export]
+	fn create_register_server(
+		&self,
+		_: TRef<Reference>,
+		port: u16,
+		token: i64,
+		name: String,
+		map: String,
+		max_players: u8,
+		description: String,
+		status: Dictionary,
+	) -> TypedArray<u8> {
+		encode(Request::RegisterServer(Register {
+			port,
+			token: token as u64,
+			name: &name,
+			map: &map,
+			max_players,
+			description: &description,
+			status: decode_status(&status),
+		}))
+	}
+
+	#[export]
+	fn create_remove_server(&self, _: TRef<Reference>, port: u16, token: i64) -> TypedArray<u8> {
+		encode(Request::RemoveServer {
+			port,
+			token: token as u64,
+		})
+	}
+
+	#[export]
+	fn create_ping(
+		&self,
+		_: TRef<Reference>,
+		port: u16,
+		token: i64,
+		status: Dictionary,
+	) -> TypedArray<u8> {
+		encode(Request::Ping {
+			port,
+			token: token as u64,
+			status: decode_status(&status),
+		})
+	}
+
+	#[export]
+	fn create_server_info(&self, _: TRef<Reference>, addr: String) -> TypedArray<u8> {
+		match addr.parse() {
+			Ok(addr) => encode(Request::ServerInfo { addr }),
+			Err(_) => invalid_addr(&addr),
+		}
+	}
+
+	#[export]
+	fn create_punch_hole(&self, _: TRef<Reference>, addr: String) -> TypedArray<u8> {
+		match addr.parse() {
+			Ok(addr) => encode(Request::PunchHole { addr }),
+			Err(_) => invalid_addr(&addr),
+		}
+	}
+
+	#[export]
+	fn create_who_am_i(&self, _: TRef<Reference>) -> TypedArray<u8> {
+		encode(Request::WhoAmI)
+	}
+
+	#[export]
+	fn create_punch_request(&self, _: TRef<Reference>, addr: String) -> TypedArray<u8> {
+		match addr.parse() {
+			Ok(addr) => encode(Request::PunchRequest { addr }),
+			Err(_) => invalid_addr(&addr),
+		}
+	}
+
+	/// Create a `PunchAck`. `result` is 0 if the `Punch` was received, 1 if a connection was
+	/// made and 2 if connecting failed.
+	#[export]
+	fn create_punch_ack(&self, _: TRef<Reference>, session: i64, result: u8) -> TypedArray<u8> {
+		match PunchResult::try_from(result) {
+			Ok(result) => encode(Request::PunchAck {
+				session: session as u64,
+				result,
+			}),
+			Err(e) => {
+				godot_error!("{}", e);
+				TypedArray::new()
+			}
+		}
+	}
+
+	#[export]
+	fn create_advertise(
+		&self,
+		_: TRef<Reference>,
+		port: u16,
+		token: i64,
+		team_size: u8,
+		region: String,
+	) -> TypedArray<u8> {
+		encode(Request::Advertise {
+			port,
+			token: token as u64,
+			team_size,
+			region: &region,
+		})
+	}
+
+	/// Create an `Enqueue` request. Empty strings and a team size of 0 match anything.
+	#[export]
+	fn create_enqueue(
+		&self,
+		_: TRef<Reference>,
+		map: String,
+		team_size: u8,
+		region: String,
+	) -> TypedArray<u8> {
+		encode(Request::Enqueue(Preferences {
+			map: &map,
+			team_size,
+			region: &region,
+		}))
+	}
+
+	#[export]
+	fn create_dequeue(&self, _: TRef<Reference>) -> TypedArray<u8> {
+		encode(Request::Dequeue)
+	}
+
+	/// Create a chunk of an `UploadVehicle` request. `data` is the part of the `.owv` file
+	/// starting at `offset` and at most `VEHICLE_CHUNK_SIZE` bytes long.
+	#[export]
+	fn create_upload_vehicle(
+		&self,
+		_: TRef<Reference>,
+		total: u32,
+		offset: u32,
+		tags: String,
+		data: TypedArray<u8>,
+	) -> TypedArray<u8> {
+		let data = data.read();
+		encode(Request::UploadVehicle(Upload {
+			total,
+			offset,
+			tags: &tags,
+			data: &data[..],
+		}))
+	}
+
+	/// Create a `SearchVehicles` request. `cursor` is 0 to get the first page.
+	#[export]
+	fn create_search_vehicles(
+		&self,
+		_: TRef<Reference>,
+		cursor: i64,
+		limit: u8,
+		name: String,
+		tags: String,
+	) -> TypedArray<u8> {
+		encode(Request::SearchVehicles(SearchVehicles {
+			cursor: cursor as u64,
+			limit,
+			name: &name,
+			tags: &tags,
+		}))
+	}
+
+	#[export]
+	fn create_download_vehicle(&self, _: TRef<Reference>, id: i64, offset: u32) -> TypedArray<u8> {
+		encode(Request::DownloadVehicle {
+			id: id as u64,
+			offset,
+		})
+	}
+
+	/// Create a `Verify` request. `cookie` is 0 or the cookie of the last `Verify` response.
+	#[export]
+	fn create_verify(&self, _: TRef<Reference>, cookie: i64) -> TypedArray<u8> {
+		encode(Request::Verify {
+			cookie: cookie as u64,
+		})
+	}
+
+	/// Create a `ListServers` request. `cursor` may be empty to get the first page. `filter`
+	/// may contain the keys `map`, `name`, `free_slots` and `version`.
+	#[export]
+	fn create_list_servers(
+		&self,
+		_: TRef<Reference>,
+		cursor: String,
+		limit: u8,
+		filter: Dictionary,
+	) -> TypedArray<u8> {
+		let cursor = match cursor.as_str() {
+			"" => None,
+			c => match c.parse::<SocketAddr>() {
+				Ok(c) => Some(c),
+				Err(_) => return invalid_addr(c),
+			},
+		};
+		let map = filter.get("map").try_to_string();
+		let name = filter.get("name").try_to_string();
+		let int = |key: &str| {
+			filter
+				.get(key)
+				.try_to_i64()
+				.and_then(|v| u8::try_from(v).ok())
+		};
+		encode(Request::ListServers(ListServers {
+			cursor,
+			limit,
+			filter: ListFilter {
+				map: map.as_deref(),
+				name: name.as_deref(),
+				free_slots: int("free_slots"),
+				version: int("version"),
+			},
+		}))
+	}
+
+	/// Decode a packet sent by the lobby. The `type` key holds the message type, the other keys
+	/// depend on the type. If the packet is invalid or uses another protocol version than
+	/// [`PROTOCOL_VERSION`], only the `error` key is set. Errors are decoded regardless of the
+	/// version.
+	#[export]
+	fn decode_response(&self, _: TRef<Reference>, packet: TypedArray<u8>) -> Dictionary {
+		let packet = packet.read();
+		let dict = Dictionary::new();
+		let rsp = Header::decode(&packet[..]).and_then(|(header, payload)| {
+			let typ = MessageType::try_from(header.message_type)?;
+			Response::decode(typ, payload).map(|rsp| (header.version, rsp))
+		});
+		let rsp = match rsp {
+			Ok((version, rsp))
+				if version == PROTOCOL_VERSION || matches!(rsp, Response::Error { .. }) =>
+			{
+				rsp
+			}
+			Ok((version, _)) => {
+				dict.insert("error", format!("unsupported protocol version {}", version));
+				return dict.into_shared();
+			}
+			Err(e) => {
+				dict.insert("error", format!("{}", e));
+				return dict.into_shared();
+			}
+		};
+		dict.insert("type", rsp.message_type() as u8);
+		match rsp {
+			Response::ServerList(entries) => {
+				let list = VariantArray::new();
+				for e in entries {
+					let d = Dictionary::new();
+					d.insert("addr", e.addr.to_string());
+					d.insert("name", e.name);
+					list.push(d.into_shared());
+				}
+				dict.insert("servers", list.into_shared());
+			}
+			Response::Registered { token } => dict.insert("token", token as i64),
+			Response::RegisterFailed { code } => dict.insert("code", code as u8),
+			Response::ServerInfo(d) => {
+				dict.insert("max_players", d.max_players);
+				dict.insert("map", d.map);
+				dict.insert("description", d.description);
+				dict.insert("status", encode_status(&d.status));
+			}
+			Response::PunchHole { port, client } => {
+				dict.insert("port", port);
+				dict.insert("client", client.to_string());
+			}
+			Response::Hello {
+				min_version,
+				max_version,
+				supported,
+			} => {
+				dict.insert("min_version", min_version);
+				dict.insert("max_version", max_version);
+				dict.insert("supported", TypedArray::from_vec(supported));
+			}
+			Response::Error {
+				request,
+				code,
+				versions,
+			} => {
+				dict.insert("request", request);
+				dict.insert("code", code as u8);
+				if let Some((min, max)) = versions {
+					dict.insert("min_version", min);
+					dict.insert("max_version", max);
+				}
+			}
+			Response::ListServers { more, entries } => {
+				let list = VariantArray::new();
+				for e in entries {
+					let d = Dictionary::new();
+					d.insert("addr", e.addr.to_string());
+					d.insert("name", e.name);
+					d.insert("map", e.map);
+					d.insert("max_players", e.max_players);
+					d.insert("description", e.description);
+					d.insert("version", e.version);
+					d.insert("status", encode_status(&e.status));
+					list.push(d.into_shared());
+				}
+				dict.insert("more", more);
+				dict.insert("servers", list.into_shared());
+			}
+			Response::WhoAmI { addr } => dict.insert("addr", addr.to_string()),
+			Response::Punch {
+				session,
+				port,
+				peer,
+			} => {
+				dict.insert("session", session as i64);
+				dict.insert("port", port);
+				dict.insert("peer", peer.to_string());
+			}
+			Response::Enqueued { waiting } => dict.insert("waiting", waiting),
+			Response::Uploading { received } => dict.insert("received", received),
+			Response::Uploaded { id } => dict.insert("id", id as i64),
+			Response::Vehicles { more, entries } => {
+				let list = VariantArray::new();
+				for e in entries {
+					let d = Dictionary::new();
+					d.insert("id", e.id as i64);
+					d.insert("size", e.size);
+					d.insert("name", e.name);
+					d.insert("tags", e.tags);
+					list.push(d.into_shared());
+				}
+				dict.insert("more", more);
+				dict.insert("vehicles", list.into_shared());
+			}
+			Response::VehicleChunk {
+				id,
+				total,
+				offset,
+				data,
+			} => {
+				dict.insert("id", id as i64);
+				dict.insert("total", total);
+				dict.insert("offset", offset);
+				dict.insert("data", TypedArray::from_vec(data.to_vec()));
+			}
+			Response::Matched { server, session } => {
+				dict.insert("server", server.to_string());
+				dict.insert("session", session as i64);
+			}
+			Response::Cookie { cookie } => dict.insert("cookie", cookie as i64),
+		}
+		dict.into_shared()
+	}
+}
+
+fn encode(req: Request) -> TypedArray<u8> {
+	match req.to_vec() {
+		Ok(data) => TypedArray::from_vec(data),
+		Err(e) => {
+			godot_error!(
+				"Failed to encode {} request: {}",
+				req.message_type().name(),
+				e
+			);
+			TypedArray::new()
+		}
+	}
+}
+
+fn invalid_addr(addr: &str) -> TypedArray<u8> {
+	godot_error!("Invalid address '{}'", addr);
+	TypedArray::new()
+}
+
+/// Convert a dictionary with the keys `players`, `phase` and `elapsed` to a match status.
+/// Missing or invalid keys are set to zero.
+fn decode_status(dict: &Dictionary) -> MatchStatus {
+	let int = |key: &str| dict.get(key).try_to_i64().unwrap_or(0);
+	MatchStatus {
+		players: int("players") as u8,
+		phase: MatchPhase::try_from(int("phase") as u8).unwrap_or(MatchPhase::Warmup),
+		elapsed: int("elapsed") as u32,
+	}
+}
+
+fn encode_status(status: &MatchStatus) -> Dictionary {
+	let dict = Dictionary::new();
+	dict.insert("players", status.players);
+	dict.insert("phase", status.phase as u8);
+	dict.insert("elapsed", status.elapsed);
+	dict.into_shared()
+}
+
+pub(super) fn init(handle: InitHandle) {
+	handle.add_class::<LobbyProtocol>();
+}
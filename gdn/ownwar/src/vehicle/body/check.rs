@@ -2,6 +2,7 @@
 
 use super::*;
 use crate::util::*;
+use own_war_net::Checksum;
 
 impl super::Body {
 	/// Check if all blocks are connected.
@@ -26,4 +27,26 @@ impl super::Body {
 		// Everything is connected.
 		return true;
 	}
+
+	/// Add the ID & health of all blocks, the health of all multiblocks and the same data of
+	/// all children to a checksum.
+	pub fn checksum(&self, checksum: &mut Checksum) {
+		checksum.write(&[self.offset.x, self.offset.y, self.offset.z]);
+		let end = self.end();
+		checksum.write(&[end.x, end.y, end.z]);
+		for blk in self.blocks.values() {
+			checksum.write_u16(blk.id.map(NonZeroU16::get).unwrap_or(0));
+			checksum.write_u16(blk.health.map(NonZeroU16::get).unwrap_or(0));
+		}
+		// Saturate rather than panic on absurd amounts, it is only a checksum.
+		let len = |n: usize| n.try_into().unwrap_or(u32::MAX);
+		checksum.write_u32(len(self.multi_blocks.len()));
+		for mb in self.multi_blocks.iter() {
+			checksum.write_u32(mb.as_ref().map(|mb| mb.health.get()).unwrap_or(0));
+		}
+		checksum.write_u32(len(self.children.len()));
+		for b in self.children.iter() {
+			b.checksum(checksum);
+		}
+	}
 }
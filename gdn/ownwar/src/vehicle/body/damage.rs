@@ -2,12 +2,10 @@ use super::*;
 use crate::block;
 use crate::util::*;
 use crate::vehicle::vehicle::Shared;
-use core::fmt;
 use core::mem;
 use core::num::{NonZeroU16, NonZeroU32};
-use core::slice;
 use gdnative::prelude::*;
-use std::error::Error;
+use own_war_net::Damage;
 use std::io;
 
 #[cfg(not(feature = "server"))]
@@ -29,18 +27,6 @@ pub enum DamageEvent {
 	},
 }
 
-/// Error returned if the damage type isn't known
-#[derive(Debug)]
-pub struct UnknownDamageType(u8);
-
-impl fmt::Display for UnknownDamageType {
-	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f, "Unknown damage type: {}", self.0)
-	}
-}
-
-impl Error for UnknownDamageType {}
-
 impl DamageEvent {
 	/// Serialize the damage event for transmission over a network.
 	pub(super) fn serialize(&self, out: &mut impl io::Write) -> io::Result<()> {
@@ -68,41 +54,30 @@ impl DamageEvent {
 		}
 		Ok(())
 	}
+}
 
-	/// Deserialize the damage event.
-	pub(super) fn deserialize(in_: &mut impl io::Read) -> io::Result<Self> {
-		let mut ty = 0;
-		in_.read_exact(slice::from_mut(&mut ty))?;
-		match ty {
-			0 => {
-				let mut damage = [0; 4];
-				in_.read_exact(&mut damage)?;
-				let damage = u32::from_le_bytes(damage);
-				let origin = super::Body::deserialize_vector3(in_)?;
-				let direction = super::Body::deserialize_vector3(in_)?;
-				Ok(Self::Ray {
-					damage,
-					origin,
-					direction,
-				})
-			}
-			1 => {
-				let mut damage = [0; 4];
-				in_.read_exact(&mut damage)?;
-				let damage = u32::from_le_bytes(damage);
-				let origin = super::Body::deserialize_vector3(in_)?;
-				let mut radius = 0;
-				in_.read_exact(slice::from_mut(&mut radius))?;
-				Ok(Self::Explosion {
-					damage,
-					origin,
-					radius: radius as i8,
-				})
-			}
-			ty => Err(io::Error::new(
-				io::ErrorKind::InvalidData,
-				UnknownDamageType(ty),
-			)),
+impl From<Damage> for DamageEvent {
+	fn from(damage: Damage) -> Self {
+		let vector3 = |[x, y, z]: [f32; 3]| Vector3::new(x, y, z);
+		match damage {
+			Damage::Ray {
+				damage,
+				origin,
+				direction,
+			} => Self::Ray {
+				damage,
+				origin: vector3(origin),
+				direction: vector3(direction),
+			},
+			Damage::Explosion {
+				damage,
+				origin,
+				radius,
+			} => Self::Explosion {
+				damage,
+				origin: vector3(origin),
+				radius,
+			},
 		}
 	}
 }
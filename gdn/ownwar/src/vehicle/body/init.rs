@@ -210,6 +210,24 @@ impl super::Body {
 		self.collision_shape_instance = Some(collision_shape_instance);
 	}
 
+	/// Free the Godot nodes of this body and its children, if the vehicle is rejected after
+	/// they have been created. The nodes of multiblocks are children of the body node, so
+	/// they are freed along with it.
+	pub(in super::super) fn free_nodes(&mut self) {
+		self.iter_all_bodies_mut(&mut |b| {
+			// Bodies that were already added to their parent are queued twice, which is fine
+			// as the queue skips objects that were freed already.
+			if let Some(node) = b.node.take() {
+				unsafe { node.assume_safe().queue_free() };
+			}
+			b.collision_shape_instance = None;
+			#[cfg(not(feature = "server"))]
+			{
+				b.voxel_mesh_instance = None;
+			}
+		});
+	}
+
 	/// Create a collision shape.
 	pub(super) fn create_collision_shape() -> Ref<BoxShape, Shared> {
 		Ref::<BoxShape, Unique>::new().into_shared()
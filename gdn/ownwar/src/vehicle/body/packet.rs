@@ -1,6 +1,7 @@
 use super::*;
 use core::convert::TryFrom;
 use gdnative::prelude::*;
+use own_war_net::{BodyState, Correction, Damage};
 use std::io;
 
 impl super::Body {
@@ -17,18 +18,7 @@ impl super::Body {
 			// Write temporary data
 			temporary.write_all(&[1])?; // Flag indicating we're alive
 							// FIXME
-			let (tr, mut rot) = self.position();
-			if rot.r < 0.0 {
-				// ijk == xyz, r == w
-				rot = Quat::quaternion(-rot.i, -rot.j, -rot.k, -rot.r);
-			}
-			let lv = self.linear_velocity();
-			let av = self.angular_velocity();
-
-			Self::serialize_vector3(temporary, Vector3::new(rot.i, rot.j, rot.k))?;
-			Self::serialize_vector3(temporary, tr)?;
-			Self::serialize_vector3(temporary, lv)?;
-			Self::serialize_vector3(temporary, av)?;
+			self.state().encode(temporary)?;
 
 			// Write permanent data
 
@@ -47,64 +37,110 @@ impl super::Body {
 		} else {
 			temporary.write_all(&0u8.to_le_bytes())?; // Flag indicating we're dead
 			                              // No flag is needed for permanent data since it's deterministic anyways
-			                              // Checks for determinism may be added later, but it will be done separately anyways
-			                              // as such checks will use a _lot_ of data
+			                              // Determinism is checked separately with `Body::checksum`
 		}
 
 		Ok(())
 	}
 
-	/// Apply the temporary data inside a packet.
-	pub fn process_temporary_packet(&mut self, packet: &mut impl io::Read) -> io::Result<()> {
-		let mut flag = [0; 1];
-		packet.read_exact(&mut flag)?;
+	/// The amount of children of this body and of all its children in the same order as
+	/// `iter_all_bodies`, which is the shape `TemporaryMessage::decode` expects.
+	pub fn shape(&self, shape: &mut Vec<usize>) {
+		shape.push(self.children.len());
+		for b in self.children() {
+			b.shape(shape);
+		}
+	}
 
-		if flag[0] == 1 {
-			let v = Self::deserialize_vector3(packet)?;
-			let rot = Quat::quaternion(v.x, v.y, v.z, (1.0 - v.square_length()).max(0.0).sqrt());
-			let tr = Self::deserialize_vector3(packet)?;
-			let lv = Self::deserialize_vector3(packet)?;
-			let av = Self::deserialize_vector3(packet)?;
+	/// The amount of bodies permanent data is sent for: this body and its children, unless
+	/// they are destroyed.
+	pub fn permanent_bodies(&self) -> usize {
+		if self.is_destroyed() {
+			0
+		} else {
+			1 + self.children().map(Self::permanent_bodies).sum::<usize>()
+		}
+	}
 
-			// We may still be receiving position updates even if the body is destroyed due to
-			// packets being old, so check.
-			if !self.is_destroyed() {
-				self.set_position(tr, rot.inverse());
-				self.set_linear_velocity(lv);
-				self.set_angular_velocity(av);
+	/// The states of this body and all children in the same order as `iter_all_bodies`,
+	/// with `None` for bodies that are destroyed.
+	pub fn states(&self, states: &mut Vec<Option<BodyState>>) {
+		if !self.is_destroyed() {
+			states.push(Some(self.state()));
+			for b in self.children() {
+				b.states(states);
 			}
+		} else {
+			self.iter_all_bodies(&mut |_| states.push(None));
+		}
+	}
 
-			for b in self.children_mut() {
-				b.process_temporary_packet(packet)?;
+	/// Apply a correction to this body and all children in the same order as
+	/// `iter_all_bodies`.
+	pub fn correct(&self, corrections: &mut impl Iterator<Item = Correction>) {
+		let c = corrections.next().unwrap_or(Correction::NONE);
+		if !c.is_none() && !self.is_destroyed() {
+			self.set_state(&c.apply(&self.state()));
+		}
+		for b in self.children() {
+			b.correct(corrections);
+		}
+	}
+
+	/// Set the states of this body and all children in the same order as
+	/// `iter_all_bodies`. Bodies are left alone if their state is `None`.
+	pub fn set_states(&self, states: &mut impl Iterator<Item = Option<BodyState>>) {
+		if let Some(s) = states.next().flatten() {
+			if !self.is_destroyed() {
+				self.set_state(&s);
 			}
 		}
+		for b in self.children() {
+			b.set_states(states);
+		}
+	}
 
-		Ok(())
+	/// The physics state of this body.
+	pub fn state(&self) -> BodyState {
+		let (tr, rot) = self.position();
+		let (lv, av) = (self.linear_velocity(), self.angular_velocity());
+		BodyState {
+			// ijk == xyz, r == w
+			rotation: [rot.i, rot.j, rot.k, rot.r],
+			position: [tr.x, tr.y, tr.z],
+			linear_velocity: [lv.x, lv.y, lv.z],
+			angular_velocity: [av.x, av.y, av.z],
+		}
 	}
 
-	/// Apply the permanent data inside a packet.
+	/// Set the physics state of this body.
+	pub fn set_state(&self, state: &BodyState) {
+		let [x, y, z, w] = state.rotation;
+		let rot = Quat::quaternion(x, y, z, w);
+		let vector3 = |[x, y, z]: [f32; 3]| Vector3::new(x, y, z);
+		self.set_position(vector3(state.position), rot.inverse());
+		self.set_linear_velocity(vector3(state.linear_velocity));
+		self.set_angular_velocity(vector3(state.angular_velocity));
+	}
+
+	/// Queue the damage events of a `PermanentMessage`, which has a list of events for every
+	/// body counted by `permanent_bodies`.
 	///
 	/// # Panics
 	///
 	/// The list of damage events isn't empty. This affects determinism.
-	pub fn process_permanent_packet(&mut self, packet: &mut impl io::Read) -> io::Result<()> {
+	pub fn queue_damage_events(&mut self, events: &mut impl Iterator<Item = Vec<Damage>>) {
 		if !self.is_destroyed() {
 			assert!(
 				self.damage_events.is_empty(),
 				"There are still damage events queued"
 			);
-			let mut evt = [0; 2];
-			packet.read_exact(&mut evt)?;
-			for _ in 0..u16::from_le_bytes(evt) {
-				// TODO don't panic
-				self.add_damage_event(
-					DamageEvent::deserialize(packet).expect("Failed to decode damage event"),
-				);
+			for e in events.next().unwrap_or_default() {
+				self.add_damage_event(e.into());
 			}
 			for b in self.children.iter_mut() {
-				b.process_permanent_packet(packet)?;
+				b.queue_damage_events(events);
 			}
 		}
-		Ok(())
 	}
 }
@@ -1,9 +1,8 @@
 use super::*;
-use core::convert::{TryFrom, TryInto};
-use core::mem;
+use core::convert::TryFrom;
 use core::num::NonZeroU16;
-use core::slice;
 use gdnative::prelude::*;
+use own_war_net::{full_multi_block_health, write_bits, DecodedBody};
 use std::io;
 
 /// Dummy value to ensure data is correctly transmitted. Only used for debugging
@@ -12,38 +11,55 @@ const _CANARY: &[u8; 4] = &[102, 117, 99, 107];
 
 impl super::Body {
 	/// Serialize the body for transmission over a network.
+	///
+	/// Only occupied cells are included and the health of a block is left out if it isn't
+	/// damaged.
 	pub(in super::super) fn serialize(&self, out: &mut impl io::Write) -> io::Result<()> {
 		// Serialize AABB
 		out.write_all(&[self.offset.x, self.offset.y, self.offset.z])?;
 		out.write_all(&[self.end().x, self.end().y, self.end().z])?;
 
-		// Serialize block ids & health
-		for blk in self.blocks.values() {
+		// Serialize which cells are occupied
+		write_bits(out, self.blocks.values().map(Voxel::is_occupied))?;
+
+		// Serialize the block ids, rotations & colors of occupied cells
+		let occupied = || {
+			self.blocks
+				.values()
+				.zip(self.rotations.iter().zip(self.colors.iter()))
+				.filter(|(v, _)| v.is_occupied())
+		};
+		for (blk, (rot, &clr)) in occupied() {
 			out.write_all(&blk.id.map(NonZeroU16::get).unwrap_or(0).to_le_bytes())?;
-			out.write_all(&blk.health.map(NonZeroU16::get).unwrap_or(0).to_le_bytes())?;
+			out.write_all(&[rot.get(), clr])?;
 		}
 
-		// Serialize block rotations
-		for rot in self.rotations.iter() {
-			out.write_all(&[rot.get()])?;
+		// Serialize the health of damaged blocks
+		write_bits(out, occupied().map(|(v, _)| v.health != v.full_health()))?;
+		for (blk, _) in occupied().filter(|(v, _)| v.health != v.full_health()) {
+			out.write_all(&blk.health.map(NonZeroU16::get).unwrap_or(0).to_le_bytes())?;
 		}
 
-		// Serialize block colors
-		out.write_all(&self.colors)?;
-
-		// Serialize multiblock health
+		// Serialize the health of damaged multiblocks
+		let full = full_multi_block_health(
+			&block::Registered,
+			self.blocks.values().map(|v| (v.id, v.health)),
+			self.multi_blocks.len(),
+		);
+		let health = || {
+			self.multi_blocks
+				.iter()
+				.map(|mb| mb.as_ref().map(|mb| mb.health))
+				.zip(full.iter().copied())
+		};
 		out.write_all(
 			&u16::try_from(self.multi_blocks.len())
 				.unwrap()
 				.to_le_bytes(),
 		)?;
-		for mb in self.multi_blocks.iter() {
-			out.write_all(
-				&mb.as_ref()
-					.map(|mb| mb.health.get())
-					.unwrap_or(0)
-					.to_le_bytes(),
-			)?;
+		write_bits(out, health().map(|(hp, full)| hp != full))?;
+		for (hp, _) in health().filter(|(hp, full)| hp != full) {
+			out.write_all(&hp.map(NonZeroU32::get).unwrap_or(0).to_le_bytes())?;
 		}
 
 		// Serialize bodies
@@ -71,90 +87,67 @@ impl super::Body {
 		Ok(())
 	}
 
-	/// Create a body by deserializing the given data.
-	pub(in super::super) fn deserialize(
-		in_: &mut impl io::Read,
-		shared: &mut vehicle::Shared,
-	) -> io::Result<Self> {
-		let mut buf = [0; 3];
-		in_.read_exact(&mut buf)?;
-		let offset = voxel::Position::new(buf[0], buf[1], buf[2]);
-		in_.read_exact(&mut buf)?;
-		let end = voxel::Position::new(buf[0], buf[1], buf[2]);
-
-		// Get the ID & health of each block
+	/// Create a body from data returned by `DecodedVehicle::decode`.
+	pub(in super::super) fn deserialize(data: DecodedBody, shared: &mut vehicle::Shared) -> Self {
+		let DecodedBody {
+			offset,
+			end,
+			ids,
+			health,
+			rotations,
+			colors,
+			multi_blocks,
+			children,
+			state,
+		} = data;
+		let offset = voxel::Position::new(offset[0], offset[1], offset[2]);
+		let end = voxel::Position::new(end[0], end[1], end[2]);
+
 		let mut blocks = voxel::Grid::new_uninit(end);
-		let count = blocks.len();
-		for blk in blocks.values_mut() {
-			let mut buf = [0; mem::size_of::<Option<NonZeroU16>>()];
-			in_.read_exact(&mut buf)?;
-			let id = NonZeroU16::new(u16::from_le_bytes(buf));
-			in_.read_exact(&mut buf)?;
-			let health = NonZeroU16::new(u16::from_le_bytes(buf));
+		for (blk, (&id, &health)) in blocks.values_mut().zip(ids.iter().zip(health.iter())) {
 			blk.write(Voxel { id, health });
 		}
 		// SAFETY: all elements have been initialized
 		let blocks = unsafe { blocks.assume_init() };
 
-		// Get the rotations
-		let mut rotations = Box::new_uninit_slice(count.try_into().unwrap());
-		for e in rotations.iter_mut() {
-			let mut buf = [0; 1];
-			in_.read_exact(&mut buf)?;
-			e.write(Rotation::new(buf[0]).expect("Invalid rotation"));
-		}
-		// SAFETY: all elements have been initialized
-		let rotations = unsafe { rotations.assume_init() };
-
-		// Get the colors
-		let mut colors = Box::new_uninit_slice(count.try_into().unwrap());
-		for e in colors.iter_mut() {
-			let mut buf = [0; 1];
-			in_.read_exact(&mut buf)?;
-			e.write(buf[0]);
-		}
-		// SAFETY: all elements have been initialized
-		let colors = unsafe { colors.assume_init() };
-
-		// Get the multiblock's health.
-		let mut count = [0; 2];
-		in_.read_exact(&mut count)?;
-		let count = u16::from_le_bytes(count);
-		let mut multi_blocks = Vec::with_capacity(count.into());
-		for _ in 0..count {
-			let mut health = [0; 4];
-			in_.read_exact(&mut health)?;
-			let health = NonZeroU32::new(u32::from_le_bytes(health));
-			multi_blocks.push(health.map(|health| MultiBlock {
-				health,
-				server_node: None,
-				#[cfg(not(feature = "server"))]
-				client_node: None,
-				reverse_indices: Box::new([]),
-				#[cfg(not(feature = "server"))]
-				interpolation_state_index: u16::MAX,
-				base_position: voxel::Position::new(u8::MAX, u8::MAX, u8::MAX),
-				rotation: Rotation::new(0).unwrap(),
-
-				weapon_index: u16::MAX,
-				turret_index: u16::MAX,
-				movement_index: u16::MAX,
-				steppable_index: u16::MAX,
-				saveable_index: u16::MAX,
-				temporary_index: u16::MAX,
-				permanent_index: u16::MAX,
-
-				anchor_body_index: None,
-			}));
-		}
-
-		// Deserialize the children.
-		let mut count = 0;
-		in_.read_exact(slice::from_mut(&mut count))?;
-		let mut children = Vec::with_capacity(count.into());
-		for _ in 0..count {
-			children.push(Self::deserialize(in_, shared)?);
-		}
+		// The rotations have been checked by `decode`.
+		let rotations = rotations
+			.iter()
+			.map(|&r| Rotation::new(r).unwrap())
+			.collect();
+
+		let multi_blocks = multi_blocks
+			.into_iter()
+			.map(|health| {
+				health.map(|health| MultiBlock {
+					health,
+					server_node: None,
+					#[cfg(not(feature = "server"))]
+					client_node: None,
+					reverse_indices: Box::new([]),
+					#[cfg(not(feature = "server"))]
+					interpolation_state_index: u16::MAX,
+					base_position: voxel::Position::new(u8::MAX, u8::MAX, u8::MAX),
+					rotation: Rotation::new(0).unwrap(),
+
+					weapon_index: u16::MAX,
+					turret_index: u16::MAX,
+					movement_index: u16::MAX,
+					steppable_index: u16::MAX,
+					saveable_index: u16::MAX,
+					temporary_index: u16::MAX,
+					permanent_index: u16::MAX,
+
+					anchor_body_index: None,
+				})
+			})
+			.collect();
+
+		// Create the children.
+		let children = children
+			.into_iter()
+			.map(|c| Self::deserialize(c, shared))
+			.collect();
 
 		let mut slf = Self {
 			offset,
@@ -209,25 +202,34 @@ impl super::Body {
 
 		slf.setup_connection_bitmaps();
 
-		if !slf.is_destroyed() {
+		if let Some(state) = state {
 			//slf.correct_mass();
 			slf.update_node_mass();
 
-			// If alive, get & apply position & velocity
-			let tr = Self::deserialize_vector3(in_)?;
-			let rot = Self::deserialize_vector3(in_)?;
-			let w = (1.0 - rot.square_length()).max(0.0).sqrt();
-			let rot = Quat::quaternion(rot.x, rot.y, rot.z, w);
-			let lv = Self::deserialize_vector3(in_)?;
-			let av = Self::deserialize_vector3(in_)?;
-
-			slf.set_position(tr, rot);
-			slf.set_linear_velocity(lv);
-			slf.set_angular_velocity(av);
+			// If alive, apply position & velocity
+			let [x, y, z, w] = state.rotation;
+			let vector3 = |[x, y, z]: [f32; 3]| Vector3::new(x, y, z);
+			slf.set_position(vector3(state.position), Quat::quaternion(x, y, z, w));
+			slf.set_linear_velocity(vector3(state.linear_velocity));
+			slf.set_angular_velocity(vector3(state.angular_velocity));
 		} else {
 			unsafe { slf.node.take().map(|n| n.assume_unique().queue_free()) };
 		}
 
-		Ok(slf)
+		slf
+	}
+}
+
+impl Voxel {
+	/// Whether there is a block or a part of a multiblock in this cell.
+	fn is_occupied(&self) -> bool {
+		self.id.is_some() || self.health.is_some()
+	}
+
+	/// The health of the block in this cell if it isn't damaged. Multiblocks don't store
+	/// their health in the cell, so this is `None` for them.
+	fn full_health(&self) -> Option<NonZeroU16> {
+		self.id
+			.and_then(|id| own_war_net::full_health(&block::Registered, id))
 	}
 }
@@ -43,16 +43,4 @@ impl super::Body {
 		out.write_all(&v.y.to_le_bytes())?;
 		out.write_all(&v.z.to_le_bytes())
 	}
-
-	/// Deserialize a Vector3
-	pub(in super::super) fn deserialize_vector3(in_: &mut impl io::Read) -> io::Result<Vector3> {
-		let mut buf = [0; 4];
-		in_.read_exact(&mut buf)?;
-		let x = f32::from_le_bytes(buf);
-		in_.read_exact(&mut buf)?;
-		let y = f32::from_le_bytes(buf);
-		in_.read_exact(&mut buf)?;
-		let z = f32::from_le_bytes(buf);
-		Ok(Vector3::new(x, y, z))
-	}
 }
@@ -0,0 +1,84 @@
+//! Bindings to [`own_war_net::Connection`], so the data created by `Vehicle::create_packet`
+//! can be sent with unreliable RPCs.
+
+use gdnative::prelude::*;
+use own_war_net::Connection;
+use std::time::Instant;
+
+/// The connection between the server and a client for a single vehicle.
+#[derive(NativeClass)]
+#[inherit(Reference)]
+pub struct VehicleConnection {
+	connection: Connection,
+}
+
+#[methods]
+impl VehicleConnection {
+	fn new(_: &Reference) -> Self {
+		Self {
+			connection: Connection::new(),
+		}
+	}
+
+	/// Queue data that must arrive in order. Returns `false` if it can't be queued, in which
+	/// case the other side can't be kept in sync anymore.
+	#[export]
+	fn send_permanent(&mut self, _: TRef<Reference>, data: TypedArray<u8>) -> bool {
+		match self.connection.send_permanent(&data.read()[..]) {
+			Ok(()) => true,
+			Err(e) => {
+				godot_error!("Failed to queue permanent data: {}", e);
+				false
+			}
+		}
+	}
+
+	/// Set the data to send with the next packet. Only the newest data arrives.
+	#[export]
+	fn send_temporary(&mut self, _: TRef<Reference>, data: TypedArray<u8>) {
+		if let Err(e) = self.connection.send_temporary(&data.read()[..]) {
+			godot_error!("Failed to queue temporary data: {}", e);
+		}
+	}
+
+	/// Create a packet to send to the other side. This should be done regularly even if no
+	/// data was queued, as packets also acknowledge the data received.
+	#[export]
+	fn create_packet(&mut self, _: TRef<Reference>) -> TypedArray<u8> {
+		TypedArray::from_vec(self.connection.create_packet(Instant::now()))
+	}
+
+	/// Process a packet of the other side. Returns `false` if it is invalid.
+	#[export]
+	fn receive(&mut self, _: TRef<Reference>, packet: TypedArray<u8>) -> bool {
+		match self.connection.receive(&packet.read()[..], Instant::now()) {
+			Ok(()) => true,
+			Err(e) => {
+				godot_error!("Invalid vehicle packet: {}", e);
+				false
+			}
+		}
+	}
+
+	/// The next permanent data, or `null` if it hasn't arrived yet.
+	#[export]
+	fn receive_permanent(&mut self, _: TRef<Reference>) -> Option<TypedArray<u8>> {
+		self.connection
+			.receive_permanent()
+			.map(|d| TypedArray::from_vec(d.into()))
+	}
+
+	/// The newest temporary data, or `null` if nothing arrived since the last call.
+	#[export]
+	fn receive_temporary(&mut self, _: TRef<Reference>) -> Option<TypedArray<u8>> {
+		self.connection
+			.receive_temporary()
+			.map(|d| TypedArray::from_vec(d.into()))
+	}
+
+	/// The round trip time in seconds.
+	#[export]
+	fn get_rtt(&self, _: TRef<Reference>) -> f32 {
+		self.connection.rtt().as_secs_f32()
+	}
+}
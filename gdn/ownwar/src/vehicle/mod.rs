@@ -1,7 +1,9 @@
 mod body;
+mod connection;
 mod controller;
 #[cfg(not(feature = "server"))]
 mod interpolation_state;
+mod replay;
 mod vehicle;
 #[cfg(not(feature = "server"))]
 mod voxel_mesh;
@@ -19,4 +21,7 @@ pub(super) fn init(handle: InitHandle) {
 	#[cfg(not(feature = "server"))]
 	handle.add_class::<voxel_mesh::VoxelMesh>();
 	handle.add_class::<vehicle::gd::Vehicle>();
+	handle.add_class::<connection::VehicleConnection>();
+	handle.add_class::<replay::ReplayRecorder>();
+	handle.add_class::<replay::ReplayPlayer>();
 }
@@ -0,0 +1,207 @@
+//! Bindings to [`own_war_net::Recorder`] and [`own_war_net::Player`], so matches can be
+//! recorded by the server and played back later.
+//!
+//! Replays are stored compressed as they mostly consist of vehicle packets, which repeat a
+//! lot. Records are compressed and written as they come in, so a long match doesn't have to
+//! fit in memory.
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use gdnative::api::ProjectSettings;
+use gdnative::prelude::*;
+use own_war_net::{Player, Record, RecordKind, Recorder};
+use std::fs;
+use std::io::{self, Read, Write};
+
+/// Records the keyframes & packets of all vehicles in a match.
+#[derive(NativeClass)]
+#[inherit(Reference)]
+pub struct ReplayRecorder {
+	/// `None` if no file is open or writing failed.
+	recorder: Option<Recorder<GzEncoder<io::BufWriter<fs::File>>>>,
+}
+
+#[methods]
+impl ReplayRecorder {
+	fn new(_: &Reference) -> Self {
+		Self { recorder: None }
+	}
+
+	/// Create the file to record to. Returns `false` if it can't be created.
+	#[export]
+	fn start(&mut self, _: TRef<Reference>, path: String) -> bool {
+		let real_path = ProjectSettings::godot_singleton()
+			.globalize_path(path.clone())
+			.to_string();
+		let recorder = fs::File::create(real_path).and_then(|f| {
+			Recorder::new(GzEncoder::new(
+				io::BufWriter::new(f),
+				Compression::default(),
+			))
+		});
+		match recorder {
+			Ok(r) => {
+				self.recorder = Some(r);
+				true
+			}
+			Err(e) => {
+				godot_error!("Failed to create {}: {}", path, e);
+				false
+			}
+		}
+	}
+
+	/// Set the amount of seconds between keyframes.
+	#[export]
+	fn set_keyframe_interval(&mut self, _: TRef<Reference>, interval: f64) {
+		if let Some(r) = self.recorder.as_mut() {
+			r.keyframe_interval = interval;
+		}
+	}
+
+	/// Whether keyframes of all vehicles should be recorded at the given time.
+	#[export]
+	fn is_keyframe_due(&mut self, _: TRef<Reference>, time: f64) -> bool {
+		self.recorder
+			.as_mut()
+			.map_or(false, |r| r.keyframe_due(time))
+	}
+
+	/// Record the serialized state of a vehicle, which also spawns it during playback.
+	#[export]
+	fn record_keyframe(&mut self, _: TRef<Reference>, time: f64, id: u16, data: TypedArray<u8>) {
+		self.record(time, id, RecordKind::Keyframe, &data.read()[..]);
+	}
+
+	/// Record permanent data created by `create_packet`.
+	#[export]
+	fn record_permanent(&mut self, _: TRef<Reference>, time: f64, id: u16, data: TypedArray<u8>) {
+		self.record(time, id, RecordKind::Permanent, &data.read()[..]);
+	}
+
+	/// Record temporary data created by `create_packet`.
+	#[export]
+	fn record_temporary(&mut self, _: TRef<Reference>, time: f64, id: u16, data: TypedArray<u8>) {
+		self.record(time, id, RecordKind::Temporary, &data.read()[..]);
+	}
+
+	/// Record that a vehicle has been removed.
+	#[export]
+	fn record_destroy(&mut self, _: TRef<Reference>, time: f64, id: u16) {
+		self.record(time, id, RecordKind::Destroy, &[]);
+	}
+
+	/// Write everything recorded so far to the file. The compressor holds on to data until
+	/// it has enough to compress well, so this should be called periodically to keep the
+	/// file up to date.
+	#[export]
+	fn flush(&mut self, _: TRef<Reference>) {
+		if let Some(Err(e)) = self.recorder.as_mut().map(Recorder::flush) {
+			godot_error!("Failed to write replay: {}", e);
+			self.recorder = None;
+		}
+	}
+
+	/// Finish the file. Nothing is recorded afterwards.
+	#[export]
+	fn finish(&mut self, _: TRef<Reference>) {
+		let result = self
+			.recorder
+			.take()
+			.map(|r| r.into_inner().finish().and_then(|mut out| out.flush()));
+		if let Some(Err(e)) = result {
+			godot_error!("Failed to write replay: {}", e);
+		}
+	}
+
+	fn record(&mut self, time: f64, id: u16, kind: RecordKind, data: &[u8]) {
+		if let Some(Err(e)) = self
+			.recorder
+			.as_mut()
+			.map(|r| r.record(time, id, kind, data))
+		{
+			godot_error!("Failed to record {:?}: {}", kind, e);
+			self.recorder = None;
+		}
+	}
+}
+
+/// Plays back a replay. The records it returns should be applied to vehicles created with
+/// `is_local` and `is_master` set to `false`.
+#[derive(NativeClass)]
+#[inherit(Reference)]
+pub struct ReplayPlayer {
+	player: Option<Player>,
+}
+
+#[methods]
+impl ReplayPlayer {
+	fn new(_: &Reference) -> Self {
+		Self { player: None }
+	}
+
+	/// Load a replay. Returns `false` if it can't be read.
+	#[export]
+	fn load(&mut self, _: TRef<Reference>, path: String) -> bool {
+		let real_path = ProjectSettings::godot_singleton()
+			.globalize_path(path.clone())
+			.to_string();
+		let mut data = Vec::new();
+		if let Err(e) = fs::File::open(real_path)
+			.and_then(|f| GzDecoder::new(io::BufReader::new(f)).read_to_end(&mut data))
+		{
+			godot_error!("Failed to read {}: {}", path, e);
+			return false;
+		}
+		match Player::new(&data) {
+			Ok(p) => {
+				self.player = Some(p);
+				true
+			}
+			Err(e) => {
+				godot_error!("Failed to read replay {}: {}", path, e);
+				false
+			}
+		}
+	}
+
+	/// The current time of the playback in seconds.
+	#[export]
+	fn get_time(&self, _: TRef<Reference>) -> f64 {
+		self.player.as_ref().map_or(0.0, Player::time)
+	}
+
+	/// The length of the replay in seconds.
+	#[export]
+	fn get_duration(&self, _: TRef<Reference>) -> f64 {
+		self.player.as_ref().map_or(0.0, Player::duration)
+	}
+
+	/// Advance the playback by `delta` seconds. Returns the records to apply in order as
+	/// `[kind, id, data]`, where `kind` is 0 for keyframes, 1 for permanent data, 2 for
+	/// temporary data and 3 if the vehicle was removed.
+	#[export]
+	fn advance(&mut self, _: TRef<Reference>, delta: f64) -> VariantArray {
+		Self::to_array(self.player.as_mut().map(|p| p.advance(delta)))
+	}
+
+	/// Jump to the given time. All vehicles should be removed before applying the returned
+	/// records, which are in the same format as those of `advance`.
+	#[export]
+	fn seek(&mut self, _: TRef<Reference>, time: f64) -> VariantArray {
+		Self::to_array(self.player.as_mut().map(|p| p.seek(time)))
+	}
+
+	fn to_array(records: Option<Vec<&Record>>) -> VariantArray {
+		let arr = VariantArray::new();
+		for r in records.into_iter().flatten() {
+			let rec = VariantArray::new();
+			rec.push(r.kind as i64);
+			rec.push(i64::from(r.vehicle));
+			rec.push(TypedArray::from_vec(r.data.clone()));
+			arr.push(rec.into_shared());
+		}
+		arr.into_shared()
+	}
+}
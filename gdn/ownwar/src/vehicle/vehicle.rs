@@ -81,6 +81,11 @@ pub mod gd {
 					main_body: None,
 
 					last_processed_packet_index: Cell::new(0),
+					input_index: 0,
+					inputs: InputQueue::new(),
+					prediction: None,
+					interpolation: None,
+					desynced: false,
 
 					mode: super::VehicleMode::RemotePuppet,
 				},
@@ -110,13 +115,100 @@ pub mod gd {
 			let _ = delta;
 		}
 
+		/// Apply input. `index` is the index the client sent along with the input.
+		///
+		/// Returns the index to send along with the input to the server.
 		#[export]
-		fn apply_input(&mut self, _: TRef<Reference>, bitmap: u16, aim_at: Vector3) {
+		fn apply_input(
+			&mut self,
+			_: TRef<Reference>,
+			bitmap: u16,
+			aim_at: Vector3,
+			index: u16,
+		) -> u16 {
 			if !self.vehicle.mode.is_master() {
 				// Override input, otherwise ignore and use our own.
 				self.controller = super::Controller::new(bitmap, aim_at);
 			}
-			self.vehicle.apply_input(self.controller);
+			self.vehicle.apply_input(self.controller, index)
+		}
+
+		/// Queue an input received from the client controlling the vehicle. Inputs are
+		/// applied with `apply_queued_input` in the order of their indices.
+		#[export]
+		fn queue_input(&mut self, _: TRef<Reference>, bitmap: u16, aim_at: Vector3, index: u16) {
+			let controller = super::Controller::new(bitmap, aim_at);
+			self.vehicle.inputs.push(index, controller);
+		}
+
+		/// Apply the next queued input, or keep the current one if none arrived in time.
+		/// Servers call this once per step instead of `apply_input`.
+		#[export]
+		fn apply_queued_input(&mut self, _: TRef<Reference>) {
+			let mut index = self.vehicle.input_index;
+			if let Some((i, controller)) = self.vehicle.inputs.pop() {
+				index = i;
+				if !self.vehicle.mode.is_master() {
+					self.controller = controller;
+				}
+			}
+			self.vehicle.apply_input(self.controller, index);
+		}
+
+		/// Set when the predicted state of the vehicle controlled by this client is corrected
+		/// and how quickly. Errors are in meters and radians. `blend` is the fraction of the
+		/// error corrected every step.
+		///
+		/// Does nothing if the vehicle isn't controlled by this client.
+		#[export]
+		fn set_correction_thresholds(
+			&mut self,
+			_: TRef<Reference>,
+			min_position_error: f32,
+			max_position_error: f32,
+			min_rotation_error: f32,
+			max_rotation_error: f32,
+			blend: f32,
+		) {
+			if let Some(p) = self.vehicle.prediction.as_mut() {
+				p.thresholds = Thresholds {
+					min_position_error,
+					max_position_error,
+					min_rotation_error,
+					max_rotation_error,
+					blend,
+				};
+			}
+		}
+
+		/// Set how many seconds the states of the server are shown late, which should cover
+		/// the time between packets and jitter, and how many seconds the last state may be
+		/// extrapolated if no newer state arrived in time.
+		///
+		/// Does nothing if the vehicle is controlled by this client.
+		#[export]
+		fn set_interpolation(
+			&mut self,
+			_: TRef<Reference>,
+			playout_delay: f32,
+			max_extrapolation: f32,
+		) {
+			if let Some(i) = self.vehicle.interpolation.as_mut() {
+				i.timeline.delay = playout_delay.into();
+				i.max_extrapolation = max_extrapolation.into();
+			}
+		}
+
+		/// Use the given time in seconds instead of the local clock to show the states of the
+		/// server, e.g. the time of a replay so pausing & changing the speed affect the
+		/// vehicle too. It must be set before every packet & step from then on.
+		///
+		/// Does nothing if the vehicle is controlled by this client.
+		#[export]
+		fn set_playback_time(&mut self, _: TRef<Reference>, time: f64) {
+			if let Some(i) = self.vehicle.interpolation.as_mut() {
+				i.clock = Some(time);
+			}
 		}
 
 		/// Process input. Should be preceded by `apply_input`.
@@ -369,6 +461,8 @@ pub mod gd {
 
 		/// Create two packets: one with data that *must* arrive *in order* and one with data
 		/// that does not need to arrive.
+		///
+		/// Both packets are empty if they couldn't be created.
 		#[export]
 		fn create_packet(&self, _: TRef<Reference>) -> VariantArray {
 			let (mut reliable, mut unreliable) = (TypedArray::<u8>::new(), TypedArray::<u8>::new());
@@ -377,13 +471,16 @@ pub mod gd {
 			unreliable.resize(size);
 			let (mut rel, mut unrel) = (reliable.write(), unreliable.write());
 			let (mut r, mut u) = (&mut rel[..], &mut unrel[..]);
-			self.vehicle
-				.create_packet(&mut r, &mut u)
-				.expect("Failed to create packet");
-			let (r, u) = (r.len(), u.len());
+			let (r, u) = match self.vehicle.create_packet(&mut r, &mut u) {
+				Ok(()) => (r.len() as i32, u.len() as i32),
+				Err(e) => {
+					godot_error!("Failed to create packet: {}", e);
+					(size, size)
+				}
+			};
 			drop((rel, unrel));
-			reliable.resize(size - r as i32);
-			unreliable.resize(size - u as i32);
+			reliable.resize(size - r);
+			unreliable.resize(size - u);
 			let arr = VariantArray::new();
 			arr.push(reliable.to_owned());
 			arr.push(unreliable.to_owned());
@@ -391,56 +488,46 @@ pub mod gd {
 		}
 
 		/// Process a packet with temporary data. This data includes inputs & physics state.
+		///
+		/// Malformed packets are ignored.
 		#[export]
 		fn process_temporary_packet(&mut self, _: TRef<Reference>, data: TypedArray<u8>) {
-			self.vehicle
-				.process_temporary_packet(&mut &data.read()[..])
-				.expect("Failed to process temporary data")
+			if let Err(e) = self.vehicle.process_temporary_packet(&data.read()[..]) {
+				godot_error!("Failed to process temporary data: {}", e);
+			}
 		}
 
 		/// Process a packet with permanent data. This data includes damage events.
 		///
-		/// This returns `true` if the vehicle is destroyed.
+		/// This returns `true` if the vehicle is destroyed. Malformed packets are ignored,
+		/// which will be noticed by the next checksum.
 		#[export]
 		fn process_permanent_packet(&mut self, _: TRef<Reference>, data: TypedArray<u8>) -> bool {
-			let data = &data.read()[..];
-			#[cfg(debug_assertions)]
-			let mut data = {
-				struct PanicReader<'a>(&'a [u8]);
-
-				impl io::Read for PanicReader<'_> {
-					fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-						assert!(self.0.len() >= buf.len(), "Not enough data to write");
-						buf.copy_from_slice(&self.0[..buf.len()]);
-						self.0 = &self.0[buf.len()..];
-						Ok(buf.len())
-					}
+			match self.vehicle.process_permanent_packet(&data.read()[..]) {
+				Ok(destroyed) => destroyed,
+				Err(e) => {
+					godot_error!("Failed to process permanent data: {}", e);
+					false
 				}
+			}
+		}
 
-				PanicReader(data)
-			};
-			#[cfg(not(debug_assertions))]
-			let mut data = data;
-			self.vehicle
-				.process_permanent_packet(&mut data)
-				.expect("Failed to process permanent data")
+		/// Whether the checksum of the server didn't match since the last call. If so, the
+		/// vehicle should be resynchronized with `serialize`.
+		#[export]
+		fn take_desync(&mut self, _: TRef<Reference>) -> bool {
+			mem::take(&mut self.vehicle.desynced)
 		}
 
 		/// Serialize the vehicle's state for synchronization over a network.
 		#[export]
 		fn serialize(&self, _: TRef<Reference>) -> TypedArray<u8> {
-			let mut data = TypedArray::<u8>::new();
-			let size = 1 << 16; // 64 KiB should be plenty
-			data.resize(size);
-			let len = {
-				let mut d = &mut data.write()[..];
-				self.vehicle
-					.serialize(&mut d)
-					.expect("Failed to serialize vehicle");
-				size - d.len() as i32
-			};
-			data.resize(len);
-			data
+			let mut data = Vec::new();
+			if let Err(e) = self.vehicle.serialize(&mut data) {
+				godot_error!("Failed to serialize vehicle: {}", e);
+				data.clear();
+			}
+			TypedArray::from_vec(data)
 		}
 
 		/// Deserialize a vehicle's state. This will create a new vehicle structure.
@@ -454,12 +541,12 @@ pub mod gd {
 			is_local: bool,
 			is_master: bool,
 		) -> i32 {
-			let mut d = &data.read()[..];
+			let data = data.read();
 			self.vehicle =
-				match super::Vehicle::deserialize(&mut d, team_color, is_local, is_master) {
+				match super::Vehicle::deserialize(&data[..], team_color, is_local, is_master) {
 					Ok(v) => v,
 					Err(e) => {
-						godot_error!("Failed to deserialize vehicle: {:?}", e);
+						godot_error!("Failed to deserialize vehicle: {}", e);
 						return 1;
 					}
 				};
@@ -514,8 +601,14 @@ use core::cell::Cell;
 use core::convert::{TryFrom, TryInto};
 use core::fmt;
 use core::mem;
+use gdnative::api::Engine;
 use gdnative::prelude::*;
+use own_war_net::{
+	Checksum, DecodedVehicle, DeserializeError, InputQueue, PermanentMessage, Predictor,
+	ProcessPacketError, SnapshotBuffer, TemporaryMessage, Thresholds, Timeline, VEHICLE_REVISION,
+};
 use std::io;
+use std::time::Instant;
 
 type Team = u8;
 
@@ -527,6 +620,17 @@ type VirtualTicks = u16;
 /// The amount of "virtual" ticks per second.
 const VIRTUAL_TICKS_PER_SECOND: VirtualTicks = 256;
 
+/// The interval in packets between checksums of the damage state. Servers create a packet
+/// every physics step, so this is about once per second.
+const CHECKSUM_INTERVAL: u16 = 64;
+
+/// The default amount of seconds the states of remote vehicles are shown late. Servers send
+/// 20 packets per second, so this covers one lost packet.
+const DEFAULT_PLAYOUT_DELAY: f64 = 0.1;
+
+/// The default amount of seconds remote vehicles are extrapolated.
+const DEFAULT_MAX_EXTRAPOLATION: f64 = 0.25;
+
 /// Structures shared between all bodies.
 pub(super) struct Shared {
 	/// All the weapons of the vehicle.
@@ -586,6 +690,49 @@ impl VehicleMode {
 			Self::RemoteMaster | Self::LocalMaster => true,
 		}
 	}
+
+	/// Whether the state of the vehicle is predicted ahead of the state sent by the server.
+	const fn is_predicted(&self) -> bool {
+		matches!(self, Self::RemoteMaster)
+	}
+
+	/// Whether the states sent by the server are buffered and interpolated.
+	const fn is_interpolated(&self) -> bool {
+		matches!(self, Self::RemotePuppet)
+	}
+}
+
+/// The states received for a vehicle controlled by another client.
+struct Interpolation {
+	timeline: Timeline,
+	/// The states of every body in the same order as `Body::iter_all_bodies`.
+	bodies: Vec<SnapshotBuffer>,
+	/// How many seconds the last state may be extrapolated.
+	max_extrapolation: f64,
+	/// The start of the local clock.
+	start: Instant,
+	/// The time set with `set_playback_time`, which replaces the local clock.
+	clock: Option<f64>,
+}
+
+impl Interpolation {
+	fn new() -> Self {
+		// Servers send the state of every physics step.
+		let tick = 1.0 / Engine::godot_singleton().iterations_per_second() as f64;
+		Self {
+			timeline: Timeline::new(tick, DEFAULT_PLAYOUT_DELAY),
+			bodies: Vec::new(),
+			max_extrapolation: DEFAULT_MAX_EXTRAPOLATION,
+			start: Instant::now(),
+			clock: None,
+		}
+	}
+
+	/// The local time in seconds.
+	fn now(&self) -> f64 {
+		self.clock
+			.unwrap_or_else(|| self.start.elapsed().as_secs_f64())
+	}
 }
 
 /// Representation of a vehicle.
@@ -613,12 +760,25 @@ pub struct Vehicle {
 	/// For clients, this is the index of the last processed packet.
 	/// For servers, this is the index of the packet to be sent.
 	last_processed_packet_index: Cell<u16>,
-}
 
-#[derive(Debug)]
-enum ProcessPacketError {}
+	/// The index of the last input applied. Servers send it to the client controlling the
+	/// vehicle so it knows which prediction to compare the state with.
+	input_index: u16,
+
+	/// The inputs received from the client controlling the vehicle, for servers.
+	inputs: InputQueue<Controller>,
+
+	/// The predicted states of the vehicle controlled by this client.
+	prediction: Option<Predictor>,
+
+	/// The buffered states of a vehicle controlled by another client.
+	interpolation: Option<Interpolation>,
+
+	/// Whether a checksum of the server didn't match the state of this vehicle.
+	desynced: bool,
+}
 
-/// Enum returned if Vehicle::new fails.
+/// Enum returned if Vehicle::new or Vehicle::deserialize fails.
 #[derive(Debug)]
 pub(crate) enum NewVehicleError {
 	/// An error occured while initializing the bodies.
@@ -627,6 +787,8 @@ pub(crate) enum NewVehicleError {
 	IncompatibleWeaponTypes,
 	/// The type of weapon isn't known.
 	UnknownWeaponType,
+	/// The serialized data is malformed.
+	InvalidData(DeserializeError),
 }
 
 impl fmt::Display for NewVehicleError {
@@ -635,6 +797,7 @@ impl fmt::Display for NewVehicleError {
 			Self::InitBodiesError(e) => e.fmt(f),
 			Self::IncompatibleWeaponTypes => "Multiple incompatible weapons are present".fmt(f),
 			Self::UnknownWeaponType => "The weapon type is not recognized (bug?)".fmt(f),
+			Self::InvalidData(e) => e.fmt(f),
 		}
 	}
 }
@@ -702,8 +865,12 @@ impl Vehicle {
 			);
 		});
 
+		let mode = VehicleMode::new(is_local, is_master);
+
 		Ok(Self {
-			mode: VehicleMode::new(is_local, is_master),
+			prediction: mode.is_predicted().then(Predictor::default),
+			interpolation: mode.is_interpolated().then(Interpolation::new),
+			mode,
 
 			max_cost,
 			shared,
@@ -719,6 +886,10 @@ impl Vehicle {
 			controller: Controller::default(),
 
 			last_processed_packet_index: Cell::new(0),
+			input_index: 0,
+			inputs: InputQueue::new(),
+
+			desynced: false,
 		})
 	}
 
@@ -735,6 +906,23 @@ impl Vehicle {
 
 	/// Advance the simulation.
 	fn step(&mut self, delta: VirtualTicks) -> bool {
+		// Correct mispredictions
+		if let Some(p) = self.prediction.as_mut() {
+			let corrections = p.step();
+			self.main_body
+				.as_ref()
+				.unwrap()
+				.correct(&mut corrections.into_iter());
+		}
+
+		// Show the states of the server at the current playback time
+		if let Some(i) = self.interpolation.as_ref() {
+			if let Some(time) = i.timeline.playback_time(i.now()) {
+				let mut states = i.bodies.iter().map(|b| b.sample(time, i.max_extrapolation));
+				self.main_body.as_ref().unwrap().set_states(&mut states);
+			}
+		}
+
 		// Step bodies
 		self.main_body.as_mut().unwrap().step();
 
@@ -755,9 +943,23 @@ impl Vehicle {
 		self.main_body.as_mut().unwrap().visual_step(delta);
 	}
 
-	/// Apply client input. Should only be used for the local client.
-	fn apply_input(&mut self, controller: Controller) {
+	/// Apply client input. `index` is the index the client sent along with the input.
+	///
+	/// Returns the index the client should send along with the input. If the vehicle is
+	/// predicted, the input and the current state are recorded under a new index.
+	fn apply_input(&mut self, controller: Controller, index: u16) -> u16 {
 		self.controller = controller;
+		match self.prediction.as_mut() {
+			Some(p) => {
+				let mut states = Vec::new();
+				self.main_body.as_ref().unwrap().states(&mut states);
+				p.record(&states)
+			}
+			None => {
+				self.input_index = index;
+				index
+			}
+		}
 	}
 
 	/// Process client input. This must be called only once per frame.
@@ -958,42 +1160,79 @@ impl Vehicle {
 		}
 	}
 
-	/// Process & apply temporary data.
-	fn process_temporary_packet(&mut self, packet: &mut impl io::Read) -> io::Result<()> {
-		// Read index
-		let mut index = [0; mem::size_of::<u16>()];
-		packet.read_exact(&mut index)?;
-		let index = u16::from_le_bytes(index);
+	/// Process & apply temporary data. Nothing is applied if the packet is malformed.
+	fn process_temporary_packet(&mut self, packet: &[u8]) -> Result<(), ProcessPacketError> {
+		let mut shape = Vec::new();
+		self.main_body.as_ref().unwrap().shape(&mut shape);
+		let TemporaryMessage {
+			index,
+			input_index,
+			bitmap,
+			aim_at: [x, y, z],
+			states,
+		} = TemporaryMessage::decode(packet, &shape)?;
 
 		if self.last_processed_packet_index.get().wrapping_sub(index) < 0x5000 {
 			// The packet is older than the state we currently have, so just discard it.
 			return Ok(());
 		}
+
+		let controller = Controller::new(bitmap, Vector3::new(x, y, z));
+
 		self.last_processed_packet_index.set(index);
 
-		// Read controller input
-		let mut bitmap = [0; mem::size_of::<u16>()];
-		packet.read_exact(&mut bitmap)?;
-		let bitmap = u16::from_le_bytes(bitmap);
-		let aim_at = Body::deserialize_vector3(packet)?;
-		self.controller = Controller::new(bitmap, aim_at);
+		if let Some(p) = self.prediction.as_mut() {
+			// The input of this client is newer than that of the server, so keep it and only
+			// correct the difference between the predicted and the actual state.
+			p.reconcile(input_index, &states);
+		} else if let Some(i) = self.interpolation.as_mut() {
+			// Buffer the states so they can be shown smoothly regardless of when packets
+			// arrive.
+			self.controller = controller;
+			let time = i.timeline.receive(index, i.now());
+			if i.bodies.len() < states.len() {
+				i.bodies.resize_with(states.len(), SnapshotBuffer::new);
+			}
+			for (b, s) in i.bodies.iter_mut().zip(states) {
+				if let Some(s) = s {
+					b.push(time, s);
+				}
+			}
+		} else {
+			self.controller = controller;
+			self.main_body
+				.as_ref()
+				.unwrap()
+				.set_states(&mut states.into_iter());
+		}
 
-		// Read physics state
-		self.main_body
-			.as_mut()
-			.unwrap()
-			.process_temporary_packet(packet)
+		Ok(())
 	}
 
 	/// Process a packet with permanent data. This data includes damage events.
 	///
-	/// This returns `true` if the vehicle is destroyed.
+	/// This returns `true` if the vehicle is destroyed. No damage is applied if the packet is
+	/// malformed.
 	#[must_use]
-	fn process_permanent_packet(&mut self, packet: &mut impl io::Read) -> io::Result<bool> {
-		self.main_body
-			.as_mut()
-			.unwrap()
-			.process_permanent_packet(packet)?;
+	fn process_permanent_packet(&mut self, packet: &[u8]) -> Result<bool, ProcessPacketError> {
+		let body = self.main_body.as_mut().unwrap();
+		let PermanentMessage { damage, checksum } =
+			PermanentMessage::decode(packet, body.permanent_bodies())?;
+		// The checksum is of the state before the damage events in this packet are applied.
+		body.queue_damage_events(&mut damage.into_iter());
+
+		if let Some(expected) = checksum {
+			let actual = self.checksum();
+			if expected != actual {
+				godot_error!(
+					"Vehicle desynced: checksum is {:016x}, server has {:016x}",
+					actual,
+					expected,
+				);
+				self.desynced = true;
+			}
+		}
+
 		Ok(self.apply_damage())
 	}
 
@@ -1007,12 +1246,16 @@ impl Vehicle {
 		temporary: &mut impl io::Write,
 	) -> io::Result<()> {
 		// Write out the packet index
-		temporary.write_all(&self.last_processed_packet_index.get().to_le_bytes())?;
+		let index = self.last_processed_packet_index.get();
+		temporary.write_all(&index.to_le_bytes())?;
 
 		// Increment packet index.
 		self.last_processed_packet_index
 			.set(self.last_processed_packet_index.get().wrapping_add(1));
 
+		// Write out the index of the last applied input
+		temporary.write_all(&self.input_index.to_le_bytes())?;
+
 		// Write out the inputs
 		temporary.write_all(&self.controller.bitmap.to_le_bytes())?;
 		Body::serialize_vector3(temporary, self.controller.aim_at)?;
@@ -1021,7 +1264,23 @@ impl Vehicle {
 		self.main_body
 			.as_ref()
 			.unwrap()
-			.create_packet(permanent, temporary)
+			.create_packet(permanent, temporary)?;
+
+		// Write out a checksum now and then so clients can detect desyncs. It must be made
+		// before damage is applied, as clients check it before applying the damage events.
+		if index % CHECKSUM_INTERVAL == 0 {
+			permanent.write_all(&[1])?;
+			permanent.write_all(&self.checksum().to_le_bytes())
+		} else {
+			permanent.write_all(&[0])
+		}
+	}
+
+	/// A checksum of the damage state of all bodies.
+	fn checksum(&self) -> u64 {
+		let mut checksum = Checksum::new();
+		self.main_body.as_ref().unwrap().checksum(&mut checksum);
+		checksum.get()
 	}
 
 	/// Attempt to fire a weapon. Returns `true` on success.
@@ -1072,6 +1331,9 @@ impl Vehicle {
 
 	/// Serialize the vehicle for transmission over a network.
 	fn serialize(&self, out: &mut impl io::Write) -> io::Result<()> {
+		// Serialize the revision of the format
+		out.write_all(&[VEHICLE_REVISION])?;
+
 		// Serialize the last processed packet index
 		out.write_all(&self.last_processed_packet_index.get().to_le_bytes())?;
 
@@ -1091,36 +1353,26 @@ impl Vehicle {
 		self.body(&[]).expect("Destroyed").serialize(out)
 	}
 
-	/// Deserialize a vehicle.
+	/// Deserialize a vehicle. The data is checked before anything is created, so malformed
+	/// data is rejected without side effects. Only multiple bodies on one anchor and
+	/// incompatible or unknown weapons are found after the nodes are created, in which case
+	/// the nodes are freed again.
 	fn deserialize(
-		in_: &mut impl io::Read,
+		data: &[u8],
 		team_color: Color,
 		is_local: bool,
 		is_master: bool,
-	) -> io::Result<Self> {
-		// Get the last processed packet index
-		let mut last_processed_packet_index = [0; 2];
-		in_.read_exact(&mut last_processed_packet_index)?;
-		let last_processed_packet_index =
-			Cell::new(u16::from_le_bytes(last_processed_packet_index));
-
-		// Get the team
-		let mut team = [0; 1];
-		in_.read_exact(&mut team)?;
-		let team = u8::from_le_bytes(team);
-
-		// Get the color palette
-		let mut count = [0; 1];
-		in_.read_exact(&mut count)?;
-		let count = count[0];
-		let mut colors = Box::new_uninit_slice(count.into());
-		for clr in colors.iter_mut() {
-			let mut buf = [0; 3];
-			in_.read_exact(&mut buf)?;
-			clr.write(color::RGB8::new(buf[0], buf[1], buf[2]));
-		}
-		// SAFETY: all elements have been initialized
-		let colors = unsafe { colors.assume_init() };
+	) -> Result<Self, NewVehicleError> {
+		// Decode & check the vehicle
+		let data = DecodedVehicle::decode(data, &block::Registered)
+			.map_err(NewVehicleError::InvalidData)?;
+		let last_processed_packet_index = Cell::new(data.last_processed_packet_index);
+		let team = data.team;
+		let colors = data
+			.colors
+			.iter()
+			.map(|c| color::RGB8::new(c[0], c[1], c[2]))
+			.collect::<Box<_>>();
 
 		// Deserialize bodies
 		let mut shared = Shared {
@@ -1135,18 +1387,32 @@ impl Vehicle {
 			team_color,
 			colors,
 		};
-		let mut main_body = Body::deserialize(in_, &mut shared)?;
-
-		main_body.init(&mut shared).unwrap();
+		let mut main_body = Body::deserialize(data.main_body, &mut shared);
+
+		// Anchors & weapons are described by the nodes of the blocks, so these can only be
+		// checked after the nodes are created. Free them if the vehicle is rejected.
+		let weapon_fire_volley = main_body
+			.init(&mut shared)
+			.map_err(NewVehicleError::InitBodiesError)
+			.and_then(|()| Self::init_weapons(&shared.weapons));
+		let weapon_fire_volley = match weapon_fire_volley {
+			Ok(v) => v,
+			Err(e) => {
+				main_body.free_nodes();
+				return Err(e);
+			}
+		};
 
 		let mut max_cost = 0;
 		main_body.iter_all_bodies(&mut |b| max_cost += b.max_cost());
 		main_body.create_collision_exceptions();
 
-		let weapon_fire_volley = Self::init_weapons(&shared.weapons).unwrap();
+		let mode = VehicleMode::new(is_local, is_master);
 
 		Ok(Self {
-			mode: VehicleMode::new(is_local, is_master),
+			prediction: mode.is_predicted().then(Predictor::default),
+			interpolation: mode.is_interpolated().then(Interpolation::new),
+			mode,
 
 			weapon_fire_volley,
 			next_weapon: Cell::new(0),
@@ -1160,6 +1426,10 @@ impl Vehicle {
 			controller: super::Controller::default(),
 
 			last_processed_packet_index,
+			input_index: 0,
+			inputs: InputQueue::new(),
+
+			desynced: false,
 		})
 	}
 
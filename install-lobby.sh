@@ -1,4 +1,12 @@
 #!/bin/sh
 
-scp lobby/target/release/own-war-lobby ownwar@lu0:own-war-lobby
-scp lobby/target/release/own-war-lobby ownwar@lv0:own-war-lobby
+for SERVER in lu0 lv0; do
+	echo Installing lobby on $SERVER
+	scp lobby/target/release/own-war-lobby "ownwar@$SERVER:own-war-lobby"
+	# Keep the configuration if it was tuned on the server
+	ssh "ownwar@$SERVER" test -e own-war-lobby.conf \
+		|| scp lobby/own-war-lobby.conf "ownwar@$SERVER:own-war-lobby.conf"
+	ssh "ownwar@$SERVER" mkdir -p .config/systemd/user
+	scp lobby/own-war-lobby.service "ownwar@$SERVER:.config/systemd/user/own-war-lobby.service"
+	ssh "ownwar@$SERVER" 'systemctl --user daemon-reload && systemctl --user enable own-war-lobby && systemctl --user restart own-war-lobby'
+done
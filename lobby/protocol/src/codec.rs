@@ -0,0 +1,229 @@
+//! Primitives used to encode and decode messages.
+
+use crate::*;
+use core::convert::TryInto;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Reads fields from the front of a buffer.
+pub struct Reader<'a> {
+	buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+	pub fn new(buf: &'a [u8]) -> Self {
+		Self { buf }
+	}
+
+	pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+		if self.buf.len() < n {
+			return Err(DecodeError::Truncated);
+		}
+		let (b, rest) = self.buf.split_at(n);
+		self.buf = rest;
+		Ok(b)
+	}
+
+	pub fn u8(&mut self) -> Result<u8, DecodeError> {
+		Ok(self.bytes(1)?[0])
+	}
+
+	pub fn u16(&mut self) -> Result<u16, DecodeError> {
+		Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+	}
+
+	pub fn u32(&mut self) -> Result<u32, DecodeError> {
+		Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+	}
+
+	pub fn u64(&mut self) -> Result<u64, DecodeError> {
+		Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+	}
+
+	/// A string prefixed with a `u8` length.
+	pub fn str8(&mut self) -> Result<&'a str, DecodeError> {
+		let len = self.u8()?.into();
+		self.str(len)
+	}
+
+	/// A string prefixed with a `u16` length.
+	pub fn str16(&mut self) -> Result<&'a str, DecodeError> {
+		let len = self.u16()?.into();
+		self.str(len)
+	}
+
+	/// Bytes prefixed with a `u16` length.
+	pub fn bytes16(&mut self) -> Result<&'a [u8], DecodeError> {
+		let len = self.u16()?.into();
+		self.bytes(len)
+	}
+
+	fn str(&mut self, len: usize) -> Result<&'a str, DecodeError> {
+		core::str::from_utf8(self.bytes(len)?).map_err(DecodeError::InvalidUtf8)
+	}
+
+	pub fn addr(&mut self) -> Result<SocketAddr, DecodeError> {
+		let (addr, rest) = decode_addr(self.buf)?;
+		self.buf = rest;
+		Ok(addr)
+	}
+
+	pub fn status(&mut self) -> Result<MatchStatus, DecodeError> {
+		Ok(MatchStatus {
+			players: self.u8()?,
+			phase: MatchPhase::try_from(self.u8()?)?,
+			elapsed: self.u32()?,
+		})
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.buf.is_empty()
+	}
+
+	/// The bytes that haven't been read yet.
+	pub fn rest(self) -> &'a [u8] {
+		self.buf
+	}
+
+	/// Ensure all bytes have been read.
+	pub fn finish(self) -> Result<(), DecodeError> {
+		if self.buf.is_empty() {
+			Ok(())
+		} else {
+			Err(DecodeError::TrailingData)
+		}
+	}
+}
+
+pub fn put_str8(buf: &mut Vec<u8>, s: &str) -> Result<(), EncodeError> {
+	let len = u8::try_from(s.len()).map_err(|_| EncodeError::StringTooLong)?;
+	buf.push(len);
+	buf.extend(s.as_bytes());
+	Ok(())
+}
+
+pub fn put_str16(buf: &mut Vec<u8>, s: &str) -> Result<(), EncodeError> {
+	let len = u16::try_from(s.len()).map_err(|_| EncodeError::StringTooLong)?;
+	buf.extend(&len.to_le_bytes());
+	buf.extend(s.as_bytes());
+	Ok(())
+}
+
+pub fn put_bytes16(buf: &mut Vec<u8>, b: &[u8]) -> Result<(), EncodeError> {
+	let len = u16::try_from(b.len()).map_err(|_| EncodeError::DataTooLong)?;
+	buf.extend(&len.to_le_bytes());
+	buf.extend(b);
+	Ok(())
+}
+
+pub fn put_status(buf: &mut Vec<u8>, status: &MatchStatus) {
+	buf.push(status.players);
+	buf.push(status.phase as u8);
+	buf.extend(&status.elapsed.to_le_bytes());
+}
+
+/// Decode an address, which is a type byte (0 for IPv4, 1 for IPv6) followed by the octets
+/// or segments and the port. IPv4-mapped IPv6 addresses are converted to IPv4 addresses.
+pub fn decode_addr(buf: &[u8]) -> Result<(SocketAddr, &[u8]), DecodeError> {
+	let mut r = Reader::new(buf);
+	let ip = match r.u8()? {
+		0 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(r.bytes(4)?).unwrap())),
+		1 => {
+			let mut s = [0; 8];
+			for n in s.iter_mut() {
+				*n = r.u16()?;
+			}
+			IpAddr::V6(Ipv6Addr::from(s)).to_canonical()
+		}
+		t => return Err(DecodeError::InvalidAddressType(t)),
+	};
+	let port = r.u16()?;
+	Ok((SocketAddr::new(ip, port), r.rest()))
+}
+
+pub fn encode_addr(addr: SocketAddr, buf: &mut Vec<u8>) {
+	match addr.ip() {
+		IpAddr::V4(v) => {
+			buf.push(0);
+			buf.extend(&v.octets()[..])
+		}
+		IpAddr::V6(v) => {
+			buf.push(1);
+			for s in &v.segments() {
+				buf.extend(&s.to_le_bytes())
+			}
+		}
+	}
+	buf.extend(&addr.port().to_le_bytes());
+}
+
+/// The size of an encoded address.
+pub fn addr_len(addr: SocketAddr) -> usize {
+	match addr {
+		SocketAddr::V4(_) => 1 + 4 + 2,
+		SocketAddr::V6(_) => 1 + 16 + 2,
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn round_trip(addr: SocketAddr) {
+		let mut buf = Vec::new();
+		encode_addr(addr, &mut buf);
+		buf.push(42);
+		let (decoded, rest) = decode_addr(&buf).unwrap();
+		assert_eq!(decoded, addr);
+		assert_eq!(rest, &[42]);
+		// Truncated addresses must be rejected
+		assert_eq!(
+			decode_addr(&buf[..buf.len() - 2]),
+			Err(DecodeError::Truncated)
+		);
+	}
+
+	#[test]
+	fn addr_round_trip_ipv4() {
+		round_trip(SocketAddr::from(([127, 0, 0, 1], 39983)));
+		round_trip(SocketAddr::from(([255, 1, 2, 254], 0xfedc)));
+	}
+
+	#[test]
+	fn addr_round_trip_ipv6() {
+		round_trip(SocketAddr::from((Ipv6Addr::LOCALHOST, 39983)));
+		round_trip(SocketAddr::from((
+			[0x2001, 0xdb8, 0x1234, 0xabcd, 0, 0xff, 0xfe00, 0x8001],
+			0x1234,
+		)));
+	}
+
+	#[test]
+	fn addr_ipv6_layout() {
+		let addr = SocketAddr::from(([0x0102, 0, 0, 0, 0, 0, 0, 0xfffe], 0x0304));
+		let mut buf = Vec::new();
+		encode_addr(addr, &mut buf);
+		let mut expected = vec![1, 2, 1];
+		expected.extend(&[0; 12]);
+		expected.extend(&[0xfe, 0xff, 4, 3]);
+		assert_eq!(buf, expected);
+	}
+
+	#[test]
+	fn addr_mapped_ipv6_is_ipv4() {
+		let mut buf = vec![1];
+		for s in &[0, 0, 0, 0, 0, 0xffff, 0x7f00, 0x0001, 39983] {
+			buf.extend(&u16::to_le_bytes(*s));
+		}
+		let (addr, _) = decode_addr(&buf).unwrap();
+		assert_eq!(addr, SocketAddr::from(([127, 0, 0, 1], 39983)));
+	}
+
+	#[test]
+	fn addr_invalid_type() {
+		assert_eq!(
+			decode_addr(&[2, 0, 0, 0, 0, 0, 0]),
+			Err(DecodeError::InvalidAddressType(2))
+		);
+		assert_eq!(decode_addr(&[]), Err(DecodeError::Truncated));
+	}
+}
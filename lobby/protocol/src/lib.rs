@@ -0,0 +1,401 @@
+//! The wire format spoken between the lobby, game servers and game clients.
+//!
+//! Every packet starts with [`MAGIC`], the protocol version and the [`MessageType`], followed
+//! by the fields of the message. All integers are little endian. Requests are sent to the lobby,
+//! responses are sent by the lobby.
+
+pub mod codec;
+mod request;
+mod response;
+mod sign;
+
+pub use codec::{decode_addr, encode_addr};
+pub use request::*;
+pub use response::*;
+pub use sign::{Secret, MAC_SIZE, SIGNATURE_SIZE};
+
+use core::convert::TryFrom;
+use core::fmt;
+use core::str::Utf8Error;
+
+/// The first byte of every packet. Packets of clients that predate versioning start with the
+/// message type instead, which is never equal to this byte.
+pub const MAGIC: u8 = 0xff;
+
+/// The version of the protocol spoken by the lobby. Every packet starts with [`MAGIC`] and the
+/// version byte, followed by the message type.
+///
+/// Bump this whenever the layout of an existing message changes.
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// The oldest protocol version the lobby still understands.
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+
+/// The maximum amount of vehicle data in a single `UploadVehicle` or `DownloadVehicle`
+/// chunk.
+pub const VEHICLE_CHUNK_SIZE: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum MessageType {
+	GetServerList = 0,
+	RegisterServer = 1,
+	RemoveServer = 2,
+	Ping = 3,
+	ServerInfo = 4,
+	PunchHole = 5,
+	Hello = 6,
+	Error = 7,
+	/// Request a page of servers matching a [`ListFilter`], starting after the cursor address.
+	/// Each entry carries the full server info. The last entry of a page is the cursor for the
+	/// next page.
+	ListServers = 8,
+	/// Replicate registrations to another lobby. Must be signed.
+	Sync = 9,
+	/// Ask the lobby for the public address it sees for the sender.
+	WhoAmI = 10,
+	/// Ask the lobby to coordinate a hole punch with a server. Both the client and the manager
+	/// of the server receive a `Punch` message with each other's endpoint.
+	PunchRequest = 11,
+	/// Sent by the lobby to both sides of a punch session.
+	Punch = 12,
+	/// Acknowledge a `Punch` message and report whether the connection succeeded.
+	PunchAck = 13,
+	/// Offer the free slots of a registered server to matchmaking.
+	Advertise = 14,
+	/// Wait for a match with the given [`Preferences`]. Must be resent periodically to stay
+	/// in the queue. The address must be verified with `Verify` first.
+	Enqueue = 15,
+	/// Leave the matchmaking queue.
+	Dequeue = 16,
+	/// Sent by the lobby to a queued client when it is assigned to a server. A punch session
+	/// with the server starts right after.
+	Matched = 17,
+	/// Upload a chunk of a `.owv` vehicle to the repository. Chunks must be sent in order,
+	/// each after the previous one is acknowledged.
+	UploadVehicle = 18,
+	/// Request a page of shared vehicles matching a name and tags, starting after the cursor
+	/// ID.
+	SearchVehicles = 19,
+	/// Request a chunk of a shared vehicle.
+	DownloadVehicle = 20,
+	/// Prove the sender can receive packets at its address. The lobby answers with a cookie
+	/// for the address, which the sender echoes with another `Verify`. Until then, the lobby
+	/// sends at most a few times as many bytes to the address as it received from it.
+	Verify = 21,
+}
+
+impl MessageType {
+	/// All message types a client may send to the lobby, in the order they are advertised
+	/// by `Hello`.
+	pub const SUPPORTED: &'static [Self] = &[
+		Self::GetServerList,
+		Self::RegisterServer,
+		Self::RemoveServer,
+		Self::Ping,
+		Self::ServerInfo,
+		Self::PunchHole,
+		Self::Hello,
+		Self::ListServers,
+		Self::Sync,
+		Self::WhoAmI,
+		Self::PunchRequest,
+		Self::PunchAck,
+		Self::Advertise,
+		Self::Enqueue,
+		Self::Dequeue,
+		Self::UploadVehicle,
+		Self::SearchVehicles,
+		Self::DownloadVehicle,
+		Self::Verify,
+	];
+
+	/// The oldest protocol version this message type may be sent with.
+	pub fn min_version(self) -> u8 {
+		match self {
+			// These must present a registration token or signature, which were added in
+			// version 2.
+			Self::RegisterServer | Self::RemoveServer | Self::Ping | Self::Sync => 2,
+			Self::WhoAmI | Self::PunchRequest | Self::Punch | Self::PunchAck => 2,
+			Self::Advertise | Self::Enqueue | Self::Dequeue | Self::Matched => 2,
+			Self::UploadVehicle | Self::SearchVehicles | Self::DownloadVehicle => 2,
+			Self::Verify => 2,
+			_ => MIN_PROTOCOL_VERSION,
+		}
+	}
+
+	/// A human readable name of the message type.
+	pub fn name(self) -> &'static str {
+		match self {
+			Self::GetServerList => "get-server-list",
+			Self::RegisterServer => "register-server",
+			Self::RemoveServer => "remove-server",
+			Self::Ping => "ping",
+			Self::ServerInfo => "server-info",
+			Self::PunchHole => "punch-hole",
+			Self::Hello => "hello",
+			Self::Error => "error",
+			Self::ListServers => "list-servers",
+			Self::Sync => "sync",
+			Self::WhoAmI => "who-am-i",
+			Self::PunchRequest => "punch-request",
+			Self::Punch => "punch",
+			Self::PunchAck => "punch-ack",
+			Self::Advertise => "advertise",
+			Self::Enqueue => "enqueue",
+			Self::Dequeue => "dequeue",
+			Self::Matched => "matched",
+			Self::UploadVehicle => "upload-vehicle",
+			Self::SearchVehicles => "search-vehicles",
+			Self::DownloadVehicle => "download-vehicle",
+			Self::Verify => "verify",
+		}
+	}
+}
+
+impl TryFrom<u8> for MessageType {
+	type Error = DecodeError;
+
+	fn try_from(value: u8) -> Result<Self, DecodeError> {
+		use MessageType::*;
+		Ok(match value {
+			0 => GetServerList,
+			1 => RegisterServer,
+			2 => RemoveServer,
+			3 => Ping,
+			4 => ServerInfo,
+			5 => PunchHole,
+			6 => Hello,
+			7 => Error,
+			8 => ListServers,
+			9 => Sync,
+			10 => WhoAmI,
+			11 => PunchRequest,
+			12 => Punch,
+			13 => PunchAck,
+			14 => Advertise,
+			15 => Enqueue,
+			16 => Dequeue,
+			17 => Matched,
+			18 => UploadVehicle,
+			19 => SearchVehicles,
+			20 => DownloadVehicle,
+			21 => Verify,
+			_ => return Err(DecodeError::InvalidMessageType(value)),
+		})
+	}
+}
+
+/// Status codes sent back in `RegisterServer` responses and `Error` messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ErrorCode {
+	Ok = 0,
+	/// The protocol version of the peer is not supported. The lobby appends the minimum and
+	/// maximum version it supports.
+	UnsupportedVersion = 1,
+	/// The message type is not known to the lobby.
+	UnknownMessage = 2,
+	/// The token or signature does not match the registration, or the address must be
+	/// verified first.
+	Unauthorized = 3,
+	/// The host has too many servers registered already.
+	TooManyRegistrations = 4,
+	/// The response would be too large compared to the request. The request should be padded
+	/// with zeroes.
+	RequestTooSmall = 5,
+	/// The host is not allowed to register servers.
+	Denied = 6,
+	/// The name or description of the server was rejected by a content filter.
+	Rejected = 7,
+	/// The uploaded vehicle is too large or not a valid `.owv` file.
+	InvalidVehicle = 8,
+	/// The requested vehicle doesn't exist.
+	NotFound = 9,
+	/// The lobby can't store any more vehicles.
+	StorageFull = 10,
+}
+
+impl TryFrom<u8> for ErrorCode {
+	type Error = DecodeError;
+
+	fn try_from(value: u8) -> Result<Self, DecodeError> {
+		Ok(match value {
+			0 => Self::Ok,
+			1 => Self::UnsupportedVersion,
+			2 => Self::UnknownMessage,
+			3 => Self::Unauthorized,
+			4 => Self::TooManyRegistrations,
+			5 => Self::RequestTooSmall,
+			6 => Self::Denied,
+			7 => Self::Rejected,
+			8 => Self::InvalidVehicle,
+			9 => Self::NotFound,
+			10 => Self::StorageFull,
+			_ => return Err(DecodeError::InvalidErrorCode(value)),
+		})
+	}
+}
+
+/// The progress of one side of a punch session, as reported by `PunchAck`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PunchResult {
+	/// The `Punch` message was received and the side started sending packets to its peer.
+	Received = 0,
+	/// A connection with the peer was established.
+	Connected = 1,
+	/// No connection could be established.
+	Failed = 2,
+}
+
+impl TryFrom<u8> for PunchResult {
+	type Error = DecodeError;
+
+	fn try_from(value: u8) -> Result<Self, DecodeError> {
+		Ok(match value {
+			0 => Self::Received,
+			1 => Self::Connected,
+			2 => Self::Failed,
+			_ => return Err(DecodeError::InvalidPunchResult(value)),
+		})
+	}
+}
+
+/// The phase a match hosted by a server is in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MatchPhase {
+	Warmup = 0,
+	InProgress = 1,
+	Ending = 2,
+}
+
+impl TryFrom<u8> for MatchPhase {
+	type Error = DecodeError;
+
+	fn try_from(value: u8) -> Result<Self, DecodeError> {
+		Ok(match value {
+			0 => Self::Warmup,
+			1 => Self::InProgress,
+			2 => Self::Ending,
+			_ => return Err(DecodeError::InvalidMatchPhase(value)),
+		})
+	}
+}
+
+/// The state of a match as reported by `RegisterServer` and `Ping`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MatchStatus {
+	pub players: u8,
+	pub phase: MatchPhase,
+	/// The elapsed match time in seconds.
+	pub elapsed: u32,
+}
+
+impl MatchStatus {
+	/// The size of an encoded status.
+	pub const SIZE: usize = 6;
+}
+
+/// The header at the start of every packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+	/// The protocol version, which is 0 for packets without [`MAGIC`].
+	pub version: u8,
+	/// The raw message type, which may be unknown to the receiver.
+	pub message_type: u8,
+}
+
+impl Header {
+	pub const SIZE: usize = 3;
+
+	/// Create a header for the current protocol version.
+	pub fn new(message_type: MessageType) -> Self {
+		Self {
+			version: PROTOCOL_VERSION,
+			message_type: message_type as u8,
+		}
+	}
+
+	/// Split the header off a packet.
+	///
+	/// Packets that don't start with [`MAGIC`] were sent by clients that predate versioning. These
+	/// start with only the message type and are decoded as version 0, so they can be rejected
+	/// with [`ErrorCode::UnsupportedVersion`].
+	pub fn decode(packet: &[u8]) -> Result<(Self, &[u8]), DecodeError> {
+		match packet {
+			[MAGIC, version, message_type, rest @ ..] => Ok((
+				Self {
+					version: *version,
+					message_type: *message_type,
+				},
+				rest,
+			)),
+			[MAGIC, ..] | [] => Err(DecodeError::Truncated),
+			[message_type, rest @ ..] => Ok((
+				Self {
+					version: 0,
+					message_type: *message_type,
+				},
+				rest,
+			)),
+		}
+	}
+
+	pub fn encode(&self, buf: &mut Vec<u8>) {
+		buf.extend(&[MAGIC, self.version, self.message_type]);
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+	/// The packet ends before all fields are read.
+	Truncated,
+	/// The packet has data after the last field.
+	TrailingData,
+	InvalidUtf8(Utf8Error),
+	InvalidMessageType(u8),
+	InvalidAddressType(u8),
+	InvalidMatchPhase(u8),
+	InvalidErrorCode(u8),
+	InvalidPunchResult(u8),
+	/// The message type is valid but can't be sent in this direction.
+	UnexpectedMessage(MessageType),
+}
+
+impl fmt::Display for DecodeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Truncated => "truncated packet".fmt(f),
+			Self::TrailingData => "trailing data".fmt(f),
+			Self::InvalidUtf8(e) => write!(f, "invalid UTF-8 string: {}", e),
+			Self::InvalidMessageType(t) => write!(f, "invalid message type {}", t),
+			Self::InvalidAddressType(t) => write!(f, "invalid address type {}", t),
+			Self::InvalidMatchPhase(p) => write!(f, "invalid match phase {}", p),
+			Self::InvalidErrorCode(c) => write!(f, "invalid error code {}", c),
+			Self::InvalidPunchResult(r) => write!(f, "invalid punch result {}", r),
+			Self::UnexpectedMessage(t) => write!(f, "unexpected message {}", t.name()),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EncodeError {
+	/// A string is too long for its length prefix.
+	StringTooLong,
+	/// There are too many entries in a list.
+	TooManyEntries,
+	/// A chunk of data is too long for its length prefix.
+	DataTooLong,
+}
+
+impl fmt::Display for EncodeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::StringTooLong => "string too long".fmt(f),
+			Self::TooManyEntries => "too many entries".fmt(f),
+			Self::DataTooLong => "data too long".fmt(f),
+		}
+	}
+}
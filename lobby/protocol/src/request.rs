@@ -0,0 +1,726 @@
+//! Messages sent to the lobby.
+
+use crate::codec::*;
+use crate::*;
+use std::net::SocketAddr;
+
+/// The fields of a `RegisterServer` request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Register<'a> {
+	/// The port the game server listens on.
+	pub port: u16,
+	/// The token of the existing registration or 0 for a new registration.
+	pub token: u64,
+	pub name: &'a str,
+	pub map: &'a str,
+	pub max_players: u8,
+	pub description: &'a str,
+	pub status: MatchStatus,
+}
+
+/// The fields of a `ListServers` request.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ListServers<'a> {
+	/// Only servers with an address after the cursor are listed.
+	pub cursor: Option<SocketAddr>,
+	/// The maximum amount of entries to return, or 0 to let the lobby decide.
+	pub limit: u8,
+	pub filter: ListFilter<'a>,
+}
+
+/// Filters for `ListServers`. Each filter is only present in the request if the corresponding
+/// bit is set in the filter flags.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ListFilter<'a> {
+	/// The map must match exactly.
+	pub map: Option<&'a str>,
+	/// The name must contain this string, ignoring case.
+	pub name: Option<&'a str>,
+	/// The server must have at least this many free slots.
+	pub free_slots: Option<u8>,
+	/// The server must use exactly this protocol version.
+	pub version: Option<u8>,
+}
+
+impl<'a> ListFilter<'a> {
+	const MAP: u8 = 1 << 0;
+	const NAME: u8 = 1 << 1;
+	const FREE_SLOTS: u8 = 1 << 2;
+	const VERSION: u8 = 1 << 3;
+
+	fn decode(r: &mut Reader<'a>) -> Result<Self, DecodeError> {
+		let flags = r.u8()?;
+		let mut filter = Self::default();
+		if flags & Self::MAP > 0 {
+			filter.map = Some(r.str8()?);
+		}
+		if flags & Self::NAME > 0 {
+			filter.name = Some(r.str8()?);
+		}
+		if flags & Self::FREE_SLOTS > 0 {
+			filter.free_slots = Some(r.u8()?);
+		}
+		if flags & Self::VERSION > 0 {
+			filter.version = Some(r.u8()?);
+		}
+		Ok(filter)
+	}
+
+	fn encode(&self, buf: &mut Vec<u8>) -> Result<(), EncodeError> {
+		let flag = |b, f| if b { f } else { 0 };
+		buf.push(
+			flag(self.map.is_some(), Self::MAP)
+				| flag(self.name.is_some(), Self::NAME)
+				| flag(self.free_slots.is_some(), Self::FREE_SLOTS)
+				| flag(self.version.is_some(), Self::VERSION),
+		);
+		if let Some(map) = self.map {
+			put_str8(buf, map)?;
+		}
+		if let Some(name) = self.name {
+			put_str8(buf, name)?;
+		}
+		buf.extend(self.free_slots);
+		buf.extend(self.version);
+		Ok(())
+	}
+}
+
+/// The fields of a `Sync` message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sync<'a> {
+	/// A random identifier of the sending lobby.
+	pub node: u64,
+	pub entries: Vec<SyncEntry<'a>>,
+}
+
+/// A registration replicated by `Sync`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncEntry<'a> {
+	pub addr: SocketAddr,
+	/// How many milliseconds ago the server was last seen or removed by the sending lobby.
+	pub age: u32,
+	/// The registration or `None` if the server was removed.
+	pub info: Option<SyncInfo<'a>>,
+}
+
+/// The details of a registration replicated by `Sync`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncInfo<'a> {
+	pub name: &'a str,
+	pub map: &'a str,
+	pub max_players: u8,
+	pub description: &'a str,
+	pub manager_port: u16,
+	pub version: u8,
+	pub status: MatchStatus,
+	pub token: u64,
+}
+
+impl<'a> SyncEntry<'a> {
+	const REMOVED: u8 = 1 << 0;
+
+	/// The size of the encoded entry.
+	pub fn encoded_len(&self) -> usize {
+		let info = self.info.as_ref().map_or(0, |i| {
+			1 + i.name.len()
+				+ 1 + i.map.len()
+				+ 1 + 2 + i.description.len()
+				+ 2 + 1 + MatchStatus::SIZE
+				+ 8
+		});
+		addr_len(self.addr) + 1 + 4 + info
+	}
+
+	fn decode(r: &mut Reader<'a>) -> Result<Self, DecodeError> {
+		let addr = r.addr()?;
+		let flags = r.u8()?;
+		let age = r.u32()?;
+		let info = if flags & Self::REMOVED > 0 {
+			None
+		} else {
+			Some(SyncInfo {
+				name: r.str8()?,
+				map: r.str8()?,
+				max_players: r.u8()?,
+				description: r.str16()?,
+				manager_port: r.u16()?,
+				version: r.u8()?,
+				status: r.status()?,
+				token: r.u64()?,
+			})
+		};
+		Ok(Self { addr, age, info })
+	}
+
+	fn encode(&self, buf: &mut Vec<u8>) -> Result<(), EncodeError> {
+		encode_addr(self.addr, buf);
+		buf.push(if self.info.is_none() {
+			Self::REMOVED
+		} else {
+			0
+		});
+		buf.extend(&self.age.to_le_bytes());
+		if let Some(i) = &self.info {
+			put_str8(buf, i.name)?;
+			put_str8(buf, i.map)?;
+			buf.push(i.max_players);
+			put_str16(buf, i.description)?;
+			buf.extend(&i.manager_port.to_le_bytes());
+			buf.push(i.version);
+			put_status(buf, &i.status);
+			buf.extend(&i.token.to_le_bytes());
+		}
+		Ok(())
+	}
+}
+
+/// What a client in the matchmaking queue wants to play. Empty strings and 0 match anything.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Preferences<'a> {
+	pub map: &'a str,
+	/// Players per team.
+	pub team_size: u8,
+	/// A tag like `eu` or `us-west`, as advertised by servers.
+	pub region: &'a str,
+}
+
+/// A chunk of an `UploadVehicle` request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Upload<'a> {
+	/// The size of the whole `.owv` file.
+	pub total: u32,
+	/// Where the data of this chunk starts in the file.
+	pub offset: u32,
+	/// Space separated tags. Those of the chunk that completes the upload are used.
+	pub tags: &'a str,
+	pub data: &'a [u8],
+}
+
+/// The fields of a `SearchVehicles` request.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchVehicles<'a> {
+	/// Only vehicles with a greater ID are listed.
+	pub cursor: u64,
+	/// The maximum amount of entries to return, or 0 to let the lobby decide.
+	pub limit: u8,
+	/// The name must contain this string, ignoring case.
+	pub name: &'a str,
+	/// Space separated tags the vehicle must all have.
+	pub tags: &'a str,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request<'a> {
+	/// List all servers. Superseded by `ListServers`.
+	GetServerList,
+	RegisterServer(Register<'a>),
+	RemoveServer {
+		port: u16,
+		token: u64,
+	},
+	Ping {
+		port: u16,
+		token: u64,
+		status: MatchStatus,
+	},
+	/// Get the details of the server with the given address.
+	ServerInfo {
+		addr: SocketAddr,
+	},
+	/// Ask the server with the given address to punch a hole to the sender.
+	PunchHole {
+		addr: SocketAddr,
+	},
+	Hello,
+	ListServers(ListServers<'a>),
+	Sync(Sync<'a>),
+	WhoAmI,
+	/// Ask the lobby to coordinate a punch with the server with the given address.
+	PunchRequest {
+		addr: SocketAddr,
+	},
+	PunchAck {
+		session: u64,
+		result: PunchResult,
+	},
+	/// Must present the token of the registration, like `Ping`.
+	Advertise {
+		port: u16,
+		token: u64,
+		/// Players per team, 0 if the server doesn't have teams.
+		team_size: u8,
+		region: &'a str,
+	},
+	Enqueue(Preferences<'a>),
+	Dequeue,
+	UploadVehicle(Upload<'a>),
+	SearchVehicles(SearchVehicles<'a>),
+	DownloadVehicle {
+		id: u64,
+		offset: u32,
+	},
+	/// Echo the cookie of the last `Verify` response, or 0 to get one.
+	Verify {
+		cookie: u64,
+	},
+}
+
+impl<'a> Request<'a> {
+	pub fn message_type(&self) -> MessageType {
+		match self {
+			Self::GetServerList => MessageType::GetServerList,
+			Self::RegisterServer(_) => MessageType::RegisterServer,
+			Self::RemoveServer { .. } => MessageType::RemoveServer,
+			Self::Ping { .. } => MessageType::Ping,
+			Self::ServerInfo { .. } => MessageType::ServerInfo,
+			Self::PunchHole { .. } => MessageType::PunchHole,
+			Self::Hello => MessageType::Hello,
+			Self::ListServers(_) => MessageType::ListServers,
+			Self::Sync(_) => MessageType::Sync,
+			Self::WhoAmI => MessageType::WhoAmI,
+			Self::PunchRequest { .. } => MessageType::PunchRequest,
+			Self::PunchAck { .. } => MessageType::PunchAck,
+			Self::Advertise { .. } => MessageType::Advertise,
+			Self::Enqueue(_) => MessageType::Enqueue,
+			Self::Dequeue => MessageType::Dequeue,
+			Self::UploadVehicle(_) => MessageType::UploadVehicle,
+			Self::SearchVehicles(_) => MessageType::SearchVehicles,
+			Self::DownloadVehicle { .. } => MessageType::DownloadVehicle,
+			Self::Verify { .. } => MessageType::Verify,
+		}
+	}
+
+	/// Decode the payload of a request, i.e. the part after the [`Header`].
+	///
+	/// Any bytes following the request are returned too. These may be a signature or
+	/// padding.
+	pub fn decode(typ: MessageType, payload: &'a [u8]) -> Result<(Self, &'a [u8]), DecodeError> {
+		let mut r = Reader::new(payload);
+		let req = match typ {
+			MessageType::GetServerList => Self::GetServerList,
+			MessageType::RegisterServer => Self::RegisterServer(Register {
+				port: r.u16()?,
+				token: r.u64()?,
+				name: r.str8()?,
+				map: r.str8()?,
+				max_players: r.u8()?,
+				description: r.str16()?,
+				status: r.status()?,
+			}),
+			MessageType::RemoveServer => Self::RemoveServer {
+				port: r.u16()?,
+				token: r.u64()?,
+			},
+			MessageType::Ping => Self::Ping {
+				port: r.u16()?,
+				token: r.u64()?,
+				status: r.status()?,
+			},
+			MessageType::ServerInfo => Self::ServerInfo { addr: r.addr()? },
+			MessageType::PunchHole => Self::PunchHole { addr: r.addr()? },
+			MessageType::Hello => Self::Hello,
+			MessageType::ListServers => Self::ListServers(ListServers {
+				cursor: match r.u8()? {
+					0 => None,
+					_ => Some(r.addr()?),
+				},
+				limit: r.u8()?,
+				filter: ListFilter::decode(&mut r)?,
+			}),
+			MessageType::Sync => {
+				let node = r.u64()?;
+				let count = r.u8()?;
+				let entries = (0..count)
+					.map(|_| SyncEntry::decode(&mut r))
+					.collect::<Result<_, _>>()?;
+				Self::Sync(Sync { node, entries })
+			}
+			MessageType::WhoAmI => Self::WhoAmI,
+			MessageType::PunchRequest => Self::PunchRequest { addr: r.addr()? },
+			MessageType::PunchAck => Self::PunchAck {
+				session: r.u64()?,
+				result: PunchResult::try_from(r.u8()?)?,
+			},
+			MessageType::Advertise => Self::Advertise {
+				port: r.u16()?,
+				token: r.u64()?,
+				team_size: r.u8()?,
+				region: r.str8()?,
+			},
+			MessageType::Enqueue => Self::Enqueue(Preferences {
+				map: r.str8()?,
+				team_size: r.u8()?,
+				region: r.str8()?,
+			}),
+			MessageType::Dequeue => Self::Dequeue,
+			MessageType::UploadVehicle => Self::UploadVehicle(Upload {
+				total: r.u32()?,
+				offset: r.u32()?,
+				tags: r.str8()?,
+				data: r.bytes16()?,
+			}),
+			MessageType::SearchVehicles => Self::SearchVehicles(SearchVehicles {
+				cursor: r.u64()?,
+				limit: r.u8()?,
+				name: r.str8()?,
+				tags: r.str8()?,
+			}),
+			MessageType::DownloadVehicle => Self::DownloadVehicle {
+				id: r.u64()?,
+				offset: r.u32()?,
+			},
+			MessageType::Verify => Self::Verify { cookie: r.u64()? },
+			MessageType::Error | MessageType::Punch | MessageType::Matched => {
+				return Err(DecodeError::UnexpectedMessage(typ))
+			}
+		};
+		Ok((req, r.rest()))
+	}
+
+	/// Encode the request including the [`Header`].
+	pub fn encode(&self, buf: &mut Vec<u8>) -> Result<(), EncodeError> {
+		Header::new(self.message_type()).encode(buf);
+		match self {
+			Self::GetServerList | Self::Hello | Self::WhoAmI | Self::Dequeue => (),
+			Self::RegisterServer(r) => {
+				buf.extend(&r.port.to_le_bytes());
+				buf.extend(&r.token.to_le_bytes());
+				put_str8(buf, r.name)?;
+				put_str8(buf, r.map)?;
+				buf.push(r.max_players);
+				put_str16(buf, r.description)?;
+				put_status(buf, &r.status);
+			}
+			Self::RemoveServer { port, token } => {
+				buf.extend(&port.to_le_bytes());
+				buf.extend(&token.to_le_bytes());
+			}
+			Self::Ping {
+				port,
+				token,
+				status,
+			} => {
+				buf.extend(&port.to_le_bytes());
+				buf.extend(&token.to_le_bytes());
+				put_status(buf, status);
+			}
+			Self::ServerInfo { addr } | Self::PunchHole { addr } | Self::PunchRequest { addr } => {
+				encode_addr(*addr, buf)
+			}
+			Self::PunchAck { session, result } => {
+				buf.extend(&session.to_le_bytes());
+				buf.push(*result as u8);
+			}
+			Self::Advertise {
+				port,
+				token,
+				team_size,
+				region,
+			} => {
+				buf.extend(&port.to_le_bytes());
+				buf.extend(&token.to_le_bytes());
+				buf.push(*team_size);
+				put_str8(buf, region)?;
+			}
+			Self::Enqueue(p) => {
+				put_str8(buf, p.map)?;
+				buf.push(p.team_size);
+				put_str8(buf, p.region)?;
+			}
+			Self::UploadVehicle(u) => {
+				buf.extend(&u.total.to_le_bytes());
+				buf.extend(&u.offset.to_le_bytes());
+				put_str8(buf, u.tags)?;
+				put_bytes16(buf, u.data)?;
+			}
+			Self::SearchVehicles(s) => {
+				buf.extend(&s.cursor.to_le_bytes());
+				buf.push(s.limit);
+				put_str8(buf, s.name)?;
+				put_str8(buf, s.tags)?;
+			}
+			Self::DownloadVehicle { id, offset } => {
+				buf.extend(&id.to_le_bytes());
+				buf.extend(&offset.to_le_bytes());
+			}
+			Self::Verify { cookie } => buf.extend(&cookie.to_le_bytes()),
+			Self::ListServers(l) => {
+				match l.cursor {
+					Some(c) => {
+						buf.push(1);
+						encode_addr(c, buf);
+					}
+					None => buf.push(0),
+				}
+				buf.push(l.limit);
+				l.filter.encode(buf)?;
+			}
+			Self::Sync(s) => {
+				let count =
+					u8::try_from(s.entries.len()).map_err(|_| EncodeError::TooManyEntries)?;
+				buf.extend(&s.node.to_le_bytes());
+				buf.push(count);
+				for e in &s.entries {
+					e.encode(buf)?;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Encode the request into a new buffer.
+	pub fn to_vec(&self) -> Result<Vec<u8>, EncodeError> {
+		let mut buf = Vec::new();
+		self.encode(&mut buf).map(|()| buf)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	const STATUS: MatchStatus = MatchStatus {
+		players: 3,
+		phase: MatchPhase::InProgress,
+		elapsed: 0x0102,
+	};
+
+	/// Check that the request encodes to exactly the given bytes and decodes back.
+	fn golden(req: Request, bytes: &[u8]) {
+		assert_eq!(req.to_vec().unwrap(), bytes);
+		let (header, payload) = Header::decode(bytes).unwrap();
+		assert_eq!(header, Header::new(req.message_type()));
+		let typ = MessageType::try_from(header.message_type).unwrap();
+		assert_eq!(Request::decode(typ, payload), Ok((req, &[][..])));
+	}
+
+	#[test]
+	fn register_server() {
+		golden(
+			Request::RegisterServer(Register {
+				port: 0x1234,
+				token: 5,
+				name: "ab",
+				map: "m",
+				max_players: 8,
+				description: "d",
+				status: STATUS,
+			}),
+			&[
+				0xff, 2, 1, 0x34, 0x12, 5, 0, 0, 0, 0, 0, 0, 0, 2, b'a', b'b', 1, b'm', 8, 1, 0,
+				b'd', 3, 1, 2, 1, 0, 0,
+			],
+		);
+	}
+
+	#[test]
+	fn remove_server_and_ping() {
+		golden(
+			Request::RemoveServer { port: 1, token: 2 },
+			&[0xff, 2, 2, 1, 0, 2, 0, 0, 0, 0, 0, 0, 0],
+		);
+		golden(
+			Request::Ping {
+				port: 1,
+				token: 2,
+				status: STATUS,
+			},
+			&[0xff, 2, 3, 1, 0, 2, 0, 0, 0, 0, 0, 0, 0, 3, 1, 2, 1, 0, 0],
+		);
+	}
+
+	#[test]
+	fn server_info_and_punch_hole() {
+		let addr = SocketAddr::from(([1, 2, 3, 4], 5));
+		golden(
+			Request::ServerInfo { addr },
+			&[0xff, 2, 4, 0, 1, 2, 3, 4, 5, 0],
+		);
+		golden(
+			Request::PunchHole { addr },
+			&[0xff, 2, 5, 0, 1, 2, 3, 4, 5, 0],
+		);
+	}
+
+	#[test]
+	fn list_servers() {
+		golden(
+			Request::ListServers(Default::default()),
+			&[0xff, 2, 8, 0, 0, 0],
+		);
+		golden(
+			Request::ListServers(ListServers {
+				cursor: Some(SocketAddr::from(([1, 2, 3, 4], 5))),
+				limit: 10,
+				filter: ListFilter {
+					map: Some("m"),
+					name: None,
+					free_slots: Some(2),
+					version: Some(2),
+				},
+			}),
+			&[
+				0xff, 2, 8, 1, 0, 1, 2, 3, 4, 5, 0, 10, 0b1101, 1, b'm', 2, 2,
+			],
+		);
+	}
+
+	#[test]
+	fn sync() {
+		let addr = SocketAddr::from(([1, 2, 3, 4], 5));
+		let removed = SyncEntry {
+			addr,
+			age: 7,
+			info: None,
+		};
+		let updated = SyncEntry {
+			addr,
+			age: 0x0102,
+			info: Some(SyncInfo {
+				name: "n",
+				map: "m",
+				max_players: 4,
+				description: "d",
+				manager_port: 6,
+				version: 2,
+				status: STATUS,
+				token: 9,
+			}),
+		};
+		let bytes = [
+			0xff, 2, 9, 1, 0, 0, 0, 0, 0, 0, 0, 2, // header, node, count
+			0, 1, 2, 3, 4, 5, 0, 1, 7, 0, 0, 0, // removed
+			0, 1, 2, 3, 4, 5, 0, 0, 2, 1, 0, 0, // updated
+			1, b'n', 1, b'm', 4, 1, 0, b'd', 6, 0, 2, 3, 1, 2, 1, 0, 0, 9, 0, 0, 0, 0, 0, 0, 0,
+		];
+		assert_eq!(removed.encoded_len(), 12);
+		assert_eq!(updated.encoded_len(), bytes.len() - 12 - 12);
+		golden(
+			Request::Sync(Sync {
+				node: 1,
+				entries: vec![removed, updated],
+			}),
+			&bytes,
+		);
+	}
+
+	#[test]
+	fn punch() {
+		golden(Request::WhoAmI, &[0xff, 2, 10]);
+		let addr = SocketAddr::from(([1, 2, 3, 4], 5));
+		golden(
+			Request::PunchRequest { addr },
+			&[0xff, 2, 11, 0, 1, 2, 3, 4, 5, 0],
+		);
+		golden(
+			Request::PunchAck {
+				session: 0x0102,
+				result: PunchResult::Connected,
+			},
+			&[0xff, 2, 13, 2, 1, 0, 0, 0, 0, 0, 0, 1],
+		);
+	}
+
+	#[test]
+	fn matchmaking() {
+		golden(
+			Request::Advertise {
+				port: 1,
+				token: 2,
+				team_size: 4,
+				region: "eu",
+			},
+			&[0xff, 2, 14, 1, 0, 2, 0, 0, 0, 0, 0, 0, 0, 4, 2, b'e', b'u'],
+		);
+		golden(
+			Request::Enqueue(Preferences {
+				map: "m",
+				team_size: 0,
+				region: "",
+			}),
+			&[0xff, 2, 15, 1, b'm', 0, 0],
+		);
+		golden(Request::Dequeue, &[0xff, 2, 16]);
+	}
+
+	#[test]
+	fn vehicles() {
+		golden(
+			Request::UploadVehicle(Upload {
+				total: 0x0102,
+				offset: 2,
+				tags: "a",
+				data: &[7, 8],
+			}),
+			&[0xff, 2, 18, 2, 1, 0, 0, 2, 0, 0, 0, 1, b'a', 2, 0, 7, 8],
+		);
+		golden(
+			Request::SearchVehicles(SearchVehicles {
+				cursor: 5,
+				limit: 10,
+				name: "n",
+				tags: "",
+			}),
+			&[0xff, 2, 19, 5, 0, 0, 0, 0, 0, 0, 0, 10, 1, b'n', 0],
+		);
+		golden(
+			Request::DownloadVehicle { id: 5, offset: 3 },
+			&[0xff, 2, 20, 5, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0],
+		);
+	}
+
+	#[test]
+	fn verify() {
+		golden(
+			Request::Verify { cookie: 0x0102 },
+			&[0xff, 2, 21, 2, 1, 0, 0, 0, 0, 0, 0],
+		);
+	}
+
+	#[test]
+	fn legacy_header() {
+		// A `RegisterServer` of a client that predates versioning
+		let packet = [1, 0x2f, 0x9c, 1, b'a'];
+		let (header, payload) = Header::decode(&packet).unwrap();
+		assert_eq!(
+			header,
+			Header {
+				version: 0,
+				message_type: 1
+			}
+		);
+		assert_eq!(payload, &packet[1..]);
+		assert_eq!(Header::decode(&[MAGIC, 2]), Err(DecodeError::Truncated));
+		assert_eq!(Header::decode(&[]), Err(DecodeError::Truncated));
+	}
+
+	#[test]
+	fn trailing_bytes_are_returned() {
+		let mut packet = Request::RemoveServer { port: 1, token: 2 }
+			.to_vec()
+			.unwrap();
+		packet.extend(&[9; MAC_SIZE]);
+		let (_, payload) = Header::decode(&packet).unwrap();
+		let (_, rest) = Request::decode(MessageType::RemoveServer, payload).unwrap();
+		assert_eq!(rest, &[9; MAC_SIZE]);
+	}
+
+	#[test]
+	fn truncated() {
+		let packet = Request::Ping {
+			port: 1,
+			token: 2,
+			status: STATUS,
+		}
+		.to_vec()
+		.unwrap();
+		for len in Header::SIZE..packet.len() {
+			let payload = &packet[Header::SIZE..len];
+			assert_eq!(
+				Request::decode(MessageType::Ping, payload),
+				Err(DecodeError::Truncated)
+			);
+		}
+	}
+}
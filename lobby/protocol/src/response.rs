@@ -0,0 +1,611 @@
+//! Messages sent by the lobby.
+
+use crate::codec::*;
+use crate::*;
+use std::net::SocketAddr;
+
+/// A single entry of a `GetServerList` response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListEntry<'a> {
+	pub addr: SocketAddr,
+	pub name: &'a str,
+}
+
+impl<'a> ListEntry<'a> {
+	/// The size of the encoded entry.
+	pub fn encoded_len(&self) -> usize {
+		addr_len(self.addr) + 1 + self.name.len()
+	}
+}
+
+/// A single entry of a `ListServers` response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerEntry<'a> {
+	pub addr: SocketAddr,
+	pub name: &'a str,
+	pub map: &'a str,
+	pub max_players: u8,
+	pub description: &'a str,
+	/// The protocol version the server registered with.
+	pub version: u8,
+	pub status: MatchStatus,
+}
+
+impl<'a> ServerEntry<'a> {
+	/// The size of the encoded entry.
+	pub fn encoded_len(&self) -> usize {
+		addr_len(self.addr)
+			+ 1 + self.name.len()
+			+ 1 + self.map.len()
+			+ 1 + 2 + self.description.len()
+			+ 1 + MatchStatus::SIZE
+	}
+
+	fn decode(r: &mut Reader<'a>) -> Result<Self, DecodeError> {
+		Ok(Self {
+			addr: r.addr()?,
+			name: r.str8()?,
+			map: r.str8()?,
+			max_players: r.u8()?,
+			description: r.str16()?,
+			version: r.u8()?,
+			status: r.status()?,
+		})
+	}
+
+	fn encode(&self, buf: &mut Vec<u8>) -> Result<(), EncodeError> {
+		encode_addr(self.addr, buf);
+		put_str8(buf, self.name)?;
+		put_str8(buf, self.map)?;
+		buf.push(self.max_players);
+		put_str16(buf, self.description)?;
+		buf.push(self.version);
+		put_status(buf, &self.status);
+		Ok(())
+	}
+}
+
+/// The details of a server in a `ServerInfo` response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerDetails<'a> {
+	pub max_players: u8,
+	pub map: &'a str,
+	pub description: &'a str,
+	pub status: MatchStatus,
+}
+
+/// A single entry of a `SearchVehicles` response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VehicleEntry<'a> {
+	pub id: u64,
+	/// The size of the `.owv` file.
+	pub size: u32,
+	pub name: &'a str,
+	/// Space separated tags.
+	pub tags: &'a str,
+}
+
+impl<'a> VehicleEntry<'a> {
+	/// The size of the encoded entry.
+	pub fn encoded_len(&self) -> usize {
+		8 + 4 + 1 + self.name.len() + 1 + self.tags.len()
+	}
+
+	fn decode(r: &mut Reader<'a>) -> Result<Self, DecodeError> {
+		Ok(Self {
+			id: r.u64()?,
+			size: r.u32()?,
+			name: r.str8()?,
+			tags: r.str8()?,
+		})
+	}
+
+	fn encode(&self, buf: &mut Vec<u8>) -> Result<(), EncodeError> {
+		buf.extend(&self.id.to_le_bytes());
+		buf.extend(&self.size.to_le_bytes());
+		put_str8(buf, self.name)?;
+		put_str8(buf, self.tags)
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Response<'a> {
+	ServerList(Vec<ListEntry<'a>>),
+	/// The server is registered and can be updated with the given token.
+	Registered {
+		token: u64,
+	},
+	/// The registration was rejected. The code is never [`ErrorCode::Ok`].
+	RegisterFailed {
+		code: ErrorCode,
+	},
+	ServerInfo(ServerDetails<'a>),
+	/// Forwarded to the manager of a server when a client wants to connect to it.
+	PunchHole {
+		/// The port of the server the client wants to connect to.
+		port: u16,
+		/// The address of the client.
+		client: SocketAddr,
+	},
+	Hello {
+		min_version: u8,
+		max_version: u8,
+		/// The raw message types supported by the lobby, which may include types unknown to
+		/// the receiver.
+		supported: Vec<u8>,
+	},
+	Error {
+		/// The raw type of the request that failed.
+		request: u8,
+		code: ErrorCode,
+		/// The minimum and maximum supported version if the code is `UnsupportedVersion`.
+		versions: Option<(u8, u8)>,
+	},
+	ListServers {
+		/// Whether there are more entries after the last one.
+		more: bool,
+		entries: Vec<ServerEntry<'a>>,
+	},
+	/// The public address of the host that sent `WhoAmI`.
+	WhoAmI {
+		addr: SocketAddr,
+	},
+	/// Sent to both the client and the manager of the server. Both sides should start sending
+	/// packets to the peer and report their progress with `PunchAck`.
+	Punch {
+		session: u64,
+		/// The port of the server the client wants to connect to.
+		port: u16,
+		/// The public endpoint of the other side. For the manager this is the client, for the
+		/// client this is the server.
+		peer: SocketAddr,
+	},
+	/// The sender is in the matchmaking queue.
+	Enqueued {
+		/// How many clients with compatible preferences are waiting, including the sender.
+		waiting: u8,
+	},
+	Matched {
+		/// The server the client is assigned to.
+		server: SocketAddr,
+		/// The punch session that follows.
+		session: u64,
+	},
+	/// An upload chunk was received. The next chunk should start at `received`.
+	Uploading {
+		received: u32,
+	},
+	/// The upload is complete and the vehicle can be downloaded with the given ID.
+	Uploaded {
+		id: u64,
+	},
+	Vehicles {
+		/// Whether there are more entries after the last one.
+		more: bool,
+		entries: Vec<VehicleEntry<'a>>,
+	},
+	VehicleChunk {
+		id: u64,
+		/// The size of the whole `.owv` file.
+		total: u32,
+		offset: u32,
+		data: &'a [u8],
+	},
+	/// The cookie for the address of the sender of `Verify`. If the request carried a valid
+	/// cookie, the same cookie is returned and the address is verified.
+	Cookie {
+		cookie: u64,
+	},
+}
+
+impl<'a> Response<'a> {
+	pub fn message_type(&self) -> MessageType {
+		match self {
+			Self::ServerList(_) => MessageType::GetServerList,
+			Self::Registered { .. } | Self::RegisterFailed { .. } => MessageType::RegisterServer,
+			Self::ServerInfo(_) => MessageType::ServerInfo,
+			Self::PunchHole { .. } => MessageType::PunchHole,
+			Self::Hello { .. } => MessageType::Hello,
+			Self::Error { .. } => MessageType::Error,
+			Self::ListServers { .. } => MessageType::ListServers,
+			Self::WhoAmI { .. } => MessageType::WhoAmI,
+			Self::Punch { .. } => MessageType::Punch,
+			Self::Enqueued { .. } => MessageType::Enqueue,
+			Self::Matched { .. } => MessageType::Matched,
+			Self::Uploading { .. } | Self::Uploaded { .. } => MessageType::UploadVehicle,
+			Self::Vehicles { .. } => MessageType::SearchVehicles,
+			Self::VehicleChunk { .. } => MessageType::DownloadVehicle,
+			Self::Cookie { .. } => MessageType::Verify,
+		}
+	}
+
+	/// Decode the payload of a response, i.e. the part after the [`Header`].
+	pub fn decode(typ: MessageType, payload: &'a [u8]) -> Result<Self, DecodeError> {
+		let mut r = Reader::new(payload);
+		let rsp = match typ {
+			MessageType::GetServerList => {
+				let mut entries = Vec::new();
+				while !r.is_empty() {
+					entries.push(ListEntry {
+						addr: r.addr()?,
+						name: r.str8()?,
+					});
+				}
+				Self::ServerList(entries)
+			}
+			MessageType::RegisterServer => match ErrorCode::try_from(r.u8()?)? {
+				ErrorCode::Ok => Self::Registered { token: r.u64()? },
+				code => Self::RegisterFailed { code },
+			},
+			MessageType::ServerInfo => Self::ServerInfo(ServerDetails {
+				max_players: r.u8()?,
+				map: r.str8()?,
+				description: r.str16()?,
+				status: r.status()?,
+			}),
+			MessageType::PunchHole => Self::PunchHole {
+				port: r.u16()?,
+				client: r.addr()?,
+			},
+			MessageType::Hello => {
+				let (min_version, max_version) = (r.u8()?, r.u8()?);
+				let count = r.u8()?.into();
+				Self::Hello {
+					min_version,
+					max_version,
+					supported: r.bytes(count)?.into(),
+				}
+			}
+			MessageType::Error => {
+				let request = r.u8()?;
+				let code = ErrorCode::try_from(r.u8()?)?;
+				let versions = match code {
+					ErrorCode::UnsupportedVersion => Some((r.u8()?, r.u8()?)),
+					_ => None,
+				};
+				Self::Error {
+					request,
+					code,
+					versions,
+				}
+			}
+			MessageType::ListServers => {
+				let more = r.u8()? > 0;
+				let count = r.u8()?;
+				let entries = (0..count)
+					.map(|_| ServerEntry::decode(&mut r))
+					.collect::<Result<_, _>>()?;
+				Self::ListServers { more, entries }
+			}
+			MessageType::WhoAmI => Self::WhoAmI { addr: r.addr()? },
+			MessageType::Punch => Self::Punch {
+				session: r.u64()?,
+				port: r.u16()?,
+				peer: r.addr()?,
+			},
+			MessageType::Enqueue => Self::Enqueued { waiting: r.u8()? },
+			MessageType::Matched => Self::Matched {
+				server: r.addr()?,
+				session: r.u64()?,
+			},
+			MessageType::UploadVehicle => match r.u8()? {
+				0 => Self::Uploading { received: r.u32()? },
+				_ => Self::Uploaded { id: r.u64()? },
+			},
+			MessageType::SearchVehicles => {
+				let more = r.u8()? > 0;
+				let count = r.u8()?;
+				let entries = (0..count)
+					.map(|_| VehicleEntry::decode(&mut r))
+					.collect::<Result<_, _>>()?;
+				Self::Vehicles { more, entries }
+			}
+			MessageType::DownloadVehicle => Self::VehicleChunk {
+				id: r.u64()?,
+				total: r.u32()?,
+				offset: r.u32()?,
+				data: r.bytes16()?,
+			},
+			MessageType::Verify => Self::Cookie { cookie: r.u64()? },
+			MessageType::RemoveServer
+			| MessageType::Ping
+			| MessageType::Sync
+			| MessageType::PunchRequest
+			| MessageType::PunchAck
+			| MessageType::Advertise
+			| MessageType::Dequeue => return Err(DecodeError::UnexpectedMessage(typ)),
+		};
+		r.finish()?;
+		Ok(rsp)
+	}
+
+	/// Encode the response including the [`Header`].
+	pub fn encode(&self, buf: &mut Vec<u8>) -> Result<(), EncodeError> {
+		Header::new(self.message_type()).encode(buf);
+		match self {
+			Self::ServerList(entries) => {
+				for e in entries {
+					encode_addr(e.addr, buf);
+					put_str8(buf, e.name)?;
+				}
+			}
+			Self::Registered { token } => {
+				buf.push(ErrorCode::Ok as u8);
+				buf.extend(&token.to_le_bytes());
+			}
+			Self::RegisterFailed { code } => buf.push(*code as u8),
+			Self::ServerInfo(d) => {
+				buf.push(d.max_players);
+				put_str8(buf, d.map)?;
+				put_str16(buf, d.description)?;
+				put_status(buf, &d.status);
+			}
+			Self::PunchHole { port, client } => {
+				buf.extend(&port.to_le_bytes());
+				encode_addr(*client, buf);
+			}
+			Self::Hello {
+				min_version,
+				max_version,
+				supported,
+			} => {
+				let count =
+					u8::try_from(supported.len()).map_err(|_| EncodeError::TooManyEntries)?;
+				buf.extend(&[*min_version, *max_version, count]);
+				buf.extend(supported);
+			}
+			Self::Error {
+				request,
+				code,
+				versions,
+			} => {
+				buf.extend(&[*request, *code as u8]);
+				if let Some((min, max)) = versions {
+					buf.extend(&[*min, *max]);
+				}
+			}
+			Self::ListServers { more, entries } => {
+				let count = u8::try_from(entries.len()).map_err(|_| EncodeError::TooManyEntries)?;
+				buf.extend(&[u8::from(*more), count]);
+				for e in entries {
+					e.encode(buf)?;
+				}
+			}
+			Self::WhoAmI { addr } => encode_addr(*addr, buf),
+			Self::Punch {
+				session,
+				port,
+				peer,
+			} => {
+				buf.extend(&session.to_le_bytes());
+				buf.extend(&port.to_le_bytes());
+				encode_addr(*peer, buf);
+			}
+			Self::Enqueued { waiting } => buf.push(*waiting),
+			Self::Matched { server, session } => {
+				encode_addr(*server, buf);
+				buf.extend(&session.to_le_bytes());
+			}
+			Self::Uploading { received } => {
+				buf.push(0);
+				buf.extend(&received.to_le_bytes());
+			}
+			Self::Uploaded { id } => {
+				buf.push(1);
+				buf.extend(&id.to_le_bytes());
+			}
+			Self::Vehicles { more, entries } => {
+				let count = u8::try_from(entries.len()).map_err(|_| EncodeError::TooManyEntries)?;
+				buf.extend(&[u8::from(*more), count]);
+				for e in entries {
+					e.encode(buf)?;
+				}
+			}
+			Self::VehicleChunk {
+				id,
+				total,
+				offset,
+				data,
+			} => {
+				buf.extend(&id.to_le_bytes());
+				buf.extend(&total.to_le_bytes());
+				buf.extend(&offset.to_le_bytes());
+				put_bytes16(buf, data)?;
+			}
+			Self::Cookie { cookie } => buf.extend(&cookie.to_le_bytes()),
+		}
+		Ok(())
+	}
+
+	/// Encode the response into a new buffer.
+	pub fn to_vec(&self) -> Result<Vec<u8>, EncodeError> {
+		let mut buf = Vec::new();
+		self.encode(&mut buf).map(|()| buf)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Check that the response encodes to exactly the given bytes and decodes back.
+	fn golden(rsp: Response, bytes: &[u8]) {
+		assert_eq!(rsp.to_vec().unwrap(), bytes);
+		let (header, payload) = Header::decode(bytes).unwrap();
+		let typ = MessageType::try_from(header.message_type).unwrap();
+		assert_eq!(Response::decode(typ, payload), Ok(rsp));
+	}
+
+	#[test]
+	fn server_list() {
+		let addr = SocketAddr::from(([1, 2, 3, 4], 5));
+		let entry = ListEntry { addr, name: "a" };
+		assert_eq!(entry.encoded_len(), 9);
+		golden(
+			Response::ServerList(vec![entry]),
+			&[0xff, 2, 0, 0, 1, 2, 3, 4, 5, 0, 1, b'a'],
+		);
+	}
+
+	#[test]
+	fn registered() {
+		golden(
+			Response::Registered { token: 0x0102 },
+			&[0xff, 2, 1, 0, 2, 1, 0, 0, 0, 0, 0, 0],
+		);
+		golden(
+			Response::RegisterFailed {
+				code: ErrorCode::Denied,
+			},
+			&[0xff, 2, 1, 6],
+		);
+	}
+
+	#[test]
+	fn hello() {
+		golden(
+			Response::Hello {
+				min_version: 1,
+				max_version: 2,
+				supported: vec![0, 6],
+			},
+			&[0xff, 2, 6, 1, 2, 2, 0, 6],
+		);
+	}
+
+	#[test]
+	fn error() {
+		golden(
+			Response::Error {
+				request: 1,
+				code: ErrorCode::Unauthorized,
+				versions: None,
+			},
+			&[0xff, 2, 7, 1, 3],
+		);
+		golden(
+			Response::Error {
+				request: 9,
+				code: ErrorCode::UnsupportedVersion,
+				versions: Some((1, 2)),
+			},
+			&[0xff, 2, 7, 9, 1, 1, 2],
+		);
+	}
+
+	#[test]
+	fn list_servers() {
+		let entry = ServerEntry {
+			addr: SocketAddr::from(([1, 2, 3, 4], 5)),
+			name: "n",
+			map: "m",
+			max_players: 4,
+			description: "d",
+			version: 2,
+			status: MatchStatus {
+				players: 1,
+				phase: MatchPhase::Ending,
+				elapsed: 7,
+			},
+		};
+		let bytes = [
+			0xff, 2, 8, 1, 1, 0, 1, 2, 3, 4, 5, 0, 1, b'n', 1, b'm', 4, 1, 0, b'd', 2, 1, 2, 7, 0,
+			0, 0,
+		];
+		assert_eq!(entry.encoded_len(), bytes.len() - 5);
+		golden(
+			Response::ListServers {
+				more: true,
+				entries: vec![entry],
+			},
+			&bytes,
+		);
+	}
+
+	#[test]
+	fn punch() {
+		let addr = SocketAddr::from(([1, 2, 3, 4], 5));
+		golden(
+			Response::WhoAmI { addr },
+			&[0xff, 2, 10, 0, 1, 2, 3, 4, 5, 0],
+		);
+		golden(
+			Response::Punch {
+				session: 7,
+				port: 0x0102,
+				peer: addr,
+			},
+			&[
+				0xff, 2, 12, 7, 0, 0, 0, 0, 0, 0, 0, 2, 1, 0, 1, 2, 3, 4, 5, 0,
+			],
+		);
+	}
+
+	#[test]
+	fn matchmaking() {
+		golden(Response::Enqueued { waiting: 3 }, &[0xff, 2, 15, 3]);
+		golden(
+			Response::Matched {
+				server: SocketAddr::from(([1, 2, 3, 4], 5)),
+				session: 7,
+			},
+			&[0xff, 2, 17, 0, 1, 2, 3, 4, 5, 0, 7, 0, 0, 0, 0, 0, 0, 0],
+		);
+	}
+
+	#[test]
+	fn cookie() {
+		golden(
+			Response::Cookie { cookie: 7 },
+			&[0xff, 2, 21, 7, 0, 0, 0, 0, 0, 0, 0],
+		);
+	}
+
+	#[test]
+	fn vehicles() {
+		golden(
+			Response::Uploading { received: 5 },
+			&[0xff, 2, 18, 0, 5, 0, 0, 0],
+		);
+		golden(
+			Response::Uploaded { id: 5 },
+			&[0xff, 2, 18, 1, 5, 0, 0, 0, 0, 0, 0, 0],
+		);
+		let entry = VehicleEntry {
+			id: 5,
+			size: 6,
+			name: "n",
+			tags: "t",
+		};
+		let bytes = [
+			0xff, 2, 19, 0, 1, 5, 0, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 1, b'n', 1, b't',
+		];
+		assert_eq!(entry.encoded_len(), bytes.len() - 5);
+		golden(
+			Response::Vehicles {
+				more: false,
+				entries: vec![entry],
+			},
+			&bytes,
+		);
+		golden(
+			Response::VehicleChunk {
+				id: 5,
+				total: 6,
+				offset: 4,
+				data: &[1, 2],
+			},
+			&[
+				0xff, 2, 20, 5, 0, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 4, 0, 0, 0, 2, 0, 1, 2,
+			],
+		);
+	}
+
+	#[test]
+	fn trailing_data() {
+		assert_eq!(
+			Response::decode(MessageType::Error, &[1, 3, 0]),
+			Err(DecodeError::TrailingData)
+		);
+	}
+}
@@ -0,0 +1,154 @@
+//! Signing of messages with a secret shared between the lobby and trusted operators.
+//!
+//! Signed messages have a [`SIGNATURE_SIZE`] byte signature appended, which is a timestamp
+//! followed by a message authentication code computed over the entire packet including the
+//! header and the timestamp. The timestamp lets the receiver reject replayed messages.
+
+use core::convert::TryFrom;
+
+/// The size of a message authentication code appended to signed messages.
+pub const MAC_SIZE: usize = 8;
+
+/// The size of the timestamp and message authentication code appended to signed messages.
+pub const SIGNATURE_SIZE: usize = 8 + MAC_SIZE;
+
+/// A secret shared with trusted operators to sign messages.
+pub struct Secret {
+	key: (u64, u64),
+}
+
+impl Secret {
+	/// Use the given 128-bit key directly.
+	pub fn from_key(key: (u64, u64)) -> Self {
+		Self { key }
+	}
+
+	/// Derive a key from an arbitrary secret string.
+	pub fn new(secret: &str) -> Self {
+		let secret = secret.as_bytes();
+		Self {
+			key: (siphash((0, 1), secret), siphash((2, 3), secret)),
+		}
+	}
+
+	/// Sign the given message.
+	pub fn sign(&self, msg: &[u8]) -> [u8; MAC_SIZE] {
+		siphash(self.key, msg).to_le_bytes()
+	}
+
+	/// Sign a packet by appending the timestamp and a MAC over the whole packet.
+	///
+	/// The timestamp is in milliseconds since the Unix epoch and must increase with every
+	/// packet signed for the same purpose, as the receiver rejects timestamps it has seen
+	/// before.
+	pub fn sign_packet(&self, packet: &mut Vec<u8>, timestamp: u64) {
+		packet.extend(&timestamp.to_le_bytes());
+		let mac = self.sign(packet);
+		packet.extend(&mac);
+	}
+
+	/// Verify a packet signed with [`Self::sign_packet`]. Returns the timestamp if the
+	/// signature is valid.
+	pub fn verify_packet(&self, packet: &[u8]) -> Option<u64> {
+		if packet.len() < SIGNATURE_SIZE || !self.verify(packet) {
+			return None;
+		}
+		let timestamp = &packet[packet.len() - SIGNATURE_SIZE..packet.len() - MAC_SIZE];
+		Some(u64::from_le_bytes(<[u8; 8]>::try_from(timestamp).unwrap()))
+	}
+
+	/// Verify a message, where the last [`MAC_SIZE`] bytes are the MAC.
+	pub fn verify(&self, msg: &[u8]) -> bool {
+		if msg.len() < MAC_SIZE {
+			return false;
+		}
+		let (msg, mac) = msg.split_at(msg.len() - MAC_SIZE);
+		// Compare without short-circuiting to avoid leaking timing information.
+		self.sign(msg)
+			.iter()
+			.zip(mac)
+			.fold(0, |acc, (a, b)| acc | (a ^ b))
+			== 0
+	}
+}
+
+/// SipHash-2-4, which is cheap and suitable as a MAC for short messages.
+fn siphash(key: (u64, u64), msg: &[u8]) -> u64 {
+	let mut v = [
+		key.0 ^ 0x736f_6d65_7073_6575,
+		key.1 ^ 0x646f_7261_6e64_6f6d,
+		key.0 ^ 0x6c79_6765_6e65_7261,
+		key.1 ^ 0x7465_6462_7974_6573,
+	];
+
+	fn round(v: &mut [u64; 4]) {
+		v[0] = v[0].wrapping_add(v[1]);
+		v[1] = v[1].rotate_left(13) ^ v[0];
+		v[0] = v[0].rotate_left(32);
+		v[2] = v[2].wrapping_add(v[3]);
+		v[3] = v[3].rotate_left(16) ^ v[2];
+		v[0] = v[0].wrapping_add(v[3]);
+		v[3] = v[3].rotate_left(21) ^ v[0];
+		v[2] = v[2].wrapping_add(v[1]);
+		v[1] = v[1].rotate_left(17) ^ v[2];
+		v[2] = v[2].rotate_left(32);
+	}
+
+	let mut compress = |m: u64| {
+		v[3] ^= m;
+		round(&mut v);
+		round(&mut v);
+		v[0] ^= m;
+	};
+
+	let mut chunks = msg.chunks_exact(8);
+	for c in &mut chunks {
+		compress(u64::from_le_bytes(<[u8; 8]>::try_from(c).unwrap()));
+	}
+	let mut last = [0; 8];
+	last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+	last[7] = msg.len() as u8;
+	compress(u64::from_le_bytes(last));
+
+	v[2] ^= 0xff;
+	for _ in 0..4 {
+		round(&mut v);
+	}
+	v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn siphash_reference() {
+		// Test vector from the SipHash paper
+		let key = (0x0706_0504_0302_0100, 0x0f0e_0d0c_0b0a_0908);
+		let msg = (0..15).collect::<Vec<u8>>();
+		assert_eq!(siphash(key, &msg), 0xa129_ca61_49be_45e5);
+	}
+
+	#[test]
+	fn sign_verify() {
+		let secret = Secret::new("hunter2");
+		let mut msg = b"register".to_vec();
+		msg.extend(&secret.sign(&msg));
+		assert!(secret.verify(&msg));
+		msg[0] ^= 1;
+		assert!(!secret.verify(&msg));
+	}
+
+	#[test]
+	fn sign_verify_packet() {
+		let secret = Secret::new("hunter2");
+		let mut pkt = b"ping".to_vec();
+		secret.sign_packet(&mut pkt, 0x0102);
+		assert_eq!(pkt.len(), 4 + SIGNATURE_SIZE);
+		assert_eq!(secret.verify_packet(&pkt), Some(0x0102));
+		// The timestamp is covered by the MAC
+		pkt[4] ^= 1;
+		assert_eq!(secret.verify_packet(&pkt), None);
+		assert_eq!(Secret::new("hunter3").verify_packet(b"ping"), None);
+	}
+}
@@ -0,0 +1,405 @@
+//! A line based admin interface on a loopback TCP port.
+//!
+//! Every line is a command and every response ends with an empty line. The interface isn't
+//! authenticated, which is why it only accepts loopback addresses. A `GET` request is
+//! answered with the metrics over HTTP so Prometheus can scrape them directly. Any other line
+//! closes the connection, so a browser tricked into posting to the port can't run commands.
+
+use crate::config::Config;
+use crate::{Lobby, POLL_INTERVAL, SHUTDOWN};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HELP: &str = "\
+list                  List servers with the time since they last pinged
+stats                 Show the counters since startup and messages by type
+remove <ADDR>         Remove a server
+ban <IP> [SECONDS]    Drop all packets of a host and remove its servers
+unban <IP>            Lift a ban
+bans                  List banned hosts
+vehicles [NAME]       List shared vehicles whose name contains NAME
+remove-vehicle <ID>   Remove a shared vehicle
+reload                Reload the configuration file
+metrics               Show the metrics in the Prometheus text format
+quit                  Close the connection
+";
+
+/// The first words of the commands in `HELP`.
+const COMMANDS: &[&str] = &[
+	"help",
+	"list",
+	"stats",
+	"remove",
+	"ban",
+	"unban",
+	"bans",
+	"vehicles",
+	"remove-vehicle",
+	"reload",
+	"metrics",
+	"quit",
+];
+
+/// How many admin connections may be open at once. Further connections are closed right
+/// away.
+const MAX_CONNECTIONS: usize = 8;
+
+/// Accept admin connections until the lobby is shut down. `args` are the command line
+/// arguments, which are used to reload the configuration.
+pub fn serve(lobby: &Mutex<Lobby>, listener: &TcpListener, args: &[String]) {
+	let connections = AtomicUsize::new(0);
+	let connections = &connections;
+	thread::scope(|s| {
+		while !SHUTDOWN.load(Ordering::Relaxed) {
+			match listener.accept() {
+				Ok((_, addr)) if connections.load(Ordering::Relaxed) >= MAX_CONNECTIONS => {
+					warn!("Too many admin connections - closing {}", addr);
+				}
+				Ok((stream, addr)) => {
+					debug!("Admin connection from {}", addr);
+					connections.fetch_add(1, Ordering::Relaxed);
+					s.spawn(move || {
+						if let Err(e) = handle(lobby, stream, args) {
+							debug!("Admin connection from {} failed: {}", addr, e);
+						}
+						connections.fetch_sub(1, Ordering::Relaxed);
+					});
+				}
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+				Err(e) => warn!("Failed to accept admin connection: {}", e),
+			}
+		}
+	});
+}
+
+fn handle(lobby: &Mutex<Lobby>, stream: TcpStream, args: &[String]) -> io::Result<()> {
+	stream.set_nonblocking(false)?;
+	stream.set_read_timeout(Some(POLL_INTERVAL))?;
+	let mut reader = BufReader::new(stream.try_clone()?);
+	let mut stream = stream;
+	let mut line = String::new();
+	while read_line(&mut reader, &mut line)? {
+		let cmd = line.trim();
+		if cmd.starts_with("GET ") {
+			let rsp = http(lobby, cmd);
+			// Skip the headers so the response isn't reset by unread data.
+			loop {
+				line.clear();
+				if !read_line(&mut reader, &mut line)? || line.trim().is_empty() {
+					break;
+				}
+			}
+			return stream.write_all(rsp.as_bytes());
+		}
+		let known = cmd
+			.split_whitespace()
+			.next()
+			.is_none_or(|word| COMMANDS.contains(&word));
+		if !known {
+			warn!("Invalid admin command '{}' - closing the connection", cmd);
+			return Ok(());
+		}
+		if cmd == "quit" {
+			break;
+		}
+		if !cmd.is_empty() {
+			info!("Admin command '{}'", cmd);
+			let mut rsp = execute(&mut lobby.lock().unwrap(), cmd, args, Instant::now());
+			rsp.push('\n');
+			stream.write_all(rsp.as_bytes())?;
+		}
+		line.clear();
+	}
+	Ok(())
+}
+
+/// Read a line, waiting until the lobby is shut down. Returns `false` if the connection was
+/// closed or the lobby is shutting down.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<bool> {
+	while !SHUTDOWN.load(Ordering::Relaxed) {
+		match reader.read_line(line) {
+			Ok(n) => return Ok(n > 0),
+			Err(e)
+				if matches!(
+					e.kind(),
+					io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+				) => {}
+			Err(e) => return Err(e),
+		}
+	}
+	Ok(false)
+}
+
+/// Answer an HTTP request. Only `/metrics` exists.
+fn http(lobby: &Mutex<Lobby>, request_line: &str) -> String {
+	let (status, body) = match request_line.split_whitespace().nth(1) {
+		Some("/metrics") => ("200 OK", metrics(&lobby.lock().unwrap(), Instant::now())),
+		_ => ("404 Not Found", String::new()),
+	};
+	format!(
+		"HTTP/1.0 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+		status,
+		body.len(),
+		body
+	)
+}
+
+/// Run a single command and return the output.
+fn execute(lobby: &mut Lobby, cmd: &str, args: &[String], now: Instant) -> String {
+	let mut words = cmd.split_whitespace();
+	let mut out = String::new();
+	match (words.next(), words.next(), words.next()) {
+		(Some("help"), None, _) => out.push_str(HELP),
+		(Some("list"), None, _) => {
+			for (addr, info) in lobby.list.iter() {
+				let status = info.current_status();
+				let _ = writeln!(
+					out,
+					"{} '{}' map '{}' {}/{} players {:?} {}s age {}s{}{}{}{}",
+					addr,
+					info.name,
+					info.map,
+					status.players,
+					info.max_players,
+					status.phase,
+					status.elapsed,
+					now.saturating_duration_since(info.last_ping).as_secs(),
+					if info.trusted { " trusted" } else { "" },
+					if info.verified { " verified" } else { "" },
+					if info.restored { " restored" } else { "" },
+					if info.replicated { " replicated" } else { "" },
+				);
+			}
+			let _ = writeln!(out, "{} servers", lobby.list.len());
+		}
+		(Some("stats"), None, _) => {
+			let _ = writeln!(
+				out,
+				"uptime {}s, {} servers, {}",
+				now.saturating_duration_since(lobby.started).as_secs(),
+				lobby.list.len(),
+				lobby.stats
+			);
+			for (typ, n) in lobby.stats.by_type() {
+				let _ = writeln!(out, "{} {}", typ.name(), n);
+			}
+		}
+		(Some("remove"), Some(addr), None) => match addr.parse::<SocketAddr>() {
+			Ok(addr) => match lobby.remove(addr, now) {
+				Some(_) => {
+					info!("Removed {} - removed by admin", addr);
+					let _ = writeln!(out, "removed {}", addr);
+				}
+				None => {
+					let _ = writeln!(out, "no server {}", addr);
+				}
+			},
+			Err(_) => {
+				let _ = writeln!(out, "invalid address '{}'", addr);
+			}
+		},
+		(Some("ban"), Some(ip), secs) => {
+			let until = match secs.map(str::parse::<u64>) {
+				None => None,
+				Some(Ok(s)) => Some(now + Duration::from_secs(s)),
+				Some(Err(_)) => return format!("invalid duration '{}'\n", secs.unwrap()),
+			};
+			match ip.parse::<IpAddr>() {
+				Ok(ip) => {
+					let n = lobby.ban(ip, until, now);
+					info!("Banned {} - removed {} servers", ip, n);
+					let _ = writeln!(out, "banned {}, removed {} servers", ip, n);
+				}
+				Err(_) => {
+					let _ = writeln!(out, "invalid IP '{}'", ip);
+				}
+			}
+		}
+		(Some("unban"), Some(ip), None) => match ip.parse::<IpAddr>() {
+			Ok(ip) if lobby.banned.remove(&ip).is_some() => {
+				info!("Unbanned {}", ip);
+				let _ = writeln!(out, "unbanned {}", ip);
+			}
+			Ok(ip) => {
+				let _ = writeln!(out, "{} isn't banned", ip);
+			}
+			Err(_) => {
+				let _ = writeln!(out, "invalid IP '{}'", ip);
+			}
+		},
+		(Some("bans"), None, _) => {
+			for (ip, until) in lobby.banned.iter() {
+				match until {
+					Some(t) => {
+						let left = t.saturating_duration_since(now).as_secs();
+						let _ = writeln!(out, "{} for {}s", ip, left);
+					}
+					None => {
+						let _ = writeln!(out, "{}", ip);
+					}
+				}
+			}
+		}
+		(Some("vehicles"), name, None) => match &lobby.repository {
+			Some(repo) => {
+				for e in repo.search(0, name.unwrap_or(""), "") {
+					let _ = writeln!(
+						out,
+						"{:016x} '{}' tags '{}' {} bytes",
+						e.id, e.name, e.tags, e.size
+					);
+				}
+				let _ = writeln!(out, "{} vehicles", repo.len());
+			}
+			None => out.push_str("vehicle sharing is disabled\n"),
+		},
+		(Some("remove-vehicle"), Some(id), None) => {
+			let repo = match &mut lobby.repository {
+				Some(repo) => repo,
+				None => return "vehicle sharing is disabled\n".into(),
+			};
+			match u64::from_str_radix(id, 16).map(|id| (id, repo.remove(id))) {
+				Ok((id, Ok(true))) => {
+					info!("Removed vehicle {:016x} - removed by admin", id);
+					let _ = writeln!(out, "removed {:016x}", id);
+				}
+				Ok((id, Ok(false))) => {
+					let _ = writeln!(out, "no vehicle {:016x}", id);
+				}
+				Ok((id, Err(e))) => {
+					warn!("Failed to remove vehicle {:016x}: {}", id, e);
+					let _ = writeln!(out, "failed to remove {:016x}: {}", id, e);
+				}
+				Err(_) => {
+					let _ = writeln!(out, "invalid ID '{}'", id);
+				}
+			}
+		}
+		(Some("reload"), None, _) => match Config::from_args(args.iter().cloned()) {
+			Ok(config) => {
+				lobby.reload(config);
+				info!("Reloaded configuration");
+				out.push_str("reloaded\n");
+			}
+			Err(e) => {
+				warn!("Failed to reload configuration: {}", e);
+				let _ = writeln!(out, "failed to reload: {}", e);
+			}
+		},
+		(Some("metrics"), None, _) => out = metrics(lobby, now),
+		_ => {
+			let _ = writeln!(out, "invalid command '{}', try 'help'", cmd);
+		}
+	}
+	out
+}
+
+/// The current state and counters in the Prometheus text format.
+fn metrics(lobby: &Lobby, now: Instant) -> String {
+	let mut out = String::new();
+	let mut gauge = |name: &str, help: &str, value: u64| {
+		let _ = writeln!(out, "# HELP own_war_lobby_{} {}", name, help);
+		let _ = writeln!(out, "# TYPE own_war_lobby_{} gauge", name);
+		let _ = writeln!(out, "own_war_lobby_{} {}", name, value);
+	};
+	let uptime = now.saturating_duration_since(lobby.started).as_secs();
+	let players = lobby
+		.list
+		.values()
+		.map(|i| u64::from(i.status.players))
+		.sum();
+	gauge("uptime_seconds", "Seconds since the lobby started.", uptime);
+	gauge("servers", "Registered servers.", lobby.list.len() as u64);
+	gauge("players", "Players reported by all servers.", players);
+	gauge(
+		"peers",
+		"Lobbies registrations are replicated with.",
+		lobby.federation.peers.len() as u64,
+	);
+	gauge("banned_hosts", "Banned hosts.", lobby.banned.len() as u64);
+	gauge(
+		"queued_clients",
+		"Clients waiting for a match.",
+		lobby.matchmaker.queued() as u64,
+	);
+	if let Some(repo) = &lobby.repository {
+		gauge("vehicles", "Shared vehicles.", repo.len() as u64);
+		gauge(
+			"vehicle_uploads",
+			"Vehicle uploads in progress.",
+			repo.uploads() as u64,
+		);
+	}
+	lobby.stats.metrics(&mut out);
+	out
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::test::server;
+
+	#[test]
+	fn ban_and_remove() {
+		let mut lobby = Lobby::new(Config::default(), None);
+		let t = lobby.started;
+		let a = SocketAddr::from(([1, 2, 3, 4], 5));
+		let b = SocketAddr::from(([1, 2, 3, 4], 6));
+		let c = SocketAddr::from(([5, 6, 7, 8], 5));
+		for addr in [a, b, c] {
+			lobby.list.insert(addr, server(t));
+		}
+
+		assert_eq!(
+			execute(&mut lobby, "remove 5.6.7.8:5", &[], t),
+			"removed 5.6.7.8:5\n"
+		);
+		assert!(!lobby.list.contains_key(&c));
+		assert_eq!(
+			execute(&mut lobby, "remove 5.6.7.8:5", &[], t),
+			"no server 5.6.7.8:5\n"
+		);
+
+		let rsp = execute(&mut lobby, "ban 1.2.3.4 10", &[], t);
+		assert_eq!(rsp, "banned 1.2.3.4, removed 2 servers\n");
+		assert!(lobby.list.is_empty());
+		assert!(lobby.is_banned(a.ip(), t));
+		assert!(!lobby.is_banned(a.ip(), t + Duration::from_secs(10)));
+		assert_eq!(
+			execute(&mut lobby, "unban 1.2.3.4", &[], t),
+			"unbanned 1.2.3.4\n"
+		);
+		assert!(!lobby.is_banned(a.ip(), t));
+
+		assert!(execute(&mut lobby, "frobnicate", &[], t).starts_with("invalid command"));
+		assert!(execute(&mut lobby, "metrics", &[], t).contains("own_war_lobby_servers 0\n"));
+	}
+
+	#[test]
+	fn only_commands_and_get() {
+		let lobby = Mutex::new(Lobby::new(Config::default(), None));
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let talk = |request: &str| {
+			let mut client = TcpStream::connect(addr).unwrap();
+			client.write_all(request.as_bytes()).unwrap();
+			let (stream, _) = listener.accept().unwrap();
+			handle(&lobby, stream, &[]).unwrap();
+			let mut rsp = String::new();
+			io::Read::read_to_string(&mut client, &mut rsp).unwrap();
+			rsp
+		};
+
+		assert_eq!(talk("list\nquit\n"), "0 servers\n\n");
+		assert_eq!(talk("POST / HTTP/1.1\nHost: x\n\nban 1.2.3.4\n"), "");
+		assert!(lobby.lock().unwrap().banned.is_empty());
+		let rsp = talk("GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n");
+		assert!(rsp.starts_with("HTTP/1.0 200 OK\r\n"));
+		assert!(rsp.contains("own_war_lobby_servers 0\n"));
+	}
+}
@@ -0,0 +1,210 @@
+//! Ownership proofs for server registrations.
+//!
+//! Every registration is handed a random token by the lobby. Any later message that modifies
+//! the registration must present that token. Trusted operators can additionally sign their
+//! messages with a secret shared with the lobby, see [`Secret`]. Signed messages carry a
+//! timestamp, which must be recent and newer than that of the last message signed for the
+//! same purpose, so captured messages can't be replayed.
+//!
+//! Hosts prove they can receive packets at their address by echoing a cookie, see
+//! [`Cookies`].
+
+use own_war_lobby_protocol::{encode_addr, Secret};
+use std::collections::hash_map::{Entry, RandomState};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hash};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How far the timestamp of a signed message may be off from the clock of the lobby.
+pub const SIGNATURE_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long a cookie is handed out for. Cookies of the previous period are still accepted.
+const COOKIE_PERIOD: Duration = Duration::from_secs(60);
+
+/// How long an address stays verified after it echoed a cookie or presented a token.
+pub const VERIFIED_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Generates tokens that can't be guessed by other hosts.
+pub struct Tokens {
+	key: Secret,
+	counter: u64,
+}
+
+impl Tokens {
+	pub fn new() -> Self {
+		// RandomState is seeded by the OS, which makes for a good enough secret key.
+		let s = RandomState::new();
+		Self {
+			key: Secret::from_key((s.hash_one(0u8), s.hash_one(1u8))),
+			counter: 0,
+		}
+	}
+
+	/// Create a new token. Tokens are never 0, which is used to indicate a new registration.
+	pub fn generate(&mut self) -> u64 {
+		loop {
+			self.counter += 1;
+			let token = u64::from_le_bytes(self.key.sign(&self.counter.to_le_bytes()));
+			if token != 0 {
+				break token;
+			}
+		}
+	}
+}
+
+/// Cookies derived from the address of a host, which only a host that receives packets at that
+/// address can echo. They don't need to be stored, only the addresses that echoed one are.
+pub struct Cookies {
+	key: Secret,
+	started: Instant,
+	/// Addresses that proved they can receive packets and when they last did.
+	verified: HashMap<SocketAddr, Instant>,
+}
+
+impl Cookies {
+	pub fn new(now: Instant) -> Self {
+		let s = RandomState::new();
+		Self {
+			key: Secret::from_key((s.hash_one(0u8), s.hash_one(1u8))),
+			started: now,
+			verified: HashMap::new(),
+		}
+	}
+
+	/// The cookie for the given address.
+	pub fn cookie(&self, addr: SocketAddr, now: Instant) -> u64 {
+		self.cookie_of_period(addr, self.period(now))
+	}
+
+	/// Check a cookie echoed by the given address and verify the address if it is valid.
+	pub fn check(&mut self, addr: SocketAddr, cookie: u64, now: Instant) -> bool {
+		let period = self.period(now);
+		let valid = cookie == self.cookie_of_period(addr, period)
+			|| (period > 0 && cookie == self.cookie_of_period(addr, period - 1));
+		if valid {
+			self.verify(addr, now);
+		}
+		valid
+	}
+
+	/// Verify an address that proved it can receive packets by other means, like presenting
+	/// a registration token.
+	pub fn verify(&mut self, addr: SocketAddr, now: Instant) {
+		self.verified.insert(addr, now);
+	}
+
+	pub fn is_verified(&self, addr: SocketAddr, now: Instant) -> bool {
+		self.verified
+			.get(&addr)
+			.is_some_and(|t| now < *t + VERIFIED_TIMEOUT)
+	}
+
+	/// Forget addresses whose verification expired.
+	pub fn prune(&mut self, now: Instant) {
+		self.verified.retain(|_, t| now < *t + VERIFIED_TIMEOUT);
+	}
+
+	fn period(&self, now: Instant) -> u64 {
+		(now - self.started).as_secs() / COOKIE_PERIOD.as_secs()
+	}
+
+	fn cookie_of_period(&self, addr: SocketAddr, period: u64) -> u64 {
+		let mut msg = period.to_le_bytes().to_vec();
+		encode_addr(addr, &mut msg);
+		u64::from_le_bytes(self.key.sign(&msg))
+	}
+}
+
+/// The current time in milliseconds since the Unix epoch, as used by signed messages.
+pub fn unix_millis() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+/// The timestamps of the last accepted signed messages for each purpose.
+pub struct Replays<K> {
+	last: HashMap<K, u64>,
+}
+
+impl<K: Eq + Hash> Replays<K> {
+	pub fn new() -> Self {
+		Self {
+			last: HashMap::new(),
+		}
+	}
+
+	/// Accept a signed message if its timestamp is within [`SIGNATURE_WINDOW`] of `now` and
+	/// newer than that of the last accepted message with the same key.
+	pub fn check(&mut self, key: K, timestamp: u64, now: u64) -> bool {
+		if timestamp.abs_diff(now) > window() {
+			return false;
+		}
+		match self.last.entry(key) {
+			Entry::Occupied(e) if *e.get() >= timestamp => false,
+			Entry::Occupied(mut e) => {
+				e.insert(timestamp);
+				true
+			}
+			Entry::Vacant(e) => {
+				e.insert(timestamp);
+				true
+			}
+		}
+	}
+
+	/// Forget timestamps that are too old to be accepted anyways.
+	pub fn prune(&mut self, now: u64) {
+		let oldest = now.saturating_sub(window());
+		self.last.retain(|_, t| *t >= oldest);
+	}
+}
+
+fn window() -> u64 {
+	SIGNATURE_WINDOW.as_millis() as u64
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn cookies_verify_addresses() {
+		let t = Instant::now();
+		let mut cookies = Cookies::new(t);
+		let a = SocketAddr::from(([1, 2, 3, 4], 5));
+		let b = SocketAddr::from(([1, 2, 3, 4], 6));
+		let cookie = cookies.cookie(a, t);
+		assert!(!cookies.is_verified(a, t));
+		assert!(!cookies.check(b, cookie, t));
+		assert!(!cookies.is_verified(b, t));
+		assert!(cookies.check(a, cookie, t + COOKIE_PERIOD));
+		assert!(cookies.is_verified(a, t + COOKIE_PERIOD));
+		assert!(!cookies.is_verified(a, t + COOKIE_PERIOD + VERIFIED_TIMEOUT));
+		// Only the cookies of the current and previous period are accepted
+		assert!(!cookies.check(a, cookie, t + COOKIE_PERIOD * 2));
+	}
+
+	#[test]
+	fn replays_are_rejected() {
+		let mut replays = Replays::new();
+		let now = 1_000_000;
+		assert!(replays.check(1, now, now));
+		assert!(!replays.check(1, now, now));
+		assert!(!replays.check(1, now - 1, now));
+		assert!(replays.check(2, now - 1, now));
+		assert!(replays.check(1, now + 1, now));
+
+		// Too old or too far in the future
+		assert!(!replays.check(3, now - window() - 1, now));
+		assert!(!replays.check(3, now + window() + 1, now));
+
+		// Pruned entries are too old to be accepted again
+		replays.prune(now + window() + 1);
+		assert!(replays.last.contains_key(&1));
+		assert!(!replays.last.contains_key(&2));
+		assert!(!replays.check(2, now - 1, now + window() + 1));
+	}
+}
@@ -0,0 +1,454 @@
+//! Configuration of the lobby daemon.
+//!
+//! Options can be given both on the command line and in a configuration file. Options on the
+//! command line take precedence. The configuration file consists of `key = value` lines,
+//! where the keys are the same as the long command line options without the leading `--`.
+//! Empty lines and lines starting with `#` are ignored.
+
+use crate::limit::Rate;
+use own_war_lobby_protocol::MessageType;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+Usage: own-war-lobby [OPTIONS]
+
+Options:
+  --config <FILE>               Read options from the given file
+  --bind <ADDR>                 Address to listen on, may be repeated. IPv6 addresses also
+                                accept IPv4 packets. [::] falls back to 0.0.0.0 if IPv6 is
+                                disabled [default: [::]:39984]
+  --expiry <SECONDS>            Remove servers that haven't pinged for this long [default: 60]
+  --legacy-list-limit <N>       Maximum entries in a GetServerList response [default: 239]
+  --page-size <BYTES>           Maximum size of a ListServers page [default: 8192]
+  --max-registrations <N>       Maximum servers a single host can register [default: 16]
+  --amplification-factor <N>    Maximum ratio of bytes sent to bytes received for unverified
+                                addresses [default: 3]
+  --host-rate <RATE>            Packets a single host may send [default: 20/50]
+  --punch-rate <RATE>           Punch requests forwarded to a single server [default: 5/10]
+  --rate.<MESSAGE> <RATE>       Messages of a type a single host may send, e.g.
+                                --rate.list-servers 5/20
+  --secret-file <FILE>          File with the secret shared with trusted operators
+  --snapshot-file <FILE>        Save the server list to this file and restore it on startup
+  --snapshot-interval <SECONDS> How often the snapshot is saved [default: 60]
+  --grace-period <SECONDS>      How long restored servers are kept without pinging
+                                [default: 120]
+  --peer <ADDR>                 Another lobby to replicate registrations with, may be
+                                repeated. Requires a secret file shared by all lobbies
+  --sync-interval <SECONDS>     How often registrations are sent to peers [default: 5]
+  --filter-file <FILE>          File with hosts and names that may not register servers.
+                                Reloaded when it changes
+  --match-min-players <N>       Queued clients needed to start a match on an empty server
+                                [default: 2]
+  --match-max-wait <SECONDS>    Send a queued client to an empty server alone after this
+                                long [default: 30]
+  --vehicle-dir <DIR>           Store vehicles shared by players in this directory. Sharing
+                                is disabled if not set
+  --max-vehicles <N>            Maximum amount of shared vehicles [default: 10000]
+  --stats-interval <SECONDS>    Log traffic statistics this often, 0 to disable [default: 300]
+  --admin <ADDR>                Loopback address to accept admin connections on
+  --log-level <LEVEL>           One of error, warn, info or debug [default: info]
+  --help                        Print this message
+
+RATE is given as <per second>/<burst>.
+";
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+	Error = 0,
+	Warn = 1,
+	Info = 2,
+	Debug = 3,
+}
+
+pub struct Config {
+	pub bind: Vec<SocketAddr>,
+	pub expiry: Duration,
+	pub legacy_list_limit: usize,
+	pub page_size: usize,
+	pub max_registrations: usize,
+	pub amplification_factor: usize,
+	pub host_rate: Rate,
+	pub punch_rate: Rate,
+	pub message_rates: HashMap<MessageType, Rate>,
+	pub secret_file: Option<PathBuf>,
+	pub peers: Vec<SocketAddr>,
+	pub sync_interval: Duration,
+	pub snapshot_file: Option<PathBuf>,
+	pub snapshot_interval: Duration,
+	pub grace_period: Duration,
+	pub filter_file: Option<PathBuf>,
+	pub match_min_players: usize,
+	pub match_max_wait: Duration,
+	pub vehicle_dir: Option<PathBuf>,
+	pub max_vehicles: usize,
+	pub stats_interval: Duration,
+	pub admin: Option<SocketAddr>,
+	pub log_level: LogLevel,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+	/// The configuration file could not be read.
+	Io(PathBuf, io::Error),
+	/// A line in the configuration file is not a `key = value` pair.
+	InvalidLine(PathBuf, usize),
+	UnknownOption(String),
+	MissingValue(String),
+	InvalidValue(String, String),
+	/// `--help` was given.
+	Help,
+}
+
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Io(p, e) => write!(f, "failed to read '{}': {}", p.display(), e),
+			Self::InvalidLine(p, l) => write!(f, "{}:{}: expected 'key = value'", p.display(), l),
+			Self::UnknownOption(k) => write!(f, "unknown option '{}'", k),
+			Self::MissingValue(k) => write!(f, "missing value for '{}'", k),
+			Self::InvalidValue(k, v) => write!(f, "invalid value '{}' for '{}'", v, k),
+			Self::Help => USAGE.fmt(f),
+		}
+	}
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			bind: vec![SocketAddr::from(([0u16; 8], 39984))],
+			expiry: Duration::from_secs(60),
+			// 239 entries is the safe upper bound right now
+			// 2 ^ 16 / (16 + 2 + 1 + 255) = ~239.18
+			legacy_list_limit: 239,
+			page_size: 8192,
+			max_registrations: 16,
+			amplification_factor: 3,
+			host_rate: Rate::new(20.0, 50.0),
+			punch_rate: Rate::new(5.0, 10.0),
+			message_rates: MessageType::SUPPORTED
+				.iter()
+				.map(|t| (*t, default_rate(*t)))
+				.collect(),
+			secret_file: None,
+			peers: Vec::new(),
+			sync_interval: Duration::from_secs(5),
+			snapshot_file: None,
+			snapshot_interval: Duration::from_secs(60),
+			grace_period: Duration::from_secs(120),
+			filter_file: None,
+			match_min_players: 2,
+			match_max_wait: Duration::from_secs(30),
+			vehicle_dir: None,
+			max_vehicles: 10000,
+			stats_interval: Duration::from_secs(300),
+			admin: None,
+			log_level: LogLevel::Info,
+		}
+	}
+}
+
+impl Config {
+	/// Parse the command line arguments, excluding the program name. If a configuration file
+	/// is given, it is read first.
+	pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, ConfigError> {
+		let mut options = Vec::new();
+		let mut config_file = None;
+		while let Some(arg) = args.next() {
+			let key = match arg.strip_prefix("--") {
+				Some("help") => return Err(ConfigError::Help),
+				Some(k) => k.to_string(),
+				None => return Err(ConfigError::UnknownOption(arg)),
+			};
+			let value = args.next().ok_or(ConfigError::MissingValue(arg))?;
+			if key == "config" {
+				config_file = Some(PathBuf::from(value));
+			} else {
+				options.push((key, value));
+			}
+		}
+
+		let mut config = Self::default();
+		if let Some(path) = config_file {
+			config.read_file(&path)?;
+		}
+		// Addresses given on the command line replace those in the file.
+		if options.iter().any(|(k, _)| k == "bind") {
+			config.bind.clear();
+		}
+		for (key, value) in options {
+			config.set(&key, &value)?;
+		}
+		Ok(config)
+	}
+
+	/// Read options from a configuration file.
+	pub fn read_file(&mut self, path: &Path) -> Result<(), ConfigError> {
+		let data = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.into(), e))?;
+		let mut bind_cleared = false;
+		for (i, line) in data.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let (key, value) = line
+				.split_once('=')
+				.ok_or_else(|| ConfigError::InvalidLine(path.into(), i + 1))?;
+			let (key, value) = (key.trim(), value.trim());
+			if key == "bind" && !bind_cleared {
+				self.bind.clear();
+				bind_cleared = true;
+			}
+			self.set(key, value)?;
+		}
+		Ok(())
+	}
+
+	/// Set a single option.
+	fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+		match key {
+			"bind" => self.bind.push(parse(key, value)?),
+			"expiry" => self.expiry = Duration::from_secs(parse(key, value)?),
+			"legacy-list-limit" => self.legacy_list_limit = parse(key, value)?,
+			"page-size" => self.page_size = parse(key, value)?,
+			"max-registrations" => self.max_registrations = parse(key, value)?,
+			"amplification-factor" => self.amplification_factor = parse(key, value)?,
+			"host-rate" => self.host_rate = parse_rate(key, value)?,
+			"punch-rate" => self.punch_rate = parse_rate(key, value)?,
+			"secret-file" => self.secret_file = Some(value.into()),
+			"peer" => self.peers.push(parse(key, value)?),
+			"sync-interval" => self.sync_interval = Duration::from_secs(parse(key, value)?),
+			"snapshot-file" => self.snapshot_file = Some(value.into()),
+			"snapshot-interval" => self.snapshot_interval = Duration::from_secs(parse(key, value)?),
+			"grace-period" => self.grace_period = Duration::from_secs(parse(key, value)?),
+			"filter-file" => self.filter_file = Some(value.into()),
+			"match-min-players" => self.match_min_players = parse(key, value)?,
+			"match-max-wait" => self.match_max_wait = Duration::from_secs(parse(key, value)?),
+			"vehicle-dir" => self.vehicle_dir = Some(value.into()),
+			"max-vehicles" => self.max_vehicles = parse(key, value)?,
+			"stats-interval" => self.stats_interval = Duration::from_secs(parse(key, value)?),
+			"admin" => {
+				let addr = parse::<SocketAddr>(key, value)?;
+				// The admin interface isn't authenticated.
+				if !addr.ip().is_loopback() {
+					return Err(invalid(key, value));
+				}
+				self.admin = Some(addr);
+			}
+			"log-level" => {
+				self.log_level = match value {
+					"error" => LogLevel::Error,
+					"warn" => LogLevel::Warn,
+					"info" => LogLevel::Info,
+					"debug" => LogLevel::Debug,
+					_ => return Err(invalid(key, value)),
+				}
+			}
+			_ => {
+				let typ = key
+					.strip_prefix("rate.")
+					.and_then(|name| MessageType::SUPPORTED.iter().find(|t| t.name() == name))
+					.ok_or_else(|| ConfigError::UnknownOption(key.into()))?;
+				self.message_rates.insert(*typ, parse_rate(key, value)?);
+			}
+		}
+		Ok(())
+	}
+
+	/// The rate at which a single host may send messages of the given type.
+	pub fn message_rate(&self, typ: MessageType) -> Rate {
+		self.message_rates
+			.get(&typ)
+			.copied()
+			.unwrap_or(Rate::new(0.0, 0.0))
+	}
+}
+
+/// The default rate at which a single host may send messages of the given type.
+fn default_rate(typ: MessageType) -> Rate {
+	match typ {
+		// Servers reregister every 100 seconds and ping every 10 seconds.
+		MessageType::RegisterServer | MessageType::RemoveServer => Rate::new(0.2, 5.0),
+		MessageType::Advertise => Rate::new(0.2, 5.0),
+		// Queued clients resend every few seconds.
+		MessageType::Enqueue | MessageType::Dequeue => Rate::new(1.0, 5.0),
+		MessageType::Ping => Rate::new(1.0, 10.0),
+		// The legacy list is expensive, the paged list is not.
+		MessageType::GetServerList => Rate::new(0.5, 3.0),
+		MessageType::ListServers => Rate::new(5.0, 20.0),
+		MessageType::ServerInfo | MessageType::Hello => Rate::new(5.0, 20.0),
+		MessageType::PunchHole | MessageType::PunchRequest => Rate::new(1.0, 5.0),
+		// Clients ask for their address before every punch and ack every resent punch.
+		MessageType::WhoAmI | MessageType::PunchAck => Rate::new(5.0, 20.0),
+		// Clients verify once in a while, but may have to retry.
+		MessageType::Verify => Rate::new(1.0, 5.0),
+		// Vehicles are transferred in many chunks.
+		MessageType::UploadVehicle | MessageType::DownloadVehicle => Rate::new(20.0, 50.0),
+		MessageType::SearchVehicles => Rate::new(2.0, 10.0),
+		// A sync of a large list is split in many packets.
+		MessageType::Sync => Rate::new(50.0, 500.0),
+		MessageType::Error | MessageType::Punch | MessageType::Matched => Rate::new(0.0, 0.0),
+	}
+}
+
+fn invalid(key: &str, value: &str) -> ConfigError {
+	ConfigError::InvalidValue(key.into(), value.into())
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+	value.parse().map_err(|_| invalid(key, value))
+}
+
+fn parse_rate(key: &str, value: &str) -> Result<Rate, ConfigError> {
+	let (rate, burst) = value.split_once('/').ok_or_else(|| invalid(key, value))?;
+	let rate = parse::<f32>(key, rate.trim())?;
+	let burst = parse::<f32>(key, burst.trim())?;
+	if rate < 0.0 || burst < 0.0 {
+		return Err(invalid(key, value));
+	}
+	Ok(Rate::new(rate, burst))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn from_args(args: &[&str]) -> Result<Config, ConfigError> {
+		Config::from_args(args.iter().map(|a| a.to_string()))
+	}
+
+	/// Write a configuration file that is removed when dropped.
+	struct File(PathBuf);
+
+	impl File {
+		fn new(name: &str, data: &str) -> Self {
+			let dir = std::env::temp_dir().join(format!("own-war-config-{}", std::process::id()));
+			fs::create_dir_all(&dir).unwrap();
+			let path = dir.join(name);
+			fs::write(&path, data).unwrap();
+			Self(path)
+		}
+
+		fn path(&self) -> &str {
+			self.0.to_str().unwrap()
+		}
+	}
+
+	impl Drop for File {
+		fn drop(&mut self) {
+			let _ = fs::remove_file(&self.0);
+		}
+	}
+
+	fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+		addrs.iter().map(|a| a.parse().unwrap()).collect()
+	}
+
+	#[test]
+	fn bind() {
+		let file = File::new("bind", "bind = [::1]:1\nbind = 127.0.0.1:2\n");
+		let config = from_args(&["--config", file.path()]).unwrap();
+		assert_eq!(config.bind, addrs(&["[::1]:1", "127.0.0.1:2"]));
+		let config = from_args(&[
+			"--bind",
+			"127.0.0.1:3",
+			"--config",
+			file.path(),
+			"--bind",
+			"127.0.0.1:4",
+		])
+		.unwrap();
+		assert_eq!(config.bind, addrs(&["127.0.0.1:3", "127.0.0.1:4"]));
+		assert_eq!(from_args(&[]).unwrap().bind, Config::default().bind);
+	}
+
+	#[test]
+	fn config_file_first() {
+		let file = File::new("first", "# comment\n\nexpiry = 10\npage-size = 100\n");
+		for args in [
+			["--config", file.path(), "--expiry", "5"],
+			["--expiry", "5", "--config", file.path()],
+		] {
+			let config = from_args(&args).unwrap();
+			assert_eq!(config.expiry, Duration::from_secs(5));
+			assert_eq!(config.page_size, 100);
+		}
+	}
+
+	#[test]
+	fn rates() {
+		let config = from_args(&["--rate.list-servers", "1/2", "--host-rate", "3 / 4"]).unwrap();
+		let rate = config.message_rate(MessageType::ListServers);
+		assert_eq!((rate.per_second, rate.burst), (1.0, 2.0));
+		let rate = config.message_rate(MessageType::Ping);
+		assert_eq!((rate.per_second, rate.burst), (1.0, 10.0));
+		assert_eq!(
+			(config.host_rate.per_second, config.host_rate.burst),
+			(3.0, 4.0)
+		);
+
+		assert!(matches!(
+			from_args(&["--rate.foo", "1/2"]),
+			Err(ConfigError::UnknownOption(k)) if k == "rate.foo"
+		));
+		for rate in ["1", "-1/2", "a/b"] {
+			assert!(matches!(
+				from_args(&["--rate.ping", rate]),
+				Err(ConfigError::InvalidValue(k, _)) if k == "rate.ping"
+			));
+		}
+	}
+
+	#[test]
+	fn admin() {
+		let config = from_args(&["--admin", "127.0.0.1:39985"]).unwrap();
+		assert_eq!(config.admin, Some("127.0.0.1:39985".parse().unwrap()));
+		for addr in ["192.0.2.1:39985", "[::]:39985", "0.0.0.0:39985"] {
+			assert!(matches!(
+				from_args(&["--admin", addr]),
+				Err(ConfigError::InvalidValue(k, _)) if k == "admin"
+			));
+		}
+	}
+
+	#[test]
+	fn errors() {
+		assert!(matches!(
+			from_args(&["--expiry"]),
+			Err(ConfigError::MissingValue(k)) if k == "--expiry"
+		));
+		assert!(matches!(
+			from_args(&["expiry", "5"]),
+			Err(ConfigError::UnknownOption(k)) if k == "expiry"
+		));
+		assert!(matches!(
+			from_args(&["--expiry", "5", "--help"]),
+			Err(ConfigError::Help)
+		));
+		assert!(matches!(
+			from_args(&["--log-level", "trace"]),
+			Err(ConfigError::InvalidValue(..))
+		));
+
+		let file = File::new("invalid", "expiry = 1\nnope\n");
+		assert!(matches!(
+			from_args(&["--config", file.path()]),
+			Err(ConfigError::InvalidLine(p, 2)) if p == file.0
+		));
+		let file = File::new("unknown", "nope = 1\n");
+		assert!(matches!(
+			from_args(&["--config", file.path()]),
+			Err(ConfigError::UnknownOption(k)) if k == "nope"
+		));
+		assert!(matches!(
+			from_args(&["--config", "/nonexistent/own-war-lobby.conf"]),
+			Err(ConfigError::Io(..))
+		));
+	}
+}
@@ -0,0 +1,349 @@
+//! Replication of registrations between lobbies.
+//!
+//! Every lobby periodically sends the registrations it received itself to all its peers with
+//! a signed `Sync` message. Replicated entries are never forwarded again, so every lobby must
+//! be a peer of every other lobby. `Sync` messages from addresses that aren't configured as
+//! peers are rejected, so both lobbies must list each other.
+//!
+//! Conflicts are resolved by the time a server was last seen: the most recent registration,
+//! ping or removal wins. Ages are sent instead of timestamps so clocks don't need to be in
+//! sync.
+//!
+//! Only the registration itself is replicated. Whether an entry is trusted or verified is
+//! decided by every lobby on its own and replicated entries are checked against the local
+//! filter.
+
+use crate::filter::Filter;
+use crate::ServerInfo;
+use own_war_lobby_protocol::{Header, Request, Secret, Sync, SyncEntry, SyncInfo, SIGNATURE_SIZE};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::hash::BuildHasher;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// The maximum size of a `Sync` packet, which avoids IP fragmentation.
+const MAX_PACKET_SIZE: usize = 1200;
+
+pub struct Peer {
+	/// When a `Sync` was last received from the peer.
+	pub last_seen: Option<Instant>,
+}
+
+pub struct Federation {
+	/// Identifies this lobby so it can ignore its own messages.
+	node: u64,
+	pub peers: BTreeMap<SocketAddr, Peer>,
+	/// Servers removed since the last sync and when.
+	removed: Vec<(SocketAddr, Instant)>,
+	/// When the last `Sync` was sent.
+	pub last_sync: Instant,
+	/// The timestamp the last `Sync` packet was signed with. Every packet gets a newer one so
+	/// peers don't reject it as a replay.
+	last_timestamp: u64,
+}
+
+impl Federation {
+	pub fn new(peers: &[SocketAddr], now: Instant) -> Self {
+		let peer = |addr| (addr, Peer { last_seen: None });
+		Self {
+			node: RandomState::new().hash_one(0u8),
+			peers: peers.iter().copied().map(peer).collect(),
+			removed: Vec::new(),
+			last_sync: now,
+			last_timestamp: 0,
+		}
+	}
+
+	pub fn is_peer(&self, addr: SocketAddr) -> bool {
+		self.peers.contains_key(&addr)
+	}
+
+	/// Remember a server was removed so the removal can be replicated.
+	pub fn removed(&mut self, addr: SocketAddr, now: Instant) {
+		if !self.peers.is_empty() {
+			self.removed.push((addr, now));
+		}
+	}
+
+	/// Create the `Sync` packets with the registrations updated since the last sync.
+	pub fn packets(
+		&mut self,
+		list: &BTreeMap<SocketAddr, ServerInfo>,
+		secret: &Secret,
+		now: Instant,
+	) -> Vec<Vec<u8>> {
+		let age = |t: Instant| u32::try_from((now - t).as_millis()).unwrap_or(u32::MAX);
+		let last_sync = self.last_sync;
+		let updated = list
+			.iter()
+			.filter(|(_, info)| !info.replicated && info.last_ping >= last_sync)
+			.map(|(addr, info)| SyncEntry {
+				addr: *addr,
+				age: age(info.last_ping),
+				info: Some(SyncInfo {
+					name: &info.name,
+					map: &info.map,
+					max_players: info.max_players,
+					description: &info.description,
+					manager_port: info.manager_port,
+					version: info.version,
+					status: info.current_status(),
+					token: info.token,
+				}),
+			});
+		let removed = self.removed.iter().map(|(addr, t)| SyncEntry {
+			addr: *addr,
+			age: age(*t),
+			info: None,
+		});
+
+		// Header, node, count and signature
+		const OVERHEAD: usize = Header::SIZE + 8 + 1 + SIGNATURE_SIZE;
+		let mut packets = Vec::new();
+		let mut entries = Vec::new();
+		let mut size = OVERHEAD;
+		for e in updated.chain(removed) {
+			let len = e.encoded_len();
+			if !entries.is_empty()
+				&& (size + len > MAX_PACKET_SIZE || entries.len() >= usize::from(u8::MAX))
+			{
+				packets.push(Self::packet(
+					self.node,
+					&mut self.last_timestamp,
+					entries,
+					secret,
+				));
+				entries = Vec::new();
+				size = OVERHEAD;
+			}
+			size += len;
+			entries.push(e);
+		}
+		// Always send at least one packet so peers know we're still up.
+		if !entries.is_empty() || packets.is_empty() {
+			packets.push(Self::packet(
+				self.node,
+				&mut self.last_timestamp,
+				entries,
+				secret,
+			));
+		}
+		self.removed.clear();
+		self.last_sync = now;
+		packets
+	}
+
+	fn packet(
+		node: u64,
+		last_timestamp: &mut u64,
+		entries: Vec<SyncEntry>,
+		secret: &Secret,
+	) -> Vec<u8> {
+		let sync = Request::Sync(Sync { node, entries });
+		let mut pkt = sync.to_vec().expect("entries are checked on registration");
+		*last_timestamp = crate::auth::unix_millis().max(*last_timestamp + 1);
+		secret.sign_packet(&mut pkt, *last_timestamp);
+		pkt
+	}
+
+	/// Merge the entries of a `Sync` received from the given peer into the list. Entries the
+	/// filter rejects are skipped. Returns `false` if the message was sent by this lobby or
+	/// `from` isn't a peer.
+	pub fn merge(
+		&mut self,
+		from: SocketAddr,
+		sync: &Sync,
+		list: &mut BTreeMap<SocketAddr, ServerInfo>,
+		filter: &Filter,
+		now: Instant,
+	) -> bool {
+		if sync.node == self.node {
+			return false;
+		}
+		let peer = match self.peers.get_mut(&from) {
+			Some(peer) => peer,
+			None => return false,
+		};
+		if peer.last_seen.is_none() {
+			info!("Peer {} is up", from);
+		}
+		peer.last_seen = Some(now);
+
+		for e in sync.entries.iter() {
+			let seen = now
+				.checked_sub(Duration::from_millis(e.age.into()))
+				.unwrap_or(now);
+			if list.get(&e.addr).is_some_and(|info| info.last_ping >= seen) {
+				continue;
+			}
+			match &e.info {
+				None => {
+					if list.remove(&e.addr).is_some() {
+						info!("Removed {} - removed by peer {}", e.addr, from);
+					}
+				}
+				Some(i) => {
+					if let Err(code) = filter.check(e.addr.ip(), i.name, i.description) {
+						debug!("Not replicating {} from peer {} - {:?}", e.addr, from, code);
+						continue;
+					}
+					// Only keep what this lobby decided itself about the server, and only
+					// while it is the same registration.
+					let (trusted, verified) = list
+						.get(&e.addr)
+						.filter(|local| local.token == i.token)
+						.map_or((false, false), |local| (local.trusted, local.verified));
+					let info = ServerInfo {
+						name: i.name.into(),
+						map: i.map.into(),
+						max_players: i.max_players,
+						description: i.description.into(),
+						last_ping: seen,
+						manager_port: i.manager_port,
+						version: i.version,
+						status: i.status,
+						status_reported: now,
+						token: i.token,
+						trusted,
+						verified,
+						restored: false,
+						replicated: true,
+					};
+					if list.insert(e.addr, info).is_none() {
+						debug!("Replicated {} from peer {}", e.addr, from);
+					}
+				}
+			}
+		}
+		true
+	}
+
+	/// Mark peers that haven't sent anything in a while as down.
+	pub fn prune(&mut self, now: Instant, timeout: Duration) {
+		for (addr, peer) in self.peers.iter_mut() {
+			if peer.last_seen.is_some_and(|t| now - t >= timeout) {
+				info!("Peer {} is down", addr);
+				peer.last_seen = None;
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::test::server;
+	use own_war_lobby_protocol::MessageType;
+
+	fn decode(pkt: &[u8]) -> Sync<'_> {
+		let (_, payload) = Header::decode(pkt).unwrap();
+		match Request::decode(MessageType::Sync, payload).unwrap() {
+			(Request::Sync(sync), sig) if sig.len() == SIGNATURE_SIZE => sync,
+			r => panic!("unexpected {:?}", r),
+		}
+	}
+
+	#[test]
+	fn last_seen_wins() {
+		let secret = Secret::new("secret");
+		let t = Instant::now();
+		let s = Duration::from_secs;
+		let from = SocketAddr::from(([127, 0, 0, 1], 39984));
+		let (mut a, mut b) = (Federation::new(&[from], t), Federation::new(&[from], t));
+		let filter = Filter::default();
+		let addr = SocketAddr::from(([1, 2, 3, 4], 5));
+
+		let mut list_a = BTreeMap::new();
+		list_a.insert(addr, server(t + s(1)));
+		let packets = a.packets(&list_a, &secret, t + s(2));
+		assert_eq!(packets.len(), 1);
+		assert!(secret.verify_packet(&packets[0]).is_some());
+		let sync = decode(&packets[0]);
+		assert!(!a.merge(from, &sync, &mut list_a, &filter, t + s(2)));
+
+		// Newer than the local entry
+		let mut list_b = BTreeMap::new();
+		list_b.insert(addr, server(t));
+		assert!(b.merge(from, &sync, &mut list_b, &filter, t + s(2)));
+		assert_eq!(list_b[&addr].last_ping, t + s(1));
+		assert!(list_b[&addr].replicated);
+
+		// Older than the local entry
+		list_b.insert(addr, server(t + s(2)));
+		b.merge(from, &sync, &mut list_b, &filter, t + s(2));
+		assert_eq!(list_b[&addr].last_ping, t + s(2));
+		assert!(!list_b[&addr].replicated);
+
+		// Replicated entries aren't sent again
+		list_b.get_mut(&addr).unwrap().replicated = true;
+		let packets = b.packets(&list_b, &secret, t + s(3));
+		assert_eq!(packets.len(), 1);
+		assert!(decode(&packets[0]).entries.is_empty());
+
+		// Removals are newer than the last ping
+		a.removed(addr, t + s(3));
+		let packets = a.packets(&BTreeMap::new(), &secret, t + s(3));
+		b.merge(from, &decode(&packets[0]), &mut list_b, &filter, t + s(3));
+		assert!(list_b.is_empty());
+	}
+
+	#[test]
+	fn replicated_entries_are_checked() {
+		let secret = Secret::new("secret");
+		let t = Instant::now();
+		let from = SocketAddr::from(([127, 0, 0, 1], 39984));
+		let (mut a, mut b) = (Federation::new(&[from], t), Federation::new(&[from], t));
+		let (addr, denied) = (
+			SocketAddr::from(([1, 2, 3, 4], 5)),
+			SocketAddr::from(([192, 0, 2, 1], 5)),
+		);
+		let trusted = || {
+			let mut info = server(t);
+			info.trusted = true;
+			info.verified = true;
+			info
+		};
+		let mut list_a: BTreeMap<_, _> = [(addr, trusted()), (denied, trusted())].into();
+		let packets = a.packets(&list_a, &secret, t);
+		let sync = decode(&packets[0]);
+		let filter = Filter::parse("deny 192.0.2.0/24").unwrap();
+
+		// Only configured peers are accepted
+		let mut list_b = BTreeMap::new();
+		let stranger = SocketAddr::from(([127, 0, 0, 2], 39984));
+		assert!(!b.merge(stranger, &sync, &mut list_b, &filter, t));
+		assert!(list_b.is_empty() && !b.is_peer(stranger));
+
+		// The filter applies and trust isn't copied
+		assert!(b.merge(from, &sync, &mut list_b, &filter, t));
+		assert!(!list_b.contains_key(&denied));
+		assert!(!list_b[&addr].trusted && !list_b[&addr].verified);
+
+		// Unless this lobby decided so itself
+		list_b.get_mut(&addr).unwrap().trusted = true;
+		list_a.get_mut(&addr).unwrap().last_ping = t + Duration::from_secs(1);
+		let packets = a.packets(&list_a, &secret, t + Duration::from_secs(1));
+		b.merge(
+			from,
+			&decode(&packets[0]),
+			&mut list_b,
+			&filter,
+			t + Duration::from_secs(1),
+		);
+		assert_eq!(list_b[&addr].last_ping, t + Duration::from_secs(1));
+		assert!(list_b[&addr].trusted);
+	}
+
+	#[test]
+	fn timestamps_increase() {
+		let secret = Secret::new("secret");
+		let t = Instant::now();
+		let mut fed = Federation::new(&[], t);
+		let list = BTreeMap::new();
+		let a = secret.verify_packet(&fed.packets(&list, &secret, t)[0]);
+		let b = secret.verify_packet(&fed.packets(&list, &secret, t)[0]);
+		assert!(a.unwrap() < b.unwrap());
+	}
+}
@@ -0,0 +1,188 @@
+//! Lists of hosts and names that may not register servers.
+//!
+//! A filter file consists of `<kind> <value>` lines. Empty lines and lines starting with `#`
+//! are ignored. The kinds are:
+//!
+//! * `deny <IP or CIDR>` rejects hosts in the given range.
+//! * `allow <IP or CIDR>` only accepts hosts in one of the given ranges. If there are no
+//!   `allow` lines, all hosts that aren't denied are accepted.
+//! * `deny-name <TEXT>` rejects servers whose name contains the text, ignoring case.
+//! * `deny-description <TEXT>` does the same for the description.
+
+use own_war_lobby_protocol::ErrorCode;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// A range of addresses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Cidr {
+	addr: IpAddr,
+	prefix: u8,
+}
+
+#[derive(Default)]
+pub struct Filter {
+	deny: Vec<Cidr>,
+	allow: Vec<Cidr>,
+	/// Lowercase.
+	names: Vec<Box<str>>,
+	/// Lowercase.
+	descriptions: Vec<Box<str>>,
+}
+
+#[derive(Debug)]
+pub enum FilterError {
+	Io(PathBuf, io::Error),
+	InvalidLine(PathBuf, usize),
+}
+
+impl fmt::Display for FilterError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Io(p, e) => write!(f, "failed to read '{}': {}", p.display(), e),
+			Self::InvalidLine(p, l) => write!(f, "{}:{}: invalid filter", p.display(), l),
+		}
+	}
+}
+
+impl Cidr {
+	fn contains(&self, ip: IpAddr) -> bool {
+		let bits = |ip| match ip {
+			IpAddr::V4(ip) => (u128::from(u32::from(ip)) << 96, 32),
+			IpAddr::V6(ip) => (u128::from(ip), 128),
+		};
+		let ((net, len), (ip, ip_len)) = (bits(self.addr), bits(ip));
+		if len != ip_len {
+			return false;
+		}
+		let mask = u128::MAX
+			.checked_shl(128 - u32::from(self.prefix))
+			.unwrap_or(0);
+		net & mask == ip & mask
+	}
+}
+
+impl std::str::FromStr for Cidr {
+	type Err = ();
+
+	fn from_str(s: &str) -> Result<Self, ()> {
+		let (addr, prefix) = match s.split_once('/') {
+			Some((a, p)) => (a, Some(p)),
+			None => (s, None),
+		};
+		let addr = addr.parse::<IpAddr>().map_err(|_| ())?;
+		let max = if addr.is_ipv4() { 32 } else { 128 };
+		let prefix = match prefix {
+			Some(p) => p.parse::<u8>().map_err(|_| ())?,
+			None => max,
+		};
+		if prefix > max {
+			return Err(());
+		}
+		Ok(Self { addr, prefix })
+	}
+}
+
+impl Filter {
+	pub fn load(path: &Path) -> Result<Self, FilterError> {
+		let data = fs::read_to_string(path).map_err(|e| FilterError::Io(path.into(), e))?;
+		Self::parse(&data).map_err(|line| FilterError::InvalidLine(path.into(), line))
+	}
+
+	/// Parse the contents of a filter file. Returns the number of the first invalid line on
+	/// failure.
+	pub(crate) fn parse(data: &str) -> Result<Self, usize> {
+		let mut filter = Self::default();
+		for (i, line) in data.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let (kind, value) = line.split_once(char::is_whitespace).ok_or(i + 1)?;
+			let value = value.trim();
+			match kind {
+				"deny" => filter.deny.push(value.parse().map_err(|()| i + 1)?),
+				"allow" => filter.allow.push(value.parse().map_err(|()| i + 1)?),
+				"deny-name" => filter.names.push(value.to_lowercase().into()),
+				"deny-description" => filter.descriptions.push(value.to_lowercase().into()),
+				_ => return Err(i + 1),
+			}
+		}
+		Ok(filter)
+	}
+
+	/// Whether the host may register servers.
+	pub fn allows(&self, ip: IpAddr) -> bool {
+		!self.deny.iter().any(|c| c.contains(ip))
+			&& (self.allow.is_empty() || self.allow.iter().any(|c| c.contains(ip)))
+	}
+
+	/// Check whether a server may be registered.
+	pub fn check(&self, ip: IpAddr, name: &str, description: &str) -> Result<(), ErrorCode> {
+		if !self.allows(ip) {
+			return Err(ErrorCode::Denied);
+		}
+		let contains = |patterns: &[Box<str>], text: &str| {
+			let text = text.to_lowercase();
+			patterns.iter().any(|p| text.contains(&**p))
+		};
+		if contains(&self.names, name) || contains(&self.descriptions, description) {
+			return Err(ErrorCode::Rejected);
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn check() {
+		let filter = Filter::parse(
+			"# comment\n\
+			deny 192.0.2.0/24\n\
+			deny 2001:db8::1\n\
+			allow 192.0.0.0/8\n\
+			allow ::/0\n\
+			deny-name Casino\n\
+			deny-description http://",
+		)
+		.unwrap();
+		let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+		assert_eq!(filter.check(ip("192.1.2.3"), "a", "b"), Ok(()));
+		assert_eq!(
+			filter.check(ip("192.0.2.3"), "a", "b"),
+			Err(ErrorCode::Denied)
+		);
+		assert_eq!(
+			filter.check(ip("10.0.0.1"), "a", "b"),
+			Err(ErrorCode::Denied)
+		);
+		assert_eq!(filter.check(ip("2001:db8::2"), "a", "b"), Ok(()));
+		assert_eq!(
+			filter.check(ip("2001:db8::1"), "a", "b"),
+			Err(ErrorCode::Denied)
+		);
+		assert_eq!(
+			filter.check(ip("192.1.2.3"), "Best CASINO", "b"),
+			Err(ErrorCode::Rejected)
+		);
+		assert_eq!(
+			filter.check(ip("192.1.2.3"), "a", "visit http://spam"),
+			Err(ErrorCode::Rejected)
+		);
+
+		assert!(Filter::default().allows(ip("10.0.0.1")));
+		assert_eq!(Filter::parse("deny 1.2.3.4/33").err(), Some(1));
+		assert_eq!(Filter::parse("\nallow nope").err(), Some(2));
+		assert_eq!(Filter::parse("block 1.2.3.4").err(), Some(1));
+		assert!("0.0.0.0/0"
+			.parse::<Cidr>()
+			.unwrap()
+			.contains(ip("255.1.2.3")));
+	}
+}
@@ -0,0 +1,158 @@
+//! Token bucket rate limiting and byte budgets.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// The rate at which a bucket refills and the maximum amount of tokens it can hold.
+#[derive(Clone, Copy)]
+pub struct Rate {
+	pub per_second: f32,
+	pub burst: f32,
+}
+
+impl Rate {
+	pub const fn new(per_second: f32, burst: f32) -> Self {
+		Self { per_second, burst }
+	}
+}
+
+struct Bucket {
+	tokens: f32,
+	last: Instant,
+	violations: u64,
+}
+
+/// A set of token buckets, one for each key.
+pub struct Limiter<K> {
+	buckets: HashMap<K, Bucket>,
+}
+
+impl<K: Hash + Eq> Limiter<K> {
+	pub fn new() -> Self {
+		Self {
+			buckets: HashMap::new(),
+		}
+	}
+
+	/// Take a token from the bucket of the given key. If the bucket is empty, the total
+	/// amount of violations for this key is returned.
+	pub fn check(&mut self, key: K, rate: Rate, now: Instant) -> Result<(), u64> {
+		let bucket = self.buckets.entry(key).or_insert(Bucket {
+			tokens: rate.burst,
+			last: now,
+			violations: 0,
+		});
+		let dt = (now - bucket.last).as_secs_f32();
+		bucket.tokens = (bucket.tokens + dt * rate.per_second).min(rate.burst);
+		bucket.last = now;
+		if bucket.tokens >= 1.0 {
+			bucket.tokens -= 1.0;
+			Ok(())
+		} else {
+			bucket.violations += 1;
+			Err(bucket.violations)
+		}
+	}
+
+	/// Remove buckets that haven't been used for a while. Those are full anyways.
+	pub fn prune(&mut self, now: Instant, idle: Duration) {
+		self.buckets.retain(|_, b| now - b.last < idle);
+	}
+}
+
+struct Budget {
+	bytes: usize,
+	last: Instant,
+}
+
+/// The amount of bytes that may still be sent to each unverified address. Every packet received
+/// from an address adds a multiple of its size, so spoofing the address of another host can't
+/// be used to make the lobby send it much more data than the spoofer sent. The IP and UDP
+/// headers are included in all sizes.
+pub struct Budgets {
+	budgets: HashMap<SocketAddr, Budget>,
+}
+
+impl Budgets {
+	pub fn new() -> Self {
+		Self {
+			budgets: HashMap::new(),
+		}
+	}
+
+	/// Add the budget for a packet of the given size received from the address.
+	pub fn received(&mut self, addr: SocketAddr, size: usize, factor: usize, now: Instant) {
+		let budget = self.budgets.entry(addr).or_insert(Budget {
+			bytes: 0,
+			last: now,
+		});
+		budget.bytes = budget.bytes.saturating_add(size.saturating_mul(factor));
+		budget.last = now;
+	}
+
+	/// The size of the largest packet that may be sent to the address.
+	pub fn available(&self, addr: SocketAddr) -> usize {
+		self.budgets.get(&addr).map_or(0, |b| b.bytes)
+	}
+
+	/// Take the size of a packet from the budget of the address. Returns `false` and takes
+	/// nothing if the budget is too small.
+	pub fn spend(&mut self, addr: SocketAddr, size: usize) -> bool {
+		match self.budgets.get_mut(&addr) {
+			Some(b) if b.bytes >= size => {
+				b.bytes -= size;
+				true
+			}
+			_ => false,
+		}
+	}
+
+	/// Remove the budgets of addresses that haven't sent anything for a while.
+	pub fn prune(&mut self, now: Instant, idle: Duration) {
+		self.budgets.retain(|_, b| now - b.last < idle);
+	}
+}
+
+/// Whether a violation should be logged. This avoids flooding the log when a host keeps
+/// sending packets.
+pub fn should_log(violations: u64) -> bool {
+	violations == 1 || violations.is_multiple_of(100)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn refill() {
+		let mut l = Limiter::new();
+		let rate = Rate::new(1.0, 2.0);
+		let t = Instant::now();
+		assert_eq!(l.check(0, rate, t), Ok(()));
+		assert_eq!(l.check(0, rate, t), Ok(()));
+		assert_eq!(l.check(0, rate, t), Err(1));
+		assert_eq!(l.check(1, rate, t), Ok(()));
+		let t = t + Duration::from_secs(1);
+		assert_eq!(l.check(0, rate, t), Ok(()));
+		assert_eq!(l.check(0, rate, t), Err(2));
+	}
+
+	#[test]
+	fn budget() {
+		let mut b = Budgets::new();
+		let addr = SocketAddr::from(([1, 2, 3, 4], 5));
+		let t = Instant::now();
+		assert!(!b.spend(addr, 1));
+		b.received(addr, 10, 3, t);
+		assert_eq!(b.available(addr), 30);
+		assert!(!b.spend(addr, 31));
+		assert!(b.spend(addr, 20));
+		assert!(!b.spend(addr, 11));
+		b.received(addr, 10, 3, t);
+		assert!(b.spend(addr, 40));
+		b.prune(t + Duration::from_secs(1), Duration::from_secs(1));
+		assert_eq!(b.available(addr), 0);
+	}
+}
@@ -1,25 +1,82 @@
-use std::collections::HashMap;
-use std::convert::TryFrom;
-use std::mem::size_of;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
-use std::time::{Duration, Instant};
+/// The current log level, see [`LogLevel`].
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
 
-macro_rules! debug {
-	($($x:tt)*) => {
-		{
-			#[cfg(debug_assertions)]
-			{
-				print!("[DEBUG] ");
-				println!($($x)*);
-			}
-			#[cfg(not(debug_assertions))]
-			{
-				($($x)*);
-			}
+macro_rules! log {
+	($level:ident, $prefix:literal, $($x:tt)*) => {
+		if $crate::LogLevel::$level as u8 <= $crate::LOG_LEVEL.load(std::sync::atomic::Ordering::Relaxed) {
+			print!($prefix);
+			println!($($x)*);
 		}
 	}
 }
 
+macro_rules! error {
+	($($x:tt)*) => { log!(Error, "[ERROR] ", $($x)*) }
+}
+
+macro_rules! warn {
+	($($x:tt)*) => { log!(Warn, "[WARN] ", $($x)*) }
+}
+
+macro_rules! info {
+	($($x:tt)*) => { log!(Info, "", $($x)*) }
+}
+
+macro_rules! debug {
+	($($x:tt)*) => { log!(Debug, "[DEBUG] ", $($x)*) }
+}
+
+mod admin;
+mod auth;
+mod config;
+mod federation;
+mod filter;
+mod limit;
+mod matchmaking;
+mod net;
+mod owv;
+mod punch;
+mod repository;
+mod snapshot;
+mod stats;
+
+use own_war_lobby_protocol::{
+	DecodeError, ErrorCode, Header, ListEntry, ListFilter, ListServers, MatchPhase, MatchStatus,
+	MessageType, Register, Request, Response, SearchVehicles, Secret, ServerDetails, ServerEntry,
+	MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, SIGNATURE_SIZE, VEHICLE_CHUNK_SIZE,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, SocketAddr, TcpListener, UdpSocket};
+use std::ops::Bound;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use config::{Config, ConfigError, LogLevel};
+use federation::Federation;
+use filter::Filter;
+use limit::{Budgets, Limiter};
+use matchmaking::Matchmaker;
+use repository::{Progress, Repository};
+use stats::Stats;
+
+/// Set when the lobby should shut down.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// How often the sockets and timers stop waiting to check whether the lobby should shut
+/// down.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often the filter file is checked for changes.
+const FILTER_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
 struct ServerInfo {
 	name: Box<str>,
 	map: Box<str>,
@@ -27,298 +84,1418 @@ struct ServerInfo {
 	description: Box<str>,
 	last_ping: Instant,
 	manager_port: u16,
+	/// The protocol version the server registered with.
+	version: u8,
+	/// The last match status reported by the server.
+	status: MatchStatus,
+	/// When the status was reported, used to keep the elapsed time up to date.
+	status_reported: Instant,
+	/// The token that must be presented to modify this entry.
+	token: u64,
+	/// Whether the entry was registered with a signed message. Trusted entries can only be
+	/// modified with signed messages.
+	trusted: bool,
+	/// Whether the server presented its token at least once, which proves it can receive
+	/// packets at its address.
+	verified: bool,
+	/// Whether the entry was loaded from a snapshot and the server hasn't pinged since.
+	restored: bool,
+	/// Whether the entry was received from another lobby and the server hasn't pinged this
+	/// lobby since.
+	replicated: bool,
+}
+
+/// The state of the lobby.
+struct Lobby {
+	list: BTreeMap<SocketAddr, ServerInfo>,
+	tokens: auth::Tokens,
+	/// The secret shared with trusted operators, if any.
+	secret: Option<Secret>,
+	/// The timestamp of the last signed message for each server or peer and message type.
+	replays: auth::Replays<(SocketAddr, MessageType)>,
+	cookies: auth::Cookies,
+	limits: Limits,
+	config: Config,
+	/// When idle rate limit buckets were last removed.
+	last_cleanup: Instant,
+	stats: Stats,
+	/// The stats when they were last logged.
+	logged_stats: Stats,
+	/// When the stats were last logged.
+	last_stats: Instant,
+	/// Hosts whose packets are dropped and until when, if not forever.
+	banned: HashMap<IpAddr, Option<Instant>>,
+	started: Instant,
+	filter: Filter,
+	/// When the filter file was last modified, to reload it when it changes.
+	filter_modified: Option<SystemTime>,
+	/// When the filter file was last checked for changes.
+	last_filter_check: Instant,
+	/// When the last snapshot was written.
+	last_snapshot: Instant,
+	federation: Federation,
+	punches: punch::Sessions,
+	matchmaker: Matchmaker,
+	/// The shared vehicles, if a vehicle directory is configured.
+	repository: Option<Repository>,
 }
 
-#[repr(u8)]
-enum MessageType {
-	GetServerList = 0,
-	RegisterServer = 1,
-	RemoveServer = 2,
-	Ping = 3,
-	ServerInfo = 4,
-	PunchHole = 5,
+/// Rate limits for each host, for each message type of each host and for punch requests
+/// to each server.
+struct Limits {
+	host: Limiter<IpAddr>,
+	message: Limiter<(IpAddr, u8)>,
+	punch: Limiter<SocketAddr>,
+	budgets: Budgets,
 }
 
-impl TryFrom<u8> for MessageType {
-	type Error = ();
-
-	fn try_from(value: u8) -> Result<Self, Self::Error> {
-		use MessageType::*;
-		Ok(match value {
-			0 => GetServerList,
-			1 => RegisterServer,
-			2 => RemoveServer,
-			3 => Ping,
-			4 => ServerInfo,
-			5 => PunchHole,
-			_ => return Err(()),
+/// The size of the IPv4 and UDP headers.
+const UDP_OVERHEAD: usize = 28;
+
+/// All servers registered by the given host.
+fn servers_of(
+	list: &BTreeMap<SocketAddr, ServerInfo>,
+	ip: IpAddr,
+) -> impl Iterator<Item = (&SocketAddr, &ServerInfo)> {
+	list.range(SocketAddr::new(ip, 0)..=SocketAddr::new(ip, u16::MAX))
+}
+
+impl Lobby {
+	fn new(config: Config, secret: Option<Secret>) -> Self {
+		let now = Instant::now();
+		Self {
+			list: BTreeMap::new(),
+			tokens: auth::Tokens::new(),
+			secret,
+			replays: auth::Replays::new(),
+			cookies: auth::Cookies::new(now),
+			limits: Limits {
+				host: Limiter::new(),
+				message: Limiter::new(),
+				punch: Limiter::new(),
+				budgets: Budgets::new(),
+			},
+			federation: Federation::new(&config.peers, now),
+			config,
+			last_cleanup: now,
+			stats: Stats::default(),
+			logged_stats: Stats::default(),
+			last_stats: now,
+			banned: HashMap::new(),
+			started: now,
+			filter: Filter::default(),
+			filter_modified: None,
+			last_filter_check: now,
+			last_snapshot: now,
+			punches: punch::Sessions::new(),
+			matchmaker: Matchmaker::new(now),
+			repository: None,
+		}
+	}
+
+	/// Whether the host proved it can receive packets at its address. Peers are trusted by
+	/// configuration.
+	fn is_verified(&self, addr: SocketAddr, now: Instant) -> bool {
+		self.cookies.is_verified(addr, now) || self.federation.is_peer(addr)
+	}
+
+	/// The maximum size of a packet to the given address, excluding the IP and UDP headers.
+	fn budget(&self, addr: SocketAddr, now: Instant) -> usize {
+		if self.is_verified(addr, now) {
+			usize::MAX
+		} else {
+			self.limits
+				.budgets
+				.available(addr)
+				.saturating_sub(UDP_OVERHEAD)
+		}
+	}
+
+	/// Take a packet of the given size from the budget of the address. Returns `false` if the
+	/// address isn't verified and the packet doesn't fit in its budget, in which case the
+	/// packet must not be sent.
+	fn spend(&mut self, addr: SocketAddr, size: usize, now: Instant) -> bool {
+		self.is_verified(addr, now) || self.limits.budgets.spend(addr, size + UDP_OVERHEAD)
+	}
+
+	/// Send a packet that isn't a direct response to a request, if it fits in the budget of
+	/// the receiver.
+	fn send(&mut self, sockets: &[UdpSocket], data: &[u8], to: SocketAddr, now: Instant) {
+		if !self.spend(to, data.len(), now) {
+			debug!("Not sending {} bytes to unverified host {}", data.len(), to);
+			self.stats.truncated += 1;
+			return;
+		}
+		match net::send_to(sockets, data, to) {
+			Ok(_) => self.stats.sent(data.len()),
+			Err(e) => debug!("Failed to send packet to {}: {}", to, e),
+		}
+	}
+
+	/// Remove servers that haven't pinged in a while, as well as idle rate limit buckets.
+	fn remove_expired(&mut self, now: Instant) {
+		let expiry = self.config.expiry;
+		let (config, stats) = (&self.config, &mut self.stats);
+		self.list.retain(|addr, info| {
+			let keep = now < info.expires(config);
+			if !keep {
+				info!("Removed {} - expired", addr);
+				stats.expired += 1;
+			}
+			keep
+		});
+		if now >= self.last_cleanup + expiry {
+			self.limits.host.prune(now, expiry);
+			self.limits.message.prune(now, expiry);
+			self.limits.punch.prune(now, expiry);
+			self.limits.budgets.prune(now, expiry);
+			self.cookies.prune(now);
+			self.federation.prune(now, expiry);
+			self.replays.prune(auth::unix_millis());
+			if let Some(repo) = &mut self.repository {
+				repo.remove_expired(now);
+			}
+			self.banned.retain(|_, until| until.is_none_or(|t| now < t));
+			self.last_cleanup = now;
+		}
+	}
+
+	/// Log the stats if the interval has passed.
+	fn log_stats(&mut self, now: Instant) {
+		let interval = self.config.stats_interval;
+		if interval != Duration::ZERO && now >= self.last_stats + interval {
+			let stats = self.stats.since(&self.logged_stats);
+			info!("Stats: {} servers, {}", self.list.len(), stats);
+			self.logged_stats = self.stats.clone();
+			self.last_stats = now;
+		}
+	}
+
+	fn is_banned(&self, ip: IpAddr, now: Instant) -> bool {
+		self.banned
+			.get(&ip)
+			.is_some_and(|until| until.is_none_or(|t| now < t))
+	}
+
+	/// Remove a server and let the peers know.
+	fn remove(&mut self, addr: SocketAddr, now: Instant) -> Option<ServerInfo> {
+		let info = self.list.remove(&addr)?;
+		self.federation.removed(addr, now);
+		Some(info)
+	}
+
+	/// Drop all packets of a host and remove its servers. Returns the amount of removed
+	/// servers.
+	fn ban(&mut self, ip: IpAddr, until: Option<Instant>, now: Instant) -> usize {
+		self.banned.insert(ip, until);
+		let addrs = servers_of(&self.list, ip)
+			.map(|(addr, _)| *addr)
+			.collect::<Vec<_>>();
+		for addr in addrs.iter() {
+			self.remove(*addr, now);
+		}
+		addrs.len()
+	}
+
+	/// Apply a new configuration. Sockets, peers, the secret and the vehicle directory can't
+	/// be changed while running and keep their old values.
+	fn reload(&mut self, mut config: Config) {
+		let old = &mut self.config;
+		if config.bind != old.bind
+			|| config.peers != old.peers
+			|| config.secret_file != old.secret_file
+			|| config.admin != old.admin
+			|| config.vehicle_dir != old.vehicle_dir
+		{
+			warn!("Changes to bind, peer, secret-file, admin and vehicle-dir require a restart");
+		}
+		config.bind = mem::take(&mut old.bind);
+		config.peers = mem::take(&mut old.peers);
+		config.secret_file = old.secret_file.take();
+		config.admin = old.admin;
+		config.vehicle_dir = old.vehicle_dir.take();
+		if let Some(repo) = &mut self.repository {
+			repo.max_vehicles = config.max_vehicles;
+		}
+		LOG_LEVEL.store(config.log_level as u8, Ordering::Relaxed);
+		self.config = config;
+		if let Err(e) = self.load_filter(Instant::now()) {
+			error!("{} - keeping the old filter", e);
+		}
+	}
+
+	/// Load the filter file, if any, and remove the servers it doesn't allow.
+	fn load_filter(&mut self, now: Instant) -> Result<(), filter::FilterError> {
+		self.last_filter_check = now;
+		let path = match &self.config.filter_file {
+			Some(path) => path,
+			None => {
+				self.filter = Filter::default();
+				return Ok(());
+			}
+		};
+		self.filter_modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+		self.filter = Filter::load(path)?;
+		let filter = &self.filter;
+		let denied = self
+			.list
+			.iter()
+			.filter(|(addr, info)| {
+				filter
+					.check(addr.ip(), &info.name, &info.description)
+					.is_err()
+			})
+			.map(|(addr, _)| *addr)
+			.collect::<Vec<_>>();
+		for addr in denied {
+			info!("Removed {} - denied by filter", addr);
+			self.remove(addr, now);
+		}
+		Ok(())
+	}
+
+	/// Reload the filter file if the interval has passed and it was modified.
+	fn check_filter(&mut self, now: Instant) {
+		let path = match &self.config.filter_file {
+			Some(path) if now >= self.last_filter_check + FILTER_CHECK_INTERVAL => path.clone(),
+			_ => return,
+		};
+		self.last_filter_check = now;
+		let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+		if modified != self.filter_modified {
+			match self.load_filter(now) {
+				Ok(()) => info!("Reloaded filter from '{}'", path.display()),
+				Err(e) => {
+					// Don't retry until the file changes again.
+					self.filter_modified = modified;
+					error!("{} - keeping the old filter", e);
+				}
+			}
+		}
+	}
+
+	/// Write a snapshot if one is configured and the interval has passed.
+	fn save_snapshot(&mut self, now: Instant) {
+		if now >= self.last_snapshot + self.config.snapshot_interval {
+			self.write_snapshot();
+			self.last_snapshot = now;
+		}
+	}
+
+	/// Write a snapshot if one is configured.
+	fn write_snapshot(&self) {
+		if let Some(path) = &self.config.snapshot_file {
+			match snapshot::save(path, &self.list) {
+				Ok(()) => debug!("Saved snapshot with {} servers", self.list.len()),
+				Err(e) => error!("Failed to save snapshot to '{}': {}", path.display(), e),
+			}
+		}
+	}
+
+	/// Create the `Sync` packets for the peers if the interval has passed.
+	fn sync(&mut self, now: Instant) -> Vec<Vec<u8>> {
+		match &self.secret {
+			Some(secret) if now >= self.federation.last_sync + self.config.sync_interval => {
+				self.federation.packets(&self.list, secret, now)
+			}
+			_ => Vec::new(),
+		}
+	}
+
+	/// Start a punch session between a client and a server. Returns the session ID and the
+	/// `Punch` messages to send, or `None` if the server isn't registered.
+	fn start_punch(
+		&mut self,
+		client: SocketAddr,
+		server: SocketAddr,
+		now: Instant,
+	) -> Option<(u64, Vec<punch::Packet>)> {
+		let info = self.list.get(&server).or_else(|| {
+			debug!("invalid entry - {}", server);
+			None
+		})?;
+		let manager = SocketAddr::new(server.ip(), info.manager_port);
+		let id = self.tokens.generate();
+		let (session, packets) = self.punches.start(id, client, server, manager, now);
+		if session == id {
+			info!("Punch {:016x} from {} to {} started", id, client, server);
+			self.stats.punches_started += 1;
+		}
+		Some((session, packets))
+	}
+
+	/// Assign queued clients to servers if the interval has passed. Returns the `Matched`
+	/// messages and the `Punch` messages of the sessions that follow.
+	fn matchmake(&mut self, now: Instant) -> Vec<punch::Packet> {
+		if now < self.matchmaker.last_run + matchmaking::MATCH_INTERVAL {
+			return Vec::new();
+		}
+		let config = &self.config;
+		let matches = self.matchmaker.run(
+			&self.list,
+			config.match_min_players,
+			config.match_max_wait,
+			now,
+		);
+		let mut packets = Vec::new();
+		for (client, server) in matches {
+			let (session, punch) = match self.start_punch(client, server, now) {
+				Some(p) => p,
+				None => continue,
+			};
+			info!("Matched {} with {}", client, server);
+			self.stats.matched += 1;
+			if let Some(data) = encode(Response::Matched { server, session }) {
+				packets.push(punch::Packet { to: client, data });
+			}
+			packets.extend(punch);
+		}
+		packets
+	}
+
+	/// Resend unacknowledged punches and give up on punches that took too long.
+	fn punch(&mut self, now: Instant) -> Vec<punch::Packet> {
+		let stats = &mut self.stats;
+		self.punches.retransmit(now, |client, server| {
+			info!("Punch from {} to {} timed out", client, server);
+			stats.punched(punch::Outcome::TimedOut);
 		})
 	}
+
+	/// When the next server expires, idle buckets should be removed, the stats should be
+	/// logged, a snapshot should be written, the peers should be synced, a punch should be
+	/// resent, the filter should be checked or the queue should be matched, whichever comes
+	/// first.
+	fn next_deadline(&self) -> Instant {
+		let config = &self.config;
+		let mut deadline = self.last_cleanup + config.expiry;
+		if let Some(t) = self.list.values().map(|info| info.expires(config)).min() {
+			deadline = deadline.min(t);
+		}
+		if config.stats_interval != Duration::ZERO {
+			deadline = deadline.min(self.last_stats + config.stats_interval);
+		}
+		if config.snapshot_file.is_some() {
+			deadline = deadline.min(self.last_snapshot + config.snapshot_interval);
+		}
+		if !self.federation.peers.is_empty() {
+			deadline = deadline.min(self.federation.last_sync + config.sync_interval);
+		}
+		if config.filter_file.is_some() {
+			deadline = deadline.min(self.last_filter_check + FILTER_CHECK_INTERVAL);
+		}
+		if let Some(t) = self.punches.next_deadline() {
+			deadline = deadline.min(t);
+		}
+		if let Some(t) = self.matchmaker.next_deadline() {
+			deadline = deadline.min(t);
+		}
+		deadline
+	}
+}
+
+impl ServerInfo {
+	/// When the entry should be removed if the server doesn't ping. Restored entries get a
+	/// grace period to give the servers time to notice the lobby is back.
+	fn expires(&self, config: &Config) -> Instant {
+		if self.restored {
+			self.last_ping + config.grace_period
+		} else {
+			self.last_ping + config.expiry
+		}
+	}
+
+	fn free_slots(&self) -> u8 {
+		self.max_players.saturating_sub(self.status.players)
+	}
+
+	/// The last reported match status with the elapsed time extrapolated to now.
+	fn current_status(&self) -> MatchStatus {
+		let mut status = self.status;
+		if status.phase == MatchPhase::InProgress {
+			let since = self.status_reported.elapsed().as_secs();
+			status.elapsed = u32::try_from(u64::from(status.elapsed) + since).unwrap_or(u32::MAX);
+		}
+		status
+	}
+
+	fn matches(&self, filter: &ListFilter) -> bool {
+		if filter.map.is_some_and(|m| m != &*self.map) {
+			return false;
+		}
+		if let Some(name) = filter.name {
+			if !self.name.to_lowercase().contains(&name.to_lowercase()) {
+				return false;
+			}
+		}
+		if filter.free_slots.is_some_and(|n| self.free_slots() < n) {
+			return false;
+		}
+		filter.version.is_none_or(|v| v == self.version)
+	}
+
+	fn entry(&self, addr: SocketAddr) -> ServerEntry<'_> {
+		ServerEntry {
+			addr,
+			name: &self.name,
+			map: &self.map,
+			max_players: self.max_players,
+			description: &self.description,
+			version: self.version,
+			status: self.current_status(),
+		}
+	}
 }
 
 fn main() {
-	let socket = UdpSocket::bind("0.0.0.0:39984").unwrap();
+	// Kept to reload the configuration with the same command line options.
+	let args = env::args().skip(1).collect::<Vec<_>>();
+	let config = match Config::from_args(args.iter().cloned()) {
+		Ok(c) => c,
+		Err(ConfigError::Help) => {
+			print!("{}", config::USAGE);
+			return;
+		}
+		Err(e) => {
+			eprintln!("{}", e);
+			eprint!("{}", config::USAGE);
+			process::exit(2);
+		}
+	};
+	LOG_LEVEL.store(config.log_level as u8, Ordering::Relaxed);
+
+	let secret = config.secret_file.as_ref().map(|path| {
+		fs::read_to_string(path)
+			.map(|s| Secret::new(s.trim()))
+			.unwrap_or_else(|e| {
+				error!("Failed to read secret from '{}': {}", path.display(), e);
+				process::exit(1);
+			})
+	});
+	if !config.peers.is_empty() && secret.is_none() {
+		error!("Peers are configured but there is no secret to sign syncs with");
+		process::exit(1);
+	}
+
+	let sockets = config
+		.bind
+		.iter()
+		.map(|addr| {
+			let socket = net::bind_or_ipv4(*addr)
+				.and_then(|s| s.set_read_timeout(Some(POLL_INTERVAL)).map(|()| s))
+				.unwrap_or_else(|e| {
+					error!("Failed to bind to {}: {}", addr, e);
+					process::exit(1);
+				});
+			info!("Listening on {}", socket.local_addr().unwrap_or(*addr));
+			socket
+		})
+		.collect::<Vec<_>>();
+
+	let admin = config.admin.map(|addr| {
+		let listener = TcpListener::bind(addr)
+			.and_then(|l| l.set_nonblocking(true).map(|()| l))
+			.unwrap_or_else(|e| {
+				error!("Failed to bind admin interface to {}: {}", addr, e);
+				process::exit(1);
+			});
+		info!("Admin interface listening on {}", addr);
+		listener
+	});
+
+	let handler = || {
+		// A second signal means the operator doesn't want to wait.
+		if SHUTDOWN.swap(true, Ordering::Relaxed) {
+			process::exit(130);
+		}
+	};
+	if let Err(e) = ctrlc::set_handler(handler) {
+		warn!("Failed to install signal handler: {}", e);
+	}
+
+	let mut lobby = Lobby::new(config, secret);
+	if let Some(path) = &lobby.config.snapshot_file {
+		match snapshot::load(path) {
+			Ok(list) => {
+				info!("Restored {} servers from '{}'", list.len(), path.display());
+				lobby.list = list;
+			}
+			Err(snapshot::SnapshotError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {}
+			Err(e) => warn!("Failed to load snapshot from '{}': {}", path.display(), e),
+		}
+	}
+	if let Err(e) = lobby.load_filter(Instant::now()) {
+		error!("{}", e);
+		process::exit(1);
+	}
+	if let Some(dir) = &lobby.config.vehicle_dir {
+		match Repository::open(dir, lobby.config.max_vehicles) {
+			Ok(repo) => {
+				info!("Loaded {} vehicles from '{}'", repo.len(), dir.display());
+				lobby.repository = Some(repo);
+			}
+			Err(e) => {
+				error!("{}", e);
+				process::exit(1);
+			}
+		}
+	}
+	let lobby = Mutex::new(lobby);
+	let lobby = &lobby;
+	thread::scope(|s| {
+		let sockets = &sockets;
+		for socket in sockets.iter() {
+			s.spawn(move || serve(lobby, socket, sockets));
+		}
+		if let Some(listener) = &admin {
+			s.spawn(move || admin::serve(lobby, listener, &args));
+		}
+		run_timers(lobby, sockets);
+	});
+	let lobby = lobby.lock().unwrap();
+	lobby.write_snapshot();
+	info!(
+		"Shutting down - {} servers, {}",
+		lobby.list.len(),
+		lobby.stats
+	);
+}
+
+/// Remove expired servers, log stats, sync peers, resend punches and match queued clients
+/// on time, even if no packets arrive, until the lobby is shut down.
+fn run_timers(lobby: &Mutex<Lobby>, sockets: &[UdpSocket]) {
+	while !SHUTDOWN.load(Ordering::Relaxed) {
+		let deadline = {
+			let mut lobby = lobby.lock().unwrap();
+			let now = Instant::now();
+			lobby.remove_expired(now);
+			lobby.log_stats(now);
+			lobby.save_snapshot(now);
+			lobby.check_filter(now);
+			let packets = lobby.sync(now);
+			let peers = lobby.federation.peers.keys().copied().collect::<Vec<_>>();
+			for (pkt, peer) in packets
+				.iter()
+				.flat_map(|p| peers.iter().map(move |a| (p, a)))
+			{
+				lobby.send(sockets, pkt, *peer, now);
+			}
+			let mut packets = lobby.punch(now);
+			packets.extend(lobby.matchmake(now));
+			for pkt in packets {
+				lobby.send(sockets, &pkt.data, pkt.to, now);
+			}
+			lobby.next_deadline()
+		};
+		let timeout = deadline.saturating_duration_since(Instant::now());
+		thread::sleep(timeout.min(POLL_INTERVAL));
+	}
+}
+
+/// Receive and respond to packets on a single socket until the lobby is shut down.
+///
+/// `sockets` are all the sockets of the lobby, which are used to forward packets to other
+/// hosts.
+fn serve(lobby: &Mutex<Lobby>, socket: &UdpSocket, sockets: &[UdpSocket]) {
 	let mut rcv = [0; 4096];
-	let mut list = HashMap::<SocketAddr, ServerInfo>::new();
-	let mut time_start = Instant::now();
-	loop {
-		let (size, addr) = socket.recv_from(&mut rcv).unwrap();
-		// Remove first so we don't send stale entries
+	while !SHUTDOWN.load(Ordering::Relaxed) {
+		let (size, addr) = match socket.recv_from(&mut rcv) {
+			Ok(r) => r,
+			Err(e)
+				if matches!(
+					e.kind(),
+					io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+				) =>
+			{
+				continue
+			}
+			Err(e) => {
+				warn!("Failed to receive packet: {}", e);
+				continue;
+			}
+		};
+		// Responses must be sent to the original address, but mapped addresses shouldn't end up
+		// in the list.
+		let reply_addr = addr;
+		let addr = net::canonical(addr);
+		let mut lobby = lobby.lock().unwrap();
 		let now = Instant::now();
-		if now - time_start >= Duration::new(60, 0) {
-			let mut remove_addrs = Vec::new();
-			for (addr, info) in list.iter() {
-				if now - info.last_ping >= Duration::new(60, 0) {
-					remove_addrs.push(*addr);
+		debug!("Request from {} - {} bytes", addr, size);
+		lobby.stats.received(size);
+		if lobby.is_banned(addr.ip(), now) {
+			lobby.stats.banned += 1;
+			continue;
+		}
+		let host_rate = lobby.config.host_rate;
+		// Peers may send many packets at once when syncing.
+		if !lobby.federation.is_peer(addr) {
+			if let Err(n) = lobby.limits.host.check(addr.ip(), host_rate, now) {
+				lobby.stats.rate_limited += 1;
+				if limit::should_log(n) {
+					warn!("{} exceeded the packet rate limit ({} times)", addr.ip(), n);
 				}
+				continue;
 			}
-			for addr in remove_addrs {
-				println!("Removed {}", addr);
-				list.remove(&addr);
-			}
-			time_start = now;
 		}
-		debug!("Request from {} - {} bytes", addr, size);
-		if let Some(rsp) = parse_packet(&mut list, addr, &rcv[..size], &socket) {
+		let factor = lobby.config.amplification_factor;
+		lobby
+			.limits
+			.budgets
+			.received(addr, size + UDP_OVERHEAD, factor, now);
+		if let Some(mut rsp) = parse_packet(&mut lobby, addr, &rcv[..size], sockets) {
+			if !lobby.spend(addr, rsp.len(), now) {
+				debug!(
+					"Response too large for unverified host - {} bytes",
+					rsp.len()
+				);
+				let request = Header::decode(&rcv[..size]).map_or(0, |(h, _)| h.message_type);
+				rsp = error(request, ErrorCode::RequestTooSmall);
+				lobby.stats.truncated += 1;
+				if !lobby.spend(addr, rsp.len(), now) {
+					continue;
+				}
+			}
 			debug!("Sending response - {} bytes", rsp.len());
-			socket.send_to(&rsp, addr).unwrap();
+			match socket.send_to(&rsp, reply_addr) {
+				Ok(_) => lobby.stats.sent(rsp.len()),
+				Err(e) => warn!("Failed to send response to {}: {}", addr, e),
+			}
 		}
 	}
 }
 
 fn parse_packet(
-	list: &mut HashMap<SocketAddr, ServerInfo>,
+	lobby: &mut Lobby,
 	addr: SocketAddr,
-	buf: &[u8],
-	send_socket: &UdpSocket,
+	packet: &[u8],
+	sockets: &[UdpSocket],
 ) -> Option<Box<[u8]>> {
-	if buf.len() < 1 {
-		debug!("invalid packet size {}", buf.len());
+	let (header, payload) = Header::decode(packet)
+		.map_err(|_| debug!("invalid packet size {}", packet.len()))
+		.ok()?;
+	let version = header.version;
+	if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version) {
+		debug!("unsupported protocol version {}", version);
+		return Some(unsupported_version(
+			header.message_type,
+			MIN_PROTOCOL_VERSION,
+		));
+	}
+	let typ = match MessageType::try_from(header.message_type) {
+		Ok(MessageType::Error) | Err(_) => {
+			debug!("invalid packet type: {}", header.message_type);
+			return Some(error(header.message_type, ErrorCode::UnknownMessage));
+		}
+		Ok(t) => t,
+	};
+	lobby.stats.message(typ);
+	if version < typ.min_version() {
+		debug!("protocol version {} too old for message", version);
+		return Some(unsupported_version(typ as u8, typ.min_version()));
+	}
+	if let Err(n) = lobby.limits.message.check(
+		(addr.ip(), typ as u8),
+		lobby.config.message_rate(typ),
+		Instant::now(),
+	) {
+		lobby.stats.rate_limited += 1;
+		if limit::should_log(n) {
+			warn!(
+				"{} exceeded the rate limit for {} ({} times)",
+				addr.ip(),
+				typ.name(),
+				n
+			);
+		}
 		return None;
 	}
-	let typ = MessageType::try_from(buf[0])
-		.or_else(|_| {
-			debug!("invalid packet type: {}", buf[1]);
-			Err(())
-		})
+	let (request, trailing) = Request::decode(typ, payload)
+		.map_err(|e: DecodeError| debug!("invalid {} request: {}", typ.name(), e))
 		.ok()?;
-	match typ {
-		MessageType::GetServerList => {
+	let budget = lobby.budget(addr, Instant::now());
+	let config = &lobby.config;
+	let list = &mut lobby.list;
+	match request {
+		Request::GetServerList => {
 			debug!("list servers");
-			let mut rsp = Vec::new();
-			rsp.push(buf[0]);
-			// 239 entries is the safe upper bound right now
-			// 2 ^ 16 / (16 + 2 + 1 + 255) = ~239.18
-			for (ad, info) in list.iter().take(239) {
-				encode_addr(*ad, &mut rsp);
-				rsp.push(info.name.len() as u8);
-				rsp.extend(info.name.as_bytes());
-			}
-			Some(rsp.into_boxed_slice())
-		}
-		MessageType::RegisterServer => {
+			let mut size = Header::SIZE;
+			let mut entries = Vec::new();
+			for (ad, info) in list.iter().take(config.legacy_list_limit) {
+				let entry = ListEntry {
+					addr: *ad,
+					name: &info.name,
+				};
+				size += entry.encoded_len();
+				if size > budget {
+					break;
+				}
+				entries.push(entry);
+			}
+			encode(Response::ServerList(entries))
+		}
+		Request::RegisterServer(Register {
+			port,
+			token,
+			name,
+			map,
+			max_players,
+			description,
+			status,
+		}) => {
 			debug!("register server");
-			let mut rsp = Vec::new();
-			rsp.push(buf[0]);
-			let buf = &buf[1..];
-			let port = decode_u16(&buf[..2])
-				.or_else(|_| {
-					debug!("invalid packet size {}", buf.len());
-					Err(())
-				})
-				.ok()?;
-			let buf = &buf[2..];
-			let name = decode_str::<u8>(buf)
-				.or_else(|_| {
-					debug!("Failed to decode name");
-					Err(())
-				})
-				.ok()?;
-			let buf = &buf[1 + name.len()..];
-			let map = decode_str::<u8>(buf)
-				.or_else(|_| {
-					debug!("Failed to decode map");
-					Err(())
-				})
-				.ok()?;
-			let buf = &buf[1 + map.len()..];
-			let max_players = *buf.get(0).or_else(|| {
-				debug!("Invalid packet size {}", buf.len());
-				None
-			})?;
-			let buf = &buf[1..];
-			let descr = decode_str::<u16>(buf)
-				.or_else(|_| {
-					debug!("Failed to decode description");
-					Err(())
-				})
-				.ok()?;
 			let for_addr = SocketAddr::new(addr.ip(), port);
+			let replays = &mut lobby.replays;
+			let trusted =
+				match check_signature(&lobby.secret, replays, (for_addr, typ), packet, trailing)? {
+					Ok(signed) => signed,
+					Err(code) => return encode(Response::RegisterFailed { code }),
+				};
+			if let Err(code) = lobby.filter.check(addr.ip(), name, description) {
+				info!("Rejected server {} '{}' - {:?}", for_addr, name, code);
+				lobby.stats.rejected += 1;
+				return encode(Response::RegisterFailed { code });
+			}
+			let (token, verified) = match list.get(&for_addr) {
+				Some(e) => {
+					if let Err(code) = authorize(e, token, trusted) {
+						warn!(
+							"{} failed to update {} - bad token or signature",
+							addr, for_addr
+						);
+						return encode(Response::RegisterFailed { code });
+					}
+					(e.token, true)
+				}
+				None if servers_of(list, addr.ip()).count() >= config.max_registrations => {
+					warn!("{} has too many registered servers", addr.ip());
+					let code = ErrorCode::TooManyRegistrations;
+					return encode(Response::RegisterFailed { code });
+				}
+				None => {
+					lobby.stats.registered += 1;
+					(lobby.tokens.generate(), false)
+				}
+			};
+			let now = Instant::now();
 			list.insert(
 				for_addr,
 				ServerInfo {
-					last_ping: Instant::now(),
-					name: String::from(name).into_boxed_str(),
-					map: String::from(map).into_boxed_str(),
+					last_ping: now,
+					name: name.into(),
+					map: map.into(),
 					max_players,
-					description: String::from(descr).into_boxed_str(),
+					description: description.into(),
 					manager_port: addr.port(),
+					version,
+					status,
+					status_reported: now,
+					token,
+					trusted,
+					verified,
+					restored: false,
+					replicated: false,
 				},
 			);
-			println!(
-				"{} registered a server - port {} name '{}', map '{}', max players {}, description '{}'",
-				addr, for_addr.port(), name, map, max_players, descr
+			info!(
+				"{} registered a server - port {} name '{}', map '{}', max players {}, description '{}', trusted {}",
+				addr, for_addr.port(), name, map, max_players, description, trusted
 			);
-			rsp.push(0); // OK = 0
-			Some(rsp.into_boxed_slice())
+			encode(Response::Registered { token })
 		}
-		MessageType::RemoveServer => {
+		Request::RemoveServer { port, token } => {
 			debug!("remove server");
-			let port = decode_u16(buf)
-				.or_else(|_| {
-					debug!("invalid packet size {}", buf.len());
-					Err(())
-				})
-				.ok()?;
-			let addr = &SocketAddr::new(addr.ip(), port);
-			println!("Removed server {}", addr);
-			list.remove(addr);
+			let for_addr = SocketAddr::new(addr.ip(), port);
+			let replays = &mut lobby.replays;
+			let signed =
+				match check_signature(&lobby.secret, replays, (for_addr, typ), packet, trailing)? {
+					Ok(signed) => signed,
+					Err(code) => return Some(error(typ as u8, code)),
+				};
+			let e = list.get(&for_addr)?;
+			if let Err(code) = authorize(e, token, signed) {
+				warn!(
+					"{} failed to remove {} - bad token or signature",
+					addr, for_addr
+				);
+				return Some(error(typ as u8, code));
+			}
+			info!("Removed server {}", for_addr);
+			lobby.remove(for_addr, Instant::now());
 			None
 		}
-		MessageType::Ping => {
+		Request::Ping {
+			port,
+			token,
+			status,
+		} => {
 			debug!("ping");
-			let port = decode_u16(&buf[1..])
-				.or_else(|_| {
-					debug!("invalid packet size {}", buf.len());
-					Err(())
-				})
-				.ok()?;
-			if let Some(e) = list.get_mut(&SocketAddr::new(addr.ip(), port)) {
-				e.last_ping = Instant::now();
+			let for_addr = SocketAddr::new(addr.ip(), port);
+			let replays = &mut lobby.replays;
+			let signed =
+				match check_signature(&lobby.secret, replays, (for_addr, typ), packet, trailing)? {
+					Ok(signed) => signed,
+					Err(code) => return Some(error(typ as u8, code)),
+				};
+			let e = list.get_mut(&for_addr)?;
+			if let Err(code) = authorize(e, token, signed) {
+				debug!("bad token or signature for ping");
+				return Some(error(typ as u8, code));
 			}
+			let now = Instant::now();
+			e.last_ping = now;
+			e.status = status;
+			e.status_reported = now;
+			e.verified = true;
+			e.restored = false;
+			e.replicated = false;
+			// The token was sent to the address of the manager, which can now receive
+			// forwarded punches.
+			lobby.cookies.verify(addr, now);
 			None
 		}
-		MessageType::ServerInfo => {
+		Request::ServerInfo { addr } => {
 			debug!("info");
-			let mut rsp = Vec::new();
-			rsp.push(buf[0]);
-			let (addr, buf) = decode_addr(&buf[1..]).ok()?;
-			list.get(&addr).and_then(|info| {
-				rsp.push(info.max_players);
-				rsp.push(info.map.len() as u8);
-				rsp.extend(info.map.as_bytes());
-				rsp.extend(&(info.description.len() as u16).to_le_bytes());
-				rsp.extend(info.description.as_bytes());
-				Some(())
-			})?;
-			Some(rsp.into_boxed_slice())
+			let info = list.get(&addr)?;
+			encode(Response::ServerInfo(ServerDetails {
+				max_players: info.max_players,
+				map: &info.map,
+				description: &info.description,
+				status: info.current_status(),
+			}))
 		}
-		MessageType::PunchHole => {
+		Request::PunchHole { addr: server_addr } => {
 			debug!("punch hole");
-			let mut pkt = Vec::new();
-			pkt.push(buf[0]);
-			let (server_addr, buf) = decode_addr(&buf[1..]).ok()?;
-			pkt.extend(&server_addr.port().to_le_bytes());
-			encode_addr(addr, &mut pkt);
 			let info = list.get(&server_addr).or_else(|| {
 				debug!("invalid entry - {}", addr);
 				None
 			})?;
+			if let Err(n) = lobby
+				.limits
+				.punch
+				.check(server_addr, config.punch_rate, Instant::now())
+			{
+				lobby.stats.rate_limited += 1;
+				if limit::should_log(n) {
+					warn!(
+						"{} exceeded the punch rate limit ({} times)",
+						server_addr, n
+					);
+				}
+				return None;
+			}
+			let pkt = encode(Response::PunchHole {
+				port: server_addr.port(),
+				client: addr,
+			})?;
 			let manager_addr = SocketAddr::new(server_addr.ip(), info.manager_port);
-			send_socket.send_to(pkt.as_slice(), manager_addr);
+			lobby.send(sockets, &pkt, manager_addr, Instant::now());
 			None
 		}
-	}
-}
+		Request::WhoAmI => {
+			debug!("who am i");
+			encode(Response::WhoAmI { addr })
+		}
+		Request::PunchRequest { addr: server_addr } => {
+			debug!("punch request");
+			let now = Instant::now();
+			if let Err(n) = lobby
+				.limits
+				.punch
+				.check(server_addr, config.punch_rate, now)
+			{
+				lobby.stats.rate_limited += 1;
+				if limit::should_log(n) {
+					warn!(
+						"{} exceeded the punch rate limit ({} times)",
+						server_addr, n
+					);
+				}
+				return None;
+			}
+			let (_, packets) = lobby.start_punch(addr, server_addr, now)?;
+			let mut rsp = None;
+			for pkt in packets {
+				if pkt.to == addr {
+					rsp = Some(pkt.data);
+					continue;
+				}
+				lobby.send(sockets, &pkt.data, pkt.to, now);
+			}
+			rsp
+		}
+		Request::PunchAck { session, result } => {
+			debug!("punch ack - {:?}", result);
+			match lobby.punches.ack(session, addr, result) {
+				Ok(Some((client, server, outcome))) => {
+					info!(
+						"Punch {:016x} from {} to {} {}",
+						session, client, server, outcome
+					);
+					lobby.stats.punched(outcome);
+				}
+				Ok(None) => (),
+				Err(()) => debug!("{} acked unknown punch {:016x}", addr, session),
+			}
+			None
+		}
+		Request::Advertise {
+			port,
+			token,
+			team_size,
+			region,
+		} => {
+			debug!("advertise");
+			let for_addr = SocketAddr::new(addr.ip(), port);
+			let replays = &mut lobby.replays;
+			let signed =
+				match check_signature(&lobby.secret, replays, (for_addr, typ), packet, trailing)? {
+					Ok(signed) => signed,
+					Err(code) => return Some(error(typ as u8, code)),
+				};
+			let e = list.get(&for_addr)?;
+			if let Err(code) = authorize(e, token, signed) {
+				debug!("bad token or signature for advertise");
+				return Some(error(typ as u8, code));
+			}
+			lobby.matchmaker.advertise(for_addr, team_size, region);
+			None
+		}
+		Request::Enqueue(prefs) => {
+			debug!("enqueue");
+			let now = Instant::now();
+			// Matches are announced to the address in the ticket, which must not be spoofed.
+			if !lobby.is_verified(addr, now) {
+				debug!("{} tried to enqueue without verifying its address", addr);
+				return Some(error(typ as u8, ErrorCode::Unauthorized));
+			}
+			match lobby.matchmaker.enqueue(addr, &prefs, now) {
+				Some(waiting) => encode(Response::Enqueued {
+					waiting: u8::try_from(waiting).unwrap_or(u8::MAX),
+				}),
+				None => {
+					warn!("Matchmaking queue is full");
+					None
+				}
+			}
+		}
+		Request::Dequeue => {
+			debug!("dequeue");
+			lobby.matchmaker.dequeue(addr);
+			None
+		}
+		Request::UploadVehicle(upload) => {
+			debug!("upload vehicle - offset {}", upload.offset);
+			let repo = match &mut lobby.repository {
+				Some(repo) => repo,
+				None => return Some(error(typ as u8, ErrorCode::UnknownMessage)),
+			};
+			if !lobby.filter.allows(addr.ip()) {
+				return Some(error(typ as u8, ErrorCode::Denied));
+			}
+			let data = match repo.receive(addr, &upload, Instant::now()) {
+				Ok(Progress::Receiving(received)) => {
+					return encode(Response::Uploading { received })
+				}
+				Ok(Progress::Complete(data)) => data,
+				Err(code) => return Some(error(typ as u8, code)),
+			};
+			let name = match owv::validate(&data) {
+				Ok(summary) => summary.name,
+				Err(e) => {
+					info!("Rejected vehicle from {} - {}", addr, e);
+					return Some(error(typ as u8, ErrorCode::InvalidVehicle));
+				}
+			};
+			if let Err(code) = lobby.filter.check(addr.ip(), name, upload.tags) {
+				info!("Rejected vehicle '{}' from {} - {:?}", name, addr, code);
+				return Some(error(typ as u8, code));
+			}
+			match repo.insert(&data, name, upload.tags) {
+				Ok(id) => {
+					info!(
+						"{} uploaded vehicle {:016x} - name '{}', tags '{}', {} bytes",
+						addr,
+						id,
+						name,
+						upload.tags,
+						data.len()
+					);
+					lobby.stats.uploaded += 1;
+					encode(Response::Uploaded { id })
+				}
+				Err(code) => Some(error(typ as u8, code)),
+			}
+		}
+		Request::SearchVehicles(SearchVehicles {
+			cursor,
+			limit,
+			name,
+			tags,
+		}) => {
+			debug!("search vehicles");
+			let repo = match &lobby.repository {
+				Some(repo) => repo,
+				None => return Some(error(typ as u8, ErrorCode::UnknownMessage)),
+			};
+			let limit = if limit == 0 {
+				usize::MAX
+			} else {
+				usize::from(limit)
+			};
+			// Like `ListServers`, unverified hosts only get what fits in the budget.
+			let page_size = config
+				.page_size
+				.min(budget.saturating_sub(Header::SIZE + 2));
+			let at_least_one = budget == usize::MAX;
 
-trait FromLeBytes {
-	fn from_le_bytes(slice: &[u8]) -> Self;
-}
+			let mut entries = Vec::new();
+			let mut size = 0;
+			let mut more = false;
+			for entry in repo.search(cursor, name, tags) {
+				if entries.len() >= limit || entries.len() >= usize::from(u8::MAX) {
+					more = true;
+					break;
+				}
+				size += entry.encoded_len();
+				if (!entries.is_empty() || !at_least_one) && size > page_size {
+					more = true;
+					break;
+				}
+				entries.push(entry);
+			}
+			encode(Response::Vehicles { more, entries })
+		}
+		Request::DownloadVehicle { id, offset } => {
+			debug!("download vehicle {:016x} - offset {}", id, offset);
+			let repo = match &lobby.repository {
+				Some(repo) => repo,
+				None => return Some(error(typ as u8, ErrorCode::UnknownMessage)),
+			};
+			match repo.read(id, offset, VEHICLE_CHUNK_SIZE) {
+				Ok(Some((total, data))) => encode(Response::VehicleChunk {
+					id,
+					total,
+					offset,
+					data: &data,
+				}),
+				Ok(None) => Some(error(typ as u8, ErrorCode::NotFound)),
+				Err(e) => {
+					error!("Failed to read vehicle {:016x}: {}", id, e);
+					Some(error(typ as u8, ErrorCode::NotFound))
+				}
+			}
+		}
+		Request::Verify { cookie } => {
+			debug!("verify");
+			let now = Instant::now();
+			let cookie = if lobby.cookies.check(addr, cookie, now) {
+				cookie
+			} else {
+				lobby.cookies.cookie(addr, now)
+			};
+			encode(Response::Cookie { cookie })
+		}
+		Request::Hello => {
+			debug!("hello");
+			encode(Response::Hello {
+				min_version: MIN_PROTOCOL_VERSION,
+				max_version: PROTOCOL_VERSION,
+				supported: MessageType::SUPPORTED.iter().map(|t| *t as u8).collect(),
+			})
+		}
+		Request::ListServers(ListServers {
+			cursor,
+			limit,
+			filter,
+		}) => {
+			debug!("list servers paged");
+			let limit = if limit == 0 {
+				usize::MAX
+			} else {
+				usize::from(limit)
+			};
+			// Unverified hosts get as many entries as fit in the response budget, which
+			// may be none at all.
+			let page_size = config
+				.page_size
+				.min(budget.saturating_sub(Header::SIZE + 2));
+			let at_least_one = budget == usize::MAX;
 
-impl FromLeBytes for u8 {
-	fn from_le_bytes(slice: &[u8]) -> Self {
-		Self::from_le_bytes(<[u8; size_of::<Self>()]>::try_from(slice).unwrap())
+			let mut entries = Vec::new();
+			let mut size = 0;
+			let mut more = false;
+			let iter = match cursor {
+				Some(c) => list.range((Bound::Excluded(c), Bound::Unbounded)),
+				None => list.range(..),
+			};
+			for (ad, info) in iter.filter(|(_, info)| info.matches(&filter)) {
+				if entries.len() >= limit || entries.len() >= usize::from(u8::MAX) {
+					more = true;
+					break;
+				}
+				let entry = info.entry(*ad);
+				size += entry.encoded_len();
+				if (!entries.is_empty() || !at_least_one) && size > page_size {
+					more = true;
+					break;
+				}
+				entries.push(entry);
+			}
+			encode(Response::ListServers { more, entries })
+		}
+		Request::Sync(sync) => {
+			debug!("sync - {} entries", sync.entries.len());
+			if !lobby.federation.is_peer(addr) {
+				warn!("{} sent a sync but isn't a peer", addr);
+				return Some(error(typ as u8, ErrorCode::Unauthorized));
+			}
+			let replays = &mut lobby.replays;
+			match check_signature(&lobby.secret, replays, (addr, typ), packet, trailing)? {
+				Ok(true) => (),
+				Ok(false) | Err(_) => {
+					warn!("{} sent an unsigned or badly signed sync", addr);
+					return Some(error(typ as u8, ErrorCode::Unauthorized));
+				}
+			}
+			let now = Instant::now();
+			if !lobby
+				.federation
+				.merge(addr, &sync, list, &lobby.filter, now)
+			{
+				warn!(
+					"Received own sync from {} - is this lobby its own peer?",
+					addr
+				);
+			}
+			None
+		}
 	}
 }
 
-impl FromLeBytes for u16 {
-	fn from_le_bytes(slice: &[u8]) -> Self {
-		Self::from_le_bytes(<[u8; size_of::<Self>()]>::try_from(slice).unwrap())
+/// Check the optional signature at the end of a packet. `trailing` are the bytes left after
+/// all the fields of the message. `key` is the server or peer the message is for and its
+/// type. A signed message must be newer than the last signed message with the same key.
+///
+/// Returns `None` if the packet is malformed, `Some(Ok(true))` if the packet is properly
+/// signed and `Some(Ok(false))` if it isn't signed.
+fn check_signature(
+	secret: &Option<Secret>,
+	replays: &mut auth::Replays<(SocketAddr, MessageType)>,
+	key: (SocketAddr, MessageType),
+	packet: &[u8],
+	trailing: &[u8],
+) -> Option<Result<bool, ErrorCode>> {
+	match trailing.len() {
+		0 => Some(Ok(false)),
+		SIGNATURE_SIZE => match secret.as_ref().and_then(|s| s.verify_packet(packet)) {
+			Some(t) if replays.check(key, t, auth::unix_millis()) => Some(Ok(true)),
+			Some(_) => {
+				debug!("stale or replayed signature");
+				Some(Err(ErrorCode::Unauthorized))
+			}
+			None => {
+				debug!("invalid signature");
+				Some(Err(ErrorCode::Unauthorized))
+			}
+		},
+		_ => {
+			debug!("invalid packet size {}", packet.len());
+			None
+		}
 	}
 }
 
-fn decode_str<T: FromLeBytes + Into<usize>>(buf: &[u8]) -> Result<&str, ()> {
-	if buf.len() < size_of::<T>() {
-		debug!("missing length or length too short");
-		return Err(());
-	}
-	let (len_bytes, rest) = buf.split_at(size_of::<T>());
-	let len = T::from_le_bytes(len_bytes).into();
-	if rest.len() < len {
-		debug!("length {} is larger than buffer size {}", len, buf.len());
-		return Err(());
+/// Check whether a message may modify the given entry.
+fn authorize(info: &ServerInfo, token: u64, signed: bool) -> Result<(), ErrorCode> {
+	if info.token != token || (info.trusted && !signed) {
+		Err(ErrorCode::Unauthorized)
+	} else {
+		Ok(())
 	}
-	std::str::from_utf8(&rest[..len]).map_err(|e| debug!("invalid utf8 string as name {:?}", e))
 }
 
-fn decode_u16(buf: &[u8]) -> Result<u16, ()> {
-	if let Ok(array) = <[u8; 2]>::try_from(buf) {
-		Ok(u16::from_le_bytes(array))
-	} else {
-		Err(())
-	}
+/// Encode a response packet.
+fn encode(rsp: Response) -> Option<Box<[u8]>> {
+	rsp.to_vec()
+		.map(Vec::into_boxed_slice)
+		.map_err(|e| {
+			error!(
+				"Failed to encode {} response: {}",
+				rsp.message_type().name(),
+				e
+			)
+		})
+		.ok()
 }
 
-fn decode_addr(buf: &[u8]) -> Result<(SocketAddr, &[u8]), ()> {
-	let addr_type = if let Some(v) = buf.get(0) {
-		*v
-	} else {
-		return Err(());
+/// Create an `Error` packet in response to a request of the given (raw) type.
+fn error(request: u8, code: ErrorCode) -> Box<[u8]> {
+	let rsp = Response::Error {
+		request,
+		code,
+		versions: None,
 	};
-	let buf = &buf[1..];
-	let (addr, buf) = if addr_type == 0 {
-		if buf.len() < 6 {
-			debug!("Invalid packet size {}", buf.len());
-			return Err(());
-		}
-		(
-			IpAddr::V4(Ipv4Addr::new(buf[0], buf[1], buf[2], buf[3])),
-			&buf[4..],
-		)
-	} else {
-		if buf.len() < 18 {
-			debug!("Invalid packet size {}", buf.len());
-			return Err(());
-		}
-		let mut s = [0; 8];
-		for (i, n) in s.iter_mut().enumerate() {
-			*n = decode_u16(&buf[i * 2..i * 2 + 1]).unwrap();
-		}
-		(
-			IpAddr::V6(Ipv6Addr::new(
-				s[0], s[1], s[2], s[3], s[4], s[5], s[6], s[7],
-			)),
-			&buf[16..],
-		)
+	encode(rsp).expect("error responses always fit")
+}
+
+/// Create an `Error` packet for a request sent with an unsupported protocol version.
+fn unsupported_version(request: u8, min_version: u8) -> Box<[u8]> {
+	let rsp = Response::Error {
+		request,
+		code: ErrorCode::UnsupportedVersion,
+		versions: Some((min_version, PROTOCOL_VERSION)),
 	};
-	Ok((
-		SocketAddr::new(addr, decode_u16(&buf[..2]).unwrap()),
-		&buf[2..],
-	))
+	encode(rsp).expect("error responses always fit")
 }
 
-fn encode_addr(addr: SocketAddr, buf: &mut Vec<u8>) {
-	match addr.ip() {
-		IpAddr::V4(v) => {
-			buf.push(0);
-			buf.extend(&v.octets()[..])
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	pub(crate) fn server(last_ping: Instant) -> ServerInfo {
+		ServerInfo {
+			name: "".into(),
+			map: "".into(),
+			max_players: 0,
+			description: "".into(),
+			last_ping,
+			manager_port: 0,
+			version: PROTOCOL_VERSION,
+			status: MatchStatus {
+				players: 0,
+				phase: MatchPhase::Warmup,
+				elapsed: 0,
+			},
+			status_reported: last_ping,
+			token: 1,
+			trusted: false,
+			verified: false,
+			restored: false,
+			replicated: false,
 		}
-		IpAddr::V6(v) => {
-			buf.push(1);
-			for s in &v.segments() {
-				buf.extend(&s.to_le_bytes())
-			}
+	}
+
+	#[test]
+	fn expiry_is_scheduled() {
+		let config = Config {
+			stats_interval: Duration::ZERO,
+			..Default::default()
+		};
+		let mut lobby = Lobby::new(config, None);
+		let t = lobby.last_cleanup;
+		let expiry = lobby.config.expiry;
+		let a = SocketAddr::from(([1, 2, 3, 4], 5));
+		let b = SocketAddr::from(([1, 2, 3, 4], 6));
+		lobby.list.insert(a, server(t));
+		lobby.list.insert(b, server(t + Duration::from_secs(5)));
+
+		assert_eq!(lobby.next_deadline(), t + expiry);
+		lobby.remove_expired(t + expiry - Duration::from_millis(1));
+		assert_eq!(lobby.list.len(), 2);
+		lobby.remove_expired(t + expiry);
+		assert!(!lobby.list.contains_key(&a));
+		assert_eq!(lobby.next_deadline(), t + expiry + Duration::from_secs(5));
+		assert_eq!(lobby.stats.expired, 1);
+	}
+
+	#[test]
+	fn legacy_packet_is_unsupported() {
+		let mut lobby = Lobby::new(Config::default(), None);
+		let addr = SocketAddr::from(([1, 2, 3, 4], 5));
+		// A `RegisterServer` of a client that predates versioning: type, port and name
+		let packet = [1, 0x2f, 0x9c, 1, b'a'];
+		let rsp = parse_packet(&mut lobby, addr, &packet, &[]).unwrap();
+		let (header, payload) = Header::decode(&rsp).unwrap();
+		assert_eq!(header, Header::new(MessageType::Error));
+		assert_eq!(
+			Response::decode(MessageType::Error, payload),
+			Ok(Response::Error {
+				request: 1,
+				code: ErrorCode::UnsupportedVersion,
+				versions: Some((MIN_PROTOCOL_VERSION, PROTOCOL_VERSION)),
+			})
+		);
+		assert!(lobby.list.is_empty());
+	}
+
+	#[test]
+	fn replayed_signature_is_rejected() {
+		let secret = Secret::new("secret");
+		let mut lobby = Lobby::new(Config::default(), Some(Secret::new("secret")));
+		let addr = SocketAddr::from(([1, 2, 3, 4], 5));
+		let mut info = server(Instant::now());
+		info.trusted = true;
+		lobby.list.insert(addr, info);
+
+		let mut packet = Request::RemoveServer {
+			port: addr.port(),
+			token: 1,
 		}
+		.to_vec()
+		.unwrap();
+		secret.sign_packet(&mut packet, auth::unix_millis());
+		assert_eq!(parse_packet(&mut lobby, addr, &packet, &[]), None);
+		assert!(lobby.list.is_empty());
+
+		lobby.list.insert(addr, server(Instant::now()));
+		let rsp = parse_packet(&mut lobby, addr, &packet, &[]).unwrap();
+		let (_, payload) = Header::decode(&rsp).unwrap();
+		assert_eq!(
+			Response::decode(MessageType::Error, payload),
+			Ok(Response::Error {
+				request: MessageType::RemoveServer as u8,
+				code: ErrorCode::Unauthorized,
+				versions: None,
+			})
+		);
+		assert!(lobby.list.contains_key(&addr));
+	}
+
+	#[test]
+	fn verify_address() {
+		let mut lobby = Lobby::new(Config::default(), None);
+		let addr = SocketAddr::from(([1, 2, 3, 4], 5));
+		let spoofed = SocketAddr::from(([1, 2, 3, 4], 6));
+		let verify = |lobby: &mut Lobby, addr, cookie| {
+			let packet = Request::Verify { cookie }.to_vec().unwrap();
+			let rsp = parse_packet(lobby, addr, &packet, &[]).unwrap();
+			let (_, payload) = Header::decode(&rsp).unwrap();
+			match Response::decode(MessageType::Verify, payload) {
+				Ok(Response::Cookie { cookie }) => cookie,
+				r => panic!("unexpected {:?}", r),
+			}
+		};
+
+		let enqueue = |lobby: &mut Lobby| {
+			let prefs = own_war_lobby_protocol::Preferences {
+				map: "m",
+				team_size: 1,
+				region: "",
+			};
+			let packet = Request::Enqueue(prefs).to_vec().unwrap();
+			let rsp = parse_packet(lobby, addr, &packet, &[]).unwrap();
+			let (header, payload) = Header::decode(&rsp).unwrap();
+			let typ = MessageType::try_from(header.message_type).unwrap();
+			match Response::decode(typ, payload) {
+				Ok(Response::Enqueued { waiting }) => Ok(waiting),
+				Ok(Response::Error { code, .. }) => Err(code),
+				r => panic!("unexpected {:?}", r),
+			}
+		};
+
+		let cookie = verify(&mut lobby, addr, 0);
+		let now = Instant::now();
+		assert!(!lobby.is_verified(addr, now));
+		assert_eq!(enqueue(&mut lobby), Err(ErrorCode::Unauthorized));
+		assert_ne!(verify(&mut lobby, spoofed, cookie), cookie);
+		assert_eq!(verify(&mut lobby, addr, cookie), cookie);
+		let now = Instant::now();
+		assert_eq!(lobby.budget(addr, now), usize::MAX);
+		assert_eq!(lobby.budget(spoofed, now), 0);
+		assert_eq!(enqueue(&mut lobby), Ok(1));
+
+		// Packets that aren't responses are subject to the same budget.
+		lobby.send(&[], &[0; 64], spoofed, now);
+		assert_eq!(lobby.stats.truncated, 1);
 	}
-	buf.extend(&addr.port().to_le_bytes());
 }
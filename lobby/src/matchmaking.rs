@@ -0,0 +1,243 @@
+//! Matchmaking of queued clients onto servers that advertise their free slots.
+//!
+//! Clients enqueue with their [`Preferences`] and must resend `Enqueue` to stay in the queue.
+//! Servers opt in by sending `Advertise` with their team size and region. Queued clients are
+//! assigned to the server with the most players that is compatible and has free slots, so
+//! small player populations end up in the same match. An empty server only gets clients once
+//! enough of them are waiting, unless a client has been waiting for a long time.
+
+use crate::ServerInfo;
+use own_war_lobby_protocol::{MatchPhase, Preferences};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// How long a client stays in the queue without resending `Enqueue`.
+const TICKET_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long an assigned slot is counted as taken. Servers report their player count only
+/// every few seconds.
+const RESERVATION_TIME: Duration = Duration::from_secs(20);
+
+/// How often the queue is matched against the servers.
+pub const MATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The maximum amount of queued clients, so spoofed requests can't exhaust memory.
+const MAX_TICKETS: usize = 4096;
+
+/// Owned [`Preferences`].
+struct Ticket {
+	map: Box<str>,
+	team_size: u8,
+	region: Box<str>,
+	enqueued: Instant,
+	last_seen: Instant,
+}
+
+/// The matchmaking details of a server.
+struct Offer {
+	team_size: u8,
+	region: Box<str>,
+}
+
+pub struct Matchmaker {
+	tickets: BTreeMap<SocketAddr, Ticket>,
+	offers: HashMap<SocketAddr, Offer>,
+	/// Servers clients were recently assigned to.
+	reserved: Vec<(SocketAddr, Instant)>,
+	pub last_run: Instant,
+}
+
+/// Whether two preferences don't contradict each other. Empty values match anything.
+fn compatible<T: PartialEq + Default>(a: &T, b: &T) -> bool {
+	*a == T::default() || *b == T::default() || a == b
+}
+
+impl Ticket {
+	fn accepts(&self, info: &ServerInfo, offer: &Offer) -> bool {
+		(self.map.is_empty() || self.map == info.map)
+			&& compatible(&self.team_size, &offer.team_size)
+			&& (self.region.is_empty() || self.region.eq_ignore_ascii_case(&offer.region))
+	}
+}
+
+impl Matchmaker {
+	pub fn new(now: Instant) -> Self {
+		Self {
+			tickets: BTreeMap::new(),
+			offers: HashMap::new(),
+			reserved: Vec::new(),
+			last_run: now,
+		}
+	}
+
+	pub fn queued(&self) -> usize {
+		self.tickets.len()
+	}
+
+	/// Offer the free slots of a server.
+	pub fn advertise(&mut self, server: SocketAddr, team_size: u8, region: &str) {
+		let offer = Offer {
+			team_size,
+			region: region.into(),
+		};
+		self.offers.insert(server, offer);
+	}
+
+	/// Add or refresh a client in the queue. Returns how many clients with compatible
+	/// preferences are waiting, including this one, or `None` if the queue is full.
+	pub fn enqueue(
+		&mut self,
+		client: SocketAddr,
+		prefs: &Preferences,
+		now: Instant,
+	) -> Option<usize> {
+		if self.tickets.len() >= MAX_TICKETS && !self.tickets.contains_key(&client) {
+			return None;
+		}
+		let enqueued = self.tickets.get(&client).map_or(now, |t| t.enqueued);
+		let ticket = Ticket {
+			map: prefs.map.into(),
+			team_size: prefs.team_size,
+			region: prefs.region.into(),
+			enqueued,
+			last_seen: now,
+		};
+		let waiting = self
+			.tickets
+			.values()
+			.filter(|t| {
+				compatible(&t.map, &ticket.map)
+					&& compatible(&t.team_size, &ticket.team_size)
+					&& compatible(&t.region.to_lowercase(), &ticket.region.to_lowercase())
+			})
+			.count();
+		let new = self.tickets.insert(client, ticket).is_none();
+		Some(waiting + usize::from(new))
+	}
+
+	pub fn dequeue(&mut self, client: SocketAddr) -> bool {
+		self.tickets.remove(&client).is_some()
+	}
+
+	/// Assign queued clients to servers. `min_players` is the amount of clients needed to
+	/// fill an empty server, unless one of them waited at least `max_wait`.
+	pub fn run(
+		&mut self,
+		list: &BTreeMap<SocketAddr, ServerInfo>,
+		min_players: usize,
+		max_wait: Duration,
+		now: Instant,
+	) -> Vec<(SocketAddr, SocketAddr)> {
+		self.last_run = now;
+		self.tickets
+			.retain(|_, t| now < t.last_seen + TICKET_TIMEOUT);
+		self.offers.retain(|addr, _| list.contains_key(addr));
+		self.reserved.retain(|(_, t)| now < *t + RESERVATION_TIME);
+		let mut matches = Vec::new();
+		if self.tickets.is_empty() {
+			return matches;
+		}
+
+		let reserved = |server| self.reserved.iter().filter(|(s, _)| *s == server).count();
+		// Fill the servers with the most players first.
+		let mut servers = self
+			.offers
+			.iter()
+			.filter_map(|(addr, offer)| {
+				let info = &list[addr];
+				let players = usize::from(info.status.players) + reserved(*addr);
+				let free = usize::from(info.max_players).saturating_sub(players);
+				let open = info.status.phase != MatchPhase::Ending && free > 0;
+				open.then_some((*addr, info, offer, players, free))
+			})
+			.collect::<Vec<_>>();
+		servers.sort_by_key(|(addr, _, _, players, _)| (usize::MAX - players, *addr));
+
+		for (server, info, offer, players, free) in servers {
+			let mut candidates = self
+				.tickets
+				.iter()
+				.filter(|(_, t)| t.accepts(info, offer))
+				.collect::<Vec<_>>();
+			candidates.sort_by_key(|(addr, t)| (t.enqueued, **addr));
+			candidates.truncate(free);
+			let waited_long = candidates
+				.first()
+				.is_some_and(|(_, t)| now >= t.enqueued + max_wait);
+			if candidates.is_empty()
+				|| (players == 0 && candidates.len() < min_players && !waited_long)
+			{
+				continue;
+			}
+			let clients = candidates.iter().map(|(a, _)| **a).collect::<Vec<_>>();
+			for client in clients {
+				self.tickets.remove(&client);
+				self.reserved.push((server, now));
+				matches.push((client, server));
+			}
+		}
+		matches
+	}
+
+	/// When the queue should be matched next.
+	pub fn next_deadline(&self) -> Option<Instant> {
+		(!self.tickets.is_empty()).then(|| self.last_run + MATCH_INTERVAL)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::test::server;
+
+	#[test]
+	fn groups_players() {
+		let t = Instant::now();
+		let s = Duration::from_secs;
+		let addr = |n| SocketAddr::from(([10, 0, 0, n], 1));
+		let (empty, busy, other) = (addr(1), addr(2), addr(3));
+		let mut list = BTreeMap::new();
+		for a in [empty, busy, other] {
+			let mut info = server(t);
+			info.max_players = 4;
+			info.map = "m".into();
+			list.insert(a, info);
+		}
+		list.get_mut(&busy).unwrap().status.players = 3;
+		let mut mm = Matchmaker::new(t);
+		mm.advertise(empty, 2, "eu");
+		mm.advertise(busy, 2, "eu");
+		mm.advertise(other, 2, "us");
+
+		let prefs = Preferences {
+			map: "m",
+			team_size: 0,
+			region: "EU",
+		};
+		let (a, b, c) = (addr(10), addr(11), addr(12));
+		assert_eq!(mm.enqueue(a, &prefs, t), Some(1));
+		assert_eq!(mm.enqueue(b, &prefs, t), Some(2));
+		assert_eq!(mm.enqueue(c, &prefs, t + s(1)), Some(3));
+
+		// The busy server has one slot left, which goes to the client that waited longest.
+		// The other two are enough to fill the empty server.
+		let matches = mm.run(&list, 2, s(30), t + s(1));
+		assert_eq!(matches, [(a, busy), (b, empty), (c, empty)]);
+		assert_eq!(mm.queued(), 0);
+
+		// A lone client waits for others once the reservations expired, but not forever.
+		let d = addr(13);
+		list.get_mut(&busy).unwrap().status.players = 4;
+		mm.enqueue(d, &prefs, t + s(25));
+		assert!(mm.run(&list, 3, s(30), t + s(26)).is_empty());
+		mm.enqueue(d, &prefs, t + s(54));
+		assert_eq!(mm.run(&list, 3, s(30), t + s(55)), [(d, empty)]);
+
+		// Clients that stop resending are dropped.
+		mm.enqueue(d, &prefs, t);
+		mm.run(&list, 3, s(30), t + TICKET_TIMEOUT);
+		assert_eq!(mm.queued(), 0);
+		assert!(mm.next_deadline().is_none());
+	}
+}
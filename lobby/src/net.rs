@@ -0,0 +1,134 @@
+//! Helpers for dealing with both IPv4 and IPv6 sockets.
+
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+/// Bind a UDP socket. IPv6 sockets are dual-stack, i.e. they also accept IPv4 packets.
+pub fn bind(addr: SocketAddr) -> io::Result<UdpSocket> {
+	let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+	if addr.is_ipv6() {
+		socket.set_only_v6(false)?;
+	}
+	socket.bind(&addr.into())?;
+	Ok(socket.into())
+}
+
+/// Bind a UDP socket like [`bind`], but bind the unspecified IPv4 address instead of the
+/// unspecified IPv6 address if IPv6 is disabled on this host.
+pub fn bind_or_ipv4(addr: SocketAddr) -> io::Result<UdpSocket> {
+	match (bind(addr), ipv4_fallback(addr)) {
+		(Err(e), Some(v4)) => {
+			warn!(
+				"Failed to bind to {}: {} - binding to {} instead",
+				addr, e, v4
+			);
+			bind(v4)
+		}
+		(r, _) => r,
+	}
+}
+
+/// The IPv4 address to bind if `addr` can't be bound because IPv6 is disabled.
+fn ipv4_fallback(addr: SocketAddr) -> Option<SocketAddr> {
+	(addr.ip() == IpAddr::V6(Ipv6Addr::UNSPECIFIED))
+		.then(|| SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), addr.port()))
+}
+
+/// Convert IPv4-mapped IPv6 addresses, as received on dual-stack sockets, to plain IPv4
+/// addresses.
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+	match addr {
+		SocketAddr::V6(a) => match a.ip().to_ipv4_mapped() {
+			Some(ip) => SocketAddr::new(ip.into(), a.port()),
+			None => addr,
+		},
+		SocketAddr::V4(_) => addr,
+	}
+}
+
+/// Send a packet using the first socket that can reach the given address. IPv4 addresses
+/// can also be reached through dual-stack IPv6 sockets.
+pub fn send_to(sockets: &[UdpSocket], buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+	let local = |s: &UdpSocket| s.local_addr().ok();
+	if let Some(s) = sockets
+		.iter()
+		.find(|s| local(s).is_some_and(|l| l.is_ipv4() == addr.is_ipv4()))
+	{
+		return s.send_to(buf, addr);
+	}
+	match (
+		sockets
+			.iter()
+			.find(|s| local(s).is_some_and(|l| l.is_ipv6())),
+		addr,
+	) {
+		(Some(s), SocketAddr::V4(a)) => s.send_to(
+			buf,
+			SocketAddr::new(a.ip().to_ipv6_mapped().into(), a.port()),
+		),
+		_ => Err(io::Error::new(
+			io::ErrorKind::AddrNotAvailable,
+			"no socket for this address family",
+		)),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::net::Ipv6Addr;
+
+	#[test]
+	fn canonical_mapped() {
+		let v4 = SocketAddr::from(([192, 168, 1, 2], 39983));
+		let mapped = SocketAddr::new(
+			Ipv6Addr::from([0, 0, 0, 0, 0, 0xffff, 0xc0a8, 0x0102]).into(),
+			39983,
+		);
+		assert_eq!(canonical(mapped), v4);
+		assert_eq!(canonical(v4), v4);
+		let v6 = SocketAddr::new(
+			Ipv6Addr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]).into(),
+			39983,
+		);
+		assert_eq!(canonical(v6), v6);
+	}
+
+	#[test]
+	fn fallback_to_ipv4() {
+		let any = SocketAddr::from((Ipv6Addr::UNSPECIFIED, 39984));
+		let v4 = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 39984));
+		assert_eq!(ipv4_fallback(any), Some(v4));
+		assert_eq!(ipv4_fallback(v4), None);
+		assert_eq!(
+			ipv4_fallback(SocketAddr::from((Ipv6Addr::LOCALHOST, 1))),
+			None
+		);
+		assert!(bind_or_ipv4("127.0.0.1:0".parse().unwrap()).is_ok());
+	}
+
+	#[test]
+	fn dual_stack() {
+		// IPv6 may be unavailable in some environments.
+		let server = match bind((Ipv6Addr::UNSPECIFIED, 0).into()) {
+			Ok(s) => s,
+			Err(_) => return,
+		};
+		let timeout = Some(std::time::Duration::from_secs(1));
+		server.set_read_timeout(timeout).unwrap();
+		let port = server.local_addr().unwrap().port();
+		let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+		client.set_read_timeout(timeout).unwrap();
+
+		client.send_to(b"ping", ("127.0.0.1", port)).unwrap();
+		let mut buf = [0; 4];
+		let (_, from) = server.recv_from(&mut buf).unwrap();
+		let from = canonical(from);
+		assert_eq!(from, client.local_addr().unwrap());
+
+		send_to(&[server], b"pong", from).unwrap();
+		client.recv_from(&mut buf).unwrap();
+		assert_eq!(&buf, b"pong");
+	}
+}
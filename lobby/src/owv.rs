@@ -0,0 +1,281 @@
+//! Validation of uploaded `.owv` vehicles.
+//!
+//! This follows the rules of `editor::serialize::load` in the game, so every vehicle in the
+//! repository can be opened by the editor. Whether block IDs exist isn't checked as the lobby
+//! doesn't know the blocks of the game. Errors the editor only logs, like overlapping blocks
+//! or colors that don't exist, are rejected too, so a downloaded vehicle looks exactly like
+//! the uploaded one.
+
+use own_war_lobby_protocol::codec::Reader;
+use own_war_lobby_protocol::DecodeError;
+use std::collections::HashSet;
+use std::fmt;
+
+const MAGIC: u32 = 493279249;
+
+/// The highest valid rotation.
+const MAX_ROTATION: u8 = 23;
+
+/// The attributes of revision 1. Others are invalid.
+const LAYER_NAMES: u8 = 0;
+const MAX_ATTRIBUTE: u8 = 3;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum OwvError {
+	BadMagic,
+	UnknownRevision(u16),
+	Decode(DecodeError),
+	InvalidBlockId,
+	InvalidRotation(u8),
+	InvalidColor(u8),
+	InvalidAttribute(u8),
+	PositionAlreadyOccupied,
+	NoBlocks,
+}
+
+impl fmt::Display for OwvError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::BadMagic => "bad magic".fmt(f),
+			Self::UnknownRevision(r) => write!(f, "unknown revision {}", r),
+			Self::Decode(e) => e.fmt(f),
+			Self::InvalidBlockId => "invalid block ID".fmt(f),
+			Self::InvalidRotation(r) => write!(f, "invalid rotation {}", r),
+			Self::InvalidColor(c) => write!(f, "invalid color {}", c),
+			Self::InvalidAttribute(a) => write!(f, "invalid attribute {}", a),
+			Self::PositionAlreadyOccupied => "position already occupied".fmt(f),
+			Self::NoBlocks => "no blocks".fmt(f),
+		}
+	}
+}
+
+impl From<DecodeError> for OwvError {
+	fn from(e: DecodeError) -> Self {
+		Self::Decode(e)
+	}
+}
+
+/// What the repository needs to know about a valid vehicle.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Summary<'a> {
+	pub name: &'a str,
+	pub blocks: usize,
+}
+
+/// Check whether the data is a vehicle the editor can load.
+pub fn validate(data: &[u8]) -> Result<Summary<'_>, OwvError> {
+	let mut r = Reader::new(data);
+	if r.u32()? != MAGIC {
+		return Err(OwvError::BadMagic);
+	}
+	match r.u16()? {
+		0 => rev_0(r),
+		1 => rev_1(r),
+		rev => Err(OwvError::UnknownRevision(rev)),
+	}
+}
+
+fn rotation(r: &mut Reader) -> Result<u8, OwvError> {
+	match r.u8()? {
+		n if n <= MAX_ROTATION => Ok(n),
+		n => Err(OwvError::InvalidRotation(n)),
+	}
+}
+
+fn rev_1(mut r: Reader) -> Result<Summary, OwvError> {
+	let (_block_data_size, _editor_data_size) = (r.u32()?, r.u32()?);
+	let name = r.str8()?;
+	let (color_count, layer_count) = (r.u8()?, r.u8()?);
+	r.bytes(usize::from(color_count) * 3)?;
+
+	let mut occupied = HashSet::new();
+	for _ in 0..layer_count {
+		let start = (r.u8()?, r.u8()?, r.u8()?);
+		let end = (r.u8()?, r.u8()?, r.u8()?);
+		for x in start.0..=end.0 {
+			for y in start.1..=end.1 {
+				for z in start.2..=end.2 {
+					if r.u16()? == 0 {
+						continue;
+					}
+					rotation(&mut r)?;
+					let color = r.u8()?;
+					if color >= color_count {
+						return Err(OwvError::InvalidColor(color));
+					}
+					if !occupied.insert((x, y, z)) {
+						return Err(OwvError::PositionAlreadyOccupied);
+					}
+				}
+			}
+		}
+	}
+
+	for _ in 0..r.u8()? {
+		let attribute = r.u8()?;
+		let mut attr = Reader::new(r.bytes16()?);
+		match attribute {
+			LAYER_NAMES => {
+				for _ in 0..layer_count {
+					attr.str8()?;
+				}
+			}
+			a if a <= MAX_ATTRIBUTE => (),
+			a => return Err(OwvError::InvalidAttribute(a)),
+		}
+	}
+
+	match occupied.len() {
+		0 => Err(OwvError::NoBlocks),
+		blocks => Ok(Summary { name, blocks }),
+	}
+}
+
+fn rev_0(mut r: Reader) -> Result<Summary, OwvError> {
+	let mut occupied = HashSet::new();
+	for _ in 0..r.u8()? {
+		let _layer = r.u8()?;
+		let (_start, _end) = (r.bytes(3)?, r.bytes(3)?);
+		for _ in 0..r.u32()? {
+			let position = r.bytes(3)?;
+			if r.u16()? == 0 {
+				return Err(OwvError::InvalidBlockId);
+			}
+			rotation(&mut r)?;
+			let _color = r.bytes(3)?;
+			if !occupied.insert(position) {
+				return Err(OwvError::PositionAlreadyOccupied);
+			}
+		}
+	}
+	match occupied.len() {
+		0 => Err(OwvError::NoBlocks),
+		// This revision didn't store names.
+		blocks => Ok(Summary { name: "", blocks }),
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+	use super::*;
+
+	/// A revision 1 vehicle with a row of blocks along the X axis.
+	pub(crate) fn vehicle(name: &str, blocks: u8) -> Vec<u8> {
+		let mut data = MAGIC.to_le_bytes().to_vec();
+		data.extend(&1u16.to_le_bytes());
+		data.extend(&[0; 8]);
+		data.push(name.len() as u8);
+		data.extend(name.as_bytes());
+		data.extend(&[1, 1, 255, 0, 0]);
+		data.extend(&[0, 0, 0, blocks - 1, 0, 0]);
+		for _ in 0..blocks {
+			data.extend(&[1, 0, 0, 0]);
+		}
+		// The layer names
+		data.extend(&[1, 0, 2, 0, 1, b'l']);
+		data
+	}
+
+	#[test]
+	fn validate() {
+		let data = vehicle("tank", 3);
+		assert_eq!(
+			super::validate(&data),
+			Ok(Summary {
+				name: "tank",
+				blocks: 3
+			})
+		);
+		assert_eq!(
+			super::validate(&data[..data.len() - 1]),
+			Err(OwvError::Decode(DecodeError::Truncated))
+		);
+
+		let mut bad = data.clone();
+		bad[0] ^= 1;
+		assert_eq!(super::validate(&bad), Err(OwvError::BadMagic));
+		let mut bad = data.clone();
+		bad[4] = 2;
+		assert_eq!(super::validate(&bad), Err(OwvError::UnknownRevision(2)));
+		// The rotation and color of the first block
+		let block = 4 + 2 + 8 + 5 + 5 + 6 + 2;
+		let mut bad = data.clone();
+		bad[block] = 24;
+		assert_eq!(super::validate(&bad), Err(OwvError::InvalidRotation(24)));
+		let mut bad = data.clone();
+		bad[block + 1] = 1;
+		assert_eq!(super::validate(&bad), Err(OwvError::InvalidColor(1)));
+		let mut bad = data.clone();
+		bad[data.len() - 5] = 4;
+		assert_eq!(super::validate(&bad), Err(OwvError::InvalidAttribute(4)));
+	}
+
+	#[test]
+	fn validate_rev_0() {
+		let mut data = MAGIC.to_le_bytes().to_vec();
+		data.extend(&0u16.to_le_bytes());
+		data.extend(&[1, 0, 0, 0, 0, 1, 1, 1]);
+		data.extend(&2u32.to_le_bytes());
+		data.extend(&[0, 0, 0, 1, 0, 0, 255, 0, 0]);
+		data.extend(&[1, 0, 0, 1, 0, 0, 255, 0, 0]);
+		assert_eq!(
+			super::validate(&data),
+			Ok(Summary {
+				name: "",
+				blocks: 2
+			})
+		);
+		let (first, second) = (18, 27);
+		let mut bad = data.clone();
+		bad[second] = 0;
+		assert_eq!(
+			super::validate(&bad),
+			Err(OwvError::PositionAlreadyOccupied)
+		);
+		let mut bad = data.clone();
+		bad[first + 3] = 0;
+		assert_eq!(super::validate(&bad), Err(OwvError::InvalidBlockId));
+	}
+
+	#[test]
+	fn overlapping_layers() {
+		// Revision 0, two layers with a block at the origin each
+		let mut data = MAGIC.to_le_bytes().to_vec();
+		data.extend(&0u16.to_le_bytes());
+		data.push(2);
+		for layer in 0..2 {
+			data.extend(&[layer, 0, 0, 0, 0, 0, 0]);
+			data.extend(&1u32.to_le_bytes());
+			data.extend(&[0, 0, 0, 1, 0, 0, 255, 0, 0]);
+		}
+		assert_eq!(
+			super::validate(&data),
+			Err(OwvError::PositionAlreadyOccupied)
+		);
+
+		// Revision 1, the same
+		let mut data = MAGIC.to_le_bytes().to_vec();
+		data.extend(&1u16.to_le_bytes());
+		data.extend(&[0; 8]);
+		data.extend(&[0, 1, 2, 255, 0, 0]);
+		for _ in 0..2 {
+			data.extend(&[0, 0, 0, 0, 0, 0, 1, 0, 0, 0]);
+		}
+		data.extend(&[1, 0, 4, 0, 1, b'a', 1, b'b']);
+		assert_eq!(
+			super::validate(&data),
+			Err(OwvError::PositionAlreadyOccupied)
+		);
+		// Moving the block of the second layer makes it valid.
+		let second = data.len() - 8 - 10;
+		data[second] = 1;
+		data[second + 3] = 1;
+		assert_eq!(
+			super::validate(&data),
+			Ok(Summary {
+				name: "",
+				blocks: 2
+			})
+		);
+	}
+}
@@ -0,0 +1,261 @@
+//! Coordination of hole punches between clients and servers.
+//!
+//! A client sends `PunchRequest` with the address of a server. The lobby creates a session and
+//! sends a `Punch` with the endpoint of the other side to both the client and the manager of
+//! the server. Both sides acknowledge with `PunchAck`. The lobby resends `Punch` to a side
+//! until it acknowledges, and reports the outcome of every session once a side reports a
+//! connection or failure, or when the session times out.
+
+use own_war_lobby_protocol::{PunchResult, Response};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// How long to wait for an acknowledgement before resending `Punch`.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often `Punch` is sent to each side at most.
+const MAX_ATTEMPTS: u8 = 5;
+
+/// How long a session may take before it is considered failed.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// One side of a session.
+struct Side {
+	addr: SocketAddr,
+	/// The endpoint of the other side.
+	peer: SocketAddr,
+	/// The last result reported by this side.
+	result: Option<PunchResult>,
+}
+
+struct Session {
+	client: Side,
+	/// The manager of the server.
+	server: Side,
+	/// The port of the server.
+	port: u16,
+	started: Instant,
+	last_sent: Instant,
+	attempts: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+	/// A side reported a connection.
+	Succeeded,
+	/// A side reported it couldn't connect.
+	Failed,
+	/// Neither side reported anything in time.
+	TimedOut,
+}
+
+impl fmt::Display for Outcome {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Succeeded => "succeeded",
+			Self::Failed => "failed",
+			Self::TimedOut => "timed out",
+		}
+		.fmt(f)
+	}
+}
+
+/// A message to send.
+pub struct Packet {
+	pub to: SocketAddr,
+	pub data: Box<[u8]>,
+}
+
+pub struct Sessions {
+	sessions: HashMap<u64, Session>,
+}
+
+impl Session {
+	fn packet(&self, id: u64, side: &Side) -> Packet {
+		let rsp = Response::Punch {
+			session: id,
+			port: self.port,
+			peer: side.peer,
+		};
+		Packet {
+			to: side.addr,
+			data: rsp.to_vec().expect("punch fits").into_boxed_slice(),
+		}
+	}
+
+	/// The `Punch` messages for the sides that haven't acknowledged yet.
+	fn packets(&self, id: u64) -> Vec<Packet> {
+		[&self.client, &self.server]
+			.iter()
+			.filter(|s| s.result.is_none())
+			.map(|s| self.packet(id, s))
+			.collect()
+	}
+
+	fn outcome(&self) -> Option<Outcome> {
+		let results = [self.client.result, self.server.result];
+		if results.contains(&Some(PunchResult::Connected)) {
+			Some(Outcome::Succeeded)
+		} else if results.contains(&Some(PunchResult::Failed)) {
+			Some(Outcome::Failed)
+		} else {
+			None
+		}
+	}
+}
+
+impl Sessions {
+	pub fn new() -> Self {
+		Self {
+			sessions: HashMap::new(),
+		}
+	}
+
+	/// Start a session between a client and a server, or restart the existing session if the
+	/// client resent its request. `server` is the public address of the server and `manager`
+	/// the address of its manager. Returns the session ID and the packets to send.
+	pub fn start(
+		&mut self,
+		id: u64,
+		client: SocketAddr,
+		server: SocketAddr,
+		manager: SocketAddr,
+		now: Instant,
+	) -> (u64, Vec<Packet>) {
+		let existing = self
+			.sessions
+			.iter_mut()
+			.find(|(_, s)| s.client.addr == client && s.client.peer == server);
+		let (id, session) = match existing {
+			Some((id, s)) => (*id, s),
+			None => {
+				let s = Session {
+					client: Side {
+						addr: client,
+						peer: server,
+						result: None,
+					},
+					server: Side {
+						addr: manager,
+						peer: client,
+						result: None,
+					},
+					port: server.port(),
+					started: now,
+					last_sent: now,
+					attempts: 0,
+				};
+				(id, self.sessions.entry(id).or_insert(s))
+			}
+		};
+		session.last_sent = now;
+		session.attempts = session.attempts.saturating_add(1);
+		(id, session.packets(id))
+	}
+
+	/// Process an acknowledgement. Returns the session's endpoints and outcome if the session
+	/// is finished.
+	pub fn ack(
+		&mut self,
+		id: u64,
+		from: SocketAddr,
+		result: PunchResult,
+	) -> Result<Option<(SocketAddr, SocketAddr, Outcome)>, ()> {
+		let session = self.sessions.get_mut(&id).ok_or(())?;
+		let side = if session.client.addr == from {
+			&mut session.client
+		} else if session.server.addr == from {
+			&mut session.server
+		} else {
+			return Err(());
+		};
+		// Don't let a late `Received` overwrite a more useful result.
+		if side.result.is_none() || result != PunchResult::Received {
+			side.result = Some(result);
+		}
+		Ok(session.outcome().map(|o| {
+			let s = self.sessions.remove(&id).unwrap();
+			(s.client.addr, s.client.peer, o)
+		}))
+	}
+
+	/// Resend `Punch` to sides that haven't acknowledged and remove sessions that timed out.
+	pub fn retransmit(
+		&mut self,
+		now: Instant,
+		mut timed_out: impl FnMut(SocketAddr, SocketAddr),
+	) -> Vec<Packet> {
+		self.sessions.retain(|_, s| {
+			let keep = now < s.started + TIMEOUT;
+			if !keep {
+				timed_out(s.client.addr, s.client.peer);
+			}
+			keep
+		});
+		let mut packets = Vec::new();
+		for (id, s) in self.sessions.iter_mut() {
+			if s.attempts < MAX_ATTEMPTS && now >= s.last_sent + RETRY_INTERVAL {
+				s.last_sent = now;
+				s.attempts += 1;
+				packets.extend(s.packets(*id));
+			}
+		}
+		packets
+	}
+
+	/// When the next packet should be resent or session should time out.
+	pub fn next_deadline(&self) -> Option<Instant> {
+		self.sessions
+			.values()
+			.map(|s| {
+				let timeout = s.started + TIMEOUT;
+				let pending = s.client.result.is_none() || s.server.result.is_none();
+				if pending && s.attempts < MAX_ATTEMPTS {
+					timeout.min(s.last_sent + RETRY_INTERVAL)
+				} else {
+					timeout
+				}
+			})
+			.min()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn session() {
+		let t = Instant::now();
+		let client = SocketAddr::from(([1, 1, 1, 1], 1));
+		let server = SocketAddr::from(([2, 2, 2, 2], 2));
+		let manager = SocketAddr::from(([2, 2, 2, 2], 3));
+		let mut s = Sessions::new();
+
+		let (id, packets) = s.start(7, client, server, manager, t);
+		assert_eq!(id, 7);
+		let to = packets.iter().map(|p| p.to).collect::<Vec<_>>();
+		assert_eq!(to, [client, manager]);
+		// A resent request joins the existing session.
+		assert_eq!(s.start(8, client, server, manager, t).0, 7);
+
+		assert_eq!(s.ack(7, server, PunchResult::Received), Err(()));
+		assert_eq!(s.ack(7, manager, PunchResult::Received), Ok(None));
+		let packets = s.retransmit(t + RETRY_INTERVAL, |_, _| panic!());
+		assert_eq!(packets.len(), 1);
+		assert_eq!(packets[0].to, client);
+		assert_eq!(
+			s.ack(7, client, PunchResult::Connected),
+			Ok(Some((client, server, Outcome::Succeeded)))
+		);
+		assert!(s.next_deadline().is_none());
+
+		s.start(9, client, server, manager, t);
+		let mut timed_out = Vec::new();
+		s.retransmit(t + TIMEOUT, |c, s| timed_out.push((c, s)));
+		assert_eq!(timed_out, [(client, server)]);
+		assert_eq!(s.ack(9, client, PunchResult::Connected), Err(()));
+	}
+}
@@ -0,0 +1,409 @@
+//! Shared vehicles, uploaded and downloaded in chunks.
+//!
+//! Every vehicle is stored as `<ID>.owv` in the vehicle directory, with its tags in
+//! `<ID>.tags`. The ID is a hash of the file, so uploading the same vehicle twice yields the
+//! same ID. The index of names and tags is kept in memory and rebuilt from the directory on
+//! startup.
+//!
+//! Uploads are sent in chunks of at most [`VEHICLE_CHUNK_SIZE`] bytes. Each chunk must start
+//! where the previous one ended, so a client simply resends the chunk at the offset in the
+//! last acknowledgement until the upload is complete.
+
+use crate::owv;
+use own_war_lobby_protocol::{ErrorCode, Upload, VehicleEntry, VEHICLE_CHUNK_SIZE};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::net::SocketAddr;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// The maximum size of a vehicle.
+pub const MAX_SIZE: u32 = 1 << 20;
+
+/// How long an upload is kept without receiving a chunk.
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The maximum amount of uploads in progress, so spoofed uploads can't exhaust memory.
+const MAX_UPLOADS: usize = 64;
+
+const MAX_TAGS: usize = 8;
+const MAX_TAG_LEN: usize = 24;
+
+struct Entry {
+	name: Box<str>,
+	/// Lowercase and space separated.
+	tags: Box<str>,
+	size: u32,
+}
+
+/// An upload in progress.
+struct Pending {
+	data: Vec<u8>,
+	total: u32,
+	last_seen: Instant,
+}
+
+pub struct Repository {
+	dir: PathBuf,
+	pub max_vehicles: usize,
+	vehicles: BTreeMap<u64, Entry>,
+	/// The IDs of the vehicles with each tag.
+	tags: HashMap<Box<str>, BTreeSet<u64>>,
+	uploads: HashMap<SocketAddr, Pending>,
+}
+
+/// The state of an upload after receiving a chunk.
+pub enum Progress {
+	/// The next chunk should start at the given offset.
+	Receiving(u32),
+	/// All chunks have been received.
+	Complete(Vec<u8>),
+}
+
+#[derive(Debug)]
+pub struct RepositoryError(PathBuf, io::Error);
+
+impl fmt::Display for RepositoryError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "failed to read '{}': {}", self.0.display(), self.1)
+	}
+}
+
+/// The ID of a vehicle, which is the FNV-1a hash of the data. 0 is never used so it can be
+/// the first cursor of a search.
+fn id_of(data: &[u8]) -> u64 {
+	let hash = data.iter().fold(0xcbf29ce484222325, |h: u64, b| {
+		(h ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+	});
+	hash.max(1)
+}
+
+/// Normalize user supplied tags. Tags that are too long and any tags past the limit are
+/// dropped.
+fn normalize_tags(tags: &str) -> String {
+	let mut list = tags
+		.split_whitespace()
+		.filter(|t| t.len() <= MAX_TAG_LEN)
+		.map(str::to_lowercase)
+		.collect::<Vec<_>>();
+	list.sort();
+	list.dedup();
+	list.truncate(MAX_TAGS);
+	list.join(" ")
+}
+
+impl Repository {
+	/// Open a vehicle directory, creating it if it doesn't exist. Invalid files are skipped.
+	pub fn open(dir: &Path, max_vehicles: usize) -> Result<Self, RepositoryError> {
+		let error = |e| RepositoryError(dir.into(), e);
+		fs::create_dir_all(dir).map_err(error)?;
+		let mut repo = Self {
+			dir: dir.into(),
+			max_vehicles,
+			vehicles: BTreeMap::new(),
+			tags: HashMap::new(),
+			uploads: HashMap::new(),
+		};
+		for file in fs::read_dir(dir).map_err(error)? {
+			let path = file.map_err(error)?.path();
+			let id = match path
+				.extension()
+				.filter(|e| *e == "owv")
+				.and(path.file_stem())
+			{
+				Some(stem) => stem.to_str().and_then(|s| u64::from_str_radix(s, 16).ok()),
+				None => continue,
+			};
+			let data = match fs::read(&path) {
+				Ok(data) => data,
+				Err(e) => {
+					warn!("Failed to read '{}': {}", path.display(), e);
+					continue;
+				}
+			};
+			let name = match owv::validate(&data) {
+				Ok(summary) if id == Some(id_of(&data)) => summary.name,
+				Ok(_) => {
+					warn!(
+						"Skipping '{}' - contents don't match the ID",
+						path.display()
+					);
+					continue;
+				}
+				Err(e) => {
+					warn!("Skipping '{}' - {}", path.display(), e);
+					continue;
+				}
+			};
+			let tags = fs::read_to_string(path.with_extension("tags")).unwrap_or_default();
+			repo.index(id_of(&data), name, &data, &normalize_tags(&tags));
+		}
+		Ok(repo)
+	}
+
+	pub fn len(&self) -> usize {
+		self.vehicles.len()
+	}
+
+	pub fn uploads(&self) -> usize {
+		self.uploads.len()
+	}
+
+	fn path(&self, id: u64, extension: &str) -> PathBuf {
+		self.dir.join(format!("{:016x}.{}", id, extension))
+	}
+
+	fn index(&mut self, id: u64, name: &str, data: &[u8], tags: &str) {
+		for tag in tags.split_whitespace() {
+			self.tags.entry(tag.into()).or_default().insert(id);
+		}
+		let entry = Entry {
+			name: name.into(),
+			tags: tags.into(),
+			size: data.len() as u32,
+		};
+		self.vehicles.insert(id, entry);
+	}
+
+	/// Add a chunk to the upload of a host.
+	pub fn receive(
+		&mut self,
+		from: SocketAddr,
+		chunk: &Upload,
+		now: Instant,
+	) -> Result<Progress, ErrorCode> {
+		if chunk.total > MAX_SIZE || chunk.data.len() > VEHICLE_CHUNK_SIZE {
+			self.uploads.remove(&from);
+			return Err(ErrorCode::InvalidVehicle);
+		}
+		if chunk.offset == 0 && self.uploads.get(&from).map(|u| u.total) != Some(chunk.total) {
+			if self.uploads.len() >= MAX_UPLOADS && !self.uploads.contains_key(&from) {
+				return Err(ErrorCode::StorageFull);
+			}
+			let pending = Pending {
+				data: Vec::new(),
+				total: chunk.total,
+				last_seen: now,
+			};
+			self.uploads.insert(from, pending);
+		}
+		// Chunks after the first may arrive after the upload expired.
+		let upload = self.uploads.get_mut(&from).ok_or(ErrorCode::NotFound)?;
+		if upload.total != chunk.total {
+			return Err(ErrorCode::InvalidVehicle);
+		}
+		upload.last_seen = now;
+		let received = upload.data.len() as u32;
+		// Old or early chunks are answered with the offset of the chunk that is expected.
+		if chunk.offset == received {
+			let end = received as usize + chunk.data.len();
+			if end > chunk.total as usize {
+				self.uploads.remove(&from);
+				return Err(ErrorCode::InvalidVehicle);
+			}
+			upload.data.extend(chunk.data);
+		}
+		if upload.data.len() as u32 == upload.total {
+			let upload = self.uploads.remove(&from).unwrap();
+			return Ok(Progress::Complete(upload.data));
+		}
+		Ok(Progress::Receiving(upload.data.len() as u32))
+	}
+
+	/// Store a validated vehicle. Returns the ID, which may be that of an existing vehicle.
+	pub fn insert(&mut self, data: &[u8], name: &str, tags: &str) -> Result<u64, ErrorCode> {
+		let id = id_of(data);
+		if self.vehicles.contains_key(&id) {
+			return Ok(id);
+		}
+		if self.vehicles.len() >= self.max_vehicles {
+			return Err(ErrorCode::StorageFull);
+		}
+		let tags = normalize_tags(tags);
+		let write = || {
+			// The tags go first so a vehicle is never indexed without them after a crash.
+			fs::write(self.path(id, "tags"), &tags)?;
+			let tmp = self.path(id, "tmp");
+			fs::write(&tmp, data)?;
+			fs::rename(&tmp, self.path(id, "owv"))
+		};
+		if let Err(e) = write() {
+			error!("Failed to store vehicle {:016x}: {}", id, e);
+			return Err(ErrorCode::StorageFull);
+		}
+		self.index(id, name, data, &tags);
+		Ok(id)
+	}
+
+	/// Remove a vehicle. Returns whether it existed.
+	pub fn remove(&mut self, id: u64) -> io::Result<bool> {
+		let entry = match self.vehicles.remove(&id) {
+			Some(e) => e,
+			None => return Ok(false),
+		};
+		for tag in entry.tags.split_whitespace() {
+			if let Some(ids) = self.tags.get_mut(tag) {
+				ids.remove(&id);
+				if ids.is_empty() {
+					self.tags.remove(tag);
+				}
+			}
+		}
+		fs::remove_file(self.path(id, "owv"))?;
+		fs::remove_file(self.path(id, "tags")).or_else(|e| match e.kind() {
+			io::ErrorKind::NotFound => Ok(()),
+			_ => Err(e),
+		})?;
+		Ok(true)
+	}
+
+	/// Vehicles with an ID greater than the cursor whose name contains the given string,
+	/// ignoring case, and that have all of the given tags.
+	pub fn search<'a>(
+		&'a self,
+		cursor: u64,
+		name: &str,
+		tags: &str,
+	) -> impl Iterator<Item = VehicleEntry<'a>> + 'a {
+		let name = name.to_lowercase();
+		let tags = normalize_tags(tags);
+		let range = (Bound::Excluded(cursor), Bound::Unbounded);
+		// Start from the rarest tag so a search for a rare tag doesn't go through all
+		// vehicles.
+		let ids: Box<dyn Iterator<Item = u64>> = match tags
+			.split_whitespace()
+			.map(|t| self.tags.get(t).map_or(0, BTreeSet::len))
+			.zip(tags.split_whitespace())
+			.min()
+		{
+			Some((0, _)) => Box::new(None.into_iter()),
+			Some((_, tag)) => Box::new(self.tags[tag].range(range).copied()),
+			None => Box::new(self.vehicles.range(range).map(|(id, _)| *id)),
+		};
+		ids.filter_map(move |id| {
+			let e = &self.vehicles[&id];
+			let has_tags = tags
+				.split_whitespace()
+				.all(|t| e.tags.split_whitespace().any(|u| u == t));
+			let matches = has_tags && e.name.to_lowercase().contains(&name);
+			matches.then_some(VehicleEntry {
+				id,
+				size: e.size,
+				name: &e.name,
+				tags: &e.tags,
+			})
+		})
+	}
+
+	/// Read a chunk of a vehicle. Returns the size of the vehicle and the chunk, or `None`
+	/// if the vehicle doesn't exist.
+	pub fn read(&self, id: u64, offset: u32, len: usize) -> io::Result<Option<(u32, Vec<u8>)>> {
+		let size = match self.vehicles.get(&id) {
+			Some(e) => e.size,
+			None => return Ok(None),
+		};
+		let len = len.min(size.saturating_sub(offset) as usize);
+		let mut file = File::open(self.path(id, "owv"))?;
+		file.seek(SeekFrom::Start(offset.into()))?;
+		let mut data = vec![0; len];
+		file.read_exact(&mut data)?;
+		Ok(Some((size, data)))
+	}
+
+	/// Drop uploads that haven't received a chunk in a while.
+	pub fn remove_expired(&mut self, now: Instant) {
+		self.uploads
+			.retain(|_, u| now < u.last_seen + UPLOAD_TIMEOUT);
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::owv::test::vehicle;
+
+	#[test]
+	fn upload_and_search() {
+		let dir = std::env::temp_dir().join(format!("own-war-lobby-{}", std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		let mut repo = Repository::open(&dir, 2).unwrap();
+		let t = Instant::now();
+		let from = SocketAddr::from(([1, 2, 3, 4], 5));
+
+		let data = vehicle("Heavy Tank", 255);
+		let chunk = |offset: usize| Upload {
+			total: data.len() as u32,
+			offset: offset as u32,
+			tags: "Tank heavy",
+			data: &data[offset..data.len().min(offset + VEHICLE_CHUNK_SIZE)],
+		};
+		assert!(matches!(
+			repo.receive(from, &chunk(0), t),
+			Ok(Progress::Receiving(1024))
+		));
+		// Resent chunks are acknowledged again.
+		assert!(matches!(
+			repo.receive(from, &chunk(0), t),
+			Ok(Progress::Receiving(1024))
+		));
+		let received = match repo.receive(from, &chunk(1024), t) {
+			Ok(Progress::Complete(d)) => d,
+			_ => panic!("upload isn't complete"),
+		};
+		assert_eq!(received, data);
+		let id = repo.insert(&data, "Heavy Tank", "Tank heavy").unwrap();
+		assert_eq!(repo.insert(&data, "Heavy Tank", "").unwrap(), id);
+
+		let other = vehicle("Scout", 2);
+		let other_id = repo.insert(&other, "Scout", "fast").unwrap();
+		let third = vehicle("Third", 2);
+		assert_eq!(
+			repo.insert(&third, "Third", ""),
+			Err(ErrorCode::StorageFull)
+		);
+
+		let ids = |name, tags| repo.search(0, name, tags).map(|e| e.id).collect::<Vec<_>>();
+		assert_eq!(ids("tank", ""), [id]);
+		assert_eq!(ids("", "HEAVY tank"), [id]);
+		assert_eq!(ids("", "heavy fast"), []);
+		assert_eq!(ids("", "missing"), []);
+		assert_eq!(ids("", "").len(), 2);
+		let entry = repo.search(0, "sc", "").next().unwrap();
+		assert_eq!(
+			(entry.id, entry.name, entry.tags),
+			(other_id, "Scout", "fast")
+		);
+		assert_eq!(
+			repo.read(id, 1024, VEHICLE_CHUNK_SIZE).unwrap(),
+			Some((data.len() as u32, data[1024..].to_vec()))
+		);
+		assert_eq!(repo.read(1, 0, VEHICLE_CHUNK_SIZE).unwrap(), None);
+
+		// The index is rebuilt from the directory.
+		let mut repo = Repository::open(&dir, 2).unwrap();
+		assert_eq!(repo.len(), 2);
+		assert_eq!(repo.search(0, "", "heavy").next().unwrap().id, id);
+		assert!(repo.remove(other_id).unwrap());
+		assert!(!repo.remove(other_id).unwrap());
+		assert_eq!(repo.search(0, "", "fast").count(), 0);
+
+		// Uploads that don't fit are rejected right away.
+		let too_large = Upload {
+			total: MAX_SIZE + 1,
+			offset: 0,
+			tags: "",
+			data: &[],
+		};
+		assert!(matches!(
+			repo.receive(from, &too_large, t),
+			Err(ErrorCode::InvalidVehicle)
+		));
+		repo.receive(from, &chunk(0), t).ok().unwrap();
+		repo.remove_expired(t + UPLOAD_TIMEOUT);
+		assert_eq!(repo.uploads(), 0);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
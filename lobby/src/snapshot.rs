@@ -0,0 +1,242 @@
+//! Snapshots of the server list, so a restarted lobby doesn't forget all servers.
+//!
+//! A snapshot starts with [`MAGIC`], the format version and the time it was taken in seconds
+//! since the UNIX epoch, followed by the amount of entries and the entries themselves.
+
+use crate::ServerInfo;
+use own_war_lobby_protocol::codec::*;
+use own_war_lobby_protocol::{DecodeError, EncodeError};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+const MAGIC: &[u8; 4] = b"OWLS";
+
+/// Bump this whenever the layout changes.
+const VERSION: u8 = 1;
+
+const TRUSTED: u8 = 1 << 0;
+const VERIFIED: u8 = 1 << 1;
+const REPLICATED: u8 = 1 << 2;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+	Io(io::Error),
+	InvalidMagic,
+	UnsupportedVersion(u8),
+	Decode(DecodeError),
+	Encode(EncodeError),
+}
+
+impl fmt::Display for SnapshotError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Io(e) => e.fmt(f),
+			Self::InvalidMagic => "not a snapshot".fmt(f),
+			Self::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+			Self::Decode(e) => write!(f, "corrupt snapshot: {}", e),
+			Self::Encode(e) => write!(f, "failed to encode snapshot: {}", e),
+		}
+	}
+}
+
+impl From<io::Error> for SnapshotError {
+	fn from(e: io::Error) -> Self {
+		Self::Io(e)
+	}
+}
+
+impl From<DecodeError> for SnapshotError {
+	fn from(e: DecodeError) -> Self {
+		Self::Decode(e)
+	}
+}
+
+impl From<EncodeError> for SnapshotError {
+	fn from(e: EncodeError) -> Self {
+		Self::Encode(e)
+	}
+}
+
+/// Write a snapshot of the list. The snapshot is written to a temporary file first and synced
+/// to disk before it replaces the old snapshot, so neither a crash nor a power loss leaves a
+/// truncated snapshot behind.
+pub fn save(path: &Path, list: &BTreeMap<SocketAddr, ServerInfo>) -> Result<(), SnapshotError> {
+	let data = encode(list, SystemTime::now())?;
+	let tmp = path.with_extension("tmp");
+	let mut file = File::create(&tmp)?;
+	file.write_all(&data)?;
+	file.sync_all()?;
+	fs::rename(&tmp, path)?;
+	// The rename itself is only durable once the directory is synced.
+	#[cfg(unix)]
+	{
+		let dir = path
+			.parent()
+			.filter(|d| !d.as_os_str().is_empty())
+			.unwrap_or_else(|| Path::new("."));
+		File::open(dir)?.sync_all()?;
+	}
+	Ok(())
+}
+
+/// Read a snapshot. The restored entries are marked as such, see [`ServerInfo::restored`].
+pub fn load(path: &Path) -> Result<BTreeMap<SocketAddr, ServerInfo>, SnapshotError> {
+	let data = fs::read(path)?;
+	decode(&data, SystemTime::now(), Instant::now())
+}
+
+fn encode(
+	list: &BTreeMap<SocketAddr, ServerInfo>,
+	now: SystemTime,
+) -> Result<Vec<u8>, SnapshotError> {
+	let mut buf = Vec::from(&MAGIC[..]);
+	buf.push(VERSION);
+	let secs = now
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.unwrap_or_default();
+	buf.extend(&secs.as_secs().to_le_bytes());
+	buf.extend(&(list.len() as u32).to_le_bytes());
+	for (addr, info) in list {
+		encode_addr(*addr, &mut buf);
+		put_str8(&mut buf, &info.name)?;
+		put_str8(&mut buf, &info.map)?;
+		buf.push(info.max_players);
+		put_str16(&mut buf, &info.description)?;
+		buf.extend(&info.manager_port.to_le_bytes());
+		buf.push(info.version);
+		put_status(&mut buf, &info.current_status());
+		buf.extend(&info.token.to_le_bytes());
+		let flag = |b, f| if b { f } else { 0 };
+		buf.push(
+			flag(info.trusted, TRUSTED)
+				| flag(info.verified, VERIFIED)
+				| flag(info.replicated, REPLICATED),
+		);
+	}
+	Ok(buf)
+}
+
+fn decode(
+	data: &[u8],
+	now: SystemTime,
+	instant: Instant,
+) -> Result<BTreeMap<SocketAddr, ServerInfo>, SnapshotError> {
+	let mut r = Reader::new(data);
+	if r.bytes(MAGIC.len())? != MAGIC {
+		return Err(SnapshotError::InvalidMagic);
+	}
+	match r.u8()? {
+		VERSION => (),
+		v => return Err(SnapshotError::UnsupportedVersion(v)),
+	}
+	let taken = SystemTime::UNIX_EPOCH + Duration::from_secs(r.u64()?);
+	// Matches kept going while the lobby was down.
+	let downtime = now.duration_since(taken).unwrap_or_default();
+	let status_reported = instant.checked_sub(downtime).unwrap_or(instant);
+
+	let mut list = BTreeMap::new();
+	for _ in 0..r.u32()? {
+		let addr = r.addr()?;
+		let (name, map, max_players, description) = (r.str8()?, r.str8()?, r.u8()?, r.str16()?);
+		let (manager_port, version, status) = (r.u16()?, r.u8()?, r.status()?);
+		let (token, flags) = (r.u64()?, r.u8()?);
+		list.insert(
+			addr,
+			ServerInfo {
+				name: name.into(),
+				map: map.into(),
+				max_players,
+				description: description.into(),
+				last_ping: instant,
+				manager_port,
+				version,
+				status,
+				status_reported,
+				token,
+				trusted: flags & TRUSTED > 0,
+				verified: flags & VERIFIED > 0,
+				restored: true,
+				replicated: flags & REPLICATED > 0,
+			},
+		);
+	}
+	r.finish()?;
+	Ok(list)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use own_war_lobby_protocol::{MatchPhase, MatchStatus};
+
+	#[test]
+	fn round_trip() {
+		let t = Instant::now();
+		let mut list = BTreeMap::new();
+		list.insert(
+			SocketAddr::from(([1, 2, 3, 4], 5)),
+			ServerInfo {
+				name: "name".into(),
+				map: "map".into(),
+				max_players: 8,
+				description: "description".into(),
+				last_ping: t,
+				manager_port: 6,
+				version: 2,
+				status: MatchStatus {
+					players: 3,
+					phase: MatchPhase::Warmup,
+					elapsed: 10,
+				},
+				status_reported: t,
+				token: 0x1234,
+				trusted: true,
+				verified: false,
+				restored: false,
+				replicated: false,
+			},
+		);
+		let now = SystemTime::now();
+		let data = encode(&list, now).unwrap();
+		let restored = decode(&data, now, t).unwrap();
+		assert_eq!(restored.len(), 1);
+		let (addr, info) = restored.iter().next().unwrap();
+		assert_eq!(*addr, SocketAddr::from(([1, 2, 3, 4], 5)));
+		assert_eq!(&*info.name, "name");
+		assert_eq!(&*info.map, "map");
+		assert_eq!(&*info.description, "description");
+		assert_eq!(
+			(info.max_players, info.manager_port, info.version),
+			(8, 6, 2)
+		);
+		assert_eq!(info.status, list.values().next().unwrap().status);
+		assert_eq!(info.token, 0x1234);
+		assert!(info.trusted && !info.verified && info.restored);
+
+		assert!(matches!(
+			decode(&data[..data.len() - 1], now, t),
+			Err(SnapshotError::Decode(DecodeError::Truncated))
+		));
+		assert!(matches!(
+			decode(b"nope", now, t),
+			Err(SnapshotError::InvalidMagic)
+		));
+	}
+
+	#[test]
+	fn save_replaces_snapshot() {
+		let dir = std::env::temp_dir().join(format!("own-war-snapshot-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("snapshot");
+		fs::write(&path, b"old").unwrap();
+		save(&path, &BTreeMap::new()).unwrap();
+		assert!(load(&path).unwrap().is_empty());
+		assert!(!path.with_extension("tmp").exists());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
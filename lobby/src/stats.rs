@@ -0,0 +1,232 @@
+//! Counters that are logged periodically and exported as metrics.
+
+use crate::punch::Outcome;
+use own_war_lobby_protocol::MessageType;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
+
+/// Traffic counters since the lobby started.
+#[derive(Clone, Default)]
+pub struct Stats {
+	pub packets_in: u64,
+	pub bytes_in: u64,
+	pub packets_out: u64,
+	pub bytes_out: u64,
+	/// Packets dropped because a rate limit was exceeded.
+	pub rate_limited: u64,
+	/// Packets dropped because the sender is banned.
+	pub banned: u64,
+	/// Responses replaced with `RequestTooSmall` and other packets not sent because they
+	/// exceeded the budget of an unverified host.
+	pub truncated: u64,
+	pub registered: u64,
+	/// Registrations rejected by the filter.
+	pub rejected: u64,
+	pub expired: u64,
+	pub punches_started: u64,
+	pub punches_succeeded: u64,
+	pub punches_failed: u64,
+	pub punches_timed_out: u64,
+	/// Queued clients assigned to a server.
+	pub matched: u64,
+	/// Completed vehicle uploads.
+	pub uploaded: u64,
+	/// Messages with a valid header, by type.
+	pub messages: HashMap<MessageType, u64>,
+}
+
+impl Stats {
+	pub fn received(&mut self, size: usize) {
+		self.packets_in += 1;
+		self.bytes_in += size as u64;
+	}
+
+	pub fn sent(&mut self, size: usize) {
+		self.packets_out += 1;
+		self.bytes_out += size as u64;
+	}
+
+	pub fn message(&mut self, typ: MessageType) {
+		*self.messages.entry(typ).or_default() += 1;
+	}
+
+	pub fn punched(&mut self, outcome: Outcome) {
+		match outcome {
+			Outcome::Succeeded => self.punches_succeeded += 1,
+			Outcome::Failed => self.punches_failed += 1,
+			Outcome::TimedOut => self.punches_timed_out += 1,
+		}
+	}
+
+	/// The counters accumulated since an earlier copy of the stats was taken.
+	pub fn since(&self, earlier: &Self) -> Self {
+		let messages = self
+			.messages
+			.iter()
+			.map(|(t, n)| (*t, n - earlier.messages.get(t).unwrap_or(&0)))
+			.collect();
+		Self {
+			packets_in: self.packets_in - earlier.packets_in,
+			bytes_in: self.bytes_in - earlier.bytes_in,
+			packets_out: self.packets_out - earlier.packets_out,
+			bytes_out: self.bytes_out - earlier.bytes_out,
+			rate_limited: self.rate_limited - earlier.rate_limited,
+			banned: self.banned - earlier.banned,
+			truncated: self.truncated - earlier.truncated,
+			registered: self.registered - earlier.registered,
+			rejected: self.rejected - earlier.rejected,
+			expired: self.expired - earlier.expired,
+			punches_started: self.punches_started - earlier.punches_started,
+			punches_succeeded: self.punches_succeeded - earlier.punches_succeeded,
+			punches_failed: self.punches_failed - earlier.punches_failed,
+			punches_timed_out: self.punches_timed_out - earlier.punches_timed_out,
+			matched: self.matched - earlier.matched,
+			uploaded: self.uploaded - earlier.uploaded,
+			messages,
+		}
+	}
+
+	/// The amount of messages received of each type, in the order of the message types.
+	pub fn by_type(&self) -> impl Iterator<Item = (MessageType, u64)> + '_ {
+		let mut types = self.messages.keys().copied().collect::<Vec<_>>();
+		types.sort_by_key(|t| *t as u8);
+		types.into_iter().map(move |t| (t, self.messages[&t]))
+	}
+
+	/// Write the counters in the Prometheus text format.
+	pub fn metrics(&self, out: &mut String) {
+		let mut counter = |name: &str, help: &str, value: u64| {
+			let _ = writeln!(out, "# HELP own_war_lobby_{} {}", name, help);
+			let _ = writeln!(out, "# TYPE own_war_lobby_{} counter", name);
+			let _ = writeln!(out, "own_war_lobby_{} {}", name, value);
+		};
+		counter(
+			"packets_received_total",
+			"Packets received.",
+			self.packets_in,
+		);
+		counter("bytes_received_total", "Bytes received.", self.bytes_in);
+		counter("packets_sent_total", "Packets sent.", self.packets_out);
+		counter("bytes_sent_total", "Bytes sent.", self.bytes_out);
+		counter(
+			"rate_limited_total",
+			"Packets dropped by a rate limit.",
+			self.rate_limited,
+		);
+		counter(
+			"banned_total",
+			"Packets dropped from banned hosts.",
+			self.banned,
+		);
+		counter(
+			"truncated_total",
+			"Packets too large for the budget of unverified hosts.",
+			self.truncated,
+		);
+		counter("registered_total", "New registrations.", self.registered);
+		counter(
+			"rejected_total",
+			"Registrations rejected by the filter.",
+			self.rejected,
+		);
+		counter("expired_total", "Registrations that expired.", self.expired);
+		counter(
+			"punches_started_total",
+			"Punch sessions started.",
+			self.punches_started,
+		);
+		counter(
+			"matched_total",
+			"Queued clients assigned to a server.",
+			self.matched,
+		);
+		counter(
+			"vehicles_uploaded_total",
+			"Completed vehicle uploads.",
+			self.uploaded,
+		);
+
+		let _ = writeln!(
+			out,
+			"# HELP own_war_lobby_punches_total Finished punch sessions."
+		);
+		let _ = writeln!(out, "# TYPE own_war_lobby_punches_total counter");
+		for (outcome, n) in [
+			("succeeded", self.punches_succeeded),
+			("failed", self.punches_failed),
+			("timed_out", self.punches_timed_out),
+		] {
+			let _ = writeln!(
+				out,
+				"own_war_lobby_punches_total{{outcome=\"{}\"}} {}",
+				outcome, n
+			);
+		}
+
+		let _ = writeln!(
+			out,
+			"# HELP own_war_lobby_messages_total Messages received by type."
+		);
+		let _ = writeln!(out, "# TYPE own_war_lobby_messages_total counter");
+		for (typ, n) in self.by_type() {
+			let _ = writeln!(
+				out,
+				"own_war_lobby_messages_total{{type=\"{}\"}} {}",
+				typ.name(),
+				n
+			);
+		}
+	}
+}
+
+impl fmt::Display for Stats {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{} packets in ({} bytes), {} packets out ({} bytes), {} rate limited, {} banned, {} truncated, {} registered, {} rejected, {} expired, punches {} started {} succeeded {} failed {} timed out, {} matched, {} vehicles uploaded",
+			self.packets_in,
+			self.bytes_in,
+			self.packets_out,
+			self.bytes_out,
+			self.rate_limited,
+			self.banned,
+			self.truncated,
+			self.registered,
+			self.rejected,
+			self.expired,
+			self.punches_started,
+			self.punches_succeeded,
+			self.punches_failed,
+			self.punches_timed_out,
+			self.matched,
+			self.uploaded,
+		)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn since() {
+		let mut stats = Stats::default();
+		stats.received(10);
+		stats.message(MessageType::Ping);
+		let earlier = stats.clone();
+		stats.received(20);
+		stats.message(MessageType::Ping);
+		stats.message(MessageType::Hello);
+
+		let diff = stats.since(&earlier);
+		assert_eq!((diff.packets_in, diff.bytes_in), (1, 20));
+		let by_type = diff.by_type().collect::<Vec<_>>();
+		assert_eq!(by_type, [(MessageType::Ping, 1), (MessageType::Hello, 1)]);
+
+		let mut out = String::new();
+		stats.metrics(&mut out);
+		assert!(out.contains("own_war_lobby_packets_received_total 2\n"));
+		assert!(out.contains("own_war_lobby_messages_total{type=\"ping\"} 2\n"));
+	}
+}